@@ -0,0 +1,116 @@
+//! Access lists of the forwarder: the client networks it answers, checked
+//! before a query goes anywhere near upstream.
+use crate::ecs;
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+/// A network in CIDR notation, e.g. 192.168.1.0/24, a single address without
+/// a prefix length
+#[derive(Debug, Clone, PartialEq)]
+pub struct Network {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl Network {
+    pub fn contains(&self, addr: IpAddr) -> bool {
+        // clients of a dual stack socket show up as v4-mapped v6 addresses
+        let addr = addr.to_canonical();
+        addr.is_ipv4() == self.addr.is_ipv4() && ecs::mask(addr, self.prefix) == self.addr
+    }
+}
+
+impl FromStr for Network {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s, None),
+        };
+        let addr: IpAddr = addr
+            .parse()
+            .map_err(|_| std::format!("invalid network address {}", addr))?;
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => match prefix.parse::<u8>() {
+                Ok(prefix) if prefix <= max => prefix,
+                _ => return Err(std::format!("invalid network prefix length {}", prefix)),
+            },
+            None => max,
+        };
+        Ok(Network {
+            addr: ecs::mask(addr.to_canonical(), prefix),
+            prefix,
+        })
+    }
+}
+
+impl fmt::Display for Network {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix)
+    }
+}
+
+/// Networks allowed and denied. Denied ones win, and every client is allowed
+/// while no network is.
+#[derive(Debug, Clone, Default)]
+pub struct Acl {
+    allow: Vec<Network>,
+    deny: Vec<Network>,
+}
+
+impl Acl {
+    pub fn new() -> Acl {
+        Acl::default()
+    }
+
+    pub fn with_allow(mut self, network: Network) -> Acl {
+        self.allow.push(network);
+        self
+    }
+
+    pub fn with_deny(mut self, network: Network) -> Acl {
+        self.deny.push(network);
+        self
+    }
+
+    /// Whether queries of `client` are answered
+    pub fn permits(&self, client: IpAddr) -> bool {
+        if self.deny.iter().any(|network| network.contains(client)) {
+            return false;
+        }
+        self.allow.is_empty() || self.allow.iter().any(|network| network.contains(client))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn networks() {
+        let network: Network = "192.168.1.77/24".parse().unwrap();
+        assert_eq!(network.to_string(), "192.168.1.0/24");
+        assert!(network.contains("192.168.1.200".parse().unwrap()));
+        assert!(network.contains("::ffff:192.168.1.1".parse().unwrap()));
+        assert!(!network.contains("192.168.2.1".parse().unwrap()));
+        let network: Network = "2001:db8::/32".parse().unwrap();
+        assert!(network.contains("2001:db8:1::1".parse().unwrap()));
+        assert!(!network.contains("192.168.1.1".parse().unwrap()));
+        for invalid in ["192.168.1.0/33", "example.com/24", "2001:db8::/129"] {
+            assert!(invalid.parse::<Network>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn denied_networks_win() {
+        let client = "10.0.0.1".parse().unwrap();
+        assert!(Acl::new().permits(client));
+        let acl = Acl::new().with_allow("10.0.0.0/8".parse().unwrap());
+        assert!(acl.permits(client));
+        assert!(!acl.permits("192.0.2.1".parse().unwrap()));
+        assert!(!acl.with_deny("10.0.0.1".parse().unwrap()).permits(client));
+    }
+}
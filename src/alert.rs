@@ -0,0 +1,329 @@
+//! Alerts of watch mode: rules on the round trip, failures and answers of the
+//! queries watched, and the command and webhooks told when one fires or
+//! answers change.
+use crate::client::{self, ClientError, QueryType};
+use crate::sources::Sources;
+use crate::watch::Change;
+use reqwest::header::CONTENT_TYPE;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// When to alert about a query watched
+#[derive(Debug, Clone, PartialEq)]
+pub enum Rule {
+    /// Its round trip went over `limit` for `rounds` rounds in a row
+    Rtt { limit: Duration, rounds: usize },
+    /// It failed for `rounds` rounds in a row, NXDOMAIN included
+    Failure { rounds: usize },
+    /// Its answers changed from one round to the next
+    Change,
+}
+
+fn parse_limit(limit: &str) -> Option<Duration> {
+    let (value, unit) = match limit.strip_suffix("ms") {
+        Some(value) => (value, 1e-3),
+        None => (limit.strip_suffix('s')?, 1.0),
+    };
+    match value.parse::<f64>() {
+        Ok(value) if value > 0.0 && value.is_finite() => {
+            Some(Duration::from_secs_f64(value * unit))
+        }
+        _ => None,
+    }
+}
+
+fn in_a_row(rounds: usize) -> String {
+    match rounds {
+        1 => "a round".to_string(),
+        rounds => std::format!("{} rounds in a row", rounds),
+    }
+}
+
+impl FromStr for Rule {
+    type Err = String;
+
+    /// `rtt>200ms`, `failure` or `change`, a streak rule taking the rounds in
+    /// a row it needs after a colon, e.g. `rtt>200ms:3`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            std::format!(
+                "{} isn't an alert rule, e.g. rtt>200ms:3, failure:2 or change",
+                s
+            )
+        };
+        if s.eq_ignore_ascii_case("change") {
+            return Ok(Rule::Change);
+        }
+        let (rule, rounds) = match s.split_once(':') {
+            Some((rule, rounds)) => match rounds.parse::<usize>() {
+                Ok(rounds) if rounds > 0 => (rule, rounds),
+                _ => return Err(invalid()),
+            },
+            None => (s, 1),
+        };
+        match rule.to_ascii_lowercase().as_str() {
+            "failure" => Ok(Rule::Failure { rounds }),
+            rule => match rule.strip_prefix("rtt>").and_then(parse_limit) {
+                Some(limit) => Ok(Rule::Rtt { limit, rounds }),
+                None => Err(invalid()),
+            },
+        }
+    }
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Rule::Rtt { limit, rounds } => {
+                write!(f, "rtt>{}ms:{}", client::millis(*limit), rounds)
+            }
+            Rule::Failure { rounds } => write!(f, "failure:{}", rounds),
+            Rule::Change => write!(f, "change"),
+        }
+    }
+}
+
+/// A rule that fired for a query
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Alert {
+    pub rule: String,
+    pub host: String,
+    #[serde(rename = "type")]
+    pub query_type: String,
+    pub message: String,
+}
+
+impl fmt::Display for Alert {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "ALERT {} {}: {} ({})",
+            self.host, self.query_type, self.message, self.rule
+        )
+    }
+}
+
+/// Rules checked against every round of the queries watched, with the rounds
+/// in a row each one held for each query
+#[derive(Debug, Default)]
+pub struct Alerts {
+    rules: Vec<Rule>,
+    streaks: HashMap<(usize, String, QueryType), usize>,
+    seen: HashSet<(String, QueryType)>,
+}
+
+impl Alerts {
+    pub fn new(rules: Vec<Rule>) -> Alerts {
+        Alerts {
+            rules,
+            ..Alerts::default()
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// The alerts a round of `host` `query_type` fires, given how long it
+    /// took, whether it failed and how its answers changed. Streak rules fire
+    /// once when they reach their rounds, and again only after they stopped
+    /// holding. Answers of the first round are no change.
+    pub fn check(
+        &mut self,
+        host: &str,
+        query_type: &QueryType,
+        rtt: Duration,
+        error: Option<&ClientError>,
+        changes: &[Change],
+    ) -> Vec<Alert> {
+        let first = self.seen.insert((host.to_string(), query_type.clone()));
+        let mut alerts = Vec::new();
+        for (i, rule) in self.rules.iter().enumerate() {
+            let (holds, rounds) = match rule {
+                Rule::Rtt { limit, rounds } => (rtt > *limit, Some(*rounds)),
+                Rule::Failure { rounds } => (error.is_some(), Some(*rounds)),
+                Rule::Change => (!first && !changes.is_empty(), None),
+            };
+            let streak = self
+                .streaks
+                .entry((i, host.to_string(), query_type.clone()))
+                .or_default();
+            *streak = if holds { *streak + 1 } else { 0 };
+            // streak rules fire once per streak, changes every time
+            if !holds || rounds.is_some_and(|rounds| *streak != rounds) {
+                continue;
+            }
+            let message = match rule {
+                Rule::Rtt { limit, rounds } => std::format!(
+                    "round trip over {} ms for {}, {:.1} ms last",
+                    client::millis(*limit),
+                    in_a_row(*rounds),
+                    client::millis(rtt)
+                ),
+                Rule::Failure { rounds } => std::format!(
+                    "failed for {}, last with: {}",
+                    in_a_row(*rounds),
+                    error.map(|err| err.to_string()).unwrap_or_default()
+                ),
+                Rule::Change => {
+                    let changes: Vec<String> = changes.iter().map(|c| c.to_string()).collect();
+                    std::format!("answers changed: {}", changes.join("; "))
+                }
+            };
+            alerts.push(Alert {
+                rule: rule.to_string(),
+                host: host.to_string(),
+                query_type: query_type.to_string(),
+                message,
+            });
+        }
+        alerts
+    }
+}
+
+/// Body of the requests sent to webhooks
+#[derive(Debug, Clone, Copy, PartialEq, Default, clap::ValueEnum)]
+pub enum WebhookFormat {
+    /// The alert or the changes as a JSON object
+    #[default]
+    Json,
+    /// A message in the `text` of a JSON object, as Slack incoming webhooks
+    /// and the chats compatible with them take
+    Slack,
+}
+
+#[derive(Serialize)]
+struct SlackMessage {
+    text: String,
+}
+
+#[derive(Serialize)]
+struct ChangeSet<'a> {
+    host: &'a str,
+    #[serde(rename = "type")]
+    query_type: String,
+    changes: &'a [Change],
+}
+
+/// Where alerts go besides stdout: a shell command, the alert in its
+/// environment, and a webhook, sent the alert as JSON
+#[derive(Debug, Clone, Default)]
+pub struct Notifier {
+    command: Option<String>,
+    webhook: Option<String>,
+    format: WebhookFormat,
+    sources: Sources,
+}
+
+impl Notifier {
+    pub fn new() -> Notifier {
+        Notifier::default()
+    }
+
+    /// Runs `command` with `sh -c` for every alert, which it finds in the
+    /// NSQ_ALERT_HOST, NSQ_ALERT_TYPE, NSQ_ALERT_RULE and NSQ_ALERT_MESSAGE
+    /// variables
+    pub fn with_command(mut self, command: String) -> Notifier {
+        self.command = Some(command);
+        self
+    }
+
+    /// POSTs every alert to `url`, in the JSON of the format
+    pub fn with_webhook(mut self, url: String) -> Notifier {
+        self.webhook = Some(url);
+        self
+    }
+
+    pub fn with_format(mut self, format: WebhookFormat) -> Notifier {
+        self.format = format;
+        self
+    }
+
+    /// Trusts the CA roots of the first of `sources` that has any
+    pub fn with_sources(mut self, sources: Sources) -> Notifier {
+        self.sources = sources;
+        self
+    }
+
+    async fn post<T: Serialize>(&self, body: &T) -> Result<(), ClientError> {
+        let Some(url) = &self.webhook else {
+            return Ok(());
+        };
+        let body =
+            serde_json::to_string(body).map_err(|err| ClientError::EncodeError(err.to_string()))?;
+        self.sources
+            .with_ca_roots(reqwest::Client::builder())?
+            .build()
+            .map_err(|err| ClientError::ConnectError(err.to_string()))?
+            .post(url)
+            .header(CONTENT_TYPE, "application/json")
+            .body(body)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|err| ClientError::SendError(std::format!("{}: {}", url, err)))?;
+        Ok(())
+    }
+
+    /// POSTs how the answers of `host` `query_type` changed in a round to
+    /// the webhook, the command being for alerts only
+    pub async fn notify_changes(
+        &self,
+        host: &str,
+        query_type: &QueryType,
+        changes: &[Change],
+    ) -> Result<(), ClientError> {
+        match self.format {
+            WebhookFormat::Json => {
+                self.post(&ChangeSet {
+                    host,
+                    query_type: query_type.to_string(),
+                    changes,
+                })
+                .await
+            }
+            WebhookFormat::Slack => {
+                let lines: Vec<String> = changes.iter().map(|c| c.to_string()).collect();
+                let text = std::format!(
+                    "Answers of {} {} changed:\n{}",
+                    host,
+                    query_type,
+                    lines.join("\n")
+                );
+                self.post(&SlackMessage { text }).await
+            }
+        }
+    }
+
+    pub async fn notify(&self, alert: &Alert) -> Result<(), ClientError> {
+        if let Some(command) = &self.command {
+            let status = tokio::process::Command::new("sh")
+                .arg("-c")
+                .arg(command)
+                .env("NSQ_ALERT_HOST", &alert.host)
+                .env("NSQ_ALERT_TYPE", &alert.query_type)
+                .env("NSQ_ALERT_RULE", &alert.rule)
+                .env("NSQ_ALERT_MESSAGE", &alert.message)
+                .status()
+                .await
+                .map_err(|err| ClientError::GenericError(std::format!("{}: {}", command, err)))?;
+            if !status.success() {
+                return Err(ClientError::GenericError(std::format!(
+                    "{} exited with {}",
+                    command,
+                    status
+                )));
+            }
+        }
+        match self.format {
+            WebhookFormat::Json => self.post(alert).await,
+            WebhookFormat::Slack => {
+                let text = alert.to_string();
+                self.post(&SlackMessage { text }).await
+            }
+        }
+    }
+}
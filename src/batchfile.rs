@@ -0,0 +1,264 @@
+//! Lines of dig batch files, `dig -f`, each the arguments of a dig command:
+//! names, types, a class, `@server` and `+options`. Options that only change
+//! how dig prints responses are taken and ignored, the output being the same
+//! for every line.
+use crate::client::{ClassType, QueryType};
+use crate::ecs::ClientSubnet;
+use crate::resolver;
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// dig options on what to print and how, with or without their `no` prefix
+const DISPLAY_OPTIONS: [&str; 16] = [
+    "all",
+    "answer",
+    "authority",
+    "additional",
+    "question",
+    "comments",
+    "cmd",
+    "stats",
+    "short",
+    "multiline",
+    "ttlid",
+    "ttlunits",
+    "class",
+    "rrcomments",
+    "qr",
+    "identify",
+];
+
+/// A name to query, in the types of the run when its line gave none
+#[derive(Debug, Clone, PartialEq)]
+pub struct BatchQuery {
+    pub name: String,
+    pub query_type: Option<QueryType>,
+}
+
+/// The queries of a line and the settings it gives them on top of the run's
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BatchLine {
+    pub queries: Vec<BatchQuery>,
+    pub class: Option<ClassType>,
+    pub server: Option<String>,
+    pub port: Option<u16>,
+    pub recursion: Option<bool>,
+    pub checking_disabled: Option<bool>,
+    pub authentic_data: Option<bool>,
+    pub cookie: Option<bool>,
+    pub idn: Option<bool>,
+    pub subnet: Option<ClientSubnet>,
+    pub timeout: Option<Duration>,
+    pub attempts: Option<usize>,
+}
+
+impl BatchLine {
+    /// `@server` with the port of `-p`, when the line has one
+    pub fn server(&self) -> Option<String> {
+        match (&self.server, self.port) {
+            (Some(server), Some(port)) if server.contains(':') && !server.starts_with('[') => {
+                Some(std::format!("[{}]:{}", server, port))
+            }
+            (Some(server), Some(port)) => Some(std::format!("{}:{}", server, port)),
+            (Some(server), None) => Some(server.clone()),
+            (None, _) => None,
+        }
+    }
+
+    /// A type applies to the name before it, or to the next one when that
+    /// name has a type already or none came before. One left over queries
+    /// the last name in it too.
+    fn push_type(&mut self, query_type: QueryType, pending: &mut Option<QueryType>) {
+        match self.queries.last_mut() {
+            Some(query) if query.query_type.is_none() => query.query_type = Some(query_type),
+            _ => *pending = Some(query_type),
+        }
+    }
+
+    /// Takes `+option`, `+nooption` or `+option=value`
+    fn option(&mut self, option: &str) -> Result<(), String> {
+        let (name, value) = match option.split_once('=') {
+            Some((name, value)) => (name, Some(value)),
+            None => (option, None),
+        };
+        let (name, on) = match name.strip_prefix("no") {
+            Some(name) => (name, false),
+            None => (name, true),
+        };
+        let value = || value.ok_or_else(|| std::format!("+{} needs a value", name));
+        match name {
+            "recurse" => self.recursion = Some(on),
+            "cdflag" => self.checking_disabled = Some(on),
+            "adflag" => self.authentic_data = Some(on),
+            "cookie" => self.cookie = Some(on),
+            "idnout" => self.idn = Some(on),
+            "subnet" if on => self.subnet = Some(value()?.parse()?),
+            "time" => {
+                let secs = value()?;
+                match secs.parse::<u64>() {
+                    Ok(secs) if secs > 0 => self.timeout = Some(Duration::from_secs(secs)),
+                    _ => return Err(std::format!("{} isn't a number of seconds", secs)),
+                }
+            }
+            // retries come after the first try
+            "tries" | "retry" => {
+                let count = value()?;
+                let count = count
+                    .parse::<usize>()
+                    .map_err(|_| std::format!("{} isn't a number of tries", count))?;
+                self.attempts = Some(match name {
+                    "retry" => count + 1,
+                    _ => count.max(1),
+                });
+            }
+            name if DISPLAY_OPTIONS.contains(&name) => {}
+            _ => return Err(std::format!("unsupported dig option +{}", option)),
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for BatchLine {
+    type Err = String;
+
+    /// A line like `example.com MX @1.1.1.1 +norecurse`, or `-x 192.0.2.1`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut line = BatchLine::default();
+        let mut pending = None;
+        let mut tokens = s.split_whitespace();
+        while let Some(token) = tokens.next() {
+            let mut value = || {
+                tokens
+                    .next()
+                    .ok_or_else(|| std::format!("{} needs a value", token))
+            };
+            match token {
+                "-t" => {
+                    let query_type = value()?.parse()?;
+                    line.push_type(query_type, &mut pending);
+                }
+                "-c" => line.class = Some(value()?.parse()?),
+                "-p" => {
+                    let port = value()?;
+                    line.port = Some(
+                        port.parse()
+                            .map_err(|_| std::format!("{} isn't a port", port))?,
+                    );
+                }
+                "-q" => line.queries.push(BatchQuery {
+                    name: value()?.to_string(),
+                    query_type: pending.take(),
+                }),
+                "-x" => {
+                    let addr = value()?;
+                    let addr: IpAddr = addr
+                        .parse()
+                        .map_err(|_| std::format!("{} isn't an address", addr))?;
+                    line.queries.push(BatchQuery {
+                        name: resolver::reverse_name(&addr),
+                        query_type: Some(QueryType::PTR),
+                    });
+                }
+                _ if token.starts_with('@') => line.server = Some(token[1..].to_string()),
+                _ if token.starts_with('+') => line.option(&token[1..])?,
+                _ if token.starts_with('-') => {
+                    return Err(std::format!("unsupported dig flag {}", token))
+                }
+                _ if token.starts_with(|c: char| c.is_ascii_alphabetic())
+                    && token.parse::<QueryType>().is_ok() =>
+                {
+                    line.push_type(token.parse()?, &mut pending);
+                }
+                _ if token.parse::<ClassType>().is_ok() => line.class = Some(token.parse()?),
+                _ => line.queries.push(BatchQuery {
+                    name: token.to_string(),
+                    query_type: pending.take(),
+                }),
+            }
+        }
+        let Some(last) = line.queries.last() else {
+            return Err(std::format!("{} has no name to query", s.trim()));
+        };
+        if let Some(query_type) = pending {
+            line.queries.push(BatchQuery {
+                name: last.name.clone(),
+                query_type: Some(query_type),
+            });
+        }
+        Ok(line)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn query(name: &str, query_type: Option<QueryType>) -> BatchQuery {
+        BatchQuery {
+            name: name.to_string(),
+            query_type,
+        }
+    }
+
+    #[test]
+    fn names_and_their_types() {
+        let line: BatchLine = "example.com MX example.org".parse().unwrap();
+        assert_eq!(
+            line.queries,
+            [
+                query("example.com", Some(QueryType::MX)),
+                query("example.org", None)
+            ]
+        );
+        // a type before any name goes to the next one, one left over to the last
+        let line: BatchLine = "-t AAAA example.com TXT NS".parse().unwrap();
+        assert_eq!(
+            line.queries,
+            [
+                query("example.com", Some(QueryType::AAAA)),
+                query("example.com", Some(QueryType::NS))
+            ]
+        );
+        let line: BatchLine = "-q mx -x 192.0.2.1".parse().unwrap();
+        assert_eq!(
+            line.queries,
+            [
+                query("mx", None),
+                query("1.2.0.192.in-addr.arpa.", Some(QueryType::PTR))
+            ]
+        );
+    }
+
+    #[test]
+    fn settings_of_a_line() {
+        let line: BatchLine =
+            "CH version.bind TXT @2001:db8::53 -p 5353 +norecurse +time=2 +retry=2"
+                .parse()
+                .unwrap();
+        assert_eq!(line.class, Some(ClassType::CH));
+        assert_eq!(line.server().as_deref(), Some("[2001:db8::53]:5353"));
+        assert_eq!(line.recursion, Some(false));
+        assert_eq!(line.timeout, Some(Duration::from_secs(2)));
+        assert_eq!(line.attempts, Some(3));
+        let line: BatchLine = "example.com +tries=0 +short +noall +answer"
+            .parse()
+            .unwrap();
+        assert_eq!(line.attempts, Some(1));
+        assert_eq!(line.server(), None);
+    }
+
+    #[test]
+    fn bad_lines() {
+        for line in [
+            "MX",
+            "example.com +bogus",
+            "example.com -z",
+            "example.com +time=0",
+            "example.com -p",
+            "-x example.com",
+        ] {
+            assert!(line.parse::<BatchLine>().is_err(), "{}", line);
+        }
+    }
+}
@@ -0,0 +1,446 @@
+//! Load against a resolver: queries sent at a set rate or as fast as it
+//! answers, and the latencies and response codes they got back. Names come
+//! from templates and types from a weighted mix, to look like the load of
+//! real clients rather than one cached name.
+use crate::client::{self, Client, ClientError, QueryType, Randomness, Response};
+use futures::stream::{self, StreamExt};
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::ops::Range;
+use std::str::FromStr;
+use std::time::Duration;
+use tokio::time::{sleep_until, Instant};
+
+/// Latencies and outcomes of the queries of a benchmark
+#[derive(Debug, Default)]
+pub struct BenchStats {
+    /// Of the queries that got a response, in the order they got it
+    latencies: Vec<Duration>,
+    /// Queries per response code, or per error for those that got none
+    rcodes: BTreeMap<String, usize>,
+    /// Queries per type
+    types: BTreeMap<String, usize>,
+    queries: usize,
+    elapsed: Duration,
+}
+
+impl BenchStats {
+    pub fn new() -> BenchStats {
+        BenchStats::default()
+    }
+
+    /// Accounts for a query of `query_type` answered by `result` after
+    /// `latency`
+    pub fn record(
+        &mut self,
+        query_type: &QueryType,
+        result: &Result<Response, ClientError>,
+        latency: Duration,
+    ) {
+        self.queries += 1;
+        *self.types.entry(query_type.to_string()).or_default() += 1;
+        let outcome = match result {
+            Ok(response) => {
+                self.latencies.push(latency);
+                client::rcode_name(response.rcode())
+            }
+            Err(ClientError::Timeout(_)) => "TIMEOUT".to_string(),
+            Err(err) => std::format!("{:?}", err)
+                .split('(')
+                .next()
+                .unwrap_or_default()
+                .to_string(),
+        };
+        *self.rcodes.entry(outcome).or_default() += 1;
+    }
+
+    pub fn queries(&self) -> usize {
+        self.queries
+    }
+
+    /// Queries answered NOERROR or NXDOMAIN, the answers a healthy resolver
+    /// gives
+    pub fn succeeded(&self) -> usize {
+        ["NOERROR", "NXDOMAIN"]
+            .iter()
+            .map(|rcode| self.rcodes.get(*rcode).copied().unwrap_or(0))
+            .sum()
+    }
+
+    /// Latency `percent`% of the responses came back within, by nearest rank
+    pub fn percentile(&self, percent: f64) -> Option<Duration> {
+        let mut sorted = self.latencies.clone();
+        sorted.sort();
+        let rank = (percent / 100.0 * sorted.len() as f64).ceil() as usize;
+        sorted.get(rank.saturating_sub(1)).copied()
+    }
+
+    pub fn mean(&self) -> Option<Duration> {
+        let total: Duration = self.latencies.iter().sum();
+        (!self.latencies.is_empty()).then(|| total / self.latencies.len() as u32)
+    }
+}
+
+impl fmt::Display for BenchStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let secs = self.elapsed.as_secs_f64();
+        writeln!(
+            f,
+            "Queries: {} in {:.2} s ({:.1} qps), succeeded: {} ({:.1}%)",
+            self.queries,
+            secs,
+            if secs > 0.0 {
+                self.queries as f64 / secs
+            } else {
+                0.0
+            },
+            self.succeeded(),
+            if self.queries > 0 {
+                self.succeeded() as f64 * 100.0 / self.queries as f64
+            } else {
+                0.0
+            }
+        )?;
+        let ms = |latency: Option<Duration>| latency.map_or(0.0, client::millis);
+        writeln!(
+            f,
+            "Latency: min {:.3} ms, avg {:.3} ms, p50 {:.3} ms, p95 {:.3} ms, p99 {:.3} ms, max {:.3} ms",
+            ms(self.latencies.iter().min().copied()),
+            ms(self.mean()),
+            ms(self.percentile(50.0)),
+            ms(self.percentile(95.0)),
+            ms(self.percentile(99.0)),
+            ms(self.latencies.iter().max().copied()),
+        )?;
+        let rcodes: Vec<String> = self
+            .rcodes
+            .iter()
+            .map(|(rcode, count)| std::format!("{} {}", rcode, count))
+            .collect();
+        write!(f, "Responses: {}", rcodes.join(", "))?;
+        if self.types.len() > 1 {
+            let types: Vec<String> = self
+                .types
+                .iter()
+                .map(|(query_type, count)| std::format!("{} {}", query_type, count))
+                .collect();
+            write!(f, "\nTypes: {}", types.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+/// Stats of the measured queries, after those of the warm-up when there was
+/// one
+#[derive(Debug, Default)]
+pub struct BenchReport {
+    pub warmup: Option<BenchStats>,
+    pub measured: BenchStats,
+}
+
+impl fmt::Display for BenchReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.warmup {
+            Some(warmup) => write!(f, "Warm-up\n{}\n\nMeasured\n{}", warmup, self.measured),
+            None => write!(f, "{}", self.measured),
+        }
+    }
+}
+
+/// Longest label of a name, RFC 1035 2.3.4
+const MAX_LABEL: usize = 63;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Part {
+    Text(String),
+    /// The numbers from the first to the last, one query after the other
+    Range(u64, u64),
+    /// Letters and digits drawn for every query
+    Random(usize),
+}
+
+/// A name with placeholders filled in for each query: `{1..1000}` counts
+/// through a range, `{random}` draws 8 letters and digits, `{random:N}` N
+#[derive(Debug, Clone, PartialEq)]
+pub struct NameTemplate {
+    parts: Vec<Part>,
+}
+
+impl NameTemplate {
+    /// Name of the `nth` query of the template, its random labels drawn from
+    /// `rng`
+    pub fn name<R: Rng>(&self, nth: usize, rng: &mut R) -> String {
+        let mut name = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Text(text) => name.push_str(text),
+                Part::Range(first, last) => {
+                    let value = first + nth as u64 % (last - first + 1);
+                    name.push_str(&value.to_string());
+                }
+                Part::Random(len) => name.extend(
+                    rng.sample_iter(&Alphanumeric)
+                        .take(*len)
+                        .map(|c| (c as char).to_ascii_lowercase()),
+                ),
+            }
+        }
+        name
+    }
+}
+
+impl FromStr for NameTemplate {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = |placeholder: &str| {
+            std::format!(
+                "{{{}}} isn't a placeholder of {}, e.g. {{1..1000}}, {{random}} or {{random:12}}",
+                placeholder,
+                s
+            )
+        };
+        let mut parts = Vec::new();
+        let mut rest = s;
+        while let Some(start) = rest.find('{') {
+            let Some(len) = rest[start..].find('}') else {
+                return Err(std::format!("{} has an unclosed {{", s));
+            };
+            if start > 0 {
+                parts.push(Part::Text(rest[..start].to_string()));
+            }
+            let placeholder = &rest[start + 1..start + len];
+            let part = match placeholder.split_once("..") {
+                Some((first, last)) => match (first.parse::<u64>(), last.parse::<u64>()) {
+                    (Ok(first), Ok(last)) if first <= last => Part::Range(first, last),
+                    _ => return Err(invalid(placeholder)),
+                },
+                None => match placeholder.split_once(':') {
+                    None if placeholder == "random" => Part::Random(8),
+                    Some(("random", len)) => match len.parse::<usize>() {
+                        Ok(len) if (1..=MAX_LABEL).contains(&len) => Part::Random(len),
+                        _ => return Err(invalid(placeholder)),
+                    },
+                    _ => return Err(invalid(placeholder)),
+                },
+            };
+            parts.push(part);
+            rest = &rest[start + len + 1..];
+        }
+        if !rest.is_empty() {
+            parts.push(Part::Text(rest.to_string()));
+        }
+        Ok(NameTemplate { parts })
+    }
+}
+
+/// Types of the queries in proportion to their weights, e.g. `A=70,AAAA=30`
+#[derive(Debug, Clone, PartialEq)]
+pub struct TypeMix {
+    weights: Vec<(QueryType, u32)>,
+}
+
+impl TypeMix {
+    /// A mix of `query_type` alone
+    pub fn single(query_type: QueryType) -> TypeMix {
+        TypeMix {
+            weights: vec![(query_type, 1)],
+        }
+    }
+
+    /// Type of a query, drawn from `rng` by weight
+    pub fn pick<R: Rng>(&self, rng: &mut R) -> QueryType {
+        let total: u32 = self.weights.iter().map(|(_, weight)| weight).sum();
+        let mut draw = rng.gen_range(0..total);
+        for (query_type, weight) in &self.weights {
+            if draw < *weight {
+                return query_type.clone();
+            }
+            draw -= weight;
+        }
+        self.weights[0].0.clone()
+    }
+}
+
+impl FromStr for TypeMix {
+    type Err = String;
+
+    /// Types with their weights, `TYPE=WEIGHT` separated by commas, a type
+    /// without one weighing 1
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut weights = Vec::new();
+        for entry in s.split(',') {
+            let (query_type, weight) = match entry.split_once('=') {
+                Some((query_type, weight)) => match weight.parse::<u32>() {
+                    Ok(weight) if weight > 0 => (query_type, weight),
+                    _ => return Err(std::format!("{} isn't a weight of {}", weight, s)),
+                },
+                None => (entry, 1),
+            };
+            weights.push((query_type.trim().parse::<QueryType>()?, weight));
+        }
+        Ok(TypeMix { weights })
+    }
+}
+
+impl fmt::Display for TypeMix {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let [(query_type, _)] = self.weights.as_slice() {
+            return write!(f, "{}", query_type);
+        }
+        let weights: Vec<String> = self
+            .weights
+            .iter()
+            .map(|(query_type, weight)| std::format!("{}={}", query_type, weight))
+            .collect();
+        write!(f, "{}", weights.join(","))
+    }
+}
+
+/// Queries sent to `server`, cycling through the templates of `names`, with
+/// at most `concurrency` of them waiting for a response at once
+#[derive(Debug, Clone)]
+pub struct Bench {
+    server: String,
+    names: Vec<NameTemplate>,
+    mix: TypeMix,
+    count: usize,
+    warmup: usize,
+    /// Every name gets a label of its own in front, so none is cached
+    cache_bust: bool,
+    qps: Option<f64>,
+    concurrency: usize,
+    timeout: Duration,
+    randomness: Randomness,
+}
+
+impl Bench {
+    pub fn new(server: String, names: Vec<NameTemplate>, mix: TypeMix) -> Bench {
+        Bench {
+            server,
+            names,
+            mix,
+            count: 1000,
+            warmup: 0,
+            cache_bust: false,
+            qps: None,
+            concurrency: 64,
+            timeout: Duration::from_secs(2),
+            randomness: Randomness::default(),
+        }
+    }
+
+    pub fn with_count(mut self, count: usize) -> Bench {
+        self.count = count;
+        self
+    }
+
+    /// Sends `count` queries before the measured ones, reported apart, so
+    /// the resolver has the names in its cache
+    pub fn with_warmup(mut self, count: usize) -> Bench {
+        self.warmup = count;
+        self
+    }
+
+    /// Puts a label of its own in front of the name of every query, so the
+    /// resolver has to resolve each one rather than answer from its cache
+    pub fn with_cache_bust(mut self) -> Bench {
+        self.cache_bust = true;
+        self
+    }
+
+    /// Sends queries at `qps` per second instead of as fast as responses come
+    pub fn with_qps(mut self, qps: f64) -> Bench {
+        self.qps = Some(qps);
+        self
+    }
+
+    pub fn with_concurrency(mut self, concurrency: usize) -> Bench {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Bench {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Draws the random labels and types of the queries from `randomness`,
+    /// the same ones for the same seed
+    pub fn with_randomness(mut self, randomness: Randomness) -> Bench {
+        self.randomness = randomness;
+        self
+    }
+
+    /// Name and type of the `i`th query
+    fn nth_query(&self, i: usize) -> (String, QueryType) {
+        let mut rng = self.randomness.rng(&std::format!("bench {}", i));
+        let template = &self.names[i % self.names.len()];
+        let mut name = template.name(i / self.names.len(), &mut rng);
+        if self.cache_bust {
+            let label: String = (&mut rng)
+                .sample_iter(&Alphanumeric)
+                .take(8)
+                .map(|c| (c as char).to_ascii_lowercase())
+                .collect();
+            name = std::format!("{}-{}.{}", label, i, name);
+        }
+        (name, self.mix.pick(&mut rng))
+    }
+
+    /// Sends a single query, once, the way the benchmark measures it
+    async fn query(
+        &self,
+        name: &str,
+        query_type: QueryType,
+    ) -> (Result<Response, ClientError>, Duration) {
+        let client = match Client::new(self.server.clone()).await {
+            Ok(client) => client.with_attempts(1).with_timeout(self.timeout),
+            Err(err) => return (Err(err), Duration::ZERO),
+        };
+        let sent = Instant::now();
+        let result = client.exchange(name, query_type).await;
+        (result, sent.elapsed())
+    }
+
+    /// The warm-up queries, if any, then the measured ones, which go on
+    /// through the names where the warm-up stopped
+    pub async fn run(&self) -> BenchReport {
+        if self.names.is_empty() {
+            return BenchReport::default();
+        }
+        let warmup = match self.warmup {
+            0 => None,
+            count => Some(self.phase(0..count).await),
+        };
+        BenchReport {
+            warmup,
+            measured: self.phase(self.warmup..self.warmup + self.count).await,
+        }
+    }
+
+    /// Stats of the queries of `indices`, paced from the first one
+    async fn phase(&self, indices: Range<usize>) -> BenchStats {
+        let mut stats = BenchStats::new();
+        let started = Instant::now();
+        let first = indices.start;
+        let mut results = stream::iter(indices)
+            .map(|i| async move {
+                if let Some(qps) = self.qps {
+                    let at = Duration::from_secs_f64((i - first) as f64 / qps);
+                    sleep_until(started + at).await;
+                }
+                let (name, query_type) = self.nth_query(i);
+                let (result, latency) = self.query(&name, query_type.clone()).await;
+                (query_type, result, latency)
+            })
+            .buffer_unordered(self.concurrency);
+        while let Some((query_type, result, latency)) = results.next().await {
+            stats.record(&query_type, &result, latency);
+        }
+        stats.elapsed = started.elapsed();
+        stats
+    }
+}
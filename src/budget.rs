@@ -0,0 +1,215 @@
+use crate::client::ClientError;
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// Caps the number of upstream queries of a whole run. Clones share the same
+/// counter, so every client of a run can draw from a single budget.
+#[derive(Debug, Clone, Default)]
+pub struct QueryBudget {
+    used: Arc<AtomicUsize>,
+    max: Option<usize>,
+}
+
+impl QueryBudget {
+    pub fn new(max: Option<usize>) -> QueryBudget {
+        QueryBudget {
+            used: Arc::new(AtomicUsize::new(0)),
+            max,
+        }
+    }
+
+    /// Accounts for one more upstream query, failing once the budget is spent
+    pub fn spend(&self) -> Result<(), ClientError> {
+        let used = self.used.fetch_add(1, Ordering::SeqCst);
+        match self.max {
+            Some(max) if used >= max => {
+                self.used.fetch_sub(1, Ordering::SeqCst);
+                Err(ClientError::BudgetExhausted(max))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    pub fn used(&self) -> usize {
+        self.used.load(Ordering::SeqCst)
+    }
+
+    pub fn max(&self) -> Option<usize> {
+        self.max
+    }
+}
+
+impl fmt::Display for QueryBudget {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.max {
+            Some(max) => write!(f, "{}/{} upstream queries", self.used(), max),
+            None => write!(f, "{} upstream queries", self.used()),
+        }
+    }
+}
+
+/// Retries always available to a run, so small batches can still retry
+const RETRY_RESERVE: u64 = 10;
+
+#[derive(Debug, Default)]
+struct RetryState {
+    /// In hundredths of a retry
+    balance: u64,
+    retried: usize,
+    denied: usize,
+}
+
+/// Retry budget shared by a batch, like Finagle's: every query deposits
+/// `percent`% of a retry and every retry withdraws a whole one, so a flaky
+/// resolver can't turn a bulk run into a retry storm.
+#[derive(Debug, Clone)]
+pub struct RetryBudget {
+    state: Arc<Mutex<RetryState>>,
+    percent: u64,
+}
+
+impl RetryBudget {
+    pub fn new(percent: u64) -> RetryBudget {
+        RetryBudget {
+            state: Arc::new(Mutex::new(RetryState {
+                balance: RETRY_RESERVE * 100,
+                ..RetryState::default()
+            })),
+            percent,
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, RetryState> {
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    pub fn deposit(&self) {
+        self.lock().balance += self.percent;
+    }
+
+    /// Takes a retry out of the budget, returns false when there is none left
+    pub fn withdraw(&self) -> bool {
+        let mut state = self.lock();
+        if state.balance < 100 {
+            state.denied += 1;
+            return false;
+        }
+        state.balance -= 100;
+        state.retried += 1;
+        true
+    }
+
+    pub fn retried(&self) -> usize {
+        self.lock().retried
+    }
+
+    pub fn denied(&self) -> usize {
+        self.lock().denied
+    }
+}
+
+impl Default for RetryBudget {
+    fn default() -> RetryBudget {
+        RetryBudget::new(20)
+    }
+}
+
+impl fmt::Display for RetryBudget {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let state = self.lock();
+        write!(
+            f,
+            "retry budget {}%: {} retries, {} denied, {:.2} left",
+            self.percent,
+            state.retried,
+            state.denied,
+            state.balance as f64 / 100.0
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::{Client, QueryType};
+    use crate::testing::{self, Responder};
+    use std::time::Duration;
+
+    /// A client of a server that never answers, trying each query `attempts`
+    /// times
+    async fn unanswered(responder: &Responder, attempts: usize) -> Client {
+        Client::new(responder.addr())
+            .await
+            .unwrap()
+            .with_timeout(Duration::from_millis(50))
+            .with_attempts(attempts)
+    }
+
+    #[test]
+    fn queries_deposit_retries() {
+        let budget = RetryBudget::new(50);
+        for _ in 0..RETRY_RESERVE {
+            assert!(budget.withdraw());
+        }
+        assert!(!budget.withdraw());
+        budget.deposit();
+        assert!(!budget.withdraw());
+        budget.deposit();
+        assert!(budget.withdraw());
+        assert_eq!((budget.retried(), budget.denied()), (11, 2));
+    }
+
+    #[tokio::test]
+    async fn spent_retry_budget_stops_retries() {
+        let responder = Responder::answering(|_| Vec::new()).await;
+        let budget = RetryBudget::new(0);
+        while budget.withdraw() {}
+        let result = unanswered(&responder, 3)
+            .await
+            .with_retry_budget(budget.clone())
+            .exchange("example.com", QueryType::A)
+            .await;
+        assert!(matches!(result, Err(ClientError::Timeout(1))));
+        assert_eq!(responder.queries().len(), 1);
+        assert_eq!(budget.denied(), 2);
+    }
+
+    #[tokio::test]
+    async fn queries_earn_their_retries() {
+        let responder = Responder::answering(|_| Vec::new()).await;
+        let budget = RetryBudget::new(100);
+        while budget.withdraw() {}
+        let result = unanswered(&responder, 3)
+            .await
+            .with_retry_budget(budget.clone())
+            .exchange("example.com", QueryType::A)
+            .await;
+        assert!(matches!(result, Err(ClientError::AllFailed(failures)) if failures.len() == 2));
+        // the retry the query deposited, then none
+        assert_eq!(responder.queries().len(), 2);
+        assert_eq!(
+            (budget.retried(), budget.denied()),
+            (RETRY_RESERVE as usize + 1, 2)
+        );
+    }
+
+    #[tokio::test]
+    async fn query_budget_caps_the_run() {
+        let responder = Responder::answering(|query| vec![testing::address(query)]).await;
+        let budget = QueryBudget::new(Some(2));
+        let client = Client::new(responder.addr())
+            .await
+            .unwrap()
+            .with_budget(budget.clone());
+        for host in ["a.example", "b.example"] {
+            client.exchange(host, QueryType::A).await.unwrap();
+        }
+        let result = client.exchange("c.example", QueryType::A).await;
+        assert!(matches!(result, Err(ClientError::BudgetExhausted(2))));
+        assert_eq!(responder.queries().len(), 2);
+        assert_eq!(budget.to_string(), "2/2 upstream queries");
+    }
+}
@@ -0,0 +1,591 @@
+use crate::client::QueryType;
+use crate::rdata::{self, RData};
+use crate::wire::{Message, Question, Record};
+use std::collections::HashMap;
+use std::fmt;
+use std::mem::size_of;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::OwnedMutexGuard;
+
+/// Name, type, class and flags of a query, the name lowercased without the
+/// trailing dot
+type Key = (String, QueryType, QueryKind);
+
+/// Class of a query and its flags that change the answer: a query without
+/// recursion, one for answers that failed validation and one for DNSSEC
+/// records are each answered apart
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) struct QueryKind {
+    pub(crate) class: u16,
+    pub(crate) recursion: bool,
+    pub(crate) checking_disabled: bool,
+    pub(crate) dnssec_ok: bool,
+}
+
+#[derive(Debug)]
+struct Entry {
+    message: Message,
+    server: String,
+    size: usize,
+    stored: Instant,
+    /// Seconds the response can be served for, the lowest TTL of its records
+    ttl: u32,
+    /// Approximate memory the entry takes, key included
+    footprint: usize,
+}
+
+impl Entry {
+    /// Time left before the response expires, none once it is stale
+    fn remaining(&self) -> Duration {
+        Duration::from_secs(self.ttl as u64).saturating_sub(self.stored.elapsed())
+    }
+}
+
+/// TTL of the records of stale responses, RFC 8767 4
+const STALE_TTL: u32 = 30;
+
+/// A response served from the cache, its TTLs lowered by the time it was kept
+pub(crate) struct Cached {
+    pub(crate) message: Message,
+    pub(crate) server: String,
+    pub(crate) size: usize,
+    pub(crate) ttl: u32,
+    /// Seconds since the response was stored
+    pub(crate) age: u64,
+}
+
+/// Responses kept until their records expire, so repeated queries don't go
+/// upstream. Clones share the same entries, so every client of a run can use
+/// a single cache.
+#[derive(Debug, Clone, Default)]
+pub struct Cache {
+    entries: Arc<Mutex<HashMap<Key, Entry>>>,
+    /// One lock per query, held while it goes upstream
+    pending: Arc<Mutex<HashMap<Key, Arc<tokio::sync::Mutex<()>>>>>,
+    hits: Arc<AtomicUsize>,
+    misses: Arc<AtomicUsize>,
+    /// How long expired responses are kept to answer with when upstream fails
+    stale: Option<Duration>,
+    /// Approximate memory taken by the entries, updated with their lock held
+    memory: Arc<AtomicUsize>,
+    /// Shared by the clones, so a reload changes the limit of every one
+    max_memory: Arc<Mutex<Option<usize>>>,
+    evictions: Arc<AtomicUsize>,
+}
+
+/// Counters of a cache, for the daemon to report
+#[derive(Debug, Clone, PartialEq)]
+pub struct CacheStats {
+    pub entries: usize,
+    /// Approximate bytes taken by the entries
+    pub memory: usize,
+    pub max_memory: Option<usize>,
+    pub hits: usize,
+    pub misses: usize,
+    /// Entries dropped before they expired to stay under `max_memory`
+    pub evictions: usize,
+}
+
+/// A name and type the cache keeps a response of, for the daemon to list
+#[derive(Debug, Clone, PartialEq)]
+pub struct CachedName {
+    /// Lowercased, without the trailing dot
+    pub name: String,
+    pub query_type: QueryType,
+    /// Seconds left before the response expires, 0 once it is stale
+    pub ttl: u32,
+}
+
+impl fmt::Display for CachedName {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.ttl {
+            0 => write!(f, "{}. {} stale", self.name, self.query_type),
+            ttl => write!(f, "{}. {} {}", self.name, self.query_type, ttl),
+        }
+    }
+}
+
+fn key(host: &str, query_type: &QueryType, kind: QueryKind) -> Key {
+    (
+        host.trim_end_matches('.').to_lowercase(),
+        query_type.clone(),
+        kind,
+    )
+}
+
+/// Approximate memory taken by a cached response: the structures holding it
+/// and the names and data they point to
+fn footprint(key: &Key, message: &Message, server: &str) -> usize {
+    let records = message
+        .answers
+        .iter()
+        .chain(message.authorities.iter())
+        .chain(message.additionals.iter())
+        .map(|record| size_of::<Record>() + record.name.len() + record.rdata.len());
+    let questions = message
+        .questions
+        .iter()
+        .map(|question| size_of::<Question>() + question.name.len());
+    size_of::<(Key, Entry)>() + key.0.len() + server.len() + records.chain(questions).sum::<usize>()
+}
+
+/// Records of a message that expire, every section but the OPT pseudo-RR
+fn records(message: &mut Message) -> impl Iterator<Item = &mut Record> {
+    message
+        .answers
+        .iter_mut()
+        .chain(message.authorities.iter_mut())
+        .chain(message.additionals.iter_mut())
+        .filter(|record| record.query_type != QueryType::OPT)
+}
+
+/// How long an NXDOMAIN or NODATA response can be cached: the lower of the
+/// TTL and the MINIMUM field of the SOA record of its authority section,
+/// RFC 2308 5. Without an SOA record it isn't cached at all.
+fn negative_ttl(message: &Message) -> Option<u32> {
+    message.authorities.iter().find_map(|record| {
+        match rdata::decode(&record.query_type, &record.rdata)? {
+            RData::SOA { minimum, .. } => Some(record.ttl.min(minimum)),
+            _ => None,
+        }
+    })
+}
+
+impl Cache {
+    pub fn new() -> Cache {
+        Cache::default()
+    }
+
+    /// Keeps responses for `window` after they expire, so clients can answer
+    /// with them when every upstream server fails, RFC 8767
+    pub fn with_stale(mut self, window: Duration) -> Cache {
+        self.stale = Some(window);
+        self
+    }
+
+    /// Keeps the entries under about `bytes` of memory, evicting those
+    /// closest to expiring to make room for new ones
+    pub fn with_max_memory(mut self, bytes: usize) -> Cache {
+        self.max_memory = Arc::new(Mutex::new(Some(bytes)));
+        self
+    }
+
+    /// Changes the memory limit of the cache and its clones, evicting
+    /// entries right away to fit a lower one
+    pub fn set_max_memory(&self, bytes: Option<usize>) {
+        *self
+            .max_memory
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()) = bytes;
+        if let Some(max) = bytes {
+            let mut entries = self
+                .entries
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+            self.evict(&mut entries, max);
+        }
+    }
+
+    fn max_memory(&self) -> Option<usize> {
+        *self
+            .max_memory
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Whether an entry stored `age` seconds ago with `ttl` is kept, stale
+    /// once expired
+    fn keeps(&self, age: u64, ttl: u32) -> bool {
+        let window = self.stale.map_or(0, |window| window.as_secs());
+        age < ttl as u64 + window
+    }
+
+    /// Waits for the same query sent upstream by another client, so its
+    /// response can be served from the cache rather than asked twice
+    pub(crate) async fn pending(
+        &self,
+        host: &str,
+        query_type: &QueryType,
+        kind: QueryKind,
+    ) -> OwnedMutexGuard<()> {
+        let lock = self
+            .pending
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .entry(key(host, query_type, kind))
+            .or_default()
+            .clone();
+        lock.lock_owned().await
+    }
+
+    /// The response to a query while its records are still fresh
+    pub(crate) fn get(
+        &self,
+        host: &str,
+        query_type: &QueryType,
+        kind: QueryKind,
+    ) -> Option<Cached> {
+        let key = key(host, query_type, kind);
+        let mut entries = self
+            .entries
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let fresh = entries
+            .get(&key)
+            .map(|entry| (entry.stored.elapsed().as_secs(), entry.ttl));
+        let age = match fresh {
+            Some((age, ttl)) if age < ttl as u64 => age,
+            // expired, but kept in case upstream fails
+            Some((age, ttl)) if self.keeps(age, ttl) => {
+                self.misses.fetch_add(1, Ordering::SeqCst);
+                return None;
+            }
+            _ => {
+                if let Some(entry) = entries.remove(&key) {
+                    self.memory.fetch_sub(entry.footprint, Ordering::SeqCst);
+                }
+                self.misses.fetch_add(1, Ordering::SeqCst);
+                return None;
+            }
+        };
+        let entry = &entries[&key];
+        self.hits.fetch_add(1, Ordering::SeqCst);
+        let mut message = entry.message.clone();
+        for record in records(&mut message) {
+            record.ttl = record.ttl.saturating_sub(age as u32);
+        }
+        Some(Cached {
+            message,
+            server: entry.server.clone(),
+            size: entry.size,
+            ttl: entry.ttl - age as u32,
+            age,
+        })
+    }
+
+    /// The response to a query whose records expired, kept for the stale
+    /// window, with how long ago it expired. Its records get a TTL of 30
+    /// seconds, RFC 8767 4.
+    pub(crate) fn get_stale(
+        &self,
+        host: &str,
+        query_type: &QueryType,
+        kind: QueryKind,
+    ) -> Option<(Cached, u64)> {
+        let entries = self
+            .entries
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let entry = entries.get(&key(host, query_type, kind))?;
+        let age = entry.stored.elapsed().as_secs();
+        if age < entry.ttl as u64 || !self.keeps(age, entry.ttl) {
+            return None;
+        }
+        let mut message = entry.message.clone();
+        for record in records(&mut message) {
+            record.ttl = STALE_TTL;
+        }
+        let cached = Cached {
+            message,
+            server: entry.server.clone(),
+            size: entry.size,
+            ttl: STALE_TTL,
+            age,
+        };
+        Some((cached, age - entry.ttl as u64))
+    }
+
+    /// Keeps a response with answers for as long as its records live, and an
+    /// NXDOMAIN or NODATA one for its negative TTL
+    pub(crate) fn insert(
+        &self,
+        host: &str,
+        query_type: &QueryType,
+        kind: QueryKind,
+        message: &Message,
+        server: &str,
+        size: usize,
+    ) {
+        let mut message = message.clone();
+        let ttl = match (message.header.rcode(), message.answers.is_empty()) {
+            (0, false) => records(&mut message).map(|record| record.ttl).min(),
+            (0, true) | (3, _) => negative_ttl(&message),
+            _ => None,
+        };
+        let ttl = match ttl {
+            Some(ttl) if ttl > 0 => ttl,
+            _ => return,
+        };
+        let key = key(host, query_type, kind);
+        let footprint = footprint(&key, &message, server);
+        let max_memory = self.max_memory();
+        if max_memory.is_some_and(|max| footprint > max) {
+            return;
+        }
+        let entry = Entry {
+            message,
+            server: server.to_string(),
+            size,
+            stored: Instant::now(),
+            ttl,
+            footprint,
+        };
+        let mut entries = self
+            .entries
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        if let Some(replaced) = entries.remove(&key) {
+            self.memory.fetch_sub(replaced.footprint, Ordering::SeqCst);
+        }
+        if let Some(max) = max_memory {
+            self.evict(&mut entries, max - footprint);
+        }
+        self.memory.fetch_add(footprint, Ordering::SeqCst);
+        entries.insert(key, entry);
+    }
+
+    /// Drops entries until they take at most `target` bytes: the expired
+    /// ones first, then those closest to expiring
+    fn evict(&self, entries: &mut HashMap<Key, Entry>, target: usize) {
+        if self.memory.load(Ordering::SeqCst) <= target {
+            return;
+        }
+        entries.retain(|_, entry| {
+            let kept = self.keeps(entry.stored.elapsed().as_secs(), entry.ttl);
+            if !kept {
+                self.memory.fetch_sub(entry.footprint, Ordering::SeqCst);
+            }
+            kept
+        });
+        while self.memory.load(Ordering::SeqCst) > target {
+            let victim = entries
+                .iter()
+                .min_by_key(|(_, entry)| (entry.remaining(), entry.stored))
+                .map(|(key, _)| key.clone());
+            let Some(entry) = victim.and_then(|key| entries.remove(&key)) else {
+                break;
+            };
+            self.memory.fetch_sub(entry.footprint, Ordering::SeqCst);
+            self.evictions.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    /// The names and types of the responses kept, sorted, each listed once
+    /// with the longest TTL left of the responses of its queries' flags
+    pub fn names(&self) -> Vec<CachedName> {
+        let entries = self
+            .entries
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let mut names: Vec<CachedName> = Vec::with_capacity(entries.len());
+        for ((name, query_type, _), entry) in entries.iter() {
+            if !self.keeps(entry.stored.elapsed().as_secs(), entry.ttl) {
+                continue;
+            }
+            let ttl = entry.remaining().as_secs() as u32;
+            match names
+                .iter_mut()
+                .find(|listed| listed.name == *name && listed.query_type == *query_type)
+            {
+                Some(listed) => listed.ttl = listed.ttl.max(ttl),
+                None => names.push(CachedName {
+                    name: name.clone(),
+                    query_type: query_type.clone(),
+                    ttl,
+                }),
+            }
+        }
+        names.sort_by_key(|listed| (listed.name.clone(), listed.query_type.to_string()));
+        names
+    }
+
+    /// Drops the responses of `name`, of every type and class, or all of
+    /// them without one, returning how many were dropped
+    pub fn flush(&self, name: Option<&str>) -> usize {
+        let name = name.map(|name| name.trim_end_matches('.').to_lowercase());
+        let mut entries = self
+            .entries
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let before = entries.len();
+        entries.retain(|(host, ..), entry| {
+            let kept = name.as_ref().is_some_and(|name| name != host);
+            if !kept {
+                self.memory.fetch_sub(entry.footprint, Ordering::SeqCst);
+            }
+            kept
+        });
+        before - entries.len()
+    }
+
+    pub fn hits(&self) -> usize {
+        self.hits.load(Ordering::SeqCst)
+    }
+
+    pub fn misses(&self) -> usize {
+        self.misses.load(Ordering::SeqCst)
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        let entries = self
+            .entries
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        CacheStats {
+            entries: entries.len(),
+            memory: self.memory.load(Ordering::SeqCst),
+            max_memory: self.max_memory(),
+            hits: self.hits(),
+            misses: self.misses(),
+            evictions: self.evictions.load(Ordering::SeqCst),
+        }
+    }
+}
+
+impl fmt::Display for Cache {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "cache: {} hits, {} misses", self.hits(), self.misses())
+    }
+}
+
+impl fmt::Display for CacheStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Entries: {}", self.entries)?;
+        match self.max_memory {
+            Some(max) => writeln!(f, "Memory: {} of {} bytes", self.memory, max)?,
+            None => writeln!(f, "Memory: {} bytes", self.memory)?,
+        }
+        writeln!(f, "Hits: {}", self.hits)?;
+        writeln!(f, "Misses: {}", self.misses)?;
+        write!(f, "Evictions: {}", self.evictions)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::{Client, Decision};
+    use crate::testing::{self, Responder};
+
+    async fn client(responder: &Responder, cache: &Cache) -> Client {
+        Client::new(responder.addr())
+            .await
+            .unwrap()
+            .with_cache(cache.clone())
+    }
+
+    #[tokio::test]
+    async fn answers_are_served_while_fresh() {
+        let responder = Responder::answering(|query| vec![testing::address(query)]).await;
+        let cache = Cache::new();
+        let client = client(&responder, &cache).await;
+        client.exchange("example.com", QueryType::A).await.unwrap();
+        let cached = client.exchange("EXAMPLE.com.", QueryType::A).await.unwrap();
+        assert!(matches!(
+            cached.decisions(),
+            [Decision::Cached { ttl: 299..=300 }]
+        ));
+        assert_eq!(cached.message.answers.len(), 1);
+        // another type is another query
+        client
+            .exchange("example.com", QueryType::AAAA)
+            .await
+            .unwrap();
+        assert_eq!(responder.queries().len(), 2);
+        assert_eq!((cache.hits(), cache.misses()), (1, 2));
+    }
+
+    #[tokio::test]
+    async fn a_lower_memory_limit_evicts_right_away() {
+        let responder = Responder::answering(|query| vec![testing::address(query)]).await;
+        let cache = Cache::new();
+        let client = client(&responder, &cache).await;
+        for host in ["example.com", "example.org"] {
+            client.exchange(host, QueryType::A).await.unwrap();
+        }
+        let before = cache.stats();
+        assert_eq!((before.entries, before.max_memory), (2, None));
+        cache.set_max_memory(Some(before.memory - 1));
+        let after = cache.stats();
+        assert_eq!(after.entries, 1);
+        assert_eq!(after.evictions, 1);
+        assert_eq!(after.max_memory, Some(before.memory - 1));
+    }
+
+    #[tokio::test]
+    async fn names_are_listed_and_flushed() {
+        let responder = Responder::answering(|query| vec![testing::address(query)]).await;
+        let cache = Cache::new();
+        let client = client(&responder, &cache).await;
+        for (host, query_type) in [
+            ("www.example.com", QueryType::A),
+            ("Example.com", QueryType::AAAA),
+            ("example.com", QueryType::A),
+        ] {
+            client.exchange(host, query_type).await.unwrap();
+        }
+        let names = cache.names();
+        let listed: Vec<(&str, &QueryType)> = names
+            .iter()
+            .map(|name| (name.name.as_str(), &name.query_type))
+            .collect();
+        assert_eq!(
+            listed,
+            [
+                ("example.com", &QueryType::A),
+                ("example.com", &QueryType::AAAA),
+                ("www.example.com", &QueryType::A),
+            ]
+        );
+        assert!(names.iter().all(|name| (299..=300).contains(&name.ttl)));
+        assert_eq!(cache.flush(Some("EXAMPLE.com.")), 2);
+        assert_eq!(cache.names().len(), 1);
+        assert_eq!(cache.flush(None), 1);
+        assert_eq!((cache.names().len(), cache.stats().memory), (0, 0));
+    }
+
+    #[tokio::test]
+    async fn negative_answers_are_kept_for_their_soa_minimum() {
+        let responder = Responder::answering(|query| match query.questions[0].query_type {
+            QueryType::A => vec![testing::negative(query, 3, 3600, 60)],
+            _ => vec![testing::negative(query, 0, 30, 900)],
+        })
+        .await;
+        let cache = Cache::new();
+        let client = client(&responder, &cache).await;
+        // the lower of the TTL and MINIMUM of the SOA record
+        for (query_type, rcode, ttl) in [(QueryType::A, 3, 60), (QueryType::MX, 0, 30)] {
+            client
+                .exchange("example.com", query_type.clone())
+                .await
+                .unwrap();
+            let cached = client.exchange("example.com", query_type).await.unwrap();
+            assert_eq!(cached.message.header.rcode(), rcode);
+            assert!(cached.message.answers.is_empty());
+            let [Decision::Cached { ttl: left }] = cached.decisions() else {
+                panic!("{:?}", cached.decisions());
+            };
+            assert!(ttl - 1 <= *left && *left <= ttl);
+        }
+        assert_eq!(responder.queries().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn failures_and_negative_answers_without_soa_are_not_kept() {
+        let responder = Responder::answering(|query| match query.questions[0].query_type {
+            QueryType::A => vec![testing::reply(query, 3, &[])],
+            _ => vec![testing::reply(query, 2, &[])],
+        })
+        .await;
+        let cache = Cache::new();
+        let client = client(&responder, &cache).await;
+        for query_type in [QueryType::A, QueryType::A, QueryType::MX, QueryType::MX] {
+            let response = client.exchange("example.com", query_type).await.unwrap();
+            assert!(!matches!(response.decisions()[0], Decision::Cached { .. }));
+        }
+        assert_eq!(responder.queries().len(), 4);
+        assert_eq!(cache.stats().entries, 0);
+    }
+}
@@ -0,0 +1,341 @@
+//! Messages as they go over the wire: annotated hex dumps of the queries and
+//! responses of a client, and a pcap file of their datagrams for Wireshark.
+use crate::client::{self, ClassType, ClientError, QueryType};
+use crate::rdata;
+use crate::transport::Transport;
+use crate::wire::{self, Header, Message};
+use std::fs::File;
+use std::io::Write;
+use std::net::{IpAddr, SocketAddr};
+use std::ops::Range;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Raw IPv4 or IPv6 packets, the version telling them apart
+const LINKTYPE_RAW: u32 = 101;
+
+const UDP: u8 = 17;
+
+/// Whether a message went to the server or came back from it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Direction {
+    Query,
+    Response,
+}
+
+/// Where the messages of a client are copied, clones sharing the pcap file
+#[derive(Debug, Clone, Default)]
+pub struct Capture {
+    dump: bool,
+    pcap: Option<Arc<Mutex<File>>>,
+}
+
+impl Capture {
+    pub fn new() -> Capture {
+        Capture::default()
+    }
+
+    /// Prints an annotated hex dump of every message on stderr
+    pub fn with_dump(mut self) -> Capture {
+        self.dump = true;
+        self
+    }
+
+    /// Writes every message over UDP to a pcap file at `path`, as the
+    /// datagram that carried it
+    pub fn with_pcap(mut self, path: &str) -> Result<Capture, ClientError> {
+        let io_error = |err: std::io::Error| {
+            ClientError::GenericError(std::format!("Failed to write {}: {}", path, err))
+        };
+        let mut file = File::create(path).map_err(io_error)?;
+        let mut header = Vec::with_capacity(24);
+        header.extend(0xa1b2c3d4u32.to_le_bytes());
+        header.extend(2u16.to_le_bytes());
+        header.extend(4u16.to_le_bytes());
+        // time zone offset and accuracy of the timestamps
+        header.extend([0; 8]);
+        header.extend(65535u32.to_le_bytes());
+        header.extend(LINKTYPE_RAW.to_le_bytes());
+        file.write_all(&header).map_err(io_error)?;
+        self.pcap = Some(Arc::new(Mutex::new(file)));
+        Ok(self)
+    }
+
+    /// Copies `data`, a message going over `transport` in `direction`
+    pub fn record(&self, direction: Direction, transport: &Transport, data: &[u8]) {
+        if self.dump {
+            let title = match direction {
+                Direction::Query => std::format!("Query to {}", transport.server()),
+                Direction::Response => std::format!("Response from {}", transport.server()),
+            };
+            eprint!(
+                ";; {} over {}, {} bytes\n{}",
+                title,
+                transport.name(),
+                data.len(),
+                hex_dump(data)
+            );
+        }
+        let Some(pcap) = &self.pcap else {
+            return;
+        };
+        let Some((local, remote)) = transport.addresses() else {
+            log::debug!("Not capturing a message over {}", transport.name());
+            return;
+        };
+        let (from, to) = match direction {
+            Direction::Query => (local, remote),
+            Direction::Response => (remote, local),
+        };
+        let packet = ip_packet(from, to, data);
+        let since_epoch = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let mut record = Vec::with_capacity(16 + packet.len());
+        record.extend((since_epoch.as_secs() as u32).to_le_bytes());
+        record.extend(since_epoch.subsec_micros().to_le_bytes());
+        record.extend((packet.len() as u32).to_le_bytes());
+        record.extend((packet.len() as u32).to_le_bytes());
+        record.extend(packet);
+        let mut file = pcap.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if let Err(err) = file.write_all(&record) {
+            log::warn!("Failed to write the capture: {}", err);
+        }
+    }
+}
+
+/// Internet checksum of `data`, RFC 1071
+fn checksum(data: &[u8]) -> u16 {
+    let mut sum: u32 = data
+        .chunks(2)
+        .map(|pair| u16::from_be_bytes([pair[0], *pair.get(1).unwrap_or(&0)]) as u32)
+        .sum();
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+/// `payload` in a UDP datagram from `from` to `to`, in its IP packet
+fn ip_packet(from: SocketAddr, to: SocketAddr, payload: &[u8]) -> Vec<u8> {
+    let len = 8 + payload.len();
+    let mut udp = Vec::with_capacity(len);
+    udp.extend(from.port().to_be_bytes());
+    udp.extend(to.port().to_be_bytes());
+    udp.extend((len as u16).to_be_bytes());
+    udp.extend([0, 0]);
+    udp.extend(payload);
+    // the checksum covers a pseudo header of the addresses too
+    let mut pseudo = Vec::new();
+    match (from.ip(), to.ip()) {
+        (IpAddr::V4(source), IpAddr::V4(destination)) => {
+            pseudo.extend(source.octets());
+            pseudo.extend(destination.octets());
+            pseudo.extend([0, UDP]);
+            pseudo.extend((len as u16).to_be_bytes());
+        }
+        (source, destination) => {
+            pseudo.extend(ipv6_octets(source));
+            pseudo.extend(ipv6_octets(destination));
+            pseudo.extend((len as u32).to_be_bytes());
+            pseudo.extend([0, 0, 0, UDP]);
+        }
+    }
+    pseudo.extend(&udp);
+    let sum = match checksum(&pseudo) {
+        0 => 0xffff,
+        sum => sum,
+    };
+    udp[6..8].copy_from_slice(&sum.to_be_bytes());
+    let mut packet = Vec::with_capacity(40 + len);
+    match (from.ip(), to.ip()) {
+        (IpAddr::V4(source), IpAddr::V4(destination)) => {
+            packet.extend([0x45, 0]);
+            packet.extend(((20 + len) as u16).to_be_bytes());
+            // identification, fragment offset, time to live and protocol
+            packet.extend([0, 0, 0, 0, 64, UDP, 0, 0]);
+            packet.extend(source.octets());
+            packet.extend(destination.octets());
+            let sum = checksum(&packet);
+            packet[10..12].copy_from_slice(&sum.to_be_bytes());
+        }
+        (source, destination) => {
+            packet.extend([0x60, 0, 0, 0]);
+            packet.extend((len as u16).to_be_bytes());
+            packet.extend([UDP, 64]);
+            packet.extend(ipv6_octets(source));
+            packet.extend(ipv6_octets(destination));
+        }
+    }
+    packet.extend(udp);
+    packet
+}
+
+fn ipv6_octets(addr: IpAddr) -> [u8; 16] {
+    match addr {
+        IpAddr::V4(addr) => addr.to_ipv6_mapped().octets(),
+        IpAddr::V6(addr) => addr.octets(),
+    }
+}
+
+fn class_name(class: u16) -> String {
+    match ClassType::from_code(class) {
+        Ok(class) => class.to_string(),
+        Err(_) => std::format!("CLASS{}", class),
+    }
+}
+
+fn u16_at(data: &[u8], at: usize) -> Option<u16> {
+    Some(u16::from_be_bytes(data.get(at..at + 2)?.try_into().ok()?))
+}
+
+/// Ranges of `data` with what they hold: the header, each question and each
+/// record, then what was left over or couldn't be read
+fn annotate(data: &[u8]) -> Vec<(Range<usize>, String)> {
+    let mut fields = Vec::new();
+    if data.len() < 12 {
+        fields.push((0..data.len(), "truncated header".to_string()));
+        return fields;
+    }
+    let header = Header {
+        id: u16_at(data, 0).unwrap_or_default(),
+        flags: u16_at(data, 2).unwrap_or_default(),
+    };
+    let counts: Vec<u16> = (0..4)
+        .map(|i| u16_at(data, 4 + 2 * i).unwrap_or_default())
+        .collect();
+    fields.push((
+        0..12,
+        std::format!(
+            "header: id {:#06x}, opcode {}, {}, flags {}, {} questions, {} answers, {} authority, {} additional",
+            header.id,
+            header.opcode(),
+            client::rcode_name(header.rcode()),
+            header.flag_names().join(" "),
+            counts[0],
+            counts[1],
+            counts[2],
+            counts[3]
+        ),
+    ));
+    // decompressed records to show the data of, when the whole message reads
+    let records: Vec<wire::Record> = match Message::decode(data) {
+        Ok(message) => message
+            .answers
+            .into_iter()
+            .chain(message.authorities)
+            .chain(message.additionals)
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+    let mut at = 12;
+    for _ in 0..counts[0] {
+        let Ok((name, end)) = wire::decode_name(data, at) else {
+            break;
+        };
+        let (Some(query_type), Some(class)) = (u16_at(data, end), u16_at(data, end + 2)) else {
+            break;
+        };
+        fields.push((
+            at..end + 4,
+            std::format!(
+                "question: {} {} {}",
+                name,
+                class_name(class),
+                QueryType::from_code(query_type)
+            ),
+        ));
+        at = end + 4;
+    }
+    let sections = [
+        ("answer", counts[1]),
+        ("authority", counts[2]),
+        ("additional", counts[3]),
+    ];
+    let mut index = 0;
+    for (section, count) in sections {
+        for _ in 0..count {
+            let Ok((name, end)) = wire::decode_name(data, at) else {
+                return leftover(fields, data, at);
+            };
+            let (Some(query_type), Some(class), Some(ttl), Some(rdlength)) = (
+                u16_at(data, end),
+                u16_at(data, end + 2),
+                data.get(end + 4..end + 8),
+                u16_at(data, end + 8),
+            ) else {
+                return leftover(fields, data, at);
+            };
+            let next = end + 10 + rdlength as usize;
+            if next > data.len() {
+                return leftover(fields, data, at);
+            }
+            let query_type = QueryType::from_code(query_type);
+            let ttl = u32::from_be_bytes(ttl.try_into().unwrap_or_default());
+            let annotation = match query_type {
+                QueryType::OPT => std::format!(
+                    "{}: OPT, UDP payload {}, flags {:#010x}, {} bytes of options",
+                    section,
+                    class,
+                    ttl,
+                    rdlength
+                ),
+                _ => {
+                    let rdata = records
+                        .get(index)
+                        .and_then(|record| rdata::decode(&record.query_type, &record.rdata))
+                        .map_or(std::format!("{} bytes", rdlength), |data| data.to_string());
+                    std::format!(
+                        "{}: {} {} {} {} {}",
+                        section,
+                        name,
+                        ttl,
+                        class_name(class),
+                        query_type,
+                        rdata
+                    )
+                }
+            };
+            fields.push((at..next, annotation));
+            at = next;
+            index += 1;
+        }
+    }
+    leftover(fields, data, at)
+}
+
+fn leftover(
+    mut fields: Vec<(Range<usize>, String)>,
+    data: &[u8],
+    at: usize,
+) -> Vec<(Range<usize>, String)> {
+    if at < data.len() {
+        fields.push((at..data.len(), "unread".to_string()));
+    }
+    fields
+}
+
+/// `data` as rows of 16 bytes in hex, each field starting a row with its
+/// annotation next to it
+pub fn hex_dump(data: &[u8]) -> String {
+    let mut out = String::new();
+    for (range, annotation) in annotate(data) {
+        let mut annotation = Some(annotation);
+        for start in range.clone().step_by(16) {
+            let row = &data[start..(start + 16).min(range.end)];
+            let hex: Vec<String> = row
+                .iter()
+                .map(|byte| std::format!("{:02x}", byte))
+                .collect();
+            let line = match annotation.take() {
+                Some(annotation) => {
+                    std::format!("{:04x}  {:<47}  ; {}", start, hex.join(" "), annotation)
+                }
+                None => std::format!("{:04x}  {}", start, hex.join(" ")),
+            };
+            out.push_str(&line);
+            out.push('\n');
+        }
+    }
+    out
+}
@@ -0,0 +1,246 @@
+//! Health checks of resolvers: each one is sent a few well-known queries and
+//! probed for EDNS, TCP and DNSSEC support, and for NXDOMAIN responses
+//! rewritten into answers, to choose upstreams and keep an eye on them.
+use crate::client::{ClassType, Client, ClientError, QueryType};
+use crate::output::Table;
+use crate::transport::TcpTransport;
+use crate::wire::Message;
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use std::fmt;
+use std::time::Duration;
+use tokio::time::timeout;
+
+const NOERROR: u8 = 0;
+const NXDOMAIN: u8 = 3;
+
+/// Queries any recursive resolver answers, their round trips making its
+/// latency
+const WELL_KNOWN: [(&str, QueryType); 3] = [
+    (".", QueryType::NS),
+    ("example.com", QueryType::A),
+    ("example.org", QueryType::AAAA),
+];
+
+/// What a resolver does with DNSSEC
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Dnssec {
+    /// It sets the AD flag on the signed answers it validated
+    Validating,
+    /// It returns signatures without validating them
+    Signatures,
+    /// It strips signatures or didn't answer with them
+    Unsupported,
+}
+
+impl fmt::Display for Dnssec {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Dnssec::Validating => write!(f, "validating"),
+            Dnssec::Signatures => write!(f, "signatures only"),
+            Dnssec::Unsupported => write!(f, "no"),
+        }
+    }
+}
+
+/// How a resolver did, its support of each feature unknown when it is down
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResolverCheck {
+    pub server: String,
+    /// Well-known queries answered with NOERROR or NXDOMAIN
+    pub answered: usize,
+    pub sent: usize,
+    /// Median round trip of the well-known queries answered
+    pub latency: Option<Duration>,
+    pub edns: Option<bool>,
+    pub tcp: Option<bool>,
+    pub dnssec: Option<Dnssec>,
+    /// Whether a name that doesn't exist got an NXDOMAIN, rather than
+    /// addresses of a search or ad page
+    pub nxdomain: Option<bool>,
+}
+
+impl ResolverCheck {
+    /// Whether it answered every well-known query
+    pub fn is_available(&self) -> bool {
+        self.answered == self.sent
+    }
+}
+
+fn yes_no(value: Option<bool>) -> String {
+    match value {
+        Some(true) => "yes".to_string(),
+        Some(false) => "no".to_string(),
+        None => "-".to_string(),
+    }
+}
+
+/// Sends resolvers the well-known queries and the feature probes, each
+/// waiting `wait` for a response
+#[derive(Debug, Clone)]
+pub struct ResolverChecker {
+    wait: Duration,
+}
+
+impl ResolverChecker {
+    pub fn new(wait: Duration) -> ResolverChecker {
+        ResolverChecker { wait }
+    }
+
+    async fn client(&self, server: &str) -> Result<Client, ClientError> {
+        Ok(Client::new(server.to_string())
+            .await?
+            .without_idn()
+            .with_timeout(self.wait)
+            .with_attempts(1))
+    }
+
+    /// Checks `server`, an address with its port, skipping the feature
+    /// probes when it answered none of the well-known queries
+    pub async fn check(&self, server: &str) -> ResolverCheck {
+        let mut check = ResolverCheck {
+            server: server.to_string(),
+            answered: 0,
+            sent: WELL_KNOWN.len(),
+            latency: None,
+            edns: None,
+            tcp: None,
+            dnssec: None,
+            nxdomain: None,
+        };
+        let Ok(client) = self.client(server).await else {
+            return check;
+        };
+        let mut latencies = Vec::new();
+        for (name, query_type) in WELL_KNOWN {
+            match client.exchange(name, query_type.clone()).await {
+                Ok(response) if [NOERROR, NXDOMAIN].contains(&response.message.header.rcode()) => {
+                    latencies.push(response.query_time());
+                }
+                Ok(response) => log::info!(
+                    "{} answered {} {} with rcode {}",
+                    server,
+                    name,
+                    query_type,
+                    response.message.header.rcode()
+                ),
+                Err(err) => log::info!("{} {} at {}: {}", name, query_type, server, err),
+            }
+        }
+        check.answered = latencies.len();
+        if latencies.is_empty() {
+            return check;
+        }
+        latencies.sort();
+        check.latency = Some(latencies[(latencies.len() - 1) / 2]);
+        let (edns, dnssec) = self.edns(server).await;
+        check.edns = Some(edns);
+        check.dnssec = Some(dnssec);
+        check.tcp = Some(self.tcp(server).await);
+        check.nxdomain = self.nxdomain(&client).await;
+        check
+    }
+
+    /// Whether `server` answers EDNS queries with an OPT record, and what it
+    /// does with the DO and AD flags of a query of the signed root
+    async fn edns(&self, server: &str) -> (bool, Dnssec) {
+        let response = match self.client(server).await {
+            Ok(client) => {
+                client
+                    .with_dnssec_ok()
+                    .with_authentic_data()
+                    .exchange(".", QueryType::SOA)
+                    .await
+            }
+            Err(err) => Err(err),
+        };
+        let Ok(response) = response else {
+            return (false, Dnssec::Unsupported);
+        };
+        let message = &response.message;
+        let edns = message
+            .additionals
+            .iter()
+            .any(|record| record.query_type == QueryType::OPT);
+        let signed = message
+            .answers
+            .iter()
+            .any(|record| record.query_type == QueryType::RRSIG);
+        let dnssec = match (signed, message.header.authenticated_data()) {
+            (_, true) => Dnssec::Validating,
+            (true, false) => Dnssec::Signatures,
+            (false, false) => Dnssec::Unsupported,
+        };
+        (edns, dnssec)
+    }
+
+    /// Whether `server` answers a query of the root over TCP
+    async fn tcp(&self, server: &str) -> bool {
+        let exchange = async {
+            let transport = TcpTransport::connect(server.to_string()).await?;
+            let id = rand::random();
+            let query = Message::query(id, ".", &QueryType::SOA, ClassType::IN.code());
+            transport.send(&query.encode()).await?;
+            let response = Message::decode(&transport.recv().await?)?;
+            Ok::<bool, ClientError>(response.header.id == id && response.header.is_response())
+        };
+        match timeout(self.wait, exchange).await {
+            Ok(Ok(answered)) => answered,
+            Ok(Err(err)) => {
+                log::info!("TCP query of {}: {}", server, err);
+                false
+            }
+            Err(_) => false,
+        }
+    }
+
+    /// Whether a random name under a zone without wildcards got NXDOMAIN,
+    /// unknown when the query failed
+    async fn nxdomain(&self, client: &Client) -> Option<bool> {
+        let label: String = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(16)
+            .map(|c| (c as char).to_ascii_lowercase())
+            .collect();
+        let name = std::format!("nsq-check-{}.example.com", label);
+        match client.exchange(&name, QueryType::A).await {
+            Ok(response) => {
+                let rcode = response.message.header.rcode();
+                Some(rcode == NXDOMAIN || (rcode == NOERROR && response.message.answers.is_empty()))
+            }
+            Err(_) => None,
+        }
+    }
+}
+
+/// A row per resolver: how many well-known queries it answered, its median
+/// latency and what it supports
+pub fn checks_table(checks: &[ResolverCheck]) -> String {
+    let mut table = Table::new(
+        &[
+            "RESOLVER", "ANSWERED", "LATENCY", "EDNS", "TCP", "DNSSEC", "NXDOMAIN",
+        ],
+        16,
+        false,
+    );
+    for check in checks {
+        table.push(vec![
+            check.server.clone(),
+            std::format!("{}/{}", check.answered, check.sent),
+            check.latency.map_or("-".to_string(), |latency| {
+                std::format!("{:.1} ms", crate::client::millis(latency))
+            }),
+            yes_no(check.edns),
+            yes_no(check.tcp),
+            check
+                .dnssec
+                .map_or("-".to_string(), |dnssec| dnssec.to_string()),
+            match check.nxdomain {
+                Some(true) => "ok".to_string(),
+                Some(false) => "rewritten".to_string(),
+                None => "-".to_string(),
+            },
+        ]);
+    }
+    table.render()
+}
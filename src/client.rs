@@ -1,31 +1,135 @@
-use bincode::Options;
-use log;
+use crate::budget::{QueryBudget, RetryBudget};
+#[cfg(feature = "cache")]
+use crate::cache::{Cache, QueryKind};
+#[cfg(feature = "diagnostics")]
+use crate::capture::{Capture, Direction};
+#[cfg(feature = "dnssec")]
+use crate::dnssec::{self, DnssecStatus, Ds};
+#[cfg(feature = "encrypted-transports")]
+use crate::doh::DohMethod;
+use crate::ecs::{self, ClientSubnet};
+use crate::idn;
+use crate::nsconfig::Hosts;
+use crate::rdata::{self, RData, ResourceRecord};
+use crate::transport::{TcpTransport, Transport};
+#[cfg(feature = "zones")]
+use crate::tsig::{TsigKey, TsigVerifier};
+use crate::wire::{self, Header, Message, Record};
 use rand::prelude::*;
-use serde::{Deserialize, Serialize};
-use std::error::Error;
-use std::net::Ipv4Addr;
-use std::net::Ipv6Addr;
-use std::net::SocketAddr;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::net::IpAddr;
+use std::ops::Range;
 use std::str;
-use std::time::Duration;
-use tokio::net::UdpSocket;
-use tokio::time::timeout;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::time::{sleep, timeout};
 
 pub struct Client {
-    socket: UdpSocket,
-    max_datagram_size: usize,
+    /// The primary server first, then the ones it fails over to
+    transports: Vec<Transport>,
+    #[cfg(feature = "dnssec")]
+    trust_anchors: Option<Vec<Ds>>,
+    budget: QueryBudget,
+    retry_budget: RetryBudget,
+    timeout: Duration,
+    /// Timeouts of servers slower or faster than the rest, by address or by
+    /// transport name
+    timeouts: HashMap<String, Duration>,
+    retries: usize,
+    class: ClassType,
+    rotation: Option<Rotation>,
+    randomness: Randomness,
+    /// Internationalized names are sent in punycode and shown in Unicode
+    idn: bool,
+    /// Letters of query names are sent in random case, which responses must
+    /// echo, draft-vixie-dnsext-dns0x20
+    randomize_case: bool,
+    /// Client cookie sent with every query when DNS cookies are enabled
+    cookie: Option<[u8; 8]>,
+    /// Recursion Desired flag of queries
+    recursion: bool,
+    /// Checking Disabled flag of queries
+    checking_disabled: bool,
+    /// Authentic Data flag of queries
+    authentic_data: bool,
+    /// DNSSEC OK flag of queries, asking for the DNSSEC records of answers
+    dnssec_ok: bool,
+    /// Queries carry an EDNS OPT record even without options, RFC 6891
+    edns: bool,
+    #[cfg(feature = "cache")]
+    cache: Option<Cache>,
+    /// Names answered from a hosts file before any query goes out
+    hosts: Option<Hosts>,
+    /// Key queries are signed with, RFC 8945
+    #[cfg(feature = "zones")]
+    tsig: Option<TsigKey>,
+    /// CNAME and DNAME records a query may go through
+    cname_depth: usize,
+    /// Network queries are sent on behalf of, RFC 7871
+    subnet: Option<ClientSubnet>,
+    /// Records of the authority and additional sections are answers too
+    all_sections: bool,
+    /// Positions of the answers decoded, the others only counted
+    answer_page: Option<Range<usize>>,
+    /// Block size queries over encrypted transports are padded to, RFC 7830
+    padding: Option<usize>,
+    /// Where the messages exchanged are copied
+    #[cfg(feature = "diagnostics")]
+    capture: Option<Capture>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct DNSMessage {
-    id: [u8; 2],
-    flags: [u8; 2],
-    questions: [u8; 2],
-    answers_rrs: [u8; 2],
-    authority_rrs: [u8; 2],
-    additional_rrs: [u8; 2],
-    queries: Vec<u8>,
-    answers: Vec<u8>,
+/// Round-robin over the servers of a client, like the `rotate` option of
+/// resolv.conf. Clones share the same position, so every client of a run can
+/// spread its queries over the servers.
+#[derive(Debug, Clone, Default)]
+pub struct Rotation {
+    next: Arc<AtomicUsize>,
+}
+
+impl Rotation {
+    /// Index of the server to send a query to first, out of `servers`
+    fn next(&self, servers: usize) -> usize {
+        self.next.fetch_add(1, Ordering::SeqCst) % servers.max(1)
+    }
+}
+
+/// Source of the random choices of clients: transaction IDs, 0x20 casing,
+/// cookies, the server rotation starts at, retry jitter and the order of SRV
+/// and MX targets. With a seed, each query draws from a generator seeded by
+/// it and the query, so a run makes the same choices however its queries
+/// interleave.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Randomness {
+    seed: Option<u64>,
+}
+
+impl Randomness {
+    pub fn seeded(seed: u64) -> Randomness {
+        Randomness { seed: Some(seed) }
+    }
+
+    pub fn is_seeded(&self) -> bool {
+        self.seed.is_some()
+    }
+
+    /// Generator of the choices made for `key`, the same ones for the same
+    /// seed and key
+    pub fn rng(&self, key: &str) -> StdRng {
+        let Some(seed) = self.seed else {
+            return StdRng::from_entropy();
+        };
+        // FNV-1a, stable across builds unlike the hashers of std
+        let mut hash: u64 = 0xcbf29ce484222325;
+        for byte in seed.to_be_bytes().iter().chain(key.as_bytes()) {
+            hash ^= *byte as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+        StdRng::seed_from_u64(hash)
+    }
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -48,6 +152,34 @@ pub enum ClientError {
     DecodeIdError(String),
     #[error("GenericError")]
     GenericError(String),
+    #[error("No response after {0} attempts")]
+    Timeout(usize),
+    /// An ICMP port unreachable came back, the query reaching a host with
+    /// nothing listening on the port
+    #[error("{0} isn't listening, it answered with ICMP port unreachable")]
+    NotListening(String),
+    #[error("Query budget of {0} upstream queries exhausted")]
+    BudgetExhausted(usize),
+    #[error("{0} doesn't accept mail, it has a null MX record")]
+    NullMx(String),
+    #[error("{0} rejected the DNS cookie (BADCOOKIE) even with its server cookie")]
+    BadCookie(String),
+    #[error("TSIG: {0}")]
+    Tsig(String),
+    #[error("CNAME loop: {0}")]
+    CnameLoop(String),
+    #[error("CNAME chain {0} is longer than {1} names")]
+    CnameDepth(String, usize),
+    #[error("EDNS Client Subnet: {0}")]
+    ClientSubnet(String),
+    #[error("Response is for {1}, sent {0}: the case of the name differs")]
+    CaseMismatch(String, String),
+    #[error("Every attempt failed: {}", FailedAttempt::list(.0))]
+    AllFailed(Vec<FailedAttempt>),
+    /// Queries of a run that failed out of all of them, with the error of the
+    /// first
+    #[error("{0} of {1} queries failed, the first with: {2}")]
+    QueriesFailed(usize, usize, Box<ClientError>),
 
     #[error("DNS message RDCode format error")]
     RDCodeFormatError,
@@ -57,282 +189,2118 @@ pub enum ClientError {
     RDCodeNameError,
     #[error("DNS message RDCode not implemented server error")]
     RDCodeNotImplemented,
-    #[error("DNS message RDCode server refused error")]
-    RDCodeRefused,
+    #[error("DNS message RDCode server refused error: {0}")]
+    RDCodeRefused(String),
+    #[error("DNS message RDCode {}", rcode_name(*.0))]
+    RDCode(u8),
+}
+
+/// An exchange with a server that got no response, one of those of a query
+/// every server failed
+#[derive(Debug, Clone, PartialEq)]
+pub struct FailedAttempt {
+    pub server: String,
+    pub transport: String,
+    pub error: String,
+    pub elapsed: Duration,
+}
+
+impl FailedAttempt {
+    fn list(attempts: &[FailedAttempt]) -> String {
+        let attempts: Vec<String> = attempts.iter().map(|a| a.to_string()).collect();
+        attempts.join("; ")
+    }
 }
 
-#[derive(Debug, PartialEq)]
+impl fmt::Display for FailedAttempt {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} over {}: {} after {} ms",
+            self.server,
+            self.transport,
+            self.error,
+            millis(self.elapsed)
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum QueryType {
     A,
     AAAA,
+    NS,
     SOA,
     CNAME,
+    PTR,
+    HINFO,
+    MX,
+    TXT,
+    SRV,
+    NAPTR,
+    CERT,
+    DNAME,
+    OPT,
+    APL,
+    DS,
+    RRSIG,
+    DNSKEY,
+    SMIMEA,
+    OPENPGPKEY,
+    CSYNC,
+    SVCB,
+    HTTPS,
+    EUI48,
+    EUI64,
+    URI,
+    /// Transaction signature of a message, RFC 8945
+    TSIG,
+    /// Changes of a zone since a serial, transferred over TCP, RFC 1995
+    IXFR,
+    /// Every record of a zone, transferred over TCP, RFC 5936
+    AXFR,
+    /// Every record of a name, which servers may answer minimally, RFC 8482
+    ANY,
+    /// A type without decoding support, sent and shown by its number
+    Unknown(u16),
+}
+
+impl QueryType {
+    pub fn code(&self) -> u16 {
+        match self {
+            QueryType::A => 1,
+            QueryType::NS => 2,
+            QueryType::CNAME => 5,
+            QueryType::SOA => 6,
+            QueryType::PTR => 12,
+            QueryType::HINFO => 13,
+            QueryType::MX => 15,
+            QueryType::TXT => 16,
+            QueryType::SRV => 33,
+            QueryType::NAPTR => 35,
+            QueryType::CERT => 37,
+            QueryType::DNAME => 39,
+            QueryType::OPT => 41,
+            QueryType::APL => 42,
+            QueryType::AAAA => 0x1c,
+            QueryType::DS => 43,
+            QueryType::RRSIG => 46,
+            QueryType::DNSKEY => 48,
+            QueryType::SMIMEA => 53,
+            QueryType::OPENPGPKEY => 61,
+            QueryType::CSYNC => 62,
+            QueryType::SVCB => 64,
+            QueryType::HTTPS => 65,
+            QueryType::EUI48 => 108,
+            QueryType::EUI64 => 109,
+            QueryType::URI => 256,
+            QueryType::TSIG => 250,
+            QueryType::IXFR => 251,
+            QueryType::AXFR => 252,
+            QueryType::ANY => 255,
+            QueryType::Unknown(code) => *code,
+        }
+    }
+
+    pub fn from_code(code: u16) -> QueryType {
+        match code {
+            1 => QueryType::A,
+            2 => QueryType::NS,
+            5 => QueryType::CNAME,
+            6 => QueryType::SOA,
+            12 => QueryType::PTR,
+            13 => QueryType::HINFO,
+            15 => QueryType::MX,
+            16 => QueryType::TXT,
+            33 => QueryType::SRV,
+            35 => QueryType::NAPTR,
+            37 => QueryType::CERT,
+            39 => QueryType::DNAME,
+            41 => QueryType::OPT,
+            42 => QueryType::APL,
+            0x1c => QueryType::AAAA,
+            43 => QueryType::DS,
+            46 => QueryType::RRSIG,
+            48 => QueryType::DNSKEY,
+            53 => QueryType::SMIMEA,
+            61 => QueryType::OPENPGPKEY,
+            62 => QueryType::CSYNC,
+            64 => QueryType::SVCB,
+            65 => QueryType::HTTPS,
+            108 => QueryType::EUI48,
+            109 => QueryType::EUI64,
+            250 => QueryType::TSIG,
+            251 => QueryType::IXFR,
+            252 => QueryType::AXFR,
+            255 => QueryType::ANY,
+            256 => QueryType::URI,
+            _ => QueryType::Unknown(code),
+        }
+    }
+}
+
+impl FromStr for QueryType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_uppercase().as_str() {
+            "A" => Ok(QueryType::A),
+            "AAAA" => Ok(QueryType::AAAA),
+            "NS" => Ok(QueryType::NS),
+            "SOA" => Ok(QueryType::SOA),
+            "CNAME" => Ok(QueryType::CNAME),
+            "PTR" => Ok(QueryType::PTR),
+            "HINFO" => Ok(QueryType::HINFO),
+            "MX" => Ok(QueryType::MX),
+            "TXT" => Ok(QueryType::TXT),
+            "SRV" => Ok(QueryType::SRV),
+            "NAPTR" => Ok(QueryType::NAPTR),
+            "CERT" => Ok(QueryType::CERT),
+            "DNAME" => Ok(QueryType::DNAME),
+            "APL" => Ok(QueryType::APL),
+            "DS" => Ok(QueryType::DS),
+            "RRSIG" => Ok(QueryType::RRSIG),
+            "DNSKEY" => Ok(QueryType::DNSKEY),
+            "SMIMEA" => Ok(QueryType::SMIMEA),
+            "OPENPGPKEY" => Ok(QueryType::OPENPGPKEY),
+            "CSYNC" => Ok(QueryType::CSYNC),
+            "SVCB" => Ok(QueryType::SVCB),
+            "HTTPS" => Ok(QueryType::HTTPS),
+            "EUI48" => Ok(QueryType::EUI48),
+            "EUI64" => Ok(QueryType::EUI64),
+            "URI" => Ok(QueryType::URI),
+            "TSIG" => Ok(QueryType::TSIG),
+            "IXFR" => Ok(QueryType::IXFR),
+            "AXFR" => Ok(QueryType::AXFR),
+            "ANY" => Ok(QueryType::ANY),
+            // RFC 3597 3 generic names, or a bare number
+            other => match other.strip_prefix("TYPE").unwrap_or(other).parse::<u16>() {
+                Ok(code) => Ok(QueryType::from_code(code)),
+                Err(_) => Err(std::format!("unsupported query type {}", s)),
+            },
+        }
+    }
+}
+
+impl fmt::Display for QueryType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            QueryType::Unknown(code) => write!(f, "TYPE{}", code),
+            query_type => write!(f, "{:?}", query_type),
+        }
+    }
 }
 
-#[derive(Debug)]
-enum ClassType {
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ClassType {
     IN,
+    /// Chaos, used by servers to report their identity, e.g. `version.bind`
+    CH,
+    HS,
+    /// Only meaningful in dynamic updates, RFC 2136
+    NONE,
+    ANY,
+}
+
+impl ClassType {
+    pub fn code(&self) -> u16 {
+        match self {
+            ClassType::IN => 1,
+            ClassType::CH => 3,
+            ClassType::HS => 4,
+            ClassType::NONE => 254,
+            ClassType::ANY => 255,
+        }
+    }
+
+    pub(crate) fn from_code(code: u16) -> Result<ClassType, ClientError> {
+        match code {
+            1 => Ok(ClassType::IN),
+            3 => Ok(ClassType::CH),
+            4 => Ok(ClassType::HS),
+            254 => Ok(ClassType::NONE),
+            255 => Ok(ClassType::ANY),
+            _ => Err(ClientError::DecodeError(std::format!(
+                "Failed to decode class type {:x?}",
+                code
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for ClassType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
 }
 
-#[allow(dead_code)]
-#[derive(Debug)]
+impl FromStr for ClassType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_uppercase().as_str() {
+            "IN" => Ok(ClassType::IN),
+            "CH" => Ok(ClassType::CH),
+            "HS" => Ok(ClassType::HS),
+            "NONE" => Ok(ClassType::NONE),
+            "ANY" => Ok(ClassType::ANY),
+            _ => Err(std::format!("unsupported class {}", s)),
+        }
+    }
+}
+
+/// Section of a response, RFC 1035 4.1
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Section {
+    Answer,
+    Authority,
+    Additional,
+}
+
+impl fmt::Display for Section {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Section::Answer => "answer",
+            Section::Authority => "authority",
+            Section::Additional => "additional",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct QueryAnswer {
     host: String,
-    address: String,
+    data: RData,
     query_type: QueryType,
     class_type: ClassType,
+    ttl: u32,
+    section: Section,
+    server: String,
+    rcode: u8,
+    flags: Vec<&'static str>,
+    query_time: Duration,
+    decisions: Vec<Decision>,
+    provenance: Provenance,
+    subnet: Option<ClientSubnet>,
+    #[cfg(feature = "dnssec")]
+    dnssec: Option<DnssecStatus>,
+    /// SOA of a negative response, standing for the records that don't exist
+    negative: bool,
 }
 
-impl DNSMessage {
-    /// DNS UDP header size: id + flags + questions + answers_rrs + authority_rrs +
-    /// additional_rss
-    fn header_size() -> usize {
-        12
+impl QueryAnswer {
+    /// `record` answering `host` as a cache serves it, `age` seconds after
+    /// `server` answered it
+    #[cfg(feature = "diagnostics")]
+    pub(crate) fn cached(
+        host: String,
+        record: ResourceRecord,
+        server: String,
+        age: u64,
+    ) -> QueryAnswer {
+        QueryAnswer {
+            host,
+            data: record.data,
+            query_type: record.query_type,
+            class_type: record.class,
+            ttl: record.ttl,
+            section: Section::Answer,
+            server: server.clone(),
+            rcode: 0,
+            flags: Vec::new(),
+            query_time: Duration::ZERO,
+            decisions: Vec::new(),
+            provenance: Provenance::Cache { server, age },
+            subnet: None,
+            #[cfg(feature = "dnssec")]
+            dnssec: None,
+            negative: false,
+        }
+    }
+
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+
+    pub fn data(&self) -> &RData {
+        &self.data
+    }
+
+    pub fn query_type(&self) -> &QueryType {
+        &self.query_type
+    }
+
+    pub fn class_type(&self) -> &ClassType {
+        &self.class_type
+    }
+
+    pub fn ttl(&self) -> u32 {
+        self.ttl
+    }
+
+    /// Section of the response the record was in
+    pub fn section(&self) -> Section {
+        self.section
+    }
+
+    /// Address or URL of the server that answered
+    pub fn server(&self) -> &str {
+        &self.server
+    }
+
+    /// Response code of the response the answer came in, RFC 1035 4.1.1
+    pub fn rcode(&self) -> u8 {
+        self.rcode
+    }
+
+    /// Flags of the response the answer came in among aa, tc, ra and ad
+    pub fn flags(&self) -> &[&'static str] {
+        &self.flags
     }
 
-    fn new(queries: Vec<u8>) -> DNSMessage {
-        let id: u16 = random();
-        DNSMessage {
-            id: [(id >> 8) as u8, (id & 0xff) as u8],
-            flags: [1, 0],
-            questions: [0, 1],
-            answers_rrs: [0, 0],
-            authority_rrs: [0, 0],
-            additional_rrs: [0, 0],
-            queries: queries,
-            answers: Vec::new(),
+    /// Time from sending the query to receiving its response, retries included
+    pub fn query_time(&self) -> Duration {
+        self.query_time
+    }
+
+    /// The answer as a record of a zone file, under its fully qualified name
+    pub fn record(&self) -> ResourceRecord {
+        ResourceRecord {
+            name: match self.host.ends_with('.') {
+                true => self.host.clone(),
+                false => std::format!("{}.", self.host),
+            },
+            ttl: self.ttl,
+            class: self.class_type,
+            query_type: self.query_type.clone(),
+            data: self.data.clone(),
         }
     }
 
-    fn encode(&self) -> Result<Vec<u8>, Box<dyn Error>> {
-        let bincode_opts = bincode::DefaultOptions::new()
-            .with_big_endian()
-            .with_no_limit()
-            .with_varint_encoding();
-        let mut encoded = bincode_opts.serialize(&self)?;
-        // remove Vec's len from encoded bytes
-        encoded.remove(DNSMessage::header_size());
-        Ok(encoded)
+    /// Steps taken by the query exchange the answer came in
+    pub fn decisions(&self) -> &[Decision] {
+        &self.decisions
+    }
+
+    /// Time the query spent opening the connection it went over, for
+    /// encrypted transports that had to open one
+    pub fn connection_setup(&self) -> Option<Duration> {
+        connection_setup(&self.decisions)
+    }
+
+    /// Where the answer came from
+    pub fn provenance(&self) -> &Provenance {
+        &self.provenance
     }
 
-    fn encode_query_type(query_type: &QueryType) -> Vec<u8> {
-        match query_type {
-            QueryType::A => vec![0, 1],
-            QueryType::AAAA => vec![0, 0x1c],
-            _ => vec![],
+    /// Client subnet the server echoed, with the scope the answer holds for,
+    /// RFC 7871 7.2.1
+    pub fn subnet(&self) -> Option<&ClientSubnet> {
+        self.subnet.as_ref()
+    }
+
+    /// Whether the answer is an expired one served as upstream failed
+    pub fn is_stale(&self) -> bool {
+        matches!(self.provenance, Provenance::Stale { .. })
+    }
+
+    /// NXDOMAIN or NODATA when the answer is the SOA of a negative response,
+    /// RFC 2308
+    pub fn negative(&self) -> Option<&'static str> {
+        match (self.negative, self.rcode) {
+            (false, _) => None,
+            (true, 3) => Some("NXDOMAIN"),
+            (true, _) => Some("NODATA"),
         }
     }
 
-    fn decode_query_type(values: &[u8]) -> Result<QueryType, ClientError> {
-        match values {
-            [0, 1] => Ok(QueryType::A),
-            [0, 0x1c] => Ok(QueryType::AAAA),
-            [0, 5] => Ok(QueryType::CNAME),
-            [0, 6] => Ok(QueryType::SOA),
-            _ => Err(ClientError::DecodeError(std::format!(
-                "Failed to decode query type {:x?}",
-                &values
-            ))),
+    /// DNSSEC status of the answer, only set when the query asked for DNSSEC
+    #[cfg(feature = "dnssec")]
+    pub fn dnssec(&self) -> Option<&DnssecStatus> {
+        self.dnssec.as_ref()
+    }
+}
+
+/// Lowercased and fully qualified, to compare names
+fn normalize_name(name: &str) -> String {
+    std::format!("{}.", name.trim_end_matches('.').to_lowercase())
+}
+
+/// Name `current` maps to through a CNAME or DNAME record of `records`, with
+/// the record, RFC 1034 3.6.2 and RFC 6672 2.2
+fn next_name<'a>(current: &str, records: &'a [Record]) -> Option<(String, &'a Record)> {
+    records.iter().find_map(|record| {
+        let owner = normalize_name(&record.name);
+        let next = match (&record.query_type, &record.rdata) {
+            (QueryType::CNAME, rdata) if owner == current => {
+                wire::decode_name(rdata, 0).ok().map(|(target, _)| target)
+            }
+            (QueryType::DNAME, rdata) if current.ends_with(&std::format!(".{}", owner)) => {
+                let (target, _) = wire::decode_name(rdata, 0).ok()?;
+                let prefix = &current[..current.len() - owner.len()];
+                Some(std::format!("{}{}", prefix, target.trim_start_matches('.')))
+            }
+            _ => None,
+        };
+        next.map(|name| (normalize_name(&name), record))
+    })
+}
+
+/// Names `host` maps to through the CNAME and DNAME records of the answer,
+/// `host` first
+fn answer_chain(host: &str, records: &[Record]) -> Vec<String> {
+    let mut chain = vec![normalize_name(host)];
+    for _ in 0..records.len() {
+        match next_name(&chain[chain.len() - 1], records) {
+            Some((name, _)) if !chain.contains(&name) => chain.push(name),
+            _ => break,
         }
     }
+    chain
+}
+
+/// The CNAME or DNAME record leading away from `name`, in presentation format
+fn link(name: &str, records: &[Record]) -> Option<String> {
+    let (_, record) = next_name(name, records)?;
+    let (target, _) = wire::decode_name(&record.rdata, 0).ok()?;
+    Some(std::format!(
+        "{} {} {}",
+        normalize_name(&record.name),
+        record.query_type,
+        target
+    ))
+}
 
-    fn encode_class_type() -> Vec<u8> {
-        vec![0, 1] // IN
+/// What a query came to, names that don't exist and names without records of
+/// the type included, RFC 2308
+#[derive(Debug, Clone)]
+pub struct QueryResponse {
+    pub rcode: u8,
+    pub answers: Vec<QueryAnswer>,
+    /// Records answering the query, those left off the page of a paged client
+    /// included
+    pub total: usize,
+    /// SOA of the zone of a negative response, named after the name queried
+    pub soa: Option<QueryAnswer>,
+}
+
+impl QueryResponse {
+    pub fn is_nxdomain(&self) -> bool {
+        self.rcode == 3
     }
 
-    fn decode_class_type(values: &[u8]) -> Result<ClassType, ClientError> {
-        match values {
-            [0, 1] => Ok(ClassType::IN),
-            _ => Err(ClientError::DecodeError(std::format!(
-                "Failed to decode class type {:x?}",
-                &values
-            ))),
+    /// Whether the name exists but has no records of the type
+    pub fn is_nodata(&self) -> bool {
+        self.rcode == 0 && self.total == 0
+    }
+
+    /// The answers, then the SOA of a negative response
+    pub fn records(&self) -> Vec<QueryAnswer> {
+        self.answers.iter().chain(&self.soa).cloned().collect()
+    }
+}
+
+/// `record` of `section` as an answer of `host`, none for classes and data
+/// that don't decode
+fn decode_answer(host: String, record: &Record, section: Section) -> Option<QueryAnswer> {
+    let class_type = ClassType::from_code(record.class).ok()?;
+    let Some(data) = rdata::decode(&record.query_type, &record.rdata) else {
+        log::warn!(
+            "Skipping malformed {:?} record of {}",
+            record.query_type,
+            record.name
+        );
+        return None;
+    };
+    Some(QueryAnswer {
+        host,
+        data,
+        query_type: record.query_type.clone(),
+        class_type,
+        ttl: record.ttl,
+        section,
+        server: String::new(),
+        rcode: 0,
+        flags: Vec::new(),
+        query_time: Duration::ZERO,
+        decisions: Vec::new(),
+        provenance: Provenance::Network {
+            server: String::new(),
+        },
+        subnet: None,
+        #[cfg(feature = "dnssec")]
+        dnssec: None,
+        negative: false,
+    })
+}
+
+/// Records of the authority or additional section as answers named after
+/// their owners, without the OPT and TSIG pseudo-records
+fn decode_section(records: &[Record], section: Section) -> Vec<QueryAnswer> {
+    records
+        .iter()
+        .filter(|record| !matches!(record.query_type, QueryType::OPT | QueryType::TSIG))
+        .filter_map(|record| decode_answer(record.name.clone(), record, section))
+        .collect()
+}
+
+/// Records answering `host`, each with the name it is shown under, its owner
+/// unless it's `host` itself. Records outside of the CNAME and DNAME chain of
+/// `host` are left out.
+fn query_answer_records<'a>(host: &str, records: &'a [Record]) -> Vec<(String, &'a Record)> {
+    let chain = answer_chain(host, records);
+    if chain.len() > 1 {
+        log::info!("{} resolves through {}", host, chain[1..].join(" -> "));
+    }
+    let in_chain = |owner: &str| {
+        chain
+            .iter()
+            .any(|name| name == owner || name.ends_with(&std::format!(".{}", owner)))
+    };
+    let mut answers = Vec::new();
+    for record in records {
+        let owner = normalize_name(&record.name);
+        // DNAME owners are ancestors of names in the chain, not part of it
+        let related = match record.query_type {
+            QueryType::DNAME | QueryType::RRSIG => in_chain(&owner),
+            _ => chain.contains(&owner),
+        };
+        if !related {
+            log::debug!(
+                "Skipping {:?} record of {} outside of the answer chain",
+                record.query_type,
+                record.name
+            );
+            continue;
+        }
+        let host = match owner == chain[0] {
+            true => host.to_string(),
+            false => record.name.clone(),
+        };
+        answers.push((host, record));
+    }
+    answers
+}
+
+/// Mnemonic of a response code, RFC 1035 4.1.1 and RFC 2136 2.2
+pub fn rcode_name(rcode: u8) -> String {
+    match rcode {
+        0 => "NOERROR".to_string(),
+        1 => "FORMERR".to_string(),
+        2 => "SERVFAIL".to_string(),
+        3 => "NXDOMAIN".to_string(),
+        4 => "NOTIMP".to_string(),
+        5 => "REFUSED".to_string(),
+        6 => "YXDOMAIN".to_string(),
+        7 => "YXRRSET".to_string(),
+        8 => "NXRRSET".to_string(),
+        9 => "NOTAUTH".to_string(),
+        10 => "NOTZONE".to_string(),
+        _ => std::format!("RCODE{}", rcode),
+    }
+}
+
+/// A decoded response, how long it took to arrive and the steps taken to get it
+#[derive(Debug, Clone)]
+pub struct Response {
+    pub(crate) message: Message,
+    server: String,
+    size: usize,
+    query_time: Duration,
+    decisions: Vec<Decision>,
+    provenance: Provenance,
+}
+
+impl Response {
+    #[cfg(any(feature = "zones", test))]
+    pub(crate) fn new(
+        message: Message,
+        server: String,
+        size: usize,
+        query_time: Duration,
+    ) -> Response {
+        Response {
+            message,
+            provenance: Provenance::Network {
+                server: server.clone(),
+            },
+            server,
+            size,
+            query_time,
+            decisions: Vec::new(),
         }
     }
 
-    fn encode_host(host: &String, query_type: &QueryType) -> Vec<u8> {
-        let mut encoded: Vec<u8> = Vec::new();
-        for word in host.split(".") {
-            encoded.push(word.len() as u8);
-            for bytes in word.as_bytes() {
-                encoded.push(*bytes);
+    /// Address or URL of the server that answered
+    pub fn server(&self) -> &str {
+        &self.server
+    }
+
+    /// The answer section as `Client::query` returns it, without IDN or DNSSEC
+    /// processing
+    pub fn answers(&self) -> Vec<QueryAnswer> {
+        let host = match self.message.questions.first() {
+            Some(question) => question.name.trim_end_matches('.').to_string(),
+            None => return Vec::new(),
+        };
+        let records = query_answer_records(&host, &self.message.answers);
+        self.described(
+            records
+                .into_iter()
+                .filter_map(|(host, record)| decode_answer(host, record, Section::Answer))
+                .collect(),
+        )
+    }
+
+    /// Records of the authority section, e.g. the SOA of negative responses
+    /// and the NS records of referrals
+    pub fn authorities(&self) -> Vec<QueryAnswer> {
+        self.described(decode_section(
+            &self.message.authorities,
+            Section::Authority,
+        ))
+    }
+
+    /// Records of the additional section, e.g. glue, without the OPT record
+    pub fn additionals(&self) -> Vec<QueryAnswer> {
+        self.described(decode_section(
+            &self.message.additionals,
+            Section::Additional,
+        ))
+    }
+
+    /// `answers` with the server, timing and flags of the response
+    fn described(&self, mut answers: Vec<QueryAnswer>) -> Vec<QueryAnswer> {
+        for answer in answers.iter_mut() {
+            answer.server = self.server.clone();
+            answer.rcode = self.message.header.rcode();
+            answer.flags = self.message.header.response_flag_names();
+            answer.query_time = self.query_time;
+            answer.decisions = self.decisions.clone();
+            answer.provenance = self.provenance.clone();
+            answer.subnet = self.subnet();
+        }
+        answers
+    }
+
+    /// Size of the response message in bytes
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn query_time(&self) -> Duration {
+        self.query_time
+    }
+
+    pub fn decisions(&self) -> &[Decision] {
+        &self.decisions
+    }
+
+    /// Time spent opening the connection the query went over, for encrypted
+    /// transports that had to open one
+    pub fn connection_setup(&self) -> Option<Duration> {
+        connection_setup(&self.decisions)
+    }
+
+    /// Where the response came from
+    pub fn provenance(&self) -> &Provenance {
+        &self.provenance
+    }
+
+    /// Client subnet the server echoed, with the scope the answer holds for,
+    /// RFC 7871 7.2.1
+    pub fn subnet(&self) -> Option<ClientSubnet> {
+        ClientSubnet::of(&self.message)
+    }
+
+    /// Whether the response is an expired one served as upstream failed
+    pub fn is_stale(&self) -> bool {
+        matches!(self.provenance, Provenance::Stale { .. })
+    }
+
+    pub fn rcode(&self) -> u8 {
+        self.message.header.rcode()
+    }
+
+    /// Number of records in the answer section
+    pub fn answer_count(&self) -> usize {
+        self.message.answers.len()
+    }
+}
+
+/// CNAME and DNAME records a query may go through unless told otherwise
+pub const MAX_CNAME_DEPTH: usize = 16;
+
+/// Wait before sending a query again once every server failed, doubled on
+/// each retry
+const BACKOFF: Duration = Duration::from_millis(100);
+
+/// Milliseconds with microsecond precision, for reports
+pub fn millis(duration: Duration) -> f64 {
+    duration.as_micros() as f64 / 1000.0
+}
+
+/// A step of a query exchange, so slow answers can be explained
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Decision {
+    /// Query sent over `transport` to `server`, with an OPT record when `edns`
+    Sent {
+        transport: String,
+        server: String,
+        edns: bool,
+    },
+    /// Response received from the server, `wire_ms` after the kernel sent
+    /// the query when timestamping, `setup_ms` of the time spent opening the
+    /// connection of encrypted transports when the query needed a new one
+    Received {
+        size: usize,
+        elapsed_ms: f64,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        wire_ms: Option<f64>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        setup_ms: Option<f64>,
+    },
+    /// Datagrams dropped while waiting for the response, from another
+    /// address or without the ID and question of the query
+    Discarded { count: usize },
+    /// Exchange failed, timeouts included
+    Failed { error: String, elapsed_ms: f64 },
+    /// Every server failed, tried again after `backoff_ms`, drawing on the
+    /// retry budget
+    Retry { backoff_ms: f64 },
+    /// Failed exchange given up as the retry budget is spent
+    RetryDenied,
+    /// Served from the cache, its records fresh for `ttl` more seconds
+    Cached { ttl: u32 },
+    /// Every server failed with `error`, served from the cache a response
+    /// that expired `expired` seconds ago, RFC 8767
+    Stale { error: String, expired: u64 },
+    /// The server answered BADCOOKIE, sent again with the server cookie it
+    /// returned, RFC 7873 5.3
+    BadCookie,
+    /// The response to the query sent with the server cookie was BADCOOKIE
+    /// again, or `truncated`, sent again over TCP, RFC 7873 5.3
+    CookieOverTcp { truncated: bool },
+}
+
+/// Time spent opening connections in the exchanges of `decisions`, none when
+/// they were all made over open ones or without any
+fn connection_setup(decisions: &[Decision]) -> Option<Duration> {
+    let setups: Vec<f64> = decisions
+        .iter()
+        .filter_map(|decision| match decision {
+            Decision::Received { setup_ms, .. } => *setup_ms,
+            _ => None,
+        })
+        .collect();
+    match setups.is_empty() {
+        true => None,
+        false => Some(Duration::from_secs_f64(setups.iter().sum::<f64>() / 1000.0)),
+    }
+}
+
+impl fmt::Display for Decision {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Decision::Sent {
+                transport,
+                server,
+                edns,
+            } => write!(
+                f,
+                "sent over {} to {}{}",
+                transport,
+                server,
+                if *edns { " with EDNS" } else { "" }
+            ),
+            Decision::Received {
+                size,
+                elapsed_ms,
+                wire_ms,
+                setup_ms,
+            } => {
+                write!(f, "received {} bytes after {} ms", size, elapsed_ms)?;
+                if let Some(setup_ms) = setup_ms {
+                    write!(f, ", {} ms of connection setup", setup_ms)?;
+                }
+                match wire_ms {
+                    Some(wire_ms) => write!(f, ", {} ms on the wire", wire_ms),
+                    None => Ok(()),
+                }
             }
+            Decision::Discarded { count: 1 } => {
+                write!(f, "discarded a datagram that didn't answer the query")
+            }
+            Decision::Discarded { count } => {
+                write!(
+                    f,
+                    "discarded {} datagrams that didn't answer the query",
+                    count
+                )
+            }
+            Decision::Failed { error, elapsed_ms } => {
+                write!(f, "failed after {} ms: {}", elapsed_ms, error)
+            }
+            Decision::Retry { backoff_ms } => write!(f, "retrying after {} ms", backoff_ms),
+            Decision::RetryDenied => write!(f, "not retried, the retry budget is spent"),
+            Decision::Cached { ttl } => write!(f, "served from the cache, {} s left", ttl),
+            Decision::Stale { error, expired } => write!(
+                f,
+                "upstream failed ({}), served stale from the cache, expired {} s ago",
+                error, expired
+            ),
+            Decision::BadCookie => write!(f, "BADCOOKIE, resending with the server cookie"),
+            Decision::CookieOverTcp { truncated: true } => {
+                write!(f, "truncated after BADCOOKIE, resending over TCP")
+            }
+            Decision::CookieOverTcp { truncated: false } => {
+                write!(f, "BADCOOKIE again, resending over TCP")
+            }
+        }
+    }
+}
+
+/// Where an answer came from
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "source", rename_all = "snake_case")]
+pub enum Provenance {
+    /// Answered by `server` for this query
+    Network { server: String },
+    /// Served from the cache, answered by `server` `age` seconds ago
+    Cache { server: String, age: u64 },
+    /// Served from the cache as every server failed, a response of `server`
+    /// that expired `expired` seconds ago, RFC 8767
+    Stale { server: String, expired: u64 },
+    /// Read from a hosts file at `path`
+    Hosts { path: String },
+}
+
+impl Provenance {
+    /// Short form for a table column
+    pub fn label(&self) -> String {
+        match self {
+            Provenance::Network { .. } => "network".to_string(),
+            Provenance::Cache { age, .. } => std::format!("cache {}s", age),
+            Provenance::Stale { expired, .. } => std::format!("stale {}s", expired),
+            Provenance::Hosts { .. } => "hosts".to_string(),
+        }
+    }
+}
+
+impl fmt::Display for Provenance {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Provenance::Network { server } => write!(f, "network, {}", server),
+            Provenance::Cache { server, age } => {
+                write!(f, "cache, from {} {} s ago", server, age)
+            }
+            Provenance::Stale { server, expired } => {
+                write!(f, "stale cache, from {} expired {} s ago", server, expired)
+            }
+            Provenance::Hosts { path } => write!(f, "hosts file {}", path),
         }
-        // end of word
-        encoded.push(0x0);
+    }
+}
 
-        encoded.extend(DNSMessage::encode_query_type(&query_type));
-        encoded.extend(DNSMessage::encode_class_type());
-        encoded
+/// `data` with its question name in the case of `host`, once the response
+/// echoed the random case it was sent in, so the names compressed against
+/// it read as given
+fn restore_case(data: &[u8], host: &str) -> Vec<u8> {
+    let name = wire::encode_name(host);
+    let mut data = data.to_vec();
+    if let Some(question) = data.get_mut(12..12 + name.len()) {
+        if question.eq_ignore_ascii_case(&name) {
+            question.copy_from_slice(&name);
+        }
     }
+    data
+}
 
-    fn decode(&self, data: &Vec<u8>, rcvd_len: usize) -> Result<Self, Box<dyn Error>> {
-        let bincode_opts = bincode::DefaultOptions::new()
-            .with_big_endian()
-            .with_no_limit()
-            .allow_trailing_bytes()
-            .with_varint_encoding();
-        let msg: DNSMessage = bincode_opts.deserialize(&data[..rcvd_len])?;
-        Ok(msg)
+/// Server cookie of a BADCOOKIE response echoing `client_cookie`, RFC 7873 5.3
+fn bad_cookie(data: &[u8], client_cookie: &[u8]) -> Option<Vec<u8>> {
+    let response = Message::decode(data).ok()?;
+    if response.extended_rcode() != wire::BADCOOKIE {
+        return None;
     }
+    let cookie = response.cookie()?;
+    match cookie.split_at_checked(client_cookie.len()) {
+        Some((client, server)) if client == client_cookie && !server.is_empty() => {
+            Some(server.to_vec())
+        }
+        _ => None,
+    }
+}
 
-    fn decode_query_answers(
+/// Whether the response to a query sent with the server cookie needs TCP:
+/// BADCOOKIE again, or truncated
+fn cookie_over_tcp(data: &[u8]) -> Option<Decision> {
+    let response = Message::decode(data).ok()?;
+    if response.header.truncated() {
+        return Some(Decision::CookieOverTcp { truncated: true });
+    }
+    (response.extended_rcode() == wire::BADCOOKIE)
+        .then_some(Decision::CookieOverTcp { truncated: false })
+}
+
+/// Sends `query` to `server` over a new TCP connection, waiting `wait` for
+/// its response
+async fn exchange_tcp(
+    server: String,
+    query: &[u8],
+    wait: Duration,
+) -> Result<Vec<u8>, ClientError> {
+    let exchange = async {
+        let transport = TcpTransport::connect(server).await?;
+        transport.send(query).await?;
+        transport.recv().await
+    };
+    timeout(wait, exchange)
+        .await
+        .unwrap_or(Err(ClientError::Timeout(1)))
+}
+
+/// Likely reason a server refused a query, guessed from the flags of its
+/// response
+fn refused_hint(header: &Header) -> String {
+    match (header.authoritative(), header.recursion_available()) {
+        (true, false) => "the server is authoritative and doesn't recurse, query a resolver \
+            or a name of its own zones"
+            .to_string(),
+        (false, false) => "the server offers no recursion to this client, its ACLs may not \
+            allow it, try another resolver"
+            .to_string(),
+        (_, true) => "the resolver recurses but refused this query, likely by policy: an \
+            ACL, a blocklist or rate limiting"
+            .to_string(),
+    }
+}
+
+/// Maps a response code to its error, RFC 1035 4.1.1
+fn rcode_error(header: &Header) -> Result<(), ClientError> {
+    match header.rcode() {
+        0 => Ok(()),
+        1 => Err(ClientError::RDCodeFormatError),
+        2 => Err(ClientError::RDCodeServerFailure),
+        3 => Err(ClientError::RDCodeNameError),
+        4 => Err(ClientError::RDCodeNotImplemented),
+        5 => Err(ClientError::RDCodeRefused(refused_hint(header))),
+        rcode => Err(ClientError::RDCode(rcode)),
+    }
+}
+
+impl Client {
+    pub async fn new(remote_addr: String) -> Result<Client, ClientError> {
+        Ok(Client::with_transport(Transport::udp(remote_addr).await?))
+    }
+
+    #[cfg(feature = "encrypted-transports")]
+    pub fn new_doh(url: String, method: DohMethod) -> Result<Client, ClientError> {
+        Ok(Client::with_transport(Transport::https(url, method)?))
+    }
+
+    pub fn with_transport(transport: Transport) -> Client {
+        Client {
+            transports: vec![transport],
+            #[cfg(feature = "dnssec")]
+            trust_anchors: None,
+            budget: QueryBudget::default(),
+            retry_budget: RetryBudget::default(),
+            timeout: Duration::from_secs(3),
+            timeouts: HashMap::new(),
+            retries: 1,
+            class: ClassType::IN,
+            rotation: None,
+            randomness: Randomness::default(),
+            idn: true,
+            randomize_case: true,
+            cookie: None,
+            recursion: true,
+            checking_disabled: false,
+            authentic_data: false,
+            dnssec_ok: false,
+            edns: false,
+            #[cfg(feature = "cache")]
+            cache: None,
+            hosts: None,
+            #[cfg(feature = "zones")]
+            tsig: None,
+            cname_depth: MAX_CNAME_DEPTH,
+            subnet: None,
+            all_sections: false,
+            answer_page: None,
+            padding: None,
+            #[cfg(feature = "diagnostics")]
+            capture: None,
+        }
+    }
+
+    /// Fails over to `transport` when the servers added before it fail
+    pub fn with_fallback(mut self, transport: Transport) -> Client {
+        self.transports.push(transport);
+        self
+    }
+
+    /// Starts each query at the next server instead of always the first one
+    pub fn with_rotation(mut self, rotation: Rotation) -> Client {
+        self.rotation = Some(rotation);
+        self
+    }
+
+    /// Makes the random choices of queries with `randomness`, reproducible
+    /// when it is seeded
+    pub fn with_randomness(mut self, randomness: Randomness) -> Client {
+        self.randomness = randomness;
+        self
+    }
+
+    pub(crate) fn randomness(&self) -> Randomness {
+        self.randomness
+    }
+
+    /// Sends and shows names as given, without converting internationalized
+    /// names to punycode and back
+    pub fn without_idn(mut self) -> Client {
+        self.idn = false;
+        self
+    }
+
+    /// Sends query names in the case given, for servers that don't echo it
+    /// back as sent
+    pub fn without_0x20(mut self) -> Client {
+        self.randomize_case = false;
+        self
+    }
+
+    /// Sends queries without the Recursion Desired flag, for authoritative servers
+    pub fn without_recursion(mut self) -> Client {
+        self.recursion = false;
+        self
+    }
+
+    /// Sends queries with the Checking Disabled flag, for the answers of a
+    /// validating resolver even when they fail DNSSEC validation
+    pub fn with_checking_disabled(mut self) -> Client {
+        self.checking_disabled = true;
+        self
+    }
+
+    /// Sends queries with the Authentic Data flag, asking whether the
+    /// resolver validated the answers without the DNSSEC records
+    pub fn with_authentic_data(mut self) -> Client {
+        self.authentic_data = true;
+        self
+    }
+
+    /// Sends queries with the DNSSEC OK flag, for the signatures of the
+    /// answers without validating them, RFC 3225
+    pub fn with_dnssec_ok(mut self) -> Client {
+        self.dnssec_ok = true;
+        self
+    }
+
+    /// Sends queries with an EDNS OPT record even when no option needs one,
+    /// as a forwarder does for clients that sent one
+    pub fn with_edns(mut self) -> Client {
+        self.edns = true;
+        self
+    }
+
+    /// Returns the records of the authority and additional sections after
+    /// the answers, e.g. the SOA of NODATA responses and glue
+    pub fn with_all_sections(mut self) -> Client {
+        self.all_sections = true;
+        self
+    }
+
+    /// Copies the queries sent and the responses received to `capture`
+    #[cfg(feature = "diagnostics")]
+    pub fn with_capture(mut self, capture: Capture) -> Client {
+        self.capture = Some(capture);
+        self
+    }
+
+    /// Queries `class` instead of IN
+    pub fn with_class(mut self, class: ClassType) -> Client {
+        self.class = class;
+        self
+    }
+
+    /// Measures the round trip of queries with kernel timestamps too, Linux
+    /// and UDP only
+    pub fn with_timestamping(mut self) -> Result<Client, ClientError> {
+        for transport in self.transports.iter_mut() {
+            transport.enable_timestamping()?;
+        }
+        Ok(self)
+    }
+
+    /// Gives up on an attempt once `timeout` passed without a response
+    pub fn with_timeout(mut self, timeout: Duration) -> Client {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Waits `timeout` for `server` instead, given as the address or URL of a
+    /// server or as a transport name (`udp`, `https`) for all its servers
+    pub fn with_server_timeout(mut self, server: String, timeout: Duration) -> Client {
+        self.timeouts.insert(server, timeout);
+        self
+    }
+
+    /// How long to wait for `transport`, its own timeout first
+    fn timeout_of(&self, transport: &Transport) -> Duration {
+        self.timeouts
+            .get(&transport.server())
+            .or_else(|| self.timeouts.get(transport.name()))
+            .copied()
+            .unwrap_or(self.timeout)
+    }
+
+    /// Sends a query to the servers up to `attempts` times, the retry budget
+    /// permitting
+    pub fn with_attempts(mut self, attempts: usize) -> Client {
+        self.retries = attempts.saturating_sub(1);
+        self
+    }
+
+    /// Retries failed exchanges only while `retry_budget` allows it
+    pub fn with_retry_budget(mut self, retry_budget: RetryBudget) -> Client {
+        self.retry_budget = retry_budget;
+        self
+    }
+
+    /// Draws every upstream query, including DNSSEC chain lookups, from `budget`
+    pub fn with_budget(mut self, budget: QueryBudget) -> Client {
+        self.budget = budget;
+        self
+    }
+
+    /// Sends a client cookie with every query, and the server cookie of a
+    /// BADCOOKIE response when trying again, RFC 7873
+    pub fn with_cookies(mut self) -> Client {
+        self.cookie = Some(self.randomness.rng("cookie").gen());
+        self
+    }
+
+    /// Sends queries on behalf of `subnet`, for answers tailored to it,
+    /// RFC 7871
+    pub fn with_subnet(mut self, subnet: ClientSubnet) -> Client {
+        self.subnet = Some(subnet);
+        self
+    }
+
+    /// Pads queries over encrypted transports to a multiple of `block` bytes,
+    /// so their length doesn't tell the name, RFC 7830
+    pub fn with_padding(mut self, block: usize) -> Client {
+        self.padding = Some(block).filter(|block| *block > 0);
+        self
+    }
+
+    /// Serves responses from `cache` while their records are fresh, and
+    /// keeps new ones there
+    #[cfg(feature = "cache")]
+    pub fn with_cache(mut self, cache: Cache) -> Client {
+        self.cache = Some(cache);
+        self
+    }
+
+    /// Answers A and AAAA queries of the IN class with the addresses `hosts`
+    /// has for their names, like the system resolver, querying the servers
+    /// for the names it doesn't have
+    pub fn with_hosts(mut self, hosts: Hosts) -> Client {
+        self.hosts = Some(hosts);
+        self
+    }
+
+    /// Answers of `host` `query_type` from the hosts file, none when it has
+    /// no address of the family
+    fn hosts_answers(&self, host: &str, query_type: &QueryType) -> Option<Vec<QueryAnswer>> {
+        let hosts = self.hosts.as_ref()?;
+        if self.class != ClassType::IN {
+            return None;
+        }
+        let answers: Vec<QueryAnswer> = hosts
+            .addrs(host)
+            .iter()
+            .filter_map(|addr| match (query_type, addr) {
+                (QueryType::A, IpAddr::V4(addr)) => Some(RData::A(*addr)),
+                (QueryType::AAAA, IpAddr::V6(addr)) => Some(RData::AAAA(*addr)),
+                _ => None,
+            })
+            .map(|data| QueryAnswer {
+                host: host.to_string(),
+                data,
+                query_type: query_type.clone(),
+                class_type: ClassType::IN,
+                ttl: 0,
+                section: Section::Answer,
+                server: hosts.path().to_string(),
+                rcode: 0,
+                flags: Vec::new(),
+                query_time: Duration::ZERO,
+                decisions: Vec::new(),
+                provenance: Provenance::Hosts {
+                    path: hosts.path().to_string(),
+                },
+                subnet: None,
+                #[cfg(feature = "dnssec")]
+                dnssec: None,
+                negative: false,
+            })
+            .collect();
+        match answers.is_empty() {
+            true => None,
+            false => Some(answers),
+        }
+    }
+
+    /// Answers only the `limit` answers of page `page`, from 1, of each
+    /// query, leaving the data of the others undecoded: huge RRsets are
+    /// counted without taking the memory of every record
+    pub fn with_answer_page(mut self, limit: usize, page: usize) -> Client {
+        let start = limit.saturating_mul(page.saturating_sub(1));
+        self.answer_page = Some(start..start.saturating_add(limit));
+        self
+    }
+
+    /// Fails queries whose CNAME and DNAME chain goes through more than
+    /// `depth` records
+    pub fn with_cname_depth(mut self, depth: usize) -> Client {
+        self.cname_depth = depth;
+        self
+    }
+
+    /// Signs every query with `key` and only accepts responses signed with
+    /// it too, RFC 8945
+    #[cfg(feature = "zones")]
+    pub fn with_tsig(mut self, key: TsigKey) -> Client {
+        self.tsig = Some(key);
+        self
+    }
+
+    /// Requests DNSSEC records on every query and validates answers up to the
+    /// given trust anchors
+    #[cfg(feature = "dnssec")]
+    pub fn with_dnssec(mut self, trust_anchors: Vec<Ds>) -> Client {
+        self.trust_anchors = Some(trust_anchors);
+        self
+    }
+
+    /// Class and flags of the queries changing their answers, which the
+    /// cache keeps apart
+    #[cfg(feature = "cache")]
+    fn query_kind(&self) -> QueryKind {
+        QueryKind {
+            class: self.class.code(),
+            recursion: self.recursion,
+            checking_disabled: self.checking_disabled,
+            dnssec_ok: self.requests_dnssec(),
+        }
+    }
+
+    /// Whether queries ask for DNSSEC records, with the DO flag
+    fn requests_dnssec(&self) -> bool {
+        #[cfg(feature = "dnssec")]
+        return self.dnssec_ok || self.trust_anchors.is_some();
+        #[cfg(not(feature = "dnssec"))]
+        self.dnssec_ok
+    }
+
+    /// Answers of a `query_type` query of `host`, failing with
+    /// `RDCodeNameError` when the name doesn't exist
+    pub async fn query(
         &self,
         host: String,
-        queries_len: usize,
-        rest: &[u8],
+        query_type: QueryType,
     ) -> Result<Vec<QueryAnswer>, ClientError> {
-        let rest_len = rest.len();
+        let response = self.resolve(host, query_type).await?;
+        match response.is_nxdomain() {
+            true => Err(ClientError::RDCodeNameError),
+            false => Ok(response.answers),
+        }
+    }
+
+    /// Response to a `query_type` query of `host`, NXDOMAIN and NODATA ones
+    /// with the SOA of their authority section. A CNAME or DNAME chain the
+    /// response leaves unfinished is followed with queries of its last name,
+    /// up to the CNAME depth.
+    pub async fn resolve(
+        &self,
+        host: String,
+        query_type: QueryType,
+    ) -> Result<QueryResponse, ClientError> {
+        let host = self.encode_host(&host)?;
+        if let Some(answers) = self.hosts_answers(&host, &query_type) {
+            log::info!("{} {}: answered from the hosts file", host, query_type);
+            return Ok(QueryResponse {
+                rcode: 0,
+                total: answers.len(),
+                answers,
+                soa: None,
+            });
+        }
         let mut answers: Vec<QueryAnswer> = Vec::new();
-        if rest_len < queries_len {
-            return Ok(answers);
-        }
-        let resp = &rest[queries_len..rest_len];
-        log::debug!("Decoding query answers: {:x?}", &resp);
-        let mut i: usize = 0;
-        while i + 12 < resp.len() {
-            if resp[i] != 0xc0 {
-                return Err(ClientError::DecodeError(std::format!(
-                    "Expected 0xc0 on decoded response, found {:x?} instead",
-                    &resp[i]
-                )));
+        let mut total = 0;
+        let mut chain = vec![normalize_name(&host)];
+        // the record leading from each name of the chain to the next
+        let mut links: Vec<String> = Vec::new();
+        let mut name = host.clone();
+        loop {
+            let response = self.exchange_query(&name, &query_type).await?;
+            let rcode = response.message.header.rcode();
+            if rcode != 3 {
+                rcode_error(&response.message.header)?;
             }
-            let query_type = DNSMessage::decode_query_type(&resp[i + 2..i + 4])?;
-            let class_type = DNSMessage::decode_class_type(&resp[i + 4..i + 6])?;
-            let _ttl = &resp[i + 6..i + 10];
-            let data_len: u16 = ((resp[i + 10] as u16) << 8) | resp[i + 11] as u16;
-            if !(query_type == QueryType::A || query_type == QueryType::AAAA) {
-                i = i + 12 + (data_len as usize);
-                continue;
+            let records = &response.message.answers;
+            let names = answer_chain(&name, records);
+            // where the last name leads, when back into the chain
+            let closing = next_name(&names[names.len() - 1], records).map(|(next, _)| next);
+            for (i, next) in names[1..].iter().chain(&closing).enumerate() {
+                links.push(link(&names[i], records).unwrap_or_default());
+                if let Some(start) = chain.iter().position(|known| known == next) {
+                    return Err(ClientError::CnameLoop(links[start..].join(", ")));
+                }
+                chain.push(next.clone());
             }
-            let answer = QueryAnswer {
-                host: host.clone(),
-                address: if query_type == QueryType::A {
-                    Ipv4Addr::new(resp[i + 12], resp[i + 13], resp[i + 14], resp[i + 15])
-                        .to_string()
-                } else {
-                    Ipv6Addr::new(
-                        ((resp[i + 12] as u16) << 8) | resp[i + 13] as u16,
-                        ((resp[i + 14] as u16) << 8) | resp[i + 15] as u16,
-                        ((resp[i + 16] as u16) << 8) | resp[i + 17] as u16,
-                        ((resp[i + 18] as u16) << 8) | resp[i + 19] as u16,
-                        ((resp[i + 20] as u16) << 8) | resp[i + 21] as u16,
-                        ((resp[i + 22] as u16) << 8) | resp[i + 23] as u16,
-                        ((resp[i + 24] as u16) << 8) | resp[i + 25] as u16,
-                        ((resp[i + 26] as u16) << 8) | resp[i + 27] as u16,
-                    )
-                    .to_string()
-                },
-                query_type: query_type,
-                class_type: class_type,
+            if chain.len() > self.cname_depth + 1 {
+                return Err(ClientError::CnameDepth(
+                    chain.join(" -> "),
+                    self.cname_depth,
+                ));
+            }
+            // an answer of the type, or a chain the response finished itself
+            let target = names[names.len() - 1].clone();
+            let unfinished = names.len() > 1
+                && !matches!(
+                    query_type,
+                    QueryType::CNAME | QueryType::DNAME | QueryType::ANY
+                )
+                && !records.iter().any(|record| {
+                    record.query_type == query_type && normalize_name(&record.name) == target
+                });
+            let (found, soa, count) = self
+                .response_answers(&name, &query_type, response, total)
+                .await;
+            answers.extend(found);
+            total += count;
+            if rcode == 3 || !unfinished {
+                let mut response = QueryResponse {
+                    rcode,
+                    answers,
+                    total,
+                    soa: None,
+                };
+                if response.is_nxdomain() || response.is_nodata() {
+                    // the SOA stands for the answers, not next to them
+                    response.answers.retain(|answer| {
+                        answer.section != Section::Authority || answer.query_type != QueryType::SOA
+                    });
+                    response.soa = soa;
+                }
+                return Ok(response);
+            }
+            log::info!("{} {}: following the chain to {}", host, query_type, target);
+            name = target;
+        }
+    }
+
+    /// Answers of a response to a query of `host`, validated when DNSSEC is on,
+    /// the SOA of its authority section as a negative answer of `host` and
+    /// the count of its answers. `counted` answers came before them in the
+    /// chain, the page starting that far back.
+    async fn response_answers(
+        &self,
+        host: &str,
+        query_type: &QueryType,
+        response: Response,
+        counted: usize,
+    ) -> (Vec<QueryAnswer>, Option<QueryAnswer>, usize) {
+        let Response {
+            message: response,
+            server,
+            query_time,
+            decisions,
+            provenance,
+            ..
+        } = response;
+        let records = &response.answers;
+        let subnet = ClientSubnet::of(&response);
+        let related = query_answer_records(host, records);
+        let count = related.len();
+        let page = match &self.answer_page {
+            Some(page) => page.start.saturating_sub(counted)..page.end.saturating_sub(counted),
+            None => 0..count,
+        };
+        let mut answers: Vec<QueryAnswer> = related
+            .into_iter()
+            .skip(page.start)
+            .take(page.len())
+            .filter_map(|(host, record)| decode_answer(host, record, Section::Answer))
+            .collect();
+        if self.all_sections {
+            answers.extend(decode_section(&response.authorities, Section::Authority));
+            answers.extend(decode_section(&response.additionals, Section::Additional));
+        }
+        let mut soa = response
+            .authorities
+            .iter()
+            .find(|record| record.query_type == QueryType::SOA)
+            .and_then(|record| decode_answer(host.to_string(), record, Section::Authority));
+        if let Some(soa) = soa.as_mut() {
+            soa.negative = true;
+        }
+        for answer in answers.iter_mut().chain(soa.as_mut()) {
+            answer.server = server.clone();
+            answer.rcode = response.header.rcode();
+            answer.flags = response.header.response_flag_names();
+            answer.query_time = query_time;
+            answer.decisions = decisions.clone();
+            answer.provenance = provenance.clone();
+            answer.subnet = subnet.clone();
+            if self.idn {
+                answer.host = idn::to_unicode(&answer.host);
+                answer.data = idn::rdata_to_unicode(answer.data.clone());
+            }
+        }
+        #[cfg(feature = "dnssec")]
+        if let Some(trust_anchors) = &self.trust_anchors {
+            let status = DnssecStatus {
+                authenticated_data: response.header.authenticated_data(),
+                validation: dnssec::validate(self, records, trust_anchors).await,
             };
-            answers.push(answer);
-            i = i + 12 + (data_len as usize);
+            if let Some(reason) = status.failure() {
+                log::warn!(
+                    "DNSSEC validation of {} {:?} failed: {}",
+                    host,
+                    query_type,
+                    reason
+                );
+            }
+            for answer in answers.iter_mut().chain(soa.as_mut()) {
+                answer.dnssec = Some(status.clone());
+            }
         }
-        Ok(answers)
+        #[cfg(not(feature = "dnssec"))]
+        let _ = query_type;
+        (answers, soa, count)
+    }
+
+    /// Answer section records of `host`, fetched with DNSSEC records when enabled
+    #[cfg(feature = "dnssec")]
+    pub(crate) async fn records(
+        &self,
+        host: &str,
+        query_type: &QueryType,
+    ) -> Result<Vec<Record>, ClientError> {
+        let response = self.exchange_query(host, query_type).await?.message;
+        rcode_error(&response.header)?;
+        Ok(response.answers)
     }
 
-    fn is_answer(&self) -> bool {
-        self.flags[0] & 0x80 == 0x80
+    /// Whole response to a `query_type` query of `host`, whatever its
+    /// response code, to show every section of it
+    pub async fn exchange(
+        &self,
+        host: &str,
+        query_type: QueryType,
+    ) -> Result<Response, ClientError> {
+        self.exchange_query(&self.encode_host(host)?, &query_type)
+            .await
     }
 
-    fn rd_code(&self) -> Result<(), ClientError> {
-        match self.flags[1] & 0x0f {
-            0 => Ok(()),
-            1 => Err(ClientError::RDCodeFormatError),
-            2 => Err(ClientError::RDCodeServerFailure),
-            3 => Err(ClientError::RDCodeNameError),
-            4 => Err(ClientError::RDCodeNotImplemented),
-            5 => Err(ClientError::RDCodeRefused),
-            _ => Ok(()),
+    /// `query` as sent on the wire, signed when a TSIG key is set, and the MAC
+    /// of its signature
+    fn encode_query(&self, query: &Message) -> Result<(Vec<u8>, Vec<u8>), ClientError> {
+        #[cfg(feature = "zones")]
+        if let Some(key) = &self.tsig {
+            return key.sign(&query.encode());
         }
+        Ok((query.encode(), Vec::new()))
     }
-}
 
-impl Client {
-    pub async fn new(remote_addr: String) -> Result<Client, ClientError> {
-        let remote_addr: SocketAddr = match remote_addr.parse() {
-            Ok(addr) => addr,
-            Err(err) => return Err(ClientError::ParseError(err.to_string())),
-        };
-        let local_addr: SocketAddr = match if remote_addr.is_ipv4() {
-            "0.0.0.0:0"
+    /// `host` as sent on the wire, in punycode unless IDN support is off
+    fn encode_host(&self, host: &str) -> Result<String, ClientError> {
+        if self.idn {
+            idn::to_ascii(host)
         } else {
-            "[::]:0"
+            Ok(host.to_string())
         }
-        .parse()
+    }
+
+    /// Whether query names are sent in random case, only over UDP where
+    /// responses can be spoofed
+    fn randomizes_case(&self) -> bool {
+        self.randomize_case && self.transports[0].spoofable()
+    }
+
+    /// `host` in the case sent on the wire, every letter in random case when
+    /// randomizing so off-path attackers must guess it too. Servers compare
+    /// names ignoring case and echo the question as sent.
+    fn wire_name(&self, host: &str, rng: &mut StdRng) -> String {
+        if !self.randomizes_case() {
+            return host.to_string();
+        }
+        host.chars()
+            .map(|c| {
+                if rng.gen() {
+                    c.to_ascii_uppercase()
+                } else {
+                    c.to_ascii_lowercase()
+                }
+            })
+            .collect()
+    }
+
+    #[cfg(not(feature = "cache"))]
+    async fn exchange_query(
+        &self,
+        host: &str,
+        query_type: &QueryType,
+    ) -> Result<Response, ClientError> {
+        self.exchange_upstream(host, query_type).await
+    }
+
+    #[cfg(feature = "cache")]
+    async fn exchange_query(
+        &self,
+        host: &str,
+        query_type: &QueryType,
+    ) -> Result<Response, ClientError> {
+        let _pending = match &self.cache {
+            Some(cache) => Some(cache.pending(host, query_type, self.query_kind()).await),
+            None => None,
+        };
+        if let Some(cached) = self
+            .cache
+            .as_ref()
+            .and_then(|cache| cache.get(host, query_type, self.query_kind()))
         {
-            Ok(addr) => addr,
-            Err(err) => return Err(ClientError::ParseError(err.to_string())),
+            let decision = Decision::Cached { ttl: cached.ttl };
+            log::info!("{} {}: {}", host, query_type, decision);
+            return Ok(Response {
+                message: cached.message,
+                provenance: Provenance::Cache {
+                    server: cached.server.clone(),
+                    age: cached.age,
+                },
+                server: cached.server,
+                size: cached.size,
+                query_time: Duration::ZERO,
+                decisions: vec![decision],
+            });
+        }
+        let upstream = self.exchange_upstream(host, query_type).await;
+        let failed = match &upstream {
+            Ok(response) => response.message.header.rcode() == 2,
+            Err(_) => true,
+        };
+        let stale = match (&self.cache, failed) {
+            (Some(cache), true) => cache.get_stale(host, query_type, self.query_kind()),
+            _ => None,
         };
-        let socket = match UdpSocket::bind(local_addr).await {
-            Ok(socket) => socket,
-            Err(err) => return Err(ClientError::BindError(err.to_string())),
+        let (cached, expired) = match stale {
+            Some(stale) => stale,
+            None => return upstream,
         };
-        let max_datagram_size: usize = 65_507;
-        match socket.connect(&remote_addr).await {
-            Ok(res) => res,
-            Err(err) => return Err(ClientError::ConnectError(err.to_string())),
+        let decision = Decision::Stale {
+            error: match upstream {
+                Ok(response) => rcode_name(response.message.header.rcode()),
+                Err(err) => std::format!("{:?}", err),
+            },
+            expired,
         };
-        Ok(Client {
-            socket,
-            max_datagram_size,
+        log::warn!("{} {}: {}", host, query_type, decision);
+        Ok(Response {
+            message: cached.message,
+            provenance: Provenance::Stale {
+                server: cached.server.clone(),
+                expired,
+            },
+            server: cached.server,
+            size: cached.size,
+            query_time: Duration::ZERO,
+            decisions: vec![decision],
         })
     }
 
-    pub async fn query(
+    /// Sends a query to the servers, failing over and retrying until one
+    /// answers
+    async fn exchange_upstream(
         &self,
-        host: String,
-        query_type: QueryType,
-    ) -> Result<Vec<QueryAnswer>, ClientError> {
-        let queries = DNSMessage::encode_host(&host, &query_type);
-        let queries_len = queries.len();
-        let msg = &DNSMessage::new(queries);
-        log::debug!("Query {:x?}", msg);
-        let msg_enc = match msg.encode() {
-            Ok(encoded) => encoded,
-            Err(err) => return Err(ClientError::EncodeError(err.to_string())),
-        };
-        match self.socket.send(&msg_enc).await {
-            Ok(_) => (),
-            Err(err) => return Err(ClientError::SendError(err.to_string())),
+        host: &str,
+        query_type: &QueryType,
+    ) -> Result<Response, ClientError> {
+        self.budget.spend()?;
+        let mut rng = self
+            .randomness
+            .rng(&std::format!("{} {}", host, query_type));
+        let id: u16 = if self.transports[0].zero_id() {
+            0
+        } else {
+            rng.gen()
         };
-        let mut data = vec![0u8; self.max_datagram_size];
-        let recv = match timeout(Duration::from_secs(3), self.socket.recv(&mut data)).await {
-            Err(_) => return Err(ClientError::RecvError(
-                "Failed to receive an response within 3 secs".to_string()
-            )),
-            Ok(res) => res,
+        let wire_name = self.wire_name(host, &mut rng);
+        let mut query = Message::query(id, &wire_name, query_type, self.class.code());
+        if !self.recursion {
+            query.clear_recursion_desired();
+        }
+        if self.checking_disabled {
+            query.set_checking_disabled();
+        }
+        if self.authentic_data {
+            query.set_authentic_data();
+        }
+        let padding = self.padding.filter(|_| self.transports[0].encrypted());
+        if self.edns
+            || self.requests_dnssec()
+            || self.cookie.is_some()
+            || self.subnet.is_some()
+            || padding.is_some()
+        {
+            query.add_edns(self.requests_dnssec());
+        }
+        if let Some(cookie) = &self.cookie {
+            query.set_cookie(cookie, None);
+        }
+        if let Some(subnet) = &self.subnet {
+            query.set_option(ecs::CLIENT_SUBNET, &subnet.encode());
+        }
+        // last, as it pads what the other options leave
+        if let Some(block) = padding {
+            query.pad(block);
+        }
+        log::debug!("Query {:x?}", query);
+        let (mut msg_enc, mut request_mac) = self.encode_query(&query)?;
+        let mut cookie_retried = false;
+        self.retry_budget.deposit();
+        let started = Instant::now();
+        let (mut retries, mut attempts, mut server) = (0, 0, 0);
+        // with rotation, each query starts at the server after the last one's,
+        // or at one of the seed as the order queries run in varies
+        let first = match &self.rotation {
+            Some(_) if self.randomness.is_seeded() => rng.gen_range(0..self.transports.len()),
+            Some(rotation) => rotation.next(self.transports.len()),
+            None => 0,
         };
-        let len = match recv {
-            Ok(len) => len,
-            Err(err) => return Err(ClientError::RecvError(err.to_string())),
+        let mut decisions: Vec<Decision> = Vec::new();
+        let mut failures: Vec<FailedAttempt> = Vec::new();
+        let mut not_listening = 0;
+        let mut decide = |decision: Decision| {
+            log::info!("{} {}: {}", host, query_type, decision);
+            decisions.push(decision);
         };
-        log::debug!("Query encoded {:x?}, received {:?} bytes", msg_enc, len);
-        let msg_decoded = match msg.decode(&data, len) {
-            Ok(decoded) => decoded,
-            Err(err) => return Err(ClientError::DecodeError(err.to_string())),
+        let (transport, data) = loop {
+            let transport = &self.transports[(first + server) % self.transports.len()];
+            let sent = Instant::now();
+            attempts += 1;
+            decide(Decision::Sent {
+                transport: transport.name().to_string(),
+                server: transport.server(),
+                edns: query
+                    .additionals
+                    .iter()
+                    .any(|r| r.query_type == QueryType::OPT),
+            });
+            let wait = self.timeout_of(transport);
+            #[cfg(feature = "diagnostics")]
+            if let Some(capture) = &self.capture {
+                capture.record(Direction::Query, transport, &msg_enc);
+            }
+            let exchanged = match timeout(wait, transport.exchange(&msg_enc)).await {
+                Ok(exchanged) => exchanged,
+                Err(_) => Err(ClientError::Timeout(attempts)),
+            };
+            match exchanged {
+                Ok(received) => {
+                    #[cfg(feature = "diagnostics")]
+                    if let Some(capture) = &self.capture {
+                        capture.record(Direction::Response, transport, &received.data);
+                    }
+                    if received.discarded > 0 {
+                        decide(Decision::Discarded {
+                            count: received.discarded,
+                        });
+                    }
+                    decide(Decision::Received {
+                        size: received.data.len(),
+                        elapsed_ms: millis(sent.elapsed()),
+                        wire_ms: received.wire_time.map(millis),
+                        setup_ms: received.setup.map(millis),
+                    });
+                    if let (Some(cookie), false) = (&self.cookie, cookie_retried) {
+                        if let Some(server_cookie) = bad_cookie(&received.data, cookie) {
+                            decide(Decision::BadCookie);
+                            query.set_cookie(cookie, Some(&server_cookie));
+                            (msg_enc, request_mac) = self.encode_query(&query)?;
+                            cookie_retried = true;
+                            self.budget.spend()?;
+                            continue;
+                        }
+                    }
+                    let over_tcp = match cookie_retried && transport.spoofable() {
+                        true => cookie_over_tcp(&received.data),
+                        false => None,
+                    };
+                    let Some(over_tcp) = over_tcp else {
+                        break (transport, received.data);
+                    };
+                    decide(over_tcp);
+                    self.budget.spend()?;
+                    let sent = Instant::now();
+                    decide(Decision::Sent {
+                        transport: "tcp".to_string(),
+                        server: transport.server(),
+                        edns: true,
+                    });
+                    match exchange_tcp(transport.server(), &msg_enc, wait).await {
+                        Ok(data) => {
+                            decide(Decision::Received {
+                                size: data.len(),
+                                elapsed_ms: millis(sent.elapsed()),
+                                wire_ms: None,
+                                setup_ms: None,
+                            });
+                            break (transport, data);
+                        }
+                        // the UDP response, failing with BADCOOKIE or truncated
+                        Err(err) => {
+                            decide(Decision::Failed {
+                                error: std::format!("{:?}", err),
+                                elapsed_ms: millis(sent.elapsed()),
+                            });
+                            break (transport, received.data);
+                        }
+                    }
+                }
+                Err(err) => {
+                    let error = match &err {
+                        // next to the server already
+                        ClientError::NotListening(_) => {
+                            "not listening, ICMP port unreachable".to_string()
+                        }
+                        _ => std::format!("{:?}", err),
+                    };
+                    decide(Decision::Failed {
+                        error: error.clone(),
+                        elapsed_ms: millis(sent.elapsed()),
+                    });
+                    failures.push(FailedAttempt {
+                        server: transport.server(),
+                        transport: transport.name().to_string(),
+                        error,
+                        elapsed: sent.elapsed(),
+                    });
+                    if matches!(err, ClientError::NotListening(_)) {
+                        not_listening += 1;
+                    }
+                    if server + 1 < self.transports.len() {
+                        self.budget.spend()?;
+                        server += 1;
+                        continue;
+                    }
+                    // every server failed, start over after a while, unless
+                    // none listens and trying again won't change that
+                    let give_up = retries >= self.retries || not_listening == failures.len() || {
+                        let denied = !self.retry_budget.withdraw();
+                        if denied {
+                            decide(Decision::RetryDenied);
+                        }
+                        denied
+                    };
+                    if give_up {
+                        // a single failure says it all, several are listed
+                        return Err(match failures.len() {
+                            1 => err,
+                            _ => ClientError::AllFailed(failures),
+                        });
+                    }
+                    // up to half again as long, so clients don't retry in step
+                    let backoff = (BACKOFF * 2u32.saturating_pow(retries as u32))
+                        .mul_f64(1.0 + rng.gen::<f64>() / 2.0);
+                    decide(Decision::Retry {
+                        backoff_ms: millis(backoff),
+                    });
+                    sleep(backoff).await;
+                    self.budget.spend()?;
+                    retries += 1;
+                    server = 0;
+                }
+            }
         };
-        if !msg_decoded.is_answer() {
+        let query_time = started.elapsed();
+        log::debug!(
+            "Query encoded {:x?}, received {:?} bytes",
+            msg_enc,
+            data.len()
+        );
+        let response = Message::decode(&data)?;
+        if !response.header.is_response() {
             return Err(ClientError::DecodeError(
                 "Decoded message flag value isn't an answer".to_string(),
             ));
         };
-        let encode_size = DNSMessage::header_size();
-        let rest = &data[encode_size..len];
-        log::debug!("Rest {:x?}", rest);
-        if msg.id != msg_decoded.id {
+        if query.header.id != response.header.id {
             let err_msg: String = std::format!(
                 "Sent Query ID: {:?}, but received Response ID: {:?}",
-                msg.id,
-                msg_decoded.id
+                query.header.id,
+                response.header.id
             );
             return Err(ClientError::DecodeIdError(err_msg));
         }
-        log::debug!("Response {:x?}", &msg_decoded);
-        match msg_decoded.rd_code() {
-            Ok(()) => msg_decoded.decode_query_answers(host, queries_len, rest),
-            Err(err) => Err(err),
+        if self.randomizes_case() {
+            let name = |message: &Message| {
+                message.questions.first().map_or(String::new(), |question| {
+                    question.name.trim_end_matches('.').to_string()
+                })
+            };
+            if name(&query) != name(&response) {
+                return Err(ClientError::CaseMismatch(name(&query), name(&response)));
+            }
+        }
+        log::debug!("Response {:x?}", &response);
+        #[cfg(feature = "zones")]
+        if let Some(key) = &self.tsig {
+            TsigVerifier::new(key.clone(), request_mac).verify(&data)?;
+        }
+        #[cfg(not(feature = "zones"))]
+        let _ = request_mac;
+        let response = match self.randomizes_case() {
+            true => Message::decode(&restore_case(&data, host))?,
+            false => response,
+        };
+        if let Some(subnet) = &self.subnet {
+            subnet.check(&response)?;
         }
+        if self.cookie.is_some() && response.extended_rcode() == wire::BADCOOKIE {
+            return Err(ClientError::BadCookie(transport.server()));
+        }
+        for record in &response.answers {
+            ClassType::from_code(record.class)?;
+        }
+        #[cfg(feature = "cache")]
+        if let Some(cache) = &self.cache {
+            cache.insert(
+                host,
+                query_type,
+                self.query_kind(),
+                &response,
+                &transport.server(),
+                data.len(),
+            );
+        }
+        Ok(Response {
+            message: response,
+            server: transport.server(),
+            size: data.len(),
+            query_time,
+            decisions,
+            provenance: Provenance::Network {
+                server: transport.server(),
+            },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, Responder};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    const SERVER_COOKIE: [u8; 8] = [9; 8];
+
+    /// BADCOOKIE response to `query`, with a server cookie
+    fn bad_cookie(query: &Message) -> Vec<u8> {
+        let mut response = Message::reply(query, (wire::BADCOOKIE & 0x0f) as u8);
+        response.add_edns(false);
+        response.set_cookie(&query.cookie().unwrap()[..8], Some(&SERVER_COOKIE));
+        for opt in response.additionals.iter_mut() {
+            opt.ttl |= ((wire::BADCOOKIE >> 4) as u32) << 24;
+        }
+        response.encode()
+    }
+
+    /// Answers a query over TCP on `addr` with an address
+    async fn tcp_responder(addr: &str) {
+        let listener = TcpListener::bind(addr).await.unwrap();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut query = vec![0; stream.read_u16().await.unwrap() as usize];
+            stream.read_exact(&mut query).await.unwrap();
+            let response = testing::address(&Message::decode(&query).unwrap());
+            stream.write_u16(response.len() as u16).await.unwrap();
+            stream.write_all(&response).await.unwrap();
+        });
+    }
+
+    /// A client of `first`, failing over to `second`
+    async fn failover(first: &Responder, second: &Responder) -> Client {
+        Client::new(first.addr())
+            .await
+            .unwrap()
+            .with_fallback(Transport::udp(second.addr()).await.unwrap())
+            .with_timeout(Duration::from_millis(100))
+    }
+
+    async fn client(responder: &Responder) -> Client {
+        Client::new(responder.addr())
+            .await
+            .unwrap()
+            .with_timeout(Duration::from_secs(2))
+            .with_cookies()
+    }
+
+    #[tokio::test]
+    async fn bad_cookie_resends_with_the_server_cookie() {
+        let responder = Responder::answering(|query| match query.cookie() {
+            Some(cookie) if cookie.ends_with(&SERVER_COOKIE) => vec![testing::address(query)],
+            _ => vec![bad_cookie(query)],
+        })
+        .await;
+        let response = client(&responder)
+            .await
+            .exchange("example.com", QueryType::A)
+            .await
+            .unwrap();
+        assert_eq!(response.message.answers.len(), 1);
+        assert!(response.decisions().contains(&Decision::BadCookie));
+        assert_eq!(responder.queries().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn bad_cookie_again_resends_over_tcp() {
+        let responder = Responder::answering(|query| vec![bad_cookie(query)]).await;
+        tcp_responder(&responder.addr()).await;
+        let response = client(&responder)
+            .await
+            .exchange("example.com", QueryType::A)
+            .await
+            .unwrap();
+        assert_eq!(response.message.answers.len(), 1);
+        assert!(response
+            .decisions()
+            .contains(&Decision::CookieOverTcp { truncated: false }));
+        assert_eq!(responder.queries().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn truncated_after_bad_cookie_resends_over_tcp() {
+        let responder = Responder::answering(|query| match query.cookie() {
+            Some(cookie) if cookie.ends_with(&SERVER_COOKIE) => {
+                let mut response = Message::reply(query, 0);
+                response.truncate();
+                vec![response.encode()]
+            }
+            _ => vec![bad_cookie(query)],
+        })
+        .await;
+        tcp_responder(&responder.addr()).await;
+        let response = client(&responder)
+            .await
+            .exchange("example.com", QueryType::A)
+            .await
+            .unwrap();
+        assert_eq!(response.message.answers.len(), 1);
+        assert!(response
+            .decisions()
+            .contains(&Decision::CookieOverTcp { truncated: true }));
+    }
+
+    #[tokio::test]
+    async fn fails_over_to_the_next_server() {
+        let dead = Responder::answering(|_| Vec::new()).await;
+        let live = Responder::answering(|query| vec![testing::address(query)]).await;
+        let response = failover(&dead, &live)
+            .await
+            .exchange("example.com", QueryType::A)
+            .await
+            .unwrap();
+        assert_eq!(response.server, live.addr());
+        let sent: Vec<String> = response
+            .decisions()
+            .iter()
+            .filter_map(|decision| match decision {
+                Decision::Sent { server, .. } => Some(server.clone()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(sent, [dead.addr(), live.addr()]);
+        assert!(matches!(response.decisions()[1], Decision::Failed { .. }));
+        assert!(!response
+            .decisions()
+            .iter()
+            .any(|decision| matches!(decision, Decision::Retry { .. })));
+        assert_eq!((dead.queries().len(), live.queries().len()), (1, 1));
+    }
+
+    #[tokio::test]
+    async fn retries_every_server_after_a_backoff() {
+        let first = Responder::answering(|_| Vec::new()).await;
+        let second = Responder::answering(|_| Vec::new()).await;
+        let started = Instant::now();
+        let result = failover(&first, &second)
+            .await
+            .with_attempts(2)
+            .exchange("example.com", QueryType::A)
+            .await;
+        let Err(ClientError::AllFailed(failures)) = result else {
+            panic!("{:?}", result.map(|response| response.message));
+        };
+        let servers: Vec<&str> = failures.iter().map(|f| f.server.as_str()).collect();
+        let (first, second) = (first.addr(), second.addr());
+        assert_eq!(servers, [&first, &second, &first, &second]);
+        // four timeouts and a backoff of at least BACKOFF
+        assert!(started.elapsed() >= Duration::from_millis(400) + BACKOFF);
+    }
+
+    #[tokio::test]
+    async fn query_names_go_out_in_random_case() {
+        let responder = Responder::answering(|query| vec![testing::address(query)]).await;
+        let host = "abcdefghijklmnopqrstuvwxyz.example";
+        let client = Client::new(responder.addr())
+            .await
+            .unwrap()
+            .with_randomness(Randomness::seeded(1));
+        let response = client.exchange(host, QueryType::A).await.unwrap();
+        let sent = responder.queries()[0].questions[0].name.clone();
+        assert_ne!(sent.trim_end_matches('.'), host);
+        assert!(sent.trim_end_matches('.').eq_ignore_ascii_case(host));
+        // answered in the case of the question as asked
+        assert_eq!(
+            response.message.questions[0].name.trim_end_matches('.'),
+            host
+        );
+        client
+            .without_0x20()
+            .exchange("example.org", QueryType::A)
+            .await
+            .unwrap();
+        assert_eq!(
+            responder.queries()[1].questions[0]
+                .name
+                .trim_end_matches('.'),
+            "example.org"
+        );
+    }
+
+    #[tokio::test]
+    async fn responses_in_another_case_are_rejected() {
+        let responder = Responder::answering(|query| {
+            let mut query = query.clone();
+            query.questions[0].name = query.questions[0].name.to_lowercase();
+            vec![testing::address(&query)]
+        })
+        .await;
+        let result = Client::new(responder.addr())
+            .await
+            .unwrap()
+            .with_randomness(Randomness::seeded(1))
+            .exchange("abcdefghijklmnopqrstuvwxyz.example", QueryType::A)
+            .await;
+        assert!(matches!(result, Err(ClientError::CaseMismatch(..))));
     }
 }
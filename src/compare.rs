@@ -0,0 +1,125 @@
+use crate::client::{self, ClassType, ClientError, Response};
+use crate::rdata::{self, RData};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// A way the responses of several servers to the same query disagree
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "difference", rename_all = "snake_case")]
+pub enum Difference {
+    /// Response codes or failures differ, e.g. NOERROR against NXDOMAIN
+    Status { statuses: Vec<(String, String)> },
+    /// A record only some servers answered with
+    Missing {
+        record: String,
+        missing_from: Vec<String>,
+    },
+    /// A record all servers answered with, but with different TTLs
+    Ttl {
+        record: String,
+        ttls: Vec<(String, u32)>,
+    },
+}
+
+/// `value from server` pairs, comma separated
+fn per_server<T: fmt::Display>(values: &[(String, T)]) -> String {
+    values
+        .iter()
+        .map(|(server, value)| std::format!("{} from {}", value, server))
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+impl fmt::Display for Difference {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Difference::Status { statuses } => {
+                write!(f, "status differs: {}", per_server(statuses))
+            }
+            Difference::Missing {
+                record,
+                missing_from,
+            } => write!(f, "{} missing from {}", record, missing_from.join(", ")),
+            Difference::Ttl { record, ttls } => {
+                write!(f, "TTL of {} differs: {}", record, per_server(ttls))
+            }
+        }
+    }
+}
+
+/// Response code of a response, or the error the query failed with
+fn status(result: &Result<Response, ClientError>) -> String {
+    match result {
+        Ok(response) => client::rcode_name(response.message.header.rcode()),
+        Err(err) => std::format!("{:?}", err),
+    }
+}
+
+/// Answer records of a response in zone file format without their TTL, which
+/// is kept apart, keyed by their text so servers can be compared
+fn records(response: &Response) -> BTreeMap<String, u32> {
+    let mut records = BTreeMap::new();
+    for record in &response.message.answers {
+        let data = rdata::decode(&record.query_type, &record.rdata)
+            .unwrap_or_else(|| RData::Unknown(record.rdata.clone()));
+        let class = match ClassType::from_code(record.class) {
+            Ok(class) => class.to_string(),
+            Err(_) => std::format!("CLASS{}", record.class),
+        };
+        let key = std::format!(
+            "{} {} {} {}",
+            record.name.to_lowercase(),
+            class,
+            record.query_type,
+            data
+        );
+        records.insert(key, record.ttl);
+    }
+    records
+}
+
+/// Differences between the results of the same query sent to each server.
+/// Records are only compared between servers that responded.
+pub fn differences(results: &[(String, Result<Response, ClientError>)]) -> Vec<Difference> {
+    let mut differences = Vec::new();
+    let statuses: Vec<(String, String)> = results
+        .iter()
+        .map(|(server, result)| (server.clone(), status(result)))
+        .collect();
+    if statuses.iter().any(|(_, status)| *status != statuses[0].1) {
+        differences.push(Difference::Status { statuses });
+    }
+    let answered: Vec<(&String, BTreeMap<String, u32>)> = results
+        .iter()
+        .filter_map(|(server, result)| Some((server, records(result.as_ref().ok()?))))
+        .collect();
+    let mut all: Vec<&String> = answered.iter().flat_map(|(_, r)| r.keys()).collect();
+    all.sort();
+    all.dedup();
+    for record in all {
+        let missing_from: Vec<String> = answered
+            .iter()
+            .filter(|(_, records)| !records.contains_key(record))
+            .map(|(server, _)| server.to_string())
+            .collect();
+        if !missing_from.is_empty() {
+            differences.push(Difference::Missing {
+                record: record.clone(),
+                missing_from,
+            });
+            continue;
+        }
+        let ttls: Vec<(String, u32)> = answered
+            .iter()
+            .map(|(server, records)| (server.to_string(), records[record]))
+            .collect();
+        if ttls.iter().any(|(_, ttl)| *ttl != ttls[0].1) {
+            differences.push(Difference::Ttl {
+                record: record.clone(),
+                ttls,
+            });
+        }
+    }
+    differences
+}
@@ -0,0 +1,177 @@
+//! Defaults of the command line from a TOML file, `~/.config/nsq/config.toml`
+//! unless another is given. Each key is the long name of a flag, like
+//! `server = ["192.0.2.1", "192.0.2.2"]`, `type = "MX"`, `output = "json"`,
+//! `timeout = 5` or `no-search = true`, and becomes its default: a flag or
+//! its NSQ_ variable still wins. A table sets the flags of a command, like
+//! `cache-max-mem` under `[serve]`.
+use clap::{ArgAction, Command};
+use std::path::PathBuf;
+use toml::Value;
+
+/// The config file of the user, under $XDG_CONFIG_HOME or ~/.config
+pub fn default_path() -> Option<PathBuf> {
+    let config_home = match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(std::env::var_os("HOME")?).join(".config"),
+    };
+    Some(config_home.join("nsq").join("config.toml"))
+}
+
+/// Values of the flags a config file sets, by their long names
+type Values = Vec<(String, Vec<String>)>;
+
+/// Values of the flags the config file sets
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Config {
+    path: String,
+    values: Values,
+    /// Values of the flags of each command with a table
+    commands: Vec<(String, Values)>,
+}
+
+impl Config {
+    /// The config file at `path`
+    pub fn read(path: &str) -> Result<Config, String> {
+        match std::fs::read_to_string(path) {
+            Ok(text) => Config::parse(path, &text),
+            Err(err) => Err(std::format!("{}: {}", path, err)),
+        }
+    }
+
+    /// The config of `text`, read from `path`
+    pub fn parse(path: &str, text: &str) -> Result<Config, String> {
+        let invalid = |message: String| std::format!("{}: {}", path, message);
+        let table: toml::Table = text
+            .parse()
+            .map_err(|err: toml::de::Error| invalid(err.message().to_string()))?;
+        let mut values = Vec::with_capacity(table.len());
+        let mut commands = Vec::new();
+        for (key, value) in table {
+            match value {
+                Value::Table(table) => {
+                    let mut command = Vec::with_capacity(table.len());
+                    for (flag, value) in table {
+                        let name = std::format!("{}.{}", key, flag);
+                        command.push((flag, strings(&name, &value).map_err(invalid)?));
+                    }
+                    commands.push((key, command));
+                }
+                value => {
+                    let strings = strings(&key, &value).map_err(invalid)?;
+                    values.push((key, strings));
+                }
+            }
+        }
+        Ok(Config {
+            path: path.to_string(),
+            values,
+            commands,
+        })
+    }
+
+    /// `command` with the values of the config as the defaults of its flags,
+    /// lists only for flags that can be repeated
+    pub fn apply(&self, command: Command) -> Result<Command, String> {
+        let mut command = self.defaults(command, &self.values, "")?;
+        for (name, values) in &self.commands {
+            let Some(subcommand) = command.find_subcommand(name) else {
+                return Err(std::format!(
+                    "{}: {} isn't a command of nsq",
+                    self.path,
+                    name
+                ));
+            };
+            let prefix = std::format!("{}.", name);
+            let subcommand = self.defaults(subcommand.clone(), values, &prefix)?;
+            command = command.mut_subcommand(name, |_| subcommand);
+        }
+        Ok(command)
+    }
+
+    /// `command` with `values` as the defaults of its flags, their keys
+    /// under `prefix` in errors
+    fn defaults(
+        &self,
+        mut command: Command,
+        values: &Values,
+        prefix: &str,
+    ) -> Result<Command, String> {
+        for (key, values) in values {
+            let invalid =
+                |message: &str| std::format!("{}: {}{} {}", self.path, prefix, key, message);
+            let Some(arg) = command
+                .get_arguments()
+                .find(|arg| arg.get_long() == Some(key.as_str()))
+            else {
+                return Err(invalid("isn't a flag of nsq"));
+            };
+            if values.len() != 1 && !matches!(arg.get_action(), ArgAction::Append) {
+                return Err(invalid("takes a single value"));
+            }
+            let id = arg.get_id().clone();
+            let values = values.clone();
+            command = command.mut_arg(id, |arg| arg.default_values(values));
+        }
+        Ok(command)
+    }
+}
+
+/// The value of `key` as the strings of a flag, a list for a repeated one
+fn strings(key: &str, value: &Value) -> Result<Vec<String>, String> {
+    match value {
+        Value::Array(items) => items
+            .iter()
+            .map(|item| {
+                scalar(item).ok_or_else(|| std::format!("{} has a list or table in its list", key))
+            })
+            .collect(),
+        value => {
+            Ok(vec![scalar(value).ok_or_else(|| {
+                std::format!("{} isn't a value or a list", key)
+            })?])
+        }
+    }
+}
+
+/// A string, number or boolean as given on the command line
+fn scalar(value: &Value) -> Option<String> {
+    match value {
+        Value::String(string) => Some(string.clone()),
+        Value::Integer(integer) => Some(integer.to_string()),
+        Value::Float(float) => Some(float.to_string()),
+        Value::Boolean(boolean) => Some(boolean.to_string()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Arg;
+
+    fn command() -> Command {
+        Command::new("nsq")
+            .arg(Arg::new("timeout").long("timeout"))
+            .subcommand(Command::new("serve").arg(Arg::new("timeout").long("timeout")))
+    }
+
+    #[test]
+    fn tables_set_the_flags_of_their_command() {
+        let config = Config::parse("nsq.toml", "timeout = 1\n[serve]\ntimeout = 2\n").unwrap();
+        let matches = config
+            .apply(command())
+            .unwrap()
+            .try_get_matches_from(["nsq", "serve"])
+            .unwrap();
+        assert_eq!(matches.get_one::<String>("timeout").unwrap(), "1");
+        let (_, serve) = matches.subcommand().unwrap();
+        assert_eq!(serve.get_one::<String>("timeout").unwrap(), "2");
+
+        let config = Config::parse("nsq.toml", "[watch]\ntimeout = 2\n").unwrap();
+        let err = config.apply(command()).unwrap_err();
+        assert_eq!(err, "nsq.toml: watch isn't a command of nsq");
+        let config = Config::parse("nsq.toml", "[serve]\nlisten = 2\n").unwrap();
+        let err = config.apply(command()).unwrap_err();
+        assert_eq!(err, "nsq.toml: serve.listen isn't a flag of nsq");
+    }
+}
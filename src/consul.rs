@@ -0,0 +1,30 @@
+//! Consul DNS naming: `[<tag>.]<service>.service[.<datacenter>].<domain>` for
+//! services, with SRV records naming the nodes that run an instance as
+//! `<node>.node.<datacenter>.<domain>`
+
+/// Domain Consul serves when `-domain` isn't set
+pub const DEFAULT_DOMAIN: &str = "consul";
+
+/// Address of the DNS interface of a local Consul agent
+pub const AGENT: &str = "127.0.0.1:8600";
+
+/// Name of a service given as `<service>`, `<service>.service.<datacenter>`
+/// or in full, under `domain`
+pub fn service_name(name: &str, domain: &str) -> String {
+    let name = name.trim_end_matches('.');
+    if name.ends_with(&std::format!(".{}", domain)) {
+        return name.to_string();
+    }
+    match name.split('.').any(|label| label == "service") {
+        true => std::format!("{}.{}", name, domain),
+        false => std::format!("{}.service.{}", name, domain),
+    }
+}
+
+/// Node of an SRV target, without the `.node.<datacenter>.<domain>` suffix
+pub fn node_name(target: &str) -> &str {
+    match target.split_once(".node.") {
+        Some((node, _)) => node,
+        None => target,
+    }
+}
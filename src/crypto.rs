@@ -0,0 +1,430 @@
+//! Crypto of DNSSEC validation, the digests of DS records and the signatures
+//! of RRSIG records, and of the HMACs of TSIG, behind a trait built with ring
+//! or with the system's OpenSSL, as the crypto policy of a distribution may
+//! require. Without either, DNSSEC validation and TSIG fail.
+
+/// Hash functions of DS digests, TSIG and the owner names of email keys
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Hash {
+    Sha1,
+    Sha256,
+    Sha384,
+    Sha512,
+}
+
+/// Digests, HMACs and signature checks
+pub trait DnssecCrypto: Send + Sync {
+    /// Hash of `data`
+    fn hash(&self, hash: Hash, data: &[u8]) -> Result<Vec<u8>, String>;
+
+    /// HMAC of `data` with `key`, RFC 2104
+    fn hmac(&self, hash: Hash, key: &[u8], data: &[u8]) -> Result<Vec<u8>, String>;
+
+    /// Whether `mac` is the HMAC of `data` with `key`, compared in constant
+    /// time
+    fn verify_hmac(&self, hash: Hash, key: &[u8], data: &[u8], mac: &[u8]) -> bool {
+        match self.hmac(hash, key, data) {
+            Ok(expected) => {
+                expected.len() == mac.len()
+                    && expected
+                        .iter()
+                        .zip(mac)
+                        .fold(0, |diff, (a, b)| diff | (a ^ b))
+                        == 0
+            }
+            Err(_) => false,
+        }
+    }
+
+    /// Digest of `data` with DS digest type `digest_type`, none for the
+    /// types it doesn't implement, RFC 4034 5.1.3
+    fn digest(&self, digest_type: u8, data: &[u8]) -> Option<Vec<u8>> {
+        let hash = match digest_type {
+            1 => Hash::Sha1,
+            2 => Hash::Sha256,
+            4 => Hash::Sha384,
+            _ => return None,
+        };
+        self.hash(hash, data).ok()
+    }
+
+    /// Checks `signature` of `message` with the DNSKEY public key
+    /// `public_key` of `algorithm`, RFC 4034 A.1
+    fn verify(
+        &self,
+        algorithm: u8,
+        public_key: &[u8],
+        message: &[u8],
+        signature: &[u8],
+    ) -> Result<(), String>;
+}
+
+/// The backend nsq was built with, OpenSSL when both are
+pub fn backend() -> &'static dyn DnssecCrypto {
+    #[cfg(feature = "dnssec-openssl")]
+    return &Openssl;
+    #[cfg(all(feature = "dnssec-ring", not(feature = "dnssec-openssl")))]
+    return &Ring;
+    #[cfg(not(any(feature = "dnssec-ring", feature = "dnssec-openssl")))]
+    return &Unavailable;
+}
+
+/// The backend of builds without a crypto feature, failing every operation
+#[cfg(not(any(feature = "dnssec-ring", feature = "dnssec-openssl")))]
+struct Unavailable;
+
+#[cfg(not(any(feature = "dnssec-ring", feature = "dnssec-openssl")))]
+impl Unavailable {
+    fn error() -> String {
+        "nsq was built without the dnssec-ring or the dnssec-openssl feature".to_string()
+    }
+}
+
+#[cfg(not(any(feature = "dnssec-ring", feature = "dnssec-openssl")))]
+impl DnssecCrypto for Unavailable {
+    fn hash(&self, _: Hash, _: &[u8]) -> Result<Vec<u8>, String> {
+        Err(Unavailable::error())
+    }
+
+    fn hmac(&self, _: Hash, _: &[u8], _: &[u8]) -> Result<Vec<u8>, String> {
+        Err(Unavailable::error())
+    }
+
+    fn verify(&self, _: u8, _: &[u8], _: &[u8], _: &[u8]) -> Result<(), String> {
+        Err(Unavailable::error())
+    }
+}
+
+/// Exponent and modulus of an RSA public key, RFC 3110 2
+#[cfg(any(feature = "dnssec-ring", feature = "dnssec-openssl"))]
+fn rsa_components(public_key: &[u8]) -> Result<(&[u8], &[u8]), String> {
+    let (e_len, start) = match public_key {
+        [0, hi, lo, ..] => (u16::from_be_bytes([*hi, *lo]) as usize, 3),
+        [len, ..] => (*len as usize, 1),
+        [] => return Err("empty RSA public key".to_string()),
+    };
+    if public_key.len() <= start + e_len {
+        return Err("truncated RSA public key".to_string());
+    }
+    Ok((
+        &public_key[start..start + e_len],
+        &public_key[start + e_len..],
+    ))
+}
+
+#[cfg(any(feature = "dnssec-ring", feature = "dnssec-openssl"))]
+fn invalid(algorithm: u8) -> String {
+    std::format!("invalid signature with algorithm {}", algorithm)
+}
+
+#[cfg(any(feature = "dnssec-ring", feature = "dnssec-openssl"))]
+fn unsupported(algorithm: u8) -> String {
+    std::format!("unsupported algorithm {}", algorithm)
+}
+
+/// DNSSEC crypto of ring
+#[cfg(feature = "dnssec-ring")]
+pub struct Ring;
+
+#[cfg(feature = "dnssec-ring")]
+impl DnssecCrypto for Ring {
+    fn hash(&self, hash: Hash, data: &[u8]) -> Result<Vec<u8>, String> {
+        use ring::digest;
+
+        let algorithm = match hash {
+            Hash::Sha1 => &digest::SHA1_FOR_LEGACY_USE_ONLY,
+            Hash::Sha256 => &digest::SHA256,
+            Hash::Sha384 => &digest::SHA384,
+            Hash::Sha512 => &digest::SHA512,
+        };
+        Ok(digest::digest(algorithm, data).as_ref().to_vec())
+    }
+
+    fn hmac(&self, hash: Hash, key: &[u8], data: &[u8]) -> Result<Vec<u8>, String> {
+        use ring::hmac;
+
+        let algorithm = match hash {
+            Hash::Sha1 => hmac::HMAC_SHA1_FOR_LEGACY_USE_ONLY,
+            Hash::Sha256 => hmac::HMAC_SHA256,
+            Hash::Sha384 => hmac::HMAC_SHA384,
+            Hash::Sha512 => hmac::HMAC_SHA512,
+        };
+        let key = hmac::Key::new(algorithm, key);
+        Ok(hmac::sign(&key, data).as_ref().to_vec())
+    }
+
+    fn verify(
+        &self,
+        algorithm: u8,
+        public_key: &[u8],
+        message: &[u8],
+        sig: &[u8],
+    ) -> Result<(), String> {
+        use ring::signature::{self, RsaPublicKeyComponents, UnparsedPublicKey};
+
+        match algorithm {
+            5 | 7 | 8 | 10 => {
+                let (e, n) = rsa_components(public_key)?;
+                let params = match algorithm {
+                    8 => &signature::RSA_PKCS1_1024_8192_SHA256_FOR_LEGACY_USE_ONLY,
+                    10 => &signature::RSA_PKCS1_1024_8192_SHA512_FOR_LEGACY_USE_ONLY,
+                    _ => &signature::RSA_PKCS1_1024_8192_SHA1_FOR_LEGACY_USE_ONLY,
+                };
+                RsaPublicKeyComponents { n, e }
+                    .verify(params, message, sig)
+                    .map_err(|_| invalid(algorithm))
+            }
+            13 | 14 => {
+                // ring expects the uncompressed SEC1 point, RFC 6605 4
+                let mut point = vec![4u8];
+                point.extend(public_key);
+                let params = if algorithm == 13 {
+                    &signature::ECDSA_P256_SHA256_FIXED
+                } else {
+                    &signature::ECDSA_P384_SHA384_FIXED
+                };
+                UnparsedPublicKey::new(params, point)
+                    .verify(message, sig)
+                    .map_err(|_| invalid(algorithm))
+            }
+            15 => UnparsedPublicKey::new(&signature::ED25519, public_key)
+                .verify(message, sig)
+                .map_err(|_| invalid(algorithm)),
+            _ => Err(unsupported(algorithm)),
+        }
+    }
+}
+
+/// DNSSEC crypto of the system's OpenSSL
+#[cfg(feature = "dnssec-openssl")]
+pub struct Openssl;
+
+#[cfg(feature = "dnssec-openssl")]
+impl Openssl {
+    fn message_digest(hash: Hash) -> openssl::hash::MessageDigest {
+        use openssl::hash::MessageDigest;
+
+        match hash {
+            Hash::Sha1 => MessageDigest::sha1(),
+            Hash::Sha256 => MessageDigest::sha256(),
+            Hash::Sha384 => MessageDigest::sha384(),
+            Hash::Sha512 => MessageDigest::sha512(),
+        }
+    }
+
+    fn hmac(hash: Hash, key: &[u8], data: &[u8]) -> Result<Vec<u8>, openssl::error::ErrorStack> {
+        use openssl::pkey::PKey;
+        use openssl::sign::Signer;
+
+        let key = PKey::hmac(key)?;
+        let mut signer = Signer::new(Openssl::message_digest(hash), &key)?;
+        signer.sign_oneshot_to_vec(data)
+    }
+
+    fn rsa(
+        algorithm: u8,
+        public_key: &[u8],
+        message: &[u8],
+        sig: &[u8],
+    ) -> Result<bool, openssl::error::ErrorStack> {
+        use openssl::bn::BigNum;
+        use openssl::hash::MessageDigest;
+        use openssl::pkey::PKey;
+        use openssl::rsa::Rsa;
+        use openssl::sign::Verifier;
+
+        let Ok((e, n)) = rsa_components(public_key) else {
+            return Ok(false);
+        };
+        let key = Rsa::from_public_components(BigNum::from_slice(n)?, BigNum::from_slice(e)?)?;
+        let digest = match algorithm {
+            8 => MessageDigest::sha256(),
+            10 => MessageDigest::sha512(),
+            _ => MessageDigest::sha1(),
+        };
+        let key = PKey::from_rsa(key)?;
+        let mut verifier = Verifier::new(digest, &key)?;
+        verifier.update(message)?;
+        verifier.verify(sig)
+    }
+
+    fn ecdsa(
+        algorithm: u8,
+        public_key: &[u8],
+        message: &[u8],
+        sig: &[u8],
+    ) -> Result<bool, openssl::error::ErrorStack> {
+        use openssl::bn::{BigNum, BigNumContext};
+        use openssl::ec::{EcGroup, EcKey, EcPoint};
+        use openssl::ecdsa::EcdsaSig;
+        use openssl::hash::{hash, MessageDigest};
+        use openssl::nid::Nid;
+
+        let (curve, digest) = match algorithm {
+            13 => (Nid::X9_62_PRIME256V1, MessageDigest::sha256()),
+            _ => (Nid::SECP384R1, MessageDigest::sha384()),
+        };
+        // r and s side by side, RFC 6605 4
+        if !sig.len().is_multiple_of(2) {
+            return Ok(false);
+        }
+        let (r, s) = sig.split_at(sig.len() / 2);
+        let group = EcGroup::from_curve_name(curve)?;
+        let mut point = vec![4u8];
+        point.extend(public_key);
+        let mut context = BigNumContext::new()?;
+        let point = EcPoint::from_bytes(&group, &point, &mut context)?;
+        let key = EcKey::from_public_key(&group, &point)?;
+        let sig =
+            EcdsaSig::from_private_components(BigNum::from_slice(r)?, BigNum::from_slice(s)?)?;
+        sig.verify(&hash(digest, message)?, &key)
+    }
+
+    fn ed25519(
+        public_key: &[u8],
+        message: &[u8],
+        sig: &[u8],
+    ) -> Result<bool, openssl::error::ErrorStack> {
+        use openssl::pkey::{Id, PKey};
+        use openssl::sign::Verifier;
+
+        let key = PKey::public_key_from_raw_bytes(public_key, Id::ED25519)?;
+        let mut verifier = Verifier::new_without_digest(&key)?;
+        verifier.verify_oneshot(sig, message)
+    }
+}
+
+#[cfg(feature = "dnssec-openssl")]
+impl DnssecCrypto for Openssl {
+    fn hash(&self, hash: Hash, data: &[u8]) -> Result<Vec<u8>, String> {
+        openssl::hash::hash(Openssl::message_digest(hash), data)
+            .map(|digest| digest.to_vec())
+            .map_err(|err| std::format!("{:?} with OpenSSL: {}", hash, err))
+    }
+
+    fn hmac(&self, hash: Hash, key: &[u8], data: &[u8]) -> Result<Vec<u8>, String> {
+        Openssl::hmac(hash, key, data)
+            .map_err(|err| std::format!("HMAC-{:?} with OpenSSL: {}", hash, err))
+    }
+
+    fn verify(
+        &self,
+        algorithm: u8,
+        public_key: &[u8],
+        message: &[u8],
+        sig: &[u8],
+    ) -> Result<(), String> {
+        let verified = match algorithm {
+            5 | 7 | 8 | 10 => Openssl::rsa(algorithm, public_key, message, sig),
+            13 | 14 => Openssl::ecdsa(algorithm, public_key, message, sig),
+            15 => Openssl::ed25519(public_key, message, sig),
+            _ => return Err(unsupported(algorithm)),
+        };
+        match verified {
+            Ok(true) => Ok(()),
+            Ok(false) => Err(invalid(algorithm)),
+            Err(err) => {
+                log::debug!("OpenSSL with algorithm {}: {}", algorithm, err);
+                Err(invalid(algorithm))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ring::rand::SystemRandom;
+    use ring::signature::{self, EcdsaKeyPair, Ed25519KeyPair, KeyPair};
+
+    fn backends() -> Vec<&'static dyn DnssecCrypto> {
+        vec![
+            #[cfg(feature = "dnssec-ring")]
+            &Ring,
+            #[cfg(feature = "dnssec-openssl")]
+            &Openssl,
+        ]
+    }
+
+    #[test]
+    fn signatures_verify() {
+        let rng = SystemRandom::new();
+        let message = b"\x00\x01\x0d\x02example.com.";
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&rng).unwrap();
+        let ed25519 = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).unwrap();
+        let ed25519_sig = ed25519.sign(message);
+        let p256 = &signature::ECDSA_P256_SHA256_FIXED_SIGNING;
+        let pkcs8 = EcdsaKeyPair::generate_pkcs8(p256, &rng).unwrap();
+        let ecdsa = EcdsaKeyPair::from_pkcs8(p256, pkcs8.as_ref(), &rng).unwrap();
+        let ecdsa_sig = ecdsa.sign(&rng, message).unwrap();
+        for backend in backends() {
+            let public_key = ed25519.public_key().as_ref();
+            assert_eq!(
+                backend.verify(15, public_key, message, ed25519_sig.as_ref()),
+                Ok(())
+            );
+            assert!(backend
+                .verify(15, public_key, b"other", ed25519_sig.as_ref())
+                .is_err());
+            // without the 0x04 of SEC1, RFC 6605 4
+            let public_key = &ecdsa.public_key().as_ref()[1..];
+            assert_eq!(
+                backend.verify(13, public_key, message, ecdsa_sig.as_ref()),
+                Ok(())
+            );
+            assert!(backend
+                .verify(13, public_key, b"other", ecdsa_sig.as_ref())
+                .is_err());
+            assert!(backend
+                .verify(3, public_key, message, ecdsa_sig.as_ref())
+                .is_err());
+            assert_eq!(backend.digest(2, b"").map(|digest| digest.len()), Some(32));
+            assert_eq!(backend.digest(3, b""), None);
+        }
+    }
+
+    #[test]
+    fn hmacs() {
+        // RFC 4231 4.2
+        let key = [0x0b; 20];
+        let mac = hex::decode("b0344c61d8db38535ca8afceaf0bf12b881dc200c9833da726e9376c2e32cff7")
+            .unwrap();
+        for backend in backends() {
+            assert_eq!(
+                backend.hmac(Hash::Sha256, &key, b"Hi There"),
+                Ok(mac.clone())
+            );
+            assert!(backend.verify_hmac(Hash::Sha256, &key, b"Hi There", &mac));
+            assert!(!backend.verify_hmac(Hash::Sha256, &key, b"Hi there", &mac));
+            assert!(!backend.verify_hmac(Hash::Sha256, &key, b"Hi There", &mac[..16]));
+            assert_eq!(
+                backend.hash(Hash::Sha512, b"").map(|hash| hash.len()),
+                Ok(64)
+            );
+        }
+    }
+
+    #[cfg(feature = "dnssec-openssl")]
+    #[test]
+    fn rsa_signatures_verify() {
+        use openssl::hash::MessageDigest;
+        use openssl::pkey::PKey;
+        use openssl::rsa::Rsa;
+        use openssl::sign::Signer;
+
+        let rsa = Rsa::generate(2048).unwrap();
+        let e = rsa.e().to_vec();
+        let mut public_key = vec![e.len() as u8];
+        public_key.extend(e);
+        public_key.extend(rsa.n().to_vec());
+        let key = PKey::from_rsa(rsa).unwrap();
+        let message = b"\x00\x01\x08\x02example.com.";
+        let mut signer = Signer::new(MessageDigest::sha256(), &key).unwrap();
+        let sig = signer.sign_oneshot_to_vec(message).unwrap();
+        for backend in backends() {
+            assert_eq!(backend.verify(8, &public_key, message, &sig), Ok(()));
+            assert!(backend.verify(8, &public_key, b"other", &sig).is_err());
+            assert!(backend.verify(8, &public_key[..1], message, &sig).is_err());
+        }
+    }
+}
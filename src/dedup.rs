@@ -0,0 +1,109 @@
+use crate::client::{QueryAnswer, QueryType};
+use crate::rdata::RData;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+/// Lowercased without the trailing dot, so `WWW.example.com.` and
+/// `www.example.com` are the same query
+fn normalize(name: &str) -> String {
+    name.trim_end_matches('.').to_lowercase()
+}
+
+/// Unique queries of a bulk input in the order they were first given, with
+/// how many times each one was given
+pub fn unique_queries(queries: Vec<(String, QueryType)>) -> Vec<((String, QueryType), usize)> {
+    let mut unique: Vec<((String, QueryType), usize)> = Vec::new();
+    let mut seen: HashMap<(String, QueryType), usize> = HashMap::new();
+    for (host, query_type) in queries {
+        let key = (normalize(&host), query_type.clone());
+        match seen.get(&key) {
+            Some(index) => unique[*index].1 += 1,
+            None => {
+                seen.insert(key, unique.len());
+                unique.push(((host, query_type), 1));
+            }
+        }
+    }
+    unique
+}
+
+/// Hosts whose CNAME chains lead to the same target, by target. Targets only
+/// one host leads to are left out.
+pub fn shared_targets(answers: &[QueryAnswer]) -> BTreeMap<String, Vec<String>> {
+    let mut aliases: HashMap<String, String> = HashMap::new();
+    for answer in answers {
+        if let RData::CNAME(target) = answer.data() {
+            aliases.insert(normalize(answer.host()), normalize(target));
+        }
+    }
+    let mut shared: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for answer in answers {
+        let host = normalize(answer.host());
+        let mut target = match aliases.get(&host) {
+            Some(target) => target,
+            None => continue,
+        };
+        // bounded by the number of aliases, in case of a loop
+        for _ in 0..aliases.len() {
+            match aliases.get(target) {
+                Some(next) => target = next,
+                None => break,
+            }
+        }
+        let hosts = shared.entry(target.clone()).or_default();
+        if !hosts.contains(&host) {
+            hosts.push(host);
+        }
+    }
+    // hosts met along the chain of another host aren't inputs of their own
+    let targets: Vec<String> = aliases.values().cloned().collect();
+    for hosts in shared.values_mut() {
+        hosts.retain(|host| !targets.contains(host));
+    }
+    shared.retain(|_, hosts| hosts.len() > 1);
+    shared
+}
+
+/// Duplicate queries of a bulk run and hosts sharing CNAME targets, the
+/// queries a deduplicated run can save
+pub struct DedupReport {
+    pub queries: usize,
+    pub unique: usize,
+    pub resolved_unique: bool,
+    pub shared: BTreeMap<String, Vec<String>>,
+}
+
+impl DedupReport {
+    pub fn is_empty(&self) -> bool {
+        self.queries == self.unique && self.shared.is_empty()
+    }
+}
+
+impl fmt::Display for DedupReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut lines: Vec<String> = Vec::new();
+        let duplicates = self.queries - self.unique;
+        if duplicates > 0 && self.resolved_unique {
+            lines.push(std::format!(
+                "Resolved {} unique queries out of {}, saving {}",
+                self.unique,
+                self.queries,
+                duplicates
+            ));
+        } else if duplicates > 0 {
+            lines.push(std::format!(
+                "{} of {} queries are duplicates, --dedup would save them",
+                duplicates,
+                self.queries
+            ));
+        }
+        for (target, hosts) in &self.shared {
+            lines.push(std::format!(
+                "{} share the CNAME target {}",
+                hosts.join(", "),
+                target
+            ));
+        }
+        write!(f, "{}", lines.join("\n"))
+    }
+}
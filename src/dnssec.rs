@@ -0,0 +1,1076 @@
+use crate::client::{Client, ClientError, QueryType};
+use crate::crypto::{self, Hash};
+use crate::wire::Record;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+
+/// DS records of the root zone KSK-2017 and KSK-2024, as published by IANA
+const ROOT_TRUST_ANCHORS: &str = "
+. IN DS 20326 8 2 E06D44B80B8F1D39A95C0B0D7C65D08458E880409BBC683457104237C7F8EC8D
+. IN DS 38696 8 2 683D2D0ACB8C9B712A1948B27F741219298D0A450D612C483AF444A4C0FB2B16
+";
+
+/// Upper bound of zone cuts walked between a signer and its trust anchor
+const MAX_CHAIN_DEPTH: usize = 16;
+
+#[derive(Debug, Clone)]
+pub struct Ds {
+    zone: String,
+    key_tag: u16,
+    algorithm: u8,
+    digest_type: u8,
+    digest: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Validation {
+    /// Signatures validated up to a trust anchor
+    Secure,
+    /// The answer has no signatures, lying in a zone proven unsigned or
+    /// under no trust anchor
+    Insecure,
+    /// The answer is signed, but its signatures or chain of trust are broken
+    Bogus(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct DnssecStatus {
+    /// Whether the resolver itself vouched for the answer with the AD flag
+    pub authenticated_data: bool,
+    pub validation: Validation,
+}
+
+struct Rrsig {
+    type_covered: u16,
+    algorithm: u8,
+    labels: u8,
+    original_ttl: u32,
+    expiration: u32,
+    inception: u32,
+    key_tag: u16,
+    signer: String,
+    /// RDATA up to the signer name, the first part of the signed data
+    header: Vec<u8>,
+    signature: Vec<u8>,
+}
+
+impl DnssecStatus {
+    pub fn failure(&self) -> Option<&str> {
+        match &self.validation {
+            Validation::Bogus(reason) => Some(reason),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for DnssecStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.validation {
+            Validation::Secure => write!(f, "secure")?,
+            Validation::Insecure => write!(f, "insecure")?,
+            Validation::Bogus(reason) => write!(f, "bogus: {}", reason)?,
+        }
+        if self.authenticated_data {
+            write!(f, ", ad")?;
+        }
+        Ok(())
+    }
+}
+
+impl Ds {
+    fn from_rdata(zone: &str, rdata: &[u8]) -> Option<Ds> {
+        if rdata.len() < 5 {
+            return None;
+        }
+        Some(Ds {
+            zone: zone.to_string(),
+            key_tag: u16::from_be_bytes([rdata[0], rdata[1]]),
+            algorithm: rdata[2],
+            digest_type: rdata[3],
+            digest: rdata[4..].to_vec(),
+        })
+    }
+
+    /// Whether `dnskey` is the key this DS refers to, RFC 4034 5.1.4
+    fn matches(&self, zone: &str, dnskey: &[u8]) -> bool {
+        if self.zone != zone || dnskey.len() < 4 {
+            return false;
+        }
+        if key_tag(dnskey) != self.key_tag || dnskey[3] != self.algorithm {
+            return false;
+        }
+        let mut data = name_to_wire(zone);
+        data.extend(dnskey);
+        crypto::backend().digest(self.digest_type, &data).as_deref() == Some(self.digest.as_slice())
+    }
+}
+
+impl Rrsig {
+    fn from_rdata(rdata: &[u8]) -> Option<Rrsig> {
+        if rdata.len() < 19 {
+            return None;
+        }
+        let (signer, end) = read_name(rdata, 18)?;
+        Some(Rrsig {
+            type_covered: u16::from_be_bytes([rdata[0], rdata[1]]),
+            algorithm: rdata[2],
+            labels: rdata[3],
+            original_ttl: u32::from_be_bytes([rdata[4], rdata[5], rdata[6], rdata[7]]),
+            expiration: u32::from_be_bytes([rdata[8], rdata[9], rdata[10], rdata[11]]),
+            inception: u32::from_be_bytes([rdata[12], rdata[13], rdata[14], rdata[15]]),
+            key_tag: u16::from_be_bytes([rdata[16], rdata[17]]),
+            signer,
+            header: rdata[..18].to_vec(),
+            signature: rdata[end..].to_vec(),
+        })
+    }
+
+    /// Validity period check using serial number arithmetic, RFC 4034 3.1.5
+    fn is_valid_at(&self, now: u32) -> bool {
+        now.wrapping_sub(self.inception) as i32 >= 0
+            && self.expiration.wrapping_sub(now) as i32 >= 0
+    }
+
+    /// Signed data of RFC 4034 3.1.8.1: the RRSIG RDATA without signature,
+    /// followed by the RRset in canonical form and order
+    fn signed_data(&self, rrset: &[&Record]) -> Vec<u8> {
+        let mut data = self.header.clone();
+        data.extend(name_to_wire(&self.signer));
+        let (owner, class) = match rrset.first() {
+            Some(record) => (
+                name_to_wire(&wildcard_owner(&record.name, self.labels)),
+                record.class,
+            ),
+            None => return data,
+        };
+        let mut rdatas: Vec<Vec<u8>> = rrset
+            .iter()
+            .map(|r| canonical_rdata(self.type_covered, &r.rdata))
+            .collect();
+        rdatas.sort();
+        rdatas.dedup();
+        for rdata in rdatas {
+            data.extend(&owner);
+            data.extend(self.type_covered.to_be_bytes());
+            data.extend(class.to_be_bytes());
+            data.extend(self.original_ttl.to_be_bytes());
+            data.extend((rdata.len() as u16).to_be_bytes());
+            data.extend(rdata);
+        }
+        data
+    }
+
+    /// Whether the signer could have signed the records of `owner`: its zone
+    /// holds them and has no more labels than the signature says, RFC 4035
+    /// 5.3.1
+    fn covers(&self, owner: &str) -> Result<(), String> {
+        let owner = fqdn(owner);
+        if !in_zone(&owner, &self.signer) {
+            return Err(std::format!(
+                "RRSIG of {} made by {}, a zone it isn't in",
+                owner,
+                self.signer
+            ));
+        }
+        if label_count(&self.signer) > self.labels as usize
+            || self.labels as usize > label_count(&owner)
+        {
+            return Err(std::format!(
+                "RRSIG of {} by {} has a label count of {}",
+                owner,
+                self.signer,
+                self.labels
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Key tag of a DNSKEY RDATA, RFC 4034 appendix B
+fn key_tag(dnskey: &[u8]) -> u16 {
+    let mut ac: u32 = 0;
+    for (i, byte) in dnskey.iter().enumerate() {
+        ac += if i & 1 == 1 {
+            *byte as u32
+        } else {
+            (*byte as u32) << 8
+        };
+    }
+    ac += (ac >> 16) & 0xffff;
+    (ac & 0xffff) as u16
+}
+
+/// Lowercases the name at `start` of `rdata`, returning the offset past it
+fn lowercase_name(rdata: &mut [u8], start: usize) -> Option<usize> {
+    let mut i = start;
+    loop {
+        let len = *rdata.get(i)? as usize;
+        i += 1;
+        if len == 0 {
+            return Some(i);
+        }
+        if len & 0xc0 != 0 {
+            return None;
+        }
+        rdata.get_mut(i..i + len)?.make_ascii_lowercase();
+        i += len;
+    }
+}
+
+/// RDATA of type `code` with the domain names it embeds lowercased, RFC 4034
+/// 6.2 as amended by RFC 6840 5.1
+fn canonical_rdata(code: u16, rdata: &[u8]) -> Vec<u8> {
+    let mut rdata = rdata.to_vec();
+    // offset of the first name, and how many names follow each other there
+    let (start, names) = match code {
+        // NS, MD, MF, CNAME, MB, MG, MR, PTR and DNAME
+        2..=5 | 7..=9 | 12 | 39 => (0, 1),
+        // SOA, MINFO and RP
+        6 | 14 | 17 => (0, 2),
+        // MX, AFSDB, RT and KX
+        15 | 18 | 21 | 36 => (2, 1),
+        // PX
+        26 => (2, 2),
+        // SRV
+        33 => (6, 1),
+        // SIG and RRSIG, the signer name
+        24 | 46 => (18, 1),
+        // NAPTR, the replacement after the flags, services and regexp
+        35 => {
+            let mut i = 4;
+            for _ in 0..3 {
+                i += 1 + rdata.get(i).copied().unwrap_or(0) as usize;
+            }
+            (i, 1)
+        }
+        _ => return rdata,
+    };
+    let mut i = start;
+    for _ in 0..names {
+        match lowercase_name(&mut rdata, i) {
+            Some(end) => i = end,
+            None => break,
+        }
+    }
+    rdata
+}
+
+fn fqdn(name: &str) -> String {
+    let name = name.trim_end_matches('.').to_lowercase();
+    if name.is_empty() {
+        ".".to_string()
+    } else {
+        name + "."
+    }
+}
+
+/// Labels of a name, the root and a leading wildcard label not counted, as
+/// in the Labels field of RRSIG records, RFC 4034 3.1.3
+fn label_count(name: &str) -> usize {
+    let name = fqdn(name);
+    let name = name.strip_prefix("*.").unwrap_or(&name);
+    name.split('.').filter(|label| !label.is_empty()).count()
+}
+
+/// Whether `name` is `zone` or a name below it
+fn in_zone(name: &str, zone: &str) -> bool {
+    let (name, zone) = (fqdn(name), fqdn(zone));
+    zone == "." || name == zone || name.ends_with(&std::format!(".{}", zone))
+}
+
+/// Uncompressed, lowercased wire form of a name, RFC 4034 6.2
+fn name_to_wire(name: &str) -> Vec<u8> {
+    let mut wire: Vec<u8> = Vec::new();
+    for label in name.split('.').filter(|label| !label.is_empty()) {
+        wire.push(label.len() as u8);
+        wire.extend(label.to_lowercase().as_bytes());
+    }
+    wire.push(0);
+    wire
+}
+
+/// Reads an uncompressed name at `start`, returning it and the offset past it
+fn read_name(data: &[u8], start: usize) -> Option<(String, usize)> {
+    let mut labels: Vec<String> = Vec::new();
+    let mut i = start;
+    loop {
+        let len = *data.get(i)? as usize;
+        i += 1;
+        if len == 0 {
+            break;
+        }
+        if len & 0xc0 != 0 {
+            return None;
+        }
+        labels.push(String::from_utf8_lossy(data.get(i..i + len)?).to_string());
+        i += len;
+    }
+    Some((fqdn(&labels.join(".")), i))
+}
+
+/// Owner name a signature was made over, which for wildcard expansions has
+/// fewer labels than the name queried, RFC 4035 5.3.2
+fn wildcard_owner(name: &str, labels: u8) -> String {
+    let name = fqdn(name);
+    let parts: Vec<&str> = name.split('.').filter(|l| !l.is_empty()).collect();
+    if parts.len() <= labels as usize {
+        return name;
+    }
+    let closest = parts[parts.len() - labels as usize..].join(".");
+    fqdn(&std::format!("*.{}", closest))
+}
+
+/// Signatures of the `query_type` RRset of `owner`
+fn rrsigs(records: &[Record], owner: &str, query_type: &QueryType) -> Vec<Rrsig> {
+    let owner = fqdn(owner);
+    records
+        .iter()
+        .filter(|r| r.query_type == QueryType::RRSIG && fqdn(&r.name) == owner)
+        .filter_map(|r| Rrsig::from_rdata(&r.rdata))
+        .filter(|sig| sig.type_covered == query_type.code())
+        .collect()
+}
+
+/// The `query_type` records of `owner`
+fn rrset<'a>(records: &'a [Record], owner: &str, query_type: &QueryType) -> Vec<&'a Record> {
+    let owner = fqdn(owner);
+    records
+        .iter()
+        .filter(|r| &r.query_type == query_type && fqdn(&r.name) == owner)
+        .collect()
+}
+
+/// Records other than signatures grouped in RRsets, by owner, type and
+/// class, in the order they first appear
+fn rrsets(records: &[Record]) -> Vec<Vec<&Record>> {
+    let mut sets: Vec<Vec<&Record>> = Vec::new();
+    for record in records.iter().filter(|r| r.query_type != QueryType::RRSIG) {
+        let same = |other: &&Record| {
+            other.query_type == record.query_type
+                && other.class == record.class
+                && fqdn(&other.name) == fqdn(&record.name)
+        };
+        match sets.iter_mut().find(|set| same(&set[0])) {
+            Some(set) => set.push(record),
+            None => sets.push(vec![record]),
+        }
+    }
+    sets
+}
+
+fn verify_rrset(rrset: &[&Record], sigs: &[Rrsig], keys: &[&Record]) -> Result<(), String> {
+    let now = Utc::now().timestamp() as u32;
+    let mut reason = "no RRSIG made by a trusted DNSKEY".to_string();
+    let Some(owner) = rrset.first().map(|record| record.name.as_str()) else {
+        return Err("no records to verify".to_string());
+    };
+    for sig in sigs {
+        if let Err(err) = sig.covers(owner) {
+            reason = err;
+            continue;
+        }
+        if !sig.is_valid_at(now) {
+            reason = std::format!("RRSIG by key {} is expired or not yet valid", sig.key_tag);
+            continue;
+        }
+        for key in keys {
+            let dnskey = &key.rdata;
+            if dnskey.len() < 4 || key_tag(dnskey) != sig.key_tag || dnskey[3] != sig.algorithm {
+                continue;
+            }
+            let data = sig.signed_data(rrset);
+            match crypto::backend().verify(sig.algorithm, &dnskey[4..], &data, &sig.signature) {
+                Ok(()) => return Ok(()),
+                Err(err) => reason = err,
+            }
+        }
+    }
+    Err(reason)
+}
+
+async fn fetch(client: &Client, zone: &str, query_type: &QueryType) -> Result<Vec<Record>, String> {
+    client
+        .records(zone, query_type)
+        .await
+        .map_err(|err| std::format!("failed to fetch {:?} of {}: {:?}", query_type, zone, err))
+}
+
+/// DNSKEYs of `zone` authenticated through the DS chain from a trust anchor
+async fn trusted_keys(client: &Client, zone: &str, anchors: &[Ds]) -> Result<Vec<Record>, String> {
+    // walk up from the signer collecting each zone's DNSKEYs and its signed
+    // DS at the parent, until a zone with a trust anchor is reached
+    let mut chain: Vec<(String, Vec<Record>, Vec<Record>)> = Vec::new();
+    let mut zone = fqdn(zone);
+    loop {
+        let dnskeys = fetch(client, &zone, &QueryType::DNSKEY).await?;
+        if anchors.iter().any(|anchor| anchor.zone == zone) {
+            chain.push((zone, dnskeys, Vec::new()));
+            break;
+        }
+        if zone == "." {
+            return Err("no trust anchor configured for the root zone".to_string());
+        }
+        let ds = fetch(client, &zone, &QueryType::DS).await?;
+        let parent = match rrsigs(&ds, &zone, &QueryType::DS).first() {
+            Some(sig) => sig.signer.clone(),
+            None => return Err(std::format!("no signed DS records for {}", zone)),
+        };
+        let is_ancestor = parent == "." || zone.ends_with(&std::format!(".{}", parent));
+        if !is_ancestor || chain.len() >= MAX_CHAIN_DEPTH {
+            return Err(std::format!(
+                "DS of {} signed by unrelated zone {}",
+                zone,
+                parent
+            ));
+        }
+        chain.push((zone, dnskeys, ds));
+        zone = parent;
+    }
+
+    let mut trusted_ds: Vec<Ds> = anchors.to_vec();
+    let mut keys: Vec<Record> = Vec::new();
+    for (zone, dnskeys, ds) in chain.iter().rev() {
+        if !ds.is_empty() {
+            let ds_set = rrset(ds, zone, &QueryType::DS);
+            let parent_keys: Vec<&Record> = keys.iter().collect();
+            verify_rrset(&ds_set, &rrsigs(ds, zone, &QueryType::DS), &parent_keys)
+                .map_err(|err| std::format!("DS of {}: {}", zone, err))?;
+            trusted_ds = ds_set
+                .iter()
+                .filter_map(|r| Ds::from_rdata(zone, &r.rdata))
+                .collect();
+        }
+        let dnskey_set = rrset(dnskeys, zone, &QueryType::DNSKEY);
+        let entry_keys: Vec<&Record> = dnskey_set
+            .iter()
+            .filter(|key| trusted_ds.iter().any(|ds| ds.matches(zone, &key.rdata)))
+            .copied()
+            .collect();
+        if entry_keys.is_empty() {
+            return Err(std::format!("no DNSKEY of {} matches its DS", zone));
+        }
+        verify_rrset(
+            &dnskey_set,
+            &rrsigs(dnskeys, zone, &QueryType::DNSKEY),
+            &entry_keys,
+        )
+        .map_err(|err| std::format!("DNSKEY of {}: {}", zone, err))?;
+        keys = dnskey_set.into_iter().cloned().collect();
+    }
+    Ok(keys)
+}
+
+/// Inception and expiration of the first signature among `records`
+#[cfg(feature = "diagnostics")]
+pub(crate) fn signature_validity(records: &[Record]) -> Option<(u32, u32)> {
+    records
+        .iter()
+        .filter(|r| r.query_type == QueryType::RRSIG)
+        .find_map(|r| Rrsig::from_rdata(&r.rdata))
+        .map(|sig| (sig.inception, sig.expiration))
+}
+
+/// Types of the records denying names, RFC 4034 4 and RFC 5155 3
+const NSEC: u16 = 47;
+const NSEC3: u16 = 50;
+
+/// Whether type `code` is in the type bit maps of an NSEC or NSEC3 record,
+/// RFC 4034 4.1.2
+fn has_type(bitmaps: &[u8], code: u16) -> bool {
+    let (window, bit) = ((code >> 8) as u8, (code & 0xff) as usize);
+    let mut i = 0;
+    while i + 1 < bitmaps.len() {
+        let len = bitmaps[i + 1] as usize;
+        if bitmaps[i] == window {
+            return bitmaps
+                .get(i + 2 + bit / 8)
+                .is_some_and(|byte| bit / 8 < len && byte & (0x80 >> (bit % 8)) != 0);
+        }
+        i += 2 + len;
+    }
+    false
+}
+
+/// Bytes of the base 32 encoding with the extended hex alphabet of NSEC3
+/// owner names, RFC 4648 7
+fn unbase32hex(text: &str) -> Option<Vec<u8>> {
+    let mut bits: u64 = 0;
+    let mut count = 0;
+    let mut decoded = Vec::new();
+    for c in text.chars() {
+        bits = bits << 5 | c.to_digit(32)? as u64;
+        count += 5;
+        if count >= 8 {
+            count -= 8;
+            decoded.push((bits >> count) as u8);
+        }
+    }
+    Some(decoded)
+}
+
+/// NSEC3 hash of `name` with `salt` and `iterations` extra rounds, RFC 5155 5
+fn nsec3_hash(name: &str, salt: &[u8], iterations: u16) -> Option<Vec<u8>> {
+    let hash = |data: &[u8]| {
+        crypto::backend()
+            .hash(Hash::Sha1, &[data, salt].concat())
+            .ok()
+    };
+    let mut digest = hash(&name_to_wire(name))?;
+    for _ in 0..iterations {
+        digest = hash(&digest)?;
+    }
+    Some(digest)
+}
+
+/// What the NSEC3 record `rdata` of hash `owner` says of the DS of `name`:
+/// whether it matches the name and its type bit maps, or covers its hash and
+/// opts out of signing the delegations in between, RFC 5155 8.9
+fn nsec3_denial<'a>(owner: &[u8], rdata: &'a [u8], name: &str) -> Option<(bool, &'a [u8])> {
+    let (algorithm, flags) = (*rdata.first()?, *rdata.get(1)?);
+    let iterations = u16::from_be_bytes([*rdata.get(2)?, *rdata.get(3)?]);
+    let salt_len = *rdata.get(4)? as usize;
+    let salt = rdata.get(5..5 + salt_len)?;
+    let hash_len = *rdata.get(5 + salt_len)? as usize;
+    let next = rdata.get(6 + salt_len..6 + salt_len + hash_len)?;
+    let bitmaps = rdata.get(6 + salt_len + hash_len..)?;
+    if algorithm != 1 {
+        return None;
+    }
+    let hash = nsec3_hash(name, salt, iterations)?;
+    if hash == owner {
+        return Some((true, bitmaps));
+    }
+    let covered = match owner < next {
+        true => owner < hash.as_slice() && hash.as_slice() < next,
+        false => owner < hash.as_slice() || hash.as_slice() < next,
+    };
+    (covered && flags & 1 == 1).then_some((false, bitmaps))
+}
+
+/// Whether the NSEC and NSEC3 records of `records` authenticated by `keys`
+/// of `zone` prove that `name` is a delegation without DS, to an unsigned
+/// zone, RFC 4035 5.2 and RFC 5155 8.9
+fn unsigned_delegation(records: &[Record], name: &str, zone: &str, keys: &[&Record]) -> bool {
+    let name = fqdn(name);
+    let (ns, ds, soa) = (
+        QueryType::NS.code(),
+        QueryType::DS.code(),
+        QueryType::SOA.code(),
+    );
+    let delegation =
+        |bitmaps: &[u8]| has_type(bitmaps, ns) && !has_type(bitmaps, ds) && !has_type(bitmaps, soa);
+    rrsets(records).into_iter().any(|set| {
+        let record = set[0];
+        let proves = match &record.query_type {
+            QueryType::Unknown(NSEC) if fqdn(&record.name) == name => read_name(&record.rdata, 0)
+                .and_then(|(_, end)| record.rdata.get(end..))
+                .is_some_and(delegation),
+            QueryType::Unknown(NSEC3) => {
+                let owner = fqdn(&record.name);
+                let hash = owner.split('.').next().and_then(unbase32hex);
+                let in_zone = owner.split_once('.').map(|(_, rest)| fqdn(rest)) == Some(fqdn(zone));
+                match (hash, in_zone) {
+                    (Some(hash), true) => match nsec3_denial(&hash, &record.rdata, &name) {
+                        Some((true, bitmaps)) => delegation(bitmaps),
+                        Some((false, _)) => true,
+                        None => false,
+                    },
+                    _ => false,
+                }
+            }
+            _ => false,
+        };
+        let sigs = rrsigs(records, &record.name, &record.query_type);
+        proves
+            && sigs.iter().all(|sig| fqdn(&sig.signer) == fqdn(zone))
+            && verify_rrset(&set, &sigs, keys).is_ok()
+    })
+}
+
+/// Validates an RRset that came without signatures, insecure only when it
+/// lies under a delegation to an unsigned zone. Walking down from the closest
+/// trust anchor to `owner`, each name either has a DS, whose zone's keys
+/// are then trusted, or signed NSEC or NSEC3 records deny it, the walk ending
+/// at a delegation they prove unsigned. RFC 4035 5.2
+async fn unsigned(client: &Client, owner: &str, anchors: &[Ds]) -> Validation {
+    let owner = fqdn(owner);
+    let anchor = anchors
+        .iter()
+        .map(|anchor| anchor.zone.clone())
+        .filter(|zone| in_zone(&owner, zone))
+        .max_by_key(|zone| label_count(zone));
+    // no trust anchor vouches for the name
+    let Some(mut zone) = anchor else {
+        return Validation::Insecure;
+    };
+    let mut keys = match trusted_keys(client, &zone, anchors).await {
+        Ok(keys) => keys,
+        Err(reason) => return Validation::Bogus(reason),
+    };
+    let labels: Vec<&str> = owner.split('.').filter(|l| !l.is_empty()).collect();
+    for depth in label_count(&zone) + 1..=labels.len() {
+        let name = fqdn(&labels[labels.len() - depth..].join("."));
+        let response = match client.exchange(&name, QueryType::DS).await {
+            Ok(response) => response.message,
+            Err(err) => {
+                return Validation::Bogus(std::format!("failed to fetch DS of {}: {:?}", name, err))
+            }
+        };
+        if !rrset(&response.answers, &name, &QueryType::DS).is_empty() {
+            keys = match trusted_keys(client, &name, anchors).await {
+                Ok(keys) => keys,
+                Err(reason) => return Validation::Bogus(reason),
+            };
+            zone = name;
+            continue;
+        }
+        let trusted: Vec<&Record> = keys.iter().collect();
+        if unsigned_delegation(&response.authorities, &name, &zone, &trusted) {
+            return Validation::Insecure;
+        }
+    }
+    Validation::Bogus(std::format!(
+        "{} has no RRSIG, but {} is signed",
+        owner,
+        zone
+    ))
+}
+
+/// Validates each RRset of an answer against its own signatures,
+/// authenticating their signer's keys up to one of the trust anchors. The
+/// answer is secure when every RRset is, and insecure when one lies in a
+/// zone proven unsigned. Negative answers have no RRset to validate.
+pub(crate) async fn validate(client: &Client, records: &[Record], anchors: &[Ds]) -> Validation {
+    let mut keys: HashMap<String, Result<Vec<Record>, String>> = HashMap::new();
+    let mut validation = Validation::Secure;
+    for set in rrsets(records) {
+        let (owner, query_type) = (&set[0].name, &set[0].query_type);
+        let sigs = rrsigs(records, owner, query_type);
+        let Some(sig) = sigs.iter().find(|sig| sig.covers(owner).is_ok()) else {
+            match sigs.first().map(|sig| sig.covers(owner)) {
+                Some(Err(reason)) => return Validation::Bogus(reason),
+                _ => match unsigned(client, owner, anchors).await {
+                    Validation::Insecure => validation = Validation::Insecure,
+                    bogus => return bogus,
+                },
+            }
+            continue;
+        };
+        if !keys.contains_key(&sig.signer) {
+            let trusted = trusted_keys(client, &sig.signer, anchors).await;
+            keys.insert(sig.signer.clone(), trusted);
+        }
+        let signer_keys = match &keys[&sig.signer] {
+            Ok(keys) => keys.iter().collect::<Vec<&Record>>(),
+            Err(reason) => return Validation::Bogus(reason.clone()),
+        };
+        if let Err(reason) = verify_rrset(&set, &sigs, &signer_keys) {
+            return Validation::Bogus(std::format!("{} {}: {}", owner, query_type, reason));
+        }
+    }
+    if rrsets(records).is_empty() {
+        return Validation::Insecure;
+    }
+    validation
+}
+
+/// Parses DS records in zone file presentation format, one per line, e.g.
+/// `. IN DS 20326 8 2 E06D44B8...`
+pub fn parse_trust_anchors(text: &str) -> Result<Vec<Ds>, ClientError> {
+    let mut anchors: Vec<Ds> = Vec::new();
+    for line in text.lines() {
+        let line = line.split(';').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        let invalid = || ClientError::ParseError(std::format!("Invalid trust anchor: {}", line));
+        let pos = match tokens.iter().position(|t| t.eq_ignore_ascii_case("DS")) {
+            Some(pos) if pos > 0 && tokens.len() >= pos + 5 => pos,
+            _ => return Err(invalid()),
+        };
+        let digest = match hex::decode(tokens[pos + 4..].concat()) {
+            Ok(digest) => digest,
+            Err(_) => return Err(invalid()),
+        };
+        anchors.push(Ds {
+            zone: fqdn(tokens[0]),
+            key_tag: tokens[pos + 1].parse().map_err(|_| invalid())?,
+            algorithm: tokens[pos + 2].parse().map_err(|_| invalid())?,
+            digest_type: tokens[pos + 3].parse().map_err(|_| invalid())?,
+            digest,
+        });
+    }
+    Ok(anchors)
+}
+
+pub fn read_trust_anchors(filename: &str) -> Result<Vec<Ds>, ClientError> {
+    match fs::read_to_string(filename) {
+        Ok(text) => parse_trust_anchors(&text),
+        Err(err) => Err(ClientError::ParseError(std::format!(
+            "{}: {}", filename, err
+        ))),
+    }
+}
+
+pub fn root_trust_anchors() -> Vec<Ds> {
+    parse_trust_anchors(ROOT_TRUST_ANCHORS).unwrap_or_default()
+}
+
+fn format_time(timestamp: u32) -> String {
+    match DateTime::from_timestamp(timestamp as i64, 0) {
+        Some(time) => time.format("%Y%m%d%H%M%S").to_string(),
+        None => timestamp.to_string(),
+    }
+}
+
+/// Presentation format of DS, RRSIG and DNSKEY RDATA, RFC 4034
+pub fn rdata_to_string(query_type: &QueryType, rdata: &[u8]) -> Option<String> {
+    match query_type {
+        QueryType::DS => {
+            let ds = Ds::from_rdata(".", rdata)?;
+            Some(std::format!(
+                "{} {} {} {}",
+                ds.key_tag,
+                ds.algorithm,
+                ds.digest_type,
+                hex::encode_upper(ds.digest)
+            ))
+        }
+        QueryType::DNSKEY if rdata.len() > 4 => Some(std::format!(
+            "{} {} {} {} ; key id = {}",
+            u16::from_be_bytes([rdata[0], rdata[1]]),
+            rdata[2],
+            rdata[3],
+            STANDARD.encode(&rdata[4..]),
+            key_tag(rdata)
+        )),
+        QueryType::RRSIG => {
+            let sig = Rrsig::from_rdata(rdata)?;
+            let covered = QueryType::from_code(sig.type_covered);
+            Some(std::format!(
+                "{} {} {} {} {} {} {} {} {}",
+                covered,
+                sig.algorithm,
+                sig.labels,
+                sig.original_ttl,
+                format_time(sig.expiration),
+                format_time(sig.inception),
+                sig.key_tag,
+                sig.signer,
+                STANDARD.encode(&sig.signature)
+            ))
+        }
+        _ => None,
+    }
+}
+
+#[cfg(all(test, any(feature = "dnssec-ring", feature = "dnssec-openssl")))]
+mod tests {
+    use super::*;
+    use crate::client::ClassType;
+    use crate::testing::Responder;
+    use crate::wire::{self, Message, MessageBuilder};
+    use ring::signature::{Ed25519KeyPair, KeyPair};
+
+    /// Base 32 encoding with the extended hex alphabet, lowercase and
+    /// without padding, RFC 4648 7
+    fn base32hex(data: &[u8]) -> String {
+        const ALPHABET: &[u8] = b"0123456789abcdefghijklmnopqrstuv";
+        let mut encoded = String::new();
+        for chunk in data.chunks(5) {
+            let mut block = [0u8; 5];
+            block[..chunk.len()].copy_from_slice(chunk);
+            let bits = block
+                .iter()
+                .fold(0u64, |bits, byte| bits << 8 | *byte as u64);
+            for i in 0..(chunk.len() * 8).div_ceil(5) {
+                encoded.push(ALPHABET[(bits >> (35 - i * 5)) as usize & 0x1f] as char);
+            }
+        }
+        encoded
+    }
+
+    /// An Ed25519 key signing a zone
+    struct Key {
+        zone: String,
+        pair: Ed25519KeyPair,
+    }
+
+    impl Key {
+        fn new(zone: &str) -> Key {
+            let pkcs8 = Ed25519KeyPair::generate_pkcs8(&ring::rand::SystemRandom::new()).unwrap();
+            let pair = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).unwrap();
+            Key {
+                zone: zone.to_string(),
+                pair,
+            }
+        }
+
+        /// DNSKEY RDATA of a key signing key of algorithm 15
+        fn dnskey(&self) -> Vec<u8> {
+            [&[1, 1, 3, 15], self.pair.public_key().as_ref()].concat()
+        }
+
+        /// SHA-256 DS RDATA of the key, RFC 4034 5.1.4
+        fn ds(&self) -> Vec<u8> {
+            let data = [wire::encode_name(&self.zone), self.dnskey()].concat();
+            let digest = ring::digest::digest(&ring::digest::SHA256, &data);
+            [
+                &key_tag(&self.dnskey()).to_be_bytes()[..],
+                &[15, 2],
+                digest.as_ref(),
+            ]
+            .concat()
+        }
+
+        /// RRSIG RDATA over the RRset of `owner`, a wildcard for synthesized
+        /// answers, whose RDATA are already in canonical form, valid from
+        /// `inception` to `expiration`
+        fn sign(
+            &self,
+            owner: &str,
+            query_type: &QueryType,
+            rdatas: &[Vec<u8>],
+            (inception, expiration): (u32, u32),
+        ) -> Vec<u8> {
+            let labels = owner
+                .split('.')
+                .filter(|label| !label.is_empty() && *label != "*")
+                .count() as u8;
+            let mut rdata = query_type.code().to_be_bytes().to_vec();
+            rdata.extend([15, labels]);
+            rdata.extend(300u32.to_be_bytes());
+            rdata.extend(expiration.to_be_bytes());
+            rdata.extend(inception.to_be_bytes());
+            rdata.extend(key_tag(&self.dnskey()).to_be_bytes());
+            rdata.extend(wire::encode_name(&self.zone));
+            let mut data = rdata.clone();
+            let mut rdatas = rdatas.to_vec();
+            rdatas.sort();
+            for each in rdatas {
+                data.extend(wire::encode_name(owner));
+                data.extend(query_type.code().to_be_bytes());
+                data.extend(1u16.to_be_bytes());
+                data.extend(300u32.to_be_bytes());
+                data.extend((each.len() as u16).to_be_bytes());
+                data.extend(each);
+            }
+            rdata.extend(self.pair.sign(&data).as_ref());
+            rdata
+        }
+
+        /// The RRset of `owner` and its signature
+        fn signed(&self, owner: &str, query_type: QueryType, rdatas: &[Vec<u8>]) -> Vec<Record> {
+            let sig = self.sign(owner, &query_type, rdatas, valid());
+            let mut records = rrset(owner, query_type, rdatas);
+            records.extend(self::rrset(owner, QueryType::RRSIG, &[sig]));
+            records
+        }
+    }
+
+    fn rrset(owner: &str, query_type: QueryType, rdatas: &[Vec<u8>]) -> Vec<Record> {
+        rdatas
+            .iter()
+            .map(|rdata| Record {
+                name: owner.to_string(),
+                query_type: query_type.clone(),
+                class: 1,
+                ttl: 300,
+                rdata: rdata.clone(),
+            })
+            .collect()
+    }
+
+    fn valid() -> (u32, u32) {
+        let now = Utc::now().timestamp() as u32;
+        (now - 3600, now + 3600)
+    }
+
+    /// Bit maps of NSEC and NSEC3 records holding `codes`, all below 256
+    fn bitmaps(codes: &[u16]) -> Vec<u8> {
+        let len = codes.iter().max().map_or(0, |code| code / 8 + 1) as usize;
+        let mut bitmap = vec![0; len];
+        for code in codes {
+            bitmap[*code as usize / 8] |= 0x80 >> (code % 8);
+        }
+        [vec![0, len as u8], bitmap].concat()
+    }
+
+    /// A signed root serving the zones delegated from it: example. and
+    /// evil., securely, and unsigned. and optout., without DS, denied by NSEC
+    /// and opt-out NSEC3 records
+    struct Hierarchy {
+        responder: Responder,
+        anchors: Vec<Ds>,
+        example: Key,
+        evil: Key,
+    }
+
+    impl Hierarchy {
+        async fn start() -> Hierarchy {
+            let (root, example, evil) = (Key::new("."), Key::new("example."), Key::new("evil."));
+            let mut zones: Vec<(String, QueryType, Vec<Record>, Vec<Record>)> = Vec::new();
+            for key in [&root, &example, &evil] {
+                let dnskeys = key.signed(&key.zone, QueryType::DNSKEY, &[key.dnskey()]);
+                zones.push((key.zone.clone(), QueryType::DNSKEY, dnskeys, Vec::new()));
+            }
+            for key in [&example, &evil] {
+                let ds = root.signed(&key.zone, QueryType::DS, &[key.ds()]);
+                zones.push((key.zone.clone(), QueryType::DS, ds, Vec::new()));
+            }
+            let nsec = [wire::encode_name("unsignee."), bitmaps(&[2, 46, 47])].concat();
+            let denial = root.signed("unsigned.", QueryType::Unknown(NSEC), &[nsec]);
+            zones.push(("unsigned.".to_string(), QueryType::DS, Vec::new(), denial));
+            let hash = nsec3_hash("optout.", &[], 0).unwrap();
+            let nsec3 = [&[1, 1, 0, 0, 0, 20][..], &[0xff; 20], &bitmaps(&[2])].concat();
+            let owner = std::format!("{}.", base32hex(&hash));
+            let denial = root.signed(&owner, QueryType::Unknown(NSEC3), &[nsec3]);
+            zones.push(("optout.".to_string(), QueryType::DS, Vec::new(), denial));
+
+            let responder = Responder::answering(move |query: &Message| {
+                let question = &query.questions[0];
+                let mut response = MessageBuilder::reply(query, 0);
+                let zone = zones.iter().find(|(name, query_type, _, _)| {
+                    *name == fqdn(&question.name) && *query_type == question.query_type
+                });
+                if let Some((_, _, answers, authorities)) = zone {
+                    for record in answers {
+                        response = response.with_answer(
+                            &record.name,
+                            record.query_type.clone(),
+                            ClassType::IN,
+                            record.ttl,
+                            record.rdata.clone(),
+                        );
+                    }
+                    for record in authorities {
+                        response = response.with_authority(
+                            &record.name,
+                            record.query_type.clone(),
+                            ClassType::IN,
+                            record.ttl,
+                            record.rdata.clone(),
+                        );
+                    }
+                }
+                vec![response.build()]
+            })
+            .await;
+            Hierarchy {
+                responder,
+                anchors: vec![Ds::from_rdata(".", &root.ds()).unwrap()],
+                example,
+                evil,
+            }
+        }
+
+        async fn validate(&self, records: &[Record]) -> Validation {
+            let client = Client::new(self.responder.addr()).await.unwrap();
+            validate(&client, records, &self.anchors).await
+        }
+    }
+
+    fn bogus(validation: Validation) -> bool {
+        matches!(validation, Validation::Bogus(_))
+    }
+
+    #[tokio::test]
+    async fn signed_answers_are_secure() {
+        let zones = Hierarchy::start().await;
+        let address = zones
+            .example
+            .signed("www.example.", QueryType::A, &[vec![192, 0, 2, 1]]);
+        assert_eq!(zones.validate(&address).await, Validation::Secure);
+
+        // each RRset of a CNAME chain is validated with its own signatures
+        let target = wire::encode_name("www.example.");
+        let mut chain = zones
+            .example
+            .signed("alias.example.", QueryType::CNAME, &[target]);
+        chain.extend(address.clone());
+        assert_eq!(zones.validate(&chain).await, Validation::Secure);
+        chain[2].rdata = vec![198, 51, 100, 1];
+        assert!(bogus(zones.validate(&chain).await));
+
+        // names in RDATA are lowercased before the signature is checked
+        let exchange = |name: &str| [&[0, 10][..], &wire::encode_name(name)].concat();
+        let sig = zones.example.sign(
+            "example.",
+            &QueryType::MX,
+            &[exchange("mail.example.")],
+            valid(),
+        );
+        let mut mx = rrset("example.", QueryType::MX, &[exchange("Mail.EXAMPLE.")]);
+        mx.extend(rrset("example.", QueryType::RRSIG, &[sig]));
+        assert_eq!(zones.validate(&mx).await, Validation::Secure);
+    }
+
+    #[tokio::test]
+    async fn wildcard_answers_are_secure() {
+        let zones = Hierarchy::start().await;
+        let rdata = [vec![192, 0, 2, 1]];
+        let sig = zones
+            .example
+            .sign("*.example.", &QueryType::A, &rdata, valid());
+        let mut address = rrset("www.example.", QueryType::A, &rdata);
+        address.extend(rrset("www.example.", QueryType::RRSIG, &[sig]));
+        assert_eq!(zones.validate(&address).await, Validation::Secure);
+
+        // a label count above the owner's can't be a wildcard
+        address[1].rdata[3] = 3;
+        assert!(bogus(zones.validate(&address).await));
+    }
+
+    #[tokio::test]
+    async fn broken_signatures_are_bogus() {
+        let zones = Hierarchy::start().await;
+        let rdata = [vec![192, 0, 2, 1]];
+
+        // evil. signs validly with its own keys, but not for bank.
+        let forged = zones.evil.signed("www.bank.", QueryType::A, &rdata);
+        assert!(bogus(zones.validate(&forged).await));
+
+        let now = Utc::now().timestamp() as u32;
+        let sig = zones.example.sign(
+            "www.example.",
+            &QueryType::A,
+            &rdata,
+            (now - 7200, now - 3600),
+        );
+        let mut expired = rrset("www.example.", QueryType::A, &rdata);
+        expired.extend(rrset("www.example.", QueryType::RRSIG, &[sig]));
+        assert!(bogus(zones.validate(&expired).await));
+
+        let mut tampered = zones.example.signed("www.example.", QueryType::A, &rdata);
+        tampered[0].rdata = vec![198, 51, 100, 1];
+        assert!(bogus(zones.validate(&tampered).await));
+
+        // example. has a DS, so an answer of it without RRSIG was stripped
+        let stripped = rrset("www.example.", QueryType::A, &rdata);
+        assert!(bogus(zones.validate(&stripped).await));
+    }
+
+    #[tokio::test]
+    async fn answers_of_unsigned_zones_are_insecure() {
+        let zones = Hierarchy::start().await;
+        let rdata = [vec![192, 0, 2, 1]];
+        let nsec = rrset("www.unsigned.", QueryType::A, &rdata);
+        assert_eq!(zones.validate(&nsec).await, Validation::Insecure);
+        let nsec3 = rrset("www.optout.", QueryType::A, &rdata);
+        assert_eq!(zones.validate(&nsec3).await, Validation::Insecure);
+    }
+
+    #[test]
+    fn canonical_rdata_lowercases_embedded_names() {
+        let mx = [&[0, 10][..], &wire::encode_name("Mail.EXAMPLE.")].concat();
+        let lowercase = [&[0, 10][..], &wire::encode_name("mail.example.")].concat();
+        assert_eq!(canonical_rdata(QueryType::MX.code(), &mx), lowercase);
+        let txt = b"\x05Hello".to_vec();
+        assert_eq!(canonical_rdata(QueryType::TXT.code(), &txt), txt);
+    }
+}
@@ -0,0 +1,271 @@
+use crate::client::{Client, QueryType};
+#[cfg(feature = "dnssec")]
+use crate::dnssec;
+use crate::nsconfig;
+#[cfg(feature = "dnssec")]
+use chrono::Utc;
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio::time::timeout;
+
+const PROBE_TIMEOUT: Duration = Duration::from_secs(3);
+
+#[derive(Debug, PartialEq)]
+pub enum Status {
+    Ok,
+    Warn,
+    Fail,
+}
+
+/// Outcome of a single environment check, with a suggested fix when it didn't pass
+#[derive(Debug)]
+pub struct Check {
+    pub status: Status,
+    pub name: String,
+    pub detail: String,
+    pub fix: Option<String>,
+}
+
+impl Check {
+    fn ok(name: String, detail: String) -> Check {
+        Check {
+            status: Status::Ok,
+            name,
+            detail,
+            fix: None,
+        }
+    }
+
+    fn problem(status: Status, name: String, detail: String, fix: &str) -> Check {
+        Check {
+            status,
+            name,
+            detail,
+            fix: Some(fix.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for Check {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let status = match self.status {
+            Status::Ok => "ok",
+            Status::Warn => "warn",
+            Status::Fail => "fail",
+        };
+        write!(f, "[{}] {}: {}", status, self.name, self.detail)?;
+        if let Some(fix) = &self.fix {
+            write!(f, "\n       fix: {}", fix)?;
+        }
+        Ok(())
+    }
+}
+
+fn check_resolv_conf(filename: &str, checks: &mut Vec<Check>) -> Vec<IpAddr> {
+    let name = std::format!("resolv.conf {}", filename);
+    let nameservers = match nsconfig::read_nameservers(filename.to_string()) {
+        Ok(nameservers) => nameservers,
+        Err(err) => {
+            checks.push(Check::problem(
+                Status::Fail,
+                name,
+                err.to_string(),
+                "create it with at least one `nameserver <ip>` line",
+            ));
+            return Vec::new();
+        }
+    };
+    let mut addrs: Vec<IpAddr> = Vec::new();
+    for nameserver in &nameservers {
+        match nameserver.parse::<IpAddr>() {
+            Ok(addr) => addrs.push(addr),
+            Err(_) => checks.push(Check::problem(
+                Status::Fail,
+                name.clone(),
+                std::format!("nameserver {:?} isn't an IP address", nameserver),
+                "nameserver lines only accept IPv4 or IPv6 addresses",
+            )),
+        }
+    }
+    if nameservers.is_empty() {
+        checks.push(Check::problem(
+            Status::Fail,
+            name,
+            "no nameserver configured".to_string(),
+            "add a `nameserver <ip>` line, libc falls back to 127.0.0.1 otherwise",
+        ));
+    } else if nameservers.len() > 3 {
+        checks.push(Check::problem(
+            Status::Warn,
+            name,
+            std::format!("{} nameservers configured", nameservers.len()),
+            "libc only uses the first 3 nameservers, remove the others",
+        ));
+    } else if addrs.iter().all(|addr| addr.is_loopback()) && !addrs.is_empty() {
+        checks.push(Check::ok(
+            name,
+            "only a local stub resolver is configured, upstreams are checked through it"
+                .to_string(),
+        ));
+    } else {
+        checks.push(Check::ok(
+            name,
+            std::format!("{} nameserver(s) configured", nameservers.len()),
+        ));
+    }
+    addrs
+}
+
+async fn check_tcp(addr: SocketAddr, label: &str, fix: &str) -> Check {
+    let name = std::format!("{} egress to {}", label, addr);
+    match timeout(PROBE_TIMEOUT, TcpStream::connect(addr)).await {
+        Ok(Ok(_)) => Check::ok(name, "connected".to_string()),
+        Ok(Err(err)) => Check::problem(Status::Warn, name, err.to_string(), fix),
+        Err(_) => Check::problem(Status::Warn, name, "connection timed out".to_string(), fix),
+    }
+}
+
+async fn check_server(addr: IpAddr, checks: &mut Vec<Check>) {
+    let server = SocketAddr::new(addr, 53);
+    let name = std::format!("UDP egress to {}", server);
+    let client = match Client::new(server.to_string()).await {
+        Ok(client) => client,
+        Err(err) => {
+            checks.push(Check::problem(
+                Status::Fail,
+                name,
+                std::format!("{:?}", err),
+                "check the local network configuration and routes",
+            ));
+            return;
+        }
+    };
+    let udp_ok = match client.query(".".to_string(), QueryType::SOA).await {
+        Ok(_) => {
+            checks.push(Check::ok(name, "resolver answered".to_string()));
+            true
+        }
+        Err(err) => {
+            checks.push(Check::problem(
+                Status::Fail,
+                name,
+                std::format!("{:?}", err),
+                "allow outgoing UDP port 53 on firewalls, or use --doh",
+            ));
+            false
+        }
+    };
+    checks.push(
+        check_tcp(
+            server,
+            "TCP",
+            "allow outgoing TCP port 53, it is needed for truncated answers",
+        )
+        .await,
+    );
+    checks.push(
+        check_tcp(
+            SocketAddr::new(addr, 853),
+            "DoT",
+            "the resolver may not offer DNS-over-TLS, or port 853 is blocked",
+        )
+        .await,
+    );
+    checks.push(
+        check_tcp(
+            SocketAddr::new(addr, 443),
+            "HTTPS",
+            "the resolver may not offer DNS-over-HTTPS, or port 443 is blocked",
+        )
+        .await,
+    );
+    #[cfg(feature = "dnssec")]
+    if udp_ok {
+        check_edns(server, checks).await;
+    }
+    #[cfg(not(feature = "dnssec"))]
+    let _ = udp_ok;
+}
+
+/// Large DNSSEC answers of the root zone get fragmented over UDP, if they are
+/// lost while small answers come through, a middlebox drops fragments
+#[cfg(feature = "dnssec")]
+async fn check_edns(server: SocketAddr, checks: &mut Vec<Check>) {
+    let name = std::format!("EDNS large answers from {}", server);
+    let client = match Client::new(server.to_string()).await {
+        Ok(client) => client.with_dnssec(dnssec::root_trust_anchors()),
+        Err(_) => return,
+    };
+    let records = match client.records(".", &QueryType::DNSKEY).await {
+        Ok(records) => records,
+        Err(err) => {
+            checks.push(Check::problem(
+                Status::Warn,
+                name,
+                std::format!("{:?}", err),
+                "fragmented UDP seems to be dropped, lower the EDNS buffer size to 1232 \
+                 bytes or allow TCP port 53",
+            ));
+            return;
+        }
+    };
+    let size: usize = records.iter().map(|r| r.rdata.len()).sum();
+    if size == 0 {
+        checks.push(Check::problem(
+            Status::Warn,
+            name,
+            "no DNSKEY records in the answer".to_string(),
+            "the resolver doesn't support EDNS or strips DNSSEC records, use another one \
+             for --dnssec",
+        ));
+        return;
+    }
+    checks.push(Check::ok(
+        name.clone(),
+        std::format!("received {} bytes of DNSKEY data", size),
+    ));
+    let now = Utc::now().timestamp() as u32;
+    let name = "clock".to_string();
+    match dnssec::signature_validity(&records) {
+        Some((inception, expiration)) if now < inception || now > expiration => {
+            checks.push(Check::problem(
+                Status::Fail,
+                name,
+                "local time is outside the root zone signature validity period".to_string(),
+                "DNSSEC validation will fail, synchronize the clock with NTP",
+            ))
+        }
+        Some(_) => checks.push(Check::ok(
+            name,
+            "local time is within the root zone signature validity period".to_string(),
+        )),
+        None => (),
+    }
+}
+
+/// Checks resolv.conf, the reachability of each resolver over the usual DNS
+/// ports, large EDNS answers and the clock
+pub async fn run(servers: &[String], resolv_conf: &str) -> Vec<Check> {
+    let mut checks: Vec<Check> = Vec::new();
+    let mut addrs = check_resolv_conf(resolv_conf, &mut checks);
+    if !servers.is_empty() {
+        addrs.clear();
+        for server in servers {
+            match server.parse::<IpAddr>() {
+                Ok(addr) => addrs.push(addr),
+                Err(_) => checks.push(Check::problem(
+                    Status::Fail,
+                    std::format!("server {}", server),
+                    "isn't an IP address".to_string(),
+                    "pass resolvers as IPv4 or IPv6 addresses",
+                )),
+            }
+        }
+    }
+    for addr in addrs {
+        check_server(addr, &mut checks).await;
+    }
+    checks
+}
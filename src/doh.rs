@@ -0,0 +1,204 @@
+use crate::client::{Client, ClientError};
+use crate::resolver::Resolver;
+use crate::sources::Sources;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use reqwest::header::{ACCEPT, CONTENT_TYPE};
+use reqwest::Url;
+use std::future::Future;
+use std::net::{IpAddr, SocketAddr};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use tower_layer::Layer;
+use tower_service::Service;
+
+const DNS_MESSAGE: &str = "application/dns-message";
+
+/// HTTP method used to carry the DNS wireformat, RFC 8484 section 4.1
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum DohMethod {
+    /// Query in the `dns` URL parameter, which lets HTTP caches serve it
+    Get,
+    /// Query in the request body
+    Post,
+}
+
+pub struct DohClient {
+    http: reqwest::Client,
+    url: String,
+    method: DohMethod,
+    /// Time spent opening connections since the last exchange took it
+    setup: Arc<Mutex<Option<Duration>>>,
+}
+
+/// Times the connections the HTTP client opens: the name resolution of the
+/// endpoint, the TCP and the TLS handshakes, before any request goes out
+#[derive(Clone)]
+struct ConnectTimer {
+    setup: Arc<Mutex<Option<Duration>>>,
+}
+
+impl<S> Layer<S> for ConnectTimer {
+    type Service = TimedConnector<S>;
+
+    fn layer(&self, inner: S) -> TimedConnector<S> {
+        TimedConnector {
+            inner,
+            setup: self.setup.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+struct TimedConnector<S> {
+    inner: S,
+    setup: Arc<Mutex<Option<Duration>>>,
+}
+
+impl<S, R> Service<R> for TimedConnector<S>
+where
+    S: Service<R>,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<S::Response, S::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), S::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: R) -> Self::Future {
+        let started = Instant::now();
+        let connecting = self.inner.call(request);
+        let setup = self.setup.clone();
+        Box::pin(async move {
+            let connection = connecting.await?;
+            let mut setup = setup
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+            *setup = Some(setup.unwrap_or_default() + started.elapsed());
+            Ok(connection)
+        })
+    }
+}
+
+/// Host name of a DoH endpoint URL, `None` when it is an IP address
+pub fn endpoint_host(url: &str) -> Result<Option<String>, ClientError> {
+    let url = Url::parse(url).map_err(|err| ClientError::ParseError(err.to_string()))?;
+    match url.host_str() {
+        // IPv6 addresses are in brackets
+        Some(host) if host.parse::<IpAddr>().is_err() && !host.starts_with('[') => {
+            Ok(Some(host.to_string()))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Addresses of the host of a DoH endpoint, resolved through the plain DNS
+/// `server` rather than the system resolver, which may be the very resolver
+/// the endpoint is tested against
+pub async fn bootstrap(url: &str, server: String) -> Result<Vec<IpAddr>, ClientError> {
+    let host = match endpoint_host(url)? {
+        Some(host) => host,
+        None => return Ok(Vec::new()),
+    };
+    let addrs = Resolver::new(Client::new(server.clone()).await?)
+        .lookup_ip(&host)
+        .await?;
+    if addrs.is_empty() {
+        return Err(ClientError::GenericError(std::format!(
+            "{} has no address at the bootstrap resolver {}",
+            host,
+            server
+        )));
+    }
+    log::info!(
+        "Resolved {} through the bootstrap resolver {}, pinned to {:?} for the run",
+        host,
+        server,
+        addrs
+    );
+    Ok(addrs)
+}
+
+impl DohClient {
+    pub fn new(url: String, method: DohMethod) -> Result<DohClient, ClientError> {
+        DohClient::pinned(url, method, &[], &Sources::default())
+    }
+
+    /// Connects to `addrs` instead of resolving the host of `url`, trusting
+    /// the CA roots of the first of `sources` that has any
+    pub fn pinned(
+        url: String,
+        method: DohMethod,
+        addrs: &[IpAddr],
+        sources: &Sources,
+    ) -> Result<DohClient, ClientError> {
+        let setup = Arc::new(Mutex::new(None));
+        let mut builder = sources.with_ca_roots(reqwest::Client::builder())?;
+        builder = builder.connector_layer(ConnectTimer {
+            setup: setup.clone(),
+        });
+        if let Some(host) = endpoint_host(&url)? {
+            if !addrs.is_empty() {
+                let addrs: Vec<SocketAddr> =
+                    addrs.iter().map(|addr| SocketAddr::new(*addr, 0)).collect();
+                builder = builder.resolve_to_addrs(&host, &addrs);
+            }
+        }
+        let http = match builder.build() {
+            Ok(http) => http,
+            Err(err) => return Err(ClientError::ConnectError(err.to_string())),
+        };
+        Ok(DohClient {
+            http,
+            url,
+            method,
+            setup,
+        })
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Response to `query`, with the time spent setting up the connection
+    /// it went over when one had to be opened. Exchanges in parallel on the
+    /// same client may see the setup of each other's connections.
+    pub async fn exchange(&self, query: &[u8]) -> Result<(Vec<u8>, Option<Duration>), ClientError> {
+        let request = match self.method {
+            DohMethod::Get => self
+                .http
+                .get(&self.url)
+                .query(&[("dns", URL_SAFE_NO_PAD.encode(query))]),
+            DohMethod::Post => self
+                .http
+                .post(&self.url)
+                .header(CONTENT_TYPE, DNS_MESSAGE)
+                .body(query.to_vec()),
+        };
+        let resp = match request.header(ACCEPT, DNS_MESSAGE).send().await {
+            Ok(resp) => resp,
+            Err(err) => return Err(ClientError::SendError(err.to_string())),
+        };
+        if !resp.status().is_success() {
+            return Err(ClientError::RecvError(std::format!(
+                "DoH server {} replied with HTTP {}",
+                self.url,
+                resp.status()
+            )));
+        }
+        let setup = self
+            .setup
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .take();
+        match resp.bytes().await {
+            Ok(body) => Ok((body.to_vec(), setup)),
+            Err(err) => Err(ClientError::RecvError(err.to_string())),
+        }
+    }
+}
@@ -0,0 +1,256 @@
+//! Stats of the forwarder per domain: how many queries each one got, with
+//! which rcodes and how fast, to tell the domains that are slow or broken.
+use crate::client::{self, ClientError};
+use crate::output::Table;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Latencies kept per domain for its percentiles, the oldest dropped first
+const LATENCY_WINDOW: usize = 1000;
+
+/// Domains tracked at most, those seen once it is reached left out
+const MAX_DOMAINS: usize = 10000;
+
+/// The domain stats are kept under: the last two labels of `name`, so
+/// `www.example.com` and `api.example.com` count for `example.com`
+pub fn domain_of(name: &str) -> String {
+    let name = name.trim_end_matches('.').to_ascii_lowercase();
+    let labels: Vec<&str> = name.rsplitn(3, '.').collect();
+    match labels.as_slice() {
+        [] | [""] => ".".to_string(),
+        [last] => last.to_string(),
+        [last, second, ..] => std::format!("{}.{}", second, last),
+    }
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    queries: usize,
+    rcodes: BTreeMap<u8, usize>,
+    failures: usize,
+    latencies: VecDeque<Duration>,
+}
+
+/// The queries of each domain, clones sharing them
+#[derive(Debug, Clone, Default)]
+pub struct DomainStats {
+    domains: Arc<Mutex<HashMap<String, Counters>>>,
+}
+
+impl DomainStats {
+    pub fn new() -> DomainStats {
+        DomainStats::default()
+    }
+
+    /// Counts a query of `name` answered with `rcode` after `latency`, or
+    /// that failed without a response
+    pub fn record(&self, name: &str, rcode: Option<u8>, latency: Duration) {
+        let domain = domain_of(name);
+        let mut domains = self.domains.lock().unwrap_or_else(|p| p.into_inner());
+        if !domains.contains_key(&domain) && domains.len() >= MAX_DOMAINS {
+            log::debug!(
+                "Not keeping stats of {}, {} domains are",
+                domain,
+                MAX_DOMAINS
+            );
+            return;
+        }
+        let counters = domains.entry(domain).or_default();
+        counters.queries += 1;
+        match rcode {
+            Some(rcode) => *counters.rcodes.entry(rcode).or_default() += 1,
+            None => counters.failures += 1,
+        }
+        if counters.latencies.len() == LATENCY_WINDOW {
+            counters.latencies.pop_front();
+        }
+        counters.latencies.push_back(latency);
+    }
+
+    /// Stats of `domain`, none before it got a query
+    pub fn domain(&self, domain: &str) -> Option<DomainSummary> {
+        let domains = self.domains.lock().unwrap_or_else(|p| p.into_inner());
+        let domain = domain_of(domain);
+        domains
+            .get(&domain)
+            .map(|counters| DomainSummary::new(domain, counters))
+    }
+
+    /// Stats of the `count` domains that got the most queries, busiest first
+    pub fn busiest(&self, count: usize) -> Vec<DomainSummary> {
+        let domains = self.domains.lock().unwrap_or_else(|p| p.into_inner());
+        let mut summaries: Vec<DomainSummary> = domains
+            .iter()
+            .map(|(domain, counters)| DomainSummary::new(domain.clone(), counters))
+            .collect();
+        summaries.sort_by(|a, b| b.queries.cmp(&a.queries).then(a.domain.cmp(&b.domain)));
+        summaries.truncate(count);
+        summaries
+    }
+
+    /// Stats of every domain together, as those of a domain named `all`
+    pub fn total(&self) -> DomainSummary {
+        let domains = self.domains.lock().unwrap_or_else(|p| p.into_inner());
+        let mut total = Counters::default();
+        for counters in domains.values() {
+            total.queries += counters.queries;
+            for (rcode, count) in &counters.rcodes {
+                *total.rcodes.entry(*rcode).or_default() += count;
+            }
+            total.failures += counters.failures;
+            total.latencies.extend(&counters.latencies);
+        }
+        DomainSummary::new("all".to_string(), &total)
+    }
+}
+
+fn percentile(sorted: &[Duration], percent: f64) -> Option<Duration> {
+    let rank = (percent / 100.0 * sorted.len() as f64).ceil() as usize;
+    sorted.get(rank.saturating_sub(1)).copied()
+}
+
+/// What the queries of a domain came to
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct DomainSummary {
+    pub domain: String,
+    pub queries: usize,
+    /// Responses by rcode name
+    pub rcodes: BTreeMap<String, usize>,
+    /// Queries no upstream server answered
+    pub failures: usize,
+    pub p50: Option<Duration>,
+    pub p95: Option<Duration>,
+    pub p99: Option<Duration>,
+}
+
+impl DomainSummary {
+    fn new(domain: String, counters: &Counters) -> DomainSummary {
+        let mut sorted: Vec<Duration> = counters.latencies.iter().copied().collect();
+        sorted.sort();
+        DomainSummary {
+            domain,
+            queries: counters.queries,
+            rcodes: counters
+                .rcodes
+                .iter()
+                .map(|(rcode, count)| (client::rcode_name(*rcode), *count))
+                .collect(),
+            failures: counters.failures,
+            p50: percentile(&sorted, 50.0),
+            p95: percentile(&sorted, 95.0),
+            p99: percentile(&sorted, 99.0),
+        }
+    }
+
+    fn rcode(&self, name: &str) -> usize {
+        self.rcodes.get(name).copied().unwrap_or(0)
+    }
+
+    /// The stats as `key=value` strings of a TXT record, latencies in
+    /// microseconds and rcodes as `rcode=NAME:COUNT`
+    pub fn to_txt(&self) -> Vec<String> {
+        let mut txt = vec![
+            std::format!("domain={}", self.domain),
+            std::format!("queries={}", self.queries),
+            std::format!("failures={}", self.failures),
+        ];
+        for (rcode, count) in &self.rcodes {
+            txt.push(std::format!("rcode={}:{}", rcode, count));
+        }
+        for (key, latency) in [("p50", self.p50), ("p95", self.p95), ("p99", self.p99)] {
+            if let Some(latency) = latency {
+                txt.push(std::format!("{}={}", key, latency.as_micros()));
+            }
+        }
+        txt
+    }
+
+    /// The stats from the strings of their TXT record, those unknown ignored
+    pub fn from_txt(txt: &[String]) -> Result<DomainSummary, ClientError> {
+        let mut summary = DomainSummary::default();
+        for string in txt {
+            let invalid =
+                || ClientError::GenericError(std::format!("Invalid domain stat {:?}", string));
+            let (key, value) = string.split_once('=').ok_or_else(invalid)?;
+            let number = |value: &str| value.parse::<usize>().map_err(|_| invalid());
+            let micros = |value: &str| number(value).map(|us| Duration::from_micros(us as u64));
+            match key {
+                "domain" => summary.domain = value.to_string(),
+                "queries" => summary.queries = number(value)?,
+                "failures" => summary.failures = number(value)?,
+                "rcode" => {
+                    let (rcode, count) = value.split_once(':').ok_or_else(invalid)?;
+                    summary.rcodes.insert(rcode.to_string(), number(count)?);
+                }
+                "p50" => summary.p50 = Some(micros(value)?),
+                "p95" => summary.p95 = Some(micros(value)?),
+                "p99" => summary.p99 = Some(micros(value)?),
+                _ => {}
+            }
+        }
+        Ok(summary)
+    }
+}
+
+fn latency(latency: Option<Duration>) -> String {
+    latency.map_or("-".to_string(), |latency| {
+        std::format!("{:.1}", client::millis(latency))
+    })
+}
+
+impl fmt::Display for DomainSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Domain: {}", self.domain)?;
+        writeln!(f, "Queries: {}", self.queries)?;
+        let rcodes: Vec<String> = self
+            .rcodes
+            .iter()
+            .map(|(rcode, count)| std::format!("{} {}", rcode, count))
+            .collect();
+        writeln!(f, "Rcodes: {}", rcodes.join(", "))?;
+        writeln!(f, "Failures: {}", self.failures)?;
+        write!(
+            f,
+            "Latency: p50 {} ms, p95 {} ms, p99 {} ms",
+            latency(self.p50),
+            latency(self.p95),
+            latency(self.p99)
+        )
+    }
+}
+
+/// Domains in a row each, rcodes other than NOERROR, NXDOMAIN and SERVFAIL
+/// counted together
+pub fn domains_table(summaries: &[DomainSummary]) -> String {
+    let mut table = Table::new(
+        &[
+            "DOMAIN", "QUERIES", "NOERROR", "NXDOMAIN", "SERVFAIL", "OTHER", "FAILURES", "P50 MS",
+            "P95 MS", "P99 MS",
+        ],
+        8,
+        false,
+    );
+    for summary in summaries {
+        let (noerror, nxdomain, servfail) = (
+            summary.rcode("NOERROR"),
+            summary.rcode("NXDOMAIN"),
+            summary.rcode("SERVFAIL"),
+        );
+        let other = summary.rcodes.values().sum::<usize>() - noerror - nxdomain - servfail;
+        table.push(vec![
+            summary.domain.clone(),
+            summary.queries.to_string(),
+            noerror.to_string(),
+            nxdomain.to_string(),
+            servfail.to_string(),
+            other.to_string(),
+            summary.failures.to_string(),
+            latency(summary.p50),
+            latency(summary.p95),
+            latency(summary.p99),
+        ]);
+    }
+    table.render()
+}
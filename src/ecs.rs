@@ -0,0 +1,222 @@
+//! EDNS Client Subnet, RFC 7871: the network a query is on behalf of, which
+//! CDNs answer for, and the scope of that network the answer is valid for.
+use crate::client::ClientError;
+use crate::wire::{Message, Reader, Writer};
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+
+/// EDNS option code of client subnets, RFC 7871 6
+pub(crate) const CLIENT_SUBNET: u16 = 8;
+
+/// Address families of RFC 7871 6, as IANA numbers them
+const IPV4: u16 = 1;
+const IPV6: u16 = 2;
+
+/// A network sent in a query, or echoed back in a response with the prefix
+/// length the answer holds for
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClientSubnet {
+    addr: IpAddr,
+    source_prefix: u8,
+    scope_prefix: u8,
+}
+
+/// `addr` with the bits past `prefix` cleared, RFC 7871 6 requires them zero
+pub(crate) fn mask(addr: IpAddr, prefix: u8) -> IpAddr {
+    match addr {
+        IpAddr::V4(v4) => {
+            let bits = u32::MAX.checked_shl(32 - prefix as u32).unwrap_or(0);
+            IpAddr::V4(Ipv4Addr::from(u32::from(v4) & bits))
+        }
+        IpAddr::V6(v6) => {
+            let bits = u128::MAX.checked_shl(128 - prefix as u32).unwrap_or(0);
+            IpAddr::V6(Ipv6Addr::from(u128::from(v6) & bits))
+        }
+    }
+}
+
+impl ClientSubnet {
+    pub fn addr(&self) -> IpAddr {
+        self.addr
+    }
+
+    pub fn source_prefix(&self) -> u8 {
+        self.source_prefix
+    }
+
+    /// Prefix length the answer holds for, 0 in queries, RFC 7871 7.2.1
+    pub fn scope_prefix(&self) -> u8 {
+        self.scope_prefix
+    }
+
+    /// Value of the option: family, prefix lengths and the address truncated
+    /// to the bytes the source prefix covers
+    pub(crate) fn encode(&self) -> Vec<u8> {
+        let (family, octets) = match self.addr {
+            IpAddr::V4(v4) => (IPV4, v4.octets().to_vec()),
+            IpAddr::V6(v6) => (IPV6, v6.octets().to_vec()),
+        };
+        let mut writer = Writer::new();
+        writer.put_u16(family);
+        writer.put_bytes(&[self.source_prefix, self.scope_prefix]);
+        writer.put_bytes(&octets[..(self.source_prefix as usize).div_ceil(8)]);
+        writer.finish()
+    }
+
+    pub(crate) fn decode(data: &[u8]) -> Result<ClientSubnet, ClientError> {
+        let mut reader = Reader::new(data);
+        let family = reader.get_u16()?;
+        let prefixes = reader.get_bytes(2)?;
+        let (source_prefix, scope_prefix) = (prefixes[0], prefixes[1]);
+        let len = reader.remaining();
+        let addr = match family {
+            IPV4 if source_prefix <= 32 && len <= 4 => {
+                let mut octets = [0u8; 4];
+                octets[..len].copy_from_slice(reader.get_bytes(len)?);
+                IpAddr::V4(Ipv4Addr::from(octets))
+            }
+            IPV6 if source_prefix <= 128 && len <= 16 => {
+                let mut octets = [0u8; 16];
+                octets[..len].copy_from_slice(reader.get_bytes(len)?);
+                IpAddr::V6(Ipv6Addr::from(octets))
+            }
+            _ => {
+                return Err(ClientError::DecodeError(std::format!(
+                    "invalid client subnet option of family {} and prefix {}",
+                    family,
+                    source_prefix
+                )))
+            }
+        };
+        Ok(ClientSubnet {
+            addr,
+            source_prefix,
+            scope_prefix,
+        })
+    }
+
+    /// The option of `message`, if any
+    pub(crate) fn of(message: &Message) -> Option<ClientSubnet> {
+        let value = message.option(CLIENT_SUBNET)?;
+        match ClientSubnet::decode(&value) {
+            Ok(subnet) => Some(subnet),
+            Err(err) => {
+                log::warn!("Ignoring client subnet option: {:?}", err);
+                None
+            }
+        }
+    }
+
+    /// Fails unless the option of `response`, when it has one, echoes this
+    /// network, RFC 7871 7.3
+    pub(crate) fn check(&self, response: &Message) -> Result<(), ClientError> {
+        match ClientSubnet::of(response) {
+            Some(echoed)
+                if echoed.addr != self.addr || echoed.source_prefix != self.source_prefix =>
+            {
+                Err(ClientError::ClientSubnet(std::format!(
+                    "sent {} but the response is for {}",
+                    self,
+                    echoed
+                )))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+impl FromStr for ClientSubnet {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s, None),
+        };
+        let addr: IpAddr = addr
+            .parse()
+            .map_err(|_| std::format!("invalid subnet address {}", addr))?;
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        let source_prefix = match prefix {
+            Some(prefix) => match prefix.parse::<u8>() {
+                Ok(prefix) if prefix <= max => prefix,
+                _ => return Err(std::format!("invalid subnet prefix length {}", prefix)),
+            },
+            None => max,
+        };
+        Ok(ClientSubnet {
+            addr: mask(addr, source_prefix),
+            source_prefix,
+            scope_prefix: 0,
+        })
+    }
+}
+
+impl fmt::Display for ClientSubnet {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.source_prefix)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::QueryType;
+
+    fn subnet(text: &str) -> ClientSubnet {
+        text.parse().unwrap()
+    }
+
+    #[test]
+    fn addresses_are_truncated_to_their_source_prefix() {
+        for (text, value) in [
+            ("192.0.2.77/24", vec![0, 1, 24, 0, 192, 0, 2]),
+            ("192.0.2.200/25", vec![0, 1, 25, 0, 192, 0, 2, 128]),
+            ("192.0.2.1/0", vec![0, 1, 0, 0]),
+            (
+                "2001:db8:1234::1/48",
+                vec![0, 2, 48, 0, 0x20, 0x01, 0x0d, 0xb8, 0x12, 0x34],
+            ),
+        ] {
+            assert_eq!(subnet(text).encode(), value, "{}", text);
+            assert_eq!(ClientSubnet::decode(&value).unwrap(), subnet(text));
+        }
+        assert_eq!(subnet("192.0.2.77").to_string(), "192.0.2.77/32");
+        assert!("192.0.2.1/33".parse::<ClientSubnet>().is_err());
+    }
+
+    #[test]
+    fn options_round_trip_in_messages() {
+        let sent = subnet("198.51.100.0/24");
+        let mut query = Message::query(1, "example.com", &QueryType::A, 1);
+        query.add_edns(false);
+        query.set_option(CLIENT_SUBNET, &sent.encode());
+        let decoded = Message::decode(&query.encode()).unwrap();
+        assert_eq!(ClientSubnet::of(&decoded), Some(sent));
+    }
+
+    #[test]
+    fn responses_carry_the_scope_of_the_answer() {
+        let sent = subnet("198.51.100.0/24");
+        let response = |value: &[u8]| {
+            let mut response = Message::query(1, "example.com", &QueryType::A, 1);
+            response.add_edns(false);
+            response.set_option(CLIENT_SUBNET, value);
+            Message::decode(&response.encode()).unwrap()
+        };
+        // the answer holds for the whole /20 around the network sent
+        let scoped = response(&[0, 1, 24, 20, 198, 51, 100]);
+        let echoed = ClientSubnet::of(&scoped).unwrap();
+        assert_eq!((echoed.source_prefix(), echoed.scope_prefix()), (24, 20));
+        assert!(sent.check(&scoped).is_ok());
+        // another network fails the check, an empty option is ignored
+        assert!(sent.check(&response(&[0, 1, 24, 0, 203, 0, 113])).is_err());
+        assert!(sent.check(&response(&[])).is_ok());
+        // an unknown family or a prefix longer than the address is ignored
+        for value in [[0, 3, 24, 0, 198, 51, 100], [0, 1, 40, 0, 198, 51, 100]] {
+            assert!(ClientSubnet::decode(&value).is_err());
+            assert_eq!(ClientSubnet::of(&response(&value)), None);
+        }
+    }
+}
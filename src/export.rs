@@ -0,0 +1,220 @@
+//! Answers, errors and timings of a run written to a SQLite database, for SQL
+//! analysis of large runs. Each run appends to the tables of `SCHEMA`:
+//!
+//! - `runs`: one row per run, when it started and the servers it queried
+//! - `queries`: one row per query, its type, the server that answered, the
+//!   round trip, whether it succeeded, got NXDOMAIN or failed, and the error
+//! - `answers`: one row per answer of a query, its data in presentation format
+//!
+//! ```sql
+//! SELECT q.host, a.data FROM queries q JOIN answers a ON a.query_id = q.id
+//! WHERE q.run_id = (SELECT max(id) FROM runs) AND a.type = 'A';
+//! ```
+//!
+//! The runs are a history of the answers too: `Export::cached_at` tells what
+//! a cache filled by them would have answered at a past moment.
+use crate::client::{ClientError, QueryAnswer, QueryType};
+use crate::output::Outcome;
+use crate::rdata::ResourceRecord;
+use crate::sink::QueryResult;
+use chrono::{DateTime, Utc};
+use rusqlite::{params, Connection, OpenFlags, OptionalExtension};
+use std::str::FromStr;
+use std::time::Duration;
+
+pub const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS runs (
+    id INTEGER PRIMARY KEY,
+    started TEXT NOT NULL,        -- RFC 3339, UTC
+    servers TEXT NOT NULL         -- comma separated
+);
+CREATE TABLE IF NOT EXISTS queries (
+    id INTEGER PRIMARY KEY,
+    run_id INTEGER NOT NULL REFERENCES runs(id),
+    host TEXT NOT NULL,
+    type TEXT NOT NULL,
+    server TEXT NOT NULL,
+    rtt_ms REAL NOT NULL,
+    status TEXT NOT NULL,         -- succeeded, nxdomain or failed
+    error TEXT                    -- NULL unless failed
+);
+CREATE TABLE IF NOT EXISTS answers (
+    query_id INTEGER NOT NULL REFERENCES queries(id),
+    host TEXT NOT NULL,
+    type TEXT NOT NULL,
+    class TEXT NOT NULL,
+    ttl INTEGER NOT NULL,
+    data TEXT NOT NULL
+);
+";
+
+/// Where the results of a run are exported, given as `sqlite:FILE`
+#[derive(Debug, Clone, PartialEq)]
+pub enum Export {
+    Sqlite(String),
+}
+
+impl FromStr for Export {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            Some(("sqlite", path)) if !path.is_empty() => Ok(Export::Sqlite(path.to_string())),
+            _ => Err(std::format!(
+                "unsupported export {}, expected sqlite:FILE",
+                s
+            )),
+        }
+    }
+}
+
+fn status(outcome: &Outcome) -> &'static str {
+    match outcome {
+        Outcome::Succeeded => "succeeded",
+        Outcome::NxDomain => "nxdomain",
+        Outcome::Failed => "failed",
+    }
+}
+
+impl Export {
+    /// Appends a run started at `started` against `servers` with its queries
+    pub fn write(
+        &self,
+        started: DateTime<Utc>,
+        servers: &str,
+        queries: &[QueryResult],
+    ) -> Result<(), ClientError> {
+        let Export::Sqlite(path) = self;
+        let failed =
+            |err: rusqlite::Error| ClientError::GenericError(std::format!("{}: {}", path, err));
+        let mut conn = Connection::open(path).map_err(failed)?;
+        conn.execute_batch(SCHEMA).map_err(failed)?;
+        let tx = conn.transaction().map_err(failed)?;
+        tx.execute(
+            "INSERT INTO runs (started, servers) VALUES (?1, ?2)",
+            params![started.to_rfc3339(), servers],
+        )
+        .map_err(failed)?;
+        let run_id = tx.last_insert_rowid();
+        for query in queries {
+            tx.execute(
+                "INSERT INTO queries (run_id, host, type, server, rtt_ms, status, error) \
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                params![
+                    run_id,
+                    query.host,
+                    query.query_type.to_string(),
+                    query.server,
+                    query.rtt.as_secs_f64() * 1000.0,
+                    status(&query.outcome),
+                    query.error,
+                ],
+            )
+            .map_err(failed)?;
+            let query_id = tx.last_insert_rowid();
+            for answer in &query.answers {
+                tx.execute(
+                    "INSERT INTO answers (query_id, host, type, class, ttl, data) \
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                    params![
+                        query_id,
+                        answer.host(),
+                        answer.query_type().to_string(),
+                        answer.class_type().to_string(),
+                        answer.ttl(),
+                        answer.data().to_string(),
+                    ],
+                )
+                .map_err(failed)?;
+            }
+        }
+        tx.commit().map_err(failed)
+    }
+
+    /// What a cache filled by the runs would have answered `host`
+    /// `query_type` with at `at`: the last query of them that didn't fail,
+    /// started by then, its TTLs counted down to `at`. None when no run
+    /// queried it by then or every record of the answer had expired.
+    pub fn cached_at(
+        &self,
+        host: &str,
+        query_type: &QueryType,
+        at: DateTime<Utc>,
+    ) -> Result<Option<QueryResult>, ClientError> {
+        let Export::Sqlite(path) = self;
+        let failed =
+            |err: rusqlite::Error| ClientError::GenericError(std::format!("{}: {}", path, err));
+        let conn =
+            Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY).map_err(failed)?;
+        // RFC 3339 times in UTC sort as text
+        let query = conn
+            .query_row(
+                "SELECT q.id, r.started, q.server, q.rtt_ms, q.status \
+                 FROM queries q JOIN runs r ON r.id = q.run_id \
+                 WHERE q.host = ?1 AND q.type = ?2 AND q.status != 'failed' AND r.started <= ?3 \
+                 ORDER BY r.started DESC, q.id DESC LIMIT 1",
+                params![host, query_type.to_string(), at.to_rfc3339()],
+                |row| {
+                    Ok((
+                        row.get::<_, i64>(0)?,
+                        row.get::<_, String>(1)?,
+                        row.get::<_, String>(2)?,
+                        row.get::<_, f64>(3)?,
+                        row.get::<_, String>(4)?,
+                    ))
+                },
+            )
+            .optional()
+            .map_err(failed)?;
+        let Some((query_id, started, server, rtt_ms, status)) = query else {
+            return Ok(None);
+        };
+        let started = DateTime::parse_from_rfc3339(&started).map_err(|err| {
+            ClientError::GenericError(std::format!("{}: run started {}: {}", path, started, err))
+        })?;
+        let age = (at - started.with_timezone(&Utc)).num_seconds().max(0) as u64;
+        let mut statement = conn
+            .prepare("SELECT host, type, class, ttl, data FROM answers WHERE query_id = ?1")
+            .map_err(failed)?;
+        let rows = statement
+            .query_map(params![query_id], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, String>(2)?,
+                    row.get::<_, u32>(3)?,
+                    row.get::<_, String>(4)?,
+                ))
+            })
+            .map_err(failed)?;
+        let (mut answers, mut recorded) = (Vec::new(), 0);
+        for row in rows {
+            let (owner, record_type, class, ttl, data) = row.map_err(failed)?;
+            recorded += 1;
+            let Some(ttl) = (ttl as u64).checked_sub(age).filter(|ttl| *ttl > 0) else {
+                continue;
+            };
+            let text = std::format!("{} {} {} {} {}", owner, ttl, class, record_type, data);
+            match text.parse::<ResourceRecord>() {
+                Ok(record) => answers.push(QueryAnswer::cached(owner, record, server.clone(), age)),
+                Err(err) => log::info!("Skipping {} of {}: {}", text, path, err),
+            }
+        }
+        if recorded > 0 && answers.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(QueryResult {
+            host: host.to_string(),
+            query_type: query_type.clone(),
+            server,
+            rtt: Duration::from_secs_f64(rtt_ms / 1000.0),
+            outcome: match status.as_str() {
+                "nxdomain" => Outcome::NxDomain,
+                _ => Outcome::Succeeded,
+            },
+            error: None,
+            answers,
+            response: None,
+        }))
+    }
+}
@@ -0,0 +1,400 @@
+//! Filters of the answers printed, conditions on their fields joined with
+//! `&&`, `||`, `!` and parentheses, e.g. `type==A && ttl<60` or
+//! `data~"^10\."`, to slice large results without grep or jq.
+use crate::client::{self, QueryAnswer};
+use regex::{Regex, RegexBuilder};
+use std::str::FromStr;
+
+/// Fields of an answer conditions are on
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Field {
+    Host,
+    Type,
+    Class,
+    Ttl,
+    /// Round trip of the query, in milliseconds
+    Rtt,
+    Data,
+    Section,
+    Server,
+}
+
+impl Field {
+    fn parse(name: &str) -> Option<Field> {
+        match name.to_ascii_lowercase().as_str() {
+            "host" | "name" => Some(Field::Host),
+            "type" => Some(Field::Type),
+            "class" => Some(Field::Class),
+            "ttl" => Some(Field::Ttl),
+            "rtt" => Some(Field::Rtt),
+            "data" | "rdata" => Some(Field::Data),
+            "section" => Some(Field::Section),
+            "server" => Some(Field::Server),
+            _ => None,
+        }
+    }
+
+    fn number(&self, answer: &QueryAnswer) -> Option<f64> {
+        match self {
+            Field::Ttl => Some(answer.ttl() as f64),
+            Field::Rtt => Some(client::millis(answer.query_time())),
+            _ => None,
+        }
+    }
+
+    fn text(&self, answer: &QueryAnswer) -> String {
+        match self {
+            Field::Host => answer.host().trim_end_matches('.').to_string(),
+            Field::Type => answer.query_type().to_string(),
+            Field::Class => answer.class_type().to_string(),
+            Field::Ttl => answer.ttl().to_string(),
+            Field::Rtt => client::millis(answer.query_time()).to_string(),
+            Field::Data => answer.data().to_string(),
+            Field::Section => answer.section().to_string(),
+            Field::Server => answer.server().to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Op {
+    fn holds(&self, ordering: std::cmp::Ordering) -> bool {
+        match self {
+            Op::Eq => ordering.is_eq(),
+            Op::Ne => ordering.is_ne(),
+            Op::Lt => ordering.is_lt(),
+            Op::Le => ordering.is_le(),
+            Op::Gt => ordering.is_gt(),
+            Op::Ge => ordering.is_ge(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Expr {
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    /// A number field compared with a number
+    Number(Field, Op, f64),
+    /// A text field compared with a text, case-insensitively
+    Text(Field, Op, String),
+    /// A text field matched by a regular expression, `!~` when negated
+    Matches(Field, Regex, bool),
+}
+
+impl Expr {
+    fn matches(&self, answer: &QueryAnswer) -> bool {
+        match self {
+            Expr::And(left, right) => left.matches(answer) && right.matches(answer),
+            Expr::Or(left, right) => left.matches(answer) || right.matches(answer),
+            Expr::Not(expr) => !expr.matches(answer),
+            Expr::Number(field, op, value) => field
+                .number(answer)
+                .and_then(|number| number.partial_cmp(value))
+                .is_some_and(|ordering| op.holds(ordering)),
+            Expr::Text(field, op, value) => {
+                let text = field.text(answer).to_lowercase();
+                op.holds(text.as_str().cmp(value.as_str()))
+            }
+            Expr::Matches(field, regex, negated) => regex.is_match(&field.text(answer)) != *negated,
+        }
+    }
+}
+
+/// An expression on the fields of an answer
+#[derive(Debug, Clone)]
+pub struct Filter(Expr);
+
+impl Filter {
+    /// Whether `answer` passes the filter
+    pub fn matches(&self, answer: &QueryAnswer) -> bool {
+        self.0.matches(answer)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(String),
+    /// A quoted text, never an operator
+    Quoted(String),
+    And,
+    Or,
+    Not,
+    Open,
+    Close,
+}
+
+/// Comparison operators, the longer ones first
+const OPERATORS: [&str; 8] = ["==", "!=", "<=", ">=", "!~", "<", ">", "~"];
+
+fn after_operator(tokens: &[Token]) -> bool {
+    matches!(tokens.last(), Some(Token::Word(word)) if OPERATORS.contains(&word.as_str()))
+}
+
+fn tokens(s: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut rest = s.trim_start();
+    while let Some(c) = rest.chars().next() {
+        let (token, len) = match c {
+            '(' if !after_operator(&tokens) => (Token::Open, 1),
+            ')' => (Token::Close, 1),
+            '&' if rest.starts_with("&&") => (Token::And, 2),
+            '|' if rest.starts_with("||") => (Token::Or, 2),
+            '!' if !after_operator(&tokens)
+                && !rest.starts_with("!=")
+                && !rest.starts_with("!~") =>
+            {
+                (Token::Not, 1)
+            }
+            '"' if after_operator(&tokens) => {
+                let mut text = String::new();
+                let mut chars = rest.char_indices().skip(1);
+                let end = loop {
+                    match chars.next() {
+                        Some((_, '\\')) => match chars.next() {
+                            // \" and \\ stand for themselves, other escapes
+                            // are left to the regular expression
+                            Some((_, c @ ('"' | '\\'))) => text.push(c),
+                            Some((_, c)) => {
+                                text.push('\\');
+                                text.push(c);
+                            }
+                            None => return Err(std::format!("unterminated quote in {}", s)),
+                        },
+                        Some((i, '"')) => break i,
+                        Some((_, c)) => text.push(c),
+                        None => return Err(std::format!("unterminated quote in {}", s)),
+                    }
+                };
+                (Token::Quoted(text), end + 1)
+            }
+            // a value runs to the end of the condition, operators included
+            _ if after_operator(&tokens) => {
+                let len = rest
+                    .find(|c: char| c.is_whitespace() || c == ')')
+                    .into_iter()
+                    .chain(rest.find("&&"))
+                    .chain(rest.find("||"))
+                    .min()
+                    .unwrap_or(rest.len());
+                (Token::Word(rest[..len].to_string()), len)
+            }
+            _ => match OPERATORS.iter().find(|op| rest.starts_with(**op)) {
+                Some(op) => (Token::Word(op.to_string()), op.len()),
+                None => {
+                    let len = rest
+                        .find(|c: char| c.is_whitespace() || "()\"<>=!~&|".contains(c))
+                        .unwrap_or(rest.len())
+                        .max(1);
+                    (Token::Word(rest[..len].to_string()), len)
+                }
+            },
+        };
+        tokens.push(token);
+        rest = rest[len..].trim_start();
+    }
+    Ok(tokens)
+}
+
+/// Recursive descent over the tokens of an expression, `&&` binding tighter
+/// than `||`
+struct Parser {
+    tokens: Vec<Token>,
+    next: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.next)
+    }
+
+    fn take(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.next).cloned();
+        self.next += 1;
+        token
+    }
+
+    fn or(&mut self) -> Result<Expr, String> {
+        let mut filter = self.and()?;
+        while self.peek() == Some(&Token::Or) {
+            self.next += 1;
+            filter = Expr::Or(Box::new(filter), Box::new(self.and()?));
+        }
+        Ok(filter)
+    }
+
+    fn and(&mut self) -> Result<Expr, String> {
+        let mut filter = self.unary()?;
+        while self.peek() == Some(&Token::And) {
+            self.next += 1;
+            filter = Expr::And(Box::new(filter), Box::new(self.unary()?));
+        }
+        Ok(filter)
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        match self.take() {
+            Some(Token::Not) => Ok(Expr::Not(Box::new(self.unary()?))),
+            Some(Token::Open) => {
+                let filter = self.or()?;
+                match self.take() {
+                    Some(Token::Close) => Ok(filter),
+                    _ => Err("missing )".to_string()),
+                }
+            }
+            Some(Token::Word(name)) => self.condition(&name),
+            Some(token) => Err(std::format!("expected a field, found {:?}", token)),
+            None => Err("expected a field, found the end".to_string()),
+        }
+    }
+
+    fn condition(&mut self, name: &str) -> Result<Expr, String> {
+        let field = Field::parse(name).ok_or_else(|| {
+            std::format!(
+                "unknown field {}, expected host, type, class, ttl, rtt, data, section or server",
+                name
+            )
+        })?;
+        let op = match self.take() {
+            Some(Token::Word(op)) if OPERATORS.contains(&op.as_str()) => op,
+            _ => return Err(std::format!("expected an operator after {}", name)),
+        };
+        let value = match self.take() {
+            Some(Token::Word(value) | Token::Quoted(value)) => value,
+            _ => return Err(std::format!("expected a value after {}{}", name, op)),
+        };
+        let op = match op.as_str() {
+            "~" | "!~" => {
+                let regex = RegexBuilder::new(&value)
+                    .case_insensitive(true)
+                    .build()
+                    .map_err(|err| std::format!("invalid regular expression {}: {}", value, err))?;
+                return Ok(Expr::Matches(field, regex, op == "!~"));
+            }
+            "==" => Op::Eq,
+            "!=" => Op::Ne,
+            "<" => Op::Lt,
+            "<=" => Op::Le,
+            ">" => Op::Gt,
+            _ => Op::Ge,
+        };
+        if matches!(field, Field::Ttl | Field::Rtt) {
+            return match value.parse::<f64>() {
+                Ok(number) if number.is_finite() => Ok(Expr::Number(field, op, number)),
+                _ => Err(std::format!("{} isn't a number of {}", value, name)),
+            };
+        }
+        let value = match field {
+            Field::Host => value.trim_end_matches('.'),
+            _ => &value,
+        };
+        Ok(Expr::Text(field, op, value.to_lowercase()))
+    }
+}
+
+impl FromStr for Filter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser {
+            tokens: tokens(s)?,
+            next: 0,
+        };
+        let expr = parser.or()?;
+        match parser.peek() {
+            None => Ok(Filter(expr)),
+            Some(token) => Err(std::format!("unexpected {:?} in {}", token, s)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::{ClassType, QueryType, Response};
+    use crate::wire::{encode_name, Message, MessageBuilder};
+    use std::time::Duration;
+
+    fn answers() -> Vec<QueryAnswer> {
+        let encoded = MessageBuilder::new(1)
+            .with_response()
+            .with_question("www.example.org.", QueryType::A, ClassType::IN)
+            .with_answer(
+                "www.example.org.",
+                QueryType::CNAME,
+                ClassType::IN,
+                300,
+                encode_name("web.example.org"),
+            )
+            .with_answer(
+                "web.example.org.",
+                QueryType::A,
+                ClassType::IN,
+                30,
+                vec![10, 0, 0, 1],
+            )
+            .with_answer(
+                "web.example.org.",
+                QueryType::A,
+                ClassType::IN,
+                90,
+                vec![192, 0, 2, 1],
+            )
+            .build();
+        let message = Message::decode(&encoded).unwrap();
+        Response::new(
+            message,
+            "192.0.2.53:53".to_string(),
+            encoded.len(),
+            Duration::from_millis(12),
+        )
+        .answers()
+    }
+
+    fn matching(filter: &str) -> Vec<String> {
+        let filter: Filter = filter.parse().unwrap();
+        answers()
+            .iter()
+            .filter(|answer| filter.matches(answer))
+            .map(|answer| answer.data().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn filters_match() {
+        assert_eq!(matching("type==A && ttl<60"), vec!["10.0.0.1"]);
+        assert_eq!(matching("type==cname || ttl>=90").len(), 2);
+        assert_eq!(matching("!(type==A)").len(), 1);
+        assert_eq!(matching(r#"data~"^10\.""#), vec!["10.0.0.1"]);
+        assert_eq!(matching(r"data!~^10\. && type==A"), vec!["192.0.2.1"]);
+        assert_eq!(matching("host==WEB.example.org. && rtt<20").len(), 2);
+        assert_eq!(matching("section!=answer").len(), 0);
+    }
+
+    #[test]
+    fn invalid_filters() {
+        for filter in [
+            "",
+            "type",
+            "type==",
+            "size>1",
+            "ttl<soon",
+            "(type==A",
+            "type==A &&",
+            "data~(",
+            r#"data~"^10"#,
+        ] {
+            assert!(filter.parse::<Filter>().is_err(), "{}", filter);
+        }
+    }
+}
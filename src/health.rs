@@ -0,0 +1,115 @@
+//! Health of the upstream servers of the forwarder: each one is sent a SOA
+//! query of the root now and then, and queries skip those that didn't answer.
+use crate::client::{Client, ClientError, QueryType};
+use futures::future::join_all;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::time::sleep;
+
+/// Whether each upstream server answered its last probe, clones sharing it
+#[derive(Debug, Clone)]
+pub struct UpstreamHealth {
+    /// Each server with whether it is up, the list replaced on reloads
+    servers: Arc<RwLock<Vec<(String, bool)>>>,
+}
+
+impl UpstreamHealth {
+    /// Health of `servers`, all of them up until a probe says otherwise
+    pub fn new(servers: Vec<String>) -> UpstreamHealth {
+        let servers = servers.into_iter().map(|server| (server, true)).collect();
+        UpstreamHealth {
+            servers: Arc::new(RwLock::new(servers)),
+        }
+    }
+
+    /// Each server with whether it is up
+    pub fn states(&self) -> Vec<(String, bool)> {
+        self.servers
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .clone()
+    }
+
+    /// Replaces the servers of the clones too, those kept with their state
+    /// and the new ones up until probed
+    pub fn set_servers(&self, servers: Vec<String>) {
+        let mut states = self
+            .servers
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        *states = servers
+            .into_iter()
+            .map(|server| {
+                let up = states
+                    .iter()
+                    .find(|(kept, _)| *kept == server)
+                    .is_none_or(|(_, up)| *up);
+                (server, up)
+            })
+            .collect();
+    }
+
+    /// The servers up in order, or all of them when none is, as a server
+    /// coming back is better than failing right away
+    pub fn up_servers(&self) -> Vec<String> {
+        let states = self.states();
+        let up: Vec<String> = states
+            .iter()
+            .filter(|(_, up)| *up)
+            .map(|(server, _)| server.clone())
+            .collect();
+        match up.is_empty() {
+            true => states.into_iter().map(|(server, _)| server).collect(),
+            false => up,
+        }
+    }
+
+    async fn probe_server(server: &str, timeout: Duration) -> Result<(), ClientError> {
+        let client = Client::new(server.to_string())
+            .await?
+            .without_idn()
+            .with_timeout(timeout)
+            .with_attempts(1);
+        client.exchange(".", QueryType::SOA).await.map(|_| ())
+    }
+
+    /// Probes every server at once, logging those going down or coming back
+    pub async fn probe(&self, timeout: Duration) {
+        let servers: Vec<String> = self
+            .states()
+            .into_iter()
+            .map(|(server, _)| server)
+            .collect();
+        let probes = servers
+            .iter()
+            .map(|server| UpstreamHealth::probe_server(server, timeout));
+        let probed = join_all(probes).await;
+        let mut states = self
+            .servers
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        for (server, probed) in servers.iter().zip(probed) {
+            // a reload may have dropped the server meanwhile
+            let Some((_, up)) = states.iter_mut().find(|(kept, _)| kept == server) else {
+                continue;
+            };
+            let was_up = std::mem::replace(up, probed.is_ok());
+            match probed {
+                Err(err) if was_up => log::warn!("Upstream {} is down: {:?}", server, err),
+                Ok(()) if !was_up => log::warn!("Upstream {} is back up", server),
+                _ => {}
+            }
+        }
+    }
+
+    /// Probes the servers every `interval` in the background, waiting up to
+    /// `timeout` for each
+    pub fn spawn(self, interval: Duration, timeout: Duration) {
+        tokio::spawn(async move {
+            loop {
+                self.probe(timeout).await;
+                sleep(interval).await;
+            }
+        });
+    }
+}
@@ -0,0 +1,60 @@
+//! Internationalized domain names, IDNA 2008 (RFC 5891) as profiled by UTS 46
+//! nontransitional processing: Unicode names are sent as their punycode A-labels,
+//! RFC 3492, and A-labels of answers are shown in Unicode again.
+use crate::client::ClientError;
+use crate::rdata::RData;
+
+/// A-labels of `name` for the wire, names already in ASCII are left as given
+pub fn to_ascii(name: &str) -> Result<String, ClientError> {
+    if name.is_ascii() {
+        return Ok(name.to_string());
+    }
+    idna::domain_to_ascii(name)
+        .map_err(|err| ClientError::EncodeError(std::format!("{}: {}", name, err)))
+}
+
+/// Whether a label is a punycode A-label, RFC 5890 2.3.2.1
+fn is_a_label(label: &str) -> bool {
+    label.len() > 4 && label[..4].eq_ignore_ascii_case("xn--")
+}
+
+/// `name` with its A-labels in Unicode, as given when it has none or they
+/// aren't valid punycode
+pub fn to_unicode(name: &str) -> String {
+    if !name.split('.').any(is_a_label) {
+        return name.to_string();
+    }
+    match idna::domain_to_unicode(name) {
+        (unicode, Ok(())) => unicode,
+        (_, Err(_)) => name.to_string(),
+    }
+}
+
+/// `data` with the names it points to in Unicode, for display
+pub fn rdata_to_unicode(data: RData) -> RData {
+    match data {
+        RData::CNAME(name) => RData::CNAME(to_unicode(&name)),
+        RData::DNAME(name) => RData::DNAME(to_unicode(&name)),
+        RData::NS(name) => RData::NS(to_unicode(&name)),
+        RData::PTR(name) => RData::PTR(to_unicode(&name)),
+        RData::MX {
+            preference,
+            exchange,
+        } => RData::MX {
+            preference,
+            exchange: to_unicode(&exchange),
+        },
+        RData::SRV {
+            priority,
+            weight,
+            port,
+            target,
+        } => RData::SRV {
+            priority,
+            weight,
+            port,
+            target: to_unicode(&target),
+        },
+        data => data,
+    }
+}
@@ -0,0 +1,85 @@
+//! Kubernetes services as the cluster DNS serves them, following the naming
+//! conventions of the Kubernetes DNS-Based Service Discovery specification:
+//! `<service>.<namespace>.svc.<zone>` for the service, SRV records for its
+//! ports, and a name per endpoint for headless services.
+use crate::client::ClientError;
+use crate::resolver::{Resolver, SrvEndpoint};
+use std::net::IpAddr;
+
+/// Cluster domain when the search list doesn't tell it
+pub const DEFAULT_ZONE: &str = "cluster.local";
+
+/// Namespace and cluster domain of a pod, from the search list the kubelet
+/// gives it: `<namespace>.svc.<zone> svc.<zone> <zone>`
+pub fn pod_domain(search: &[String]) -> (Option<String>, Option<String>) {
+    let zone = search.iter().find_map(|domain| {
+        domain
+            .trim_end_matches('.')
+            .strip_prefix("svc.")
+            .map(|zone| zone.to_string())
+    });
+    let namespace = zone.as_ref().and_then(|zone| {
+        search.iter().find_map(|domain| {
+            domain
+                .trim_end_matches('.')
+                .strip_suffix(&std::format!(".svc.{}", zone))
+                .filter(|namespace| !namespace.contains('.'))
+                .map(|namespace| namespace.to_string())
+        })
+    });
+    (namespace, zone)
+}
+
+/// Name of a service given as `<service>.<namespace>`, or as `<service>` in
+/// `namespace`, under `zone`. Names already under the zone are kept.
+pub fn service_name(service: &str, namespace: &str, zone: &str) -> String {
+    let service = service.trim_end_matches('.');
+    if service.ends_with(&std::format!(".svc.{}", zone)) {
+        return service.to_string();
+    }
+    match service.contains('.') {
+        true => std::format!("{}.svc.{}", service, zone),
+        false => std::format!("{}.{}.svc.{}", service, namespace, zone),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Service {
+    pub name: String,
+    /// The cluster IP, or the addresses of every endpoint when headless
+    pub addrs: Vec<IpAddr>,
+    pub endpoints: Vec<SrvEndpoint>,
+}
+
+impl Service {
+    /// Whether the SRV records name endpoints rather than the service itself,
+    /// the mark of a service without a cluster IP
+    pub fn is_headless(&self) -> bool {
+        self.endpoints
+            .iter()
+            .any(|endpoint| endpoint.target != self.name)
+    }
+}
+
+/// Looks `name` up the way clients in the cluster would: its addresses, then
+/// the SRV records of `srv_name`, the service itself or one of its named
+/// ports, and the addresses of each endpoint they point to
+pub async fn lookup(
+    resolver: &Resolver,
+    name: &str,
+    srv_name: &str,
+) -> Result<Service, ClientError> {
+    let addrs = resolver.lookup_ip(name).await?;
+    let endpoints = match resolver.lookup_srv_endpoints(srv_name).await {
+        Ok(endpoints) => endpoints,
+        Err(err) => {
+            log::info!("No SRV record for {}: {:?}", srv_name, err);
+            Vec::new()
+        }
+    };
+    Ok(Service {
+        name: name.to_string(),
+        addrs,
+        endpoints,
+    })
+}
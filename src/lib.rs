@@ -0,0 +1,94 @@
+//! Async DNS client that partly implements RFC 1035, the library behind the
+//! `nsq` command line tool.
+//!
+//! ```no_run
+//! use nsq::{Client, QueryType, Resolver};
+//!
+//! # async fn run() -> Result<(), nsq::ClientError> {
+//! let resolver = Resolver::new(Client::new("1.1.1.1:53".to_string()).await?);
+//! for answer in resolver.query("www.crates.io", QueryType::A).await? {
+//!     println!("{} {} {}", answer.host(), answer.query_type(), answer.data());
+//! }
+//! # Ok(())
+//! # }
+//! ```
+#[cfg(feature = "serve")]
+pub mod acl;
+#[cfg(feature = "diagnostics")]
+pub mod alert;
+pub mod batchfile;
+#[cfg(feature = "diagnostics")]
+pub mod bench;
+pub mod budget;
+#[cfg(feature = "cache")]
+pub mod cache;
+#[cfg(feature = "diagnostics")]
+pub mod capture;
+#[cfg(feature = "diagnostics")]
+pub mod check;
+pub mod client;
+#[cfg(feature = "diagnostics")]
+pub mod compare;
+pub mod config;
+#[cfg(feature = "discovery")]
+pub mod consul;
+pub mod crypto;
+pub mod dedup;
+#[cfg(feature = "dnssec")]
+pub mod dnssec;
+#[cfg(feature = "diagnostics")]
+pub mod doctor;
+#[cfg(feature = "encrypted-transports")]
+pub mod doh;
+#[cfg(feature = "serve")]
+pub mod domainstats;
+pub mod ecs;
+#[cfg(feature = "diagnostics")]
+pub mod export;
+pub mod filter;
+#[cfg(feature = "serve")]
+pub mod health;
+pub mod idn;
+#[cfg(feature = "discovery")]
+pub mod k8s;
+#[cfg(feature = "discovery")]
+pub mod mdns;
+pub mod netns;
+pub mod nsconfig;
+pub mod output;
+#[cfg(feature = "serve")]
+pub mod ratelimit;
+pub mod rdata;
+#[cfg(feature = "interactive")]
+pub mod repl;
+pub mod resolver;
+pub mod roots;
+#[cfg(feature = "serve")]
+pub mod sandbox;
+#[cfg(feature = "serve")]
+pub mod serve;
+pub mod sink;
+pub mod sources;
+#[cfg(feature = "serve")]
+pub mod systemd;
+#[cfg(test)]
+mod testing;
+#[cfg(target_os = "linux")]
+pub mod timestamping;
+#[cfg(feature = "diagnostics")]
+pub mod trace;
+pub mod transport;
+#[cfg(feature = "zones")]
+pub mod tsig;
+#[cfg(feature = "zones")]
+pub mod update;
+#[cfg(feature = "diagnostics")]
+pub mod watch;
+pub mod wire;
+#[cfg(feature = "zones")]
+pub mod xfr;
+
+pub use client::{ClassType, Client, ClientError, QueryAnswer, QueryType};
+pub use rdata::{RData, ResourceRecord};
+pub use resolver::{IpStrategy, Resolver};
+pub use wire::MessageBuilder;
@@ -1,35 +1,2560 @@
-pub mod client;
-pub mod nsconfig;
 use chrono::Local;
-use clap::Parser;
-use client::{Client, ClientError, QueryAnswer, QueryType};
+#[cfg(feature = "diagnostics")]
+use chrono::{DateTime, NaiveDateTime, TimeZone, Utc};
+use clap::error::ErrorKind;
+#[cfg(feature = "serve")]
+use clap::Args;
+use clap::{CommandFactory, FromArgMatches, Parser, Subcommand};
 use env_logger::Env;
 use futures::future::join_all;
-use log;
+use futures::stream::{self, StreamExt};
+#[cfg(feature = "serve")]
+use nsq::acl::{Acl, Network};
+#[cfg(feature = "diagnostics")]
+use nsq::alert::{Alerts, Notifier, Rule, WebhookFormat};
+use nsq::batchfile::{BatchLine, BatchQuery};
+#[cfg(feature = "diagnostics")]
+use nsq::bench::{Bench, NameTemplate, TypeMix};
+use nsq::budget::{QueryBudget, RetryBudget};
+#[cfg(feature = "cache")]
+use nsq::cache::Cache;
+#[cfg(feature = "diagnostics")]
+use nsq::capture::Capture;
+#[cfg(feature = "diagnostics")]
+use nsq::check::{self, ResolverChecker};
+use nsq::client::{QueryResponse, Response};
+use nsq::client::{Randomness, Rotation};
+use nsq::config::{self, Config};
+use nsq::dedup::{self, DedupReport};
+#[cfg(feature = "dnssec")]
+use nsq::dnssec::{self, Ds};
+#[cfg(feature = "encrypted-transports")]
+use nsq::doh::{self, DohMethod};
+#[cfg(feature = "serve")]
+use nsq::domainstats;
+use nsq::ecs::ClientSubnet;
+#[cfg(feature = "diagnostics")]
+use nsq::export::Export;
+use nsq::filter::Filter;
+use nsq::netns::Netns;
+use nsq::nsconfig::{self, Hosts};
+#[cfg(any(feature = "diagnostics", feature = "zones", feature = "discovery"))]
+use nsq::output;
+use nsq::output::{
+    AnswerStream, Compat, Ipv6Format, Ipv6Style, Outcome, OutputFormat, SortKey, Summary,
+};
+#[cfg(feature = "serve")]
+use nsq::ratelimit::{ClientLimits, LimitAction};
+#[cfg(feature = "interactive")]
+use nsq::repl::{self, Line, Setting};
+use nsq::resolver;
+#[cfg(feature = "serve")]
+use nsq::sandbox;
+#[cfg(feature = "serve")]
+use nsq::serve::{self, Forwarder};
+#[cfg(feature = "diagnostics")]
+use nsq::sink::SqliteSink;
+use nsq::sink::{
+    CsvSink, DigSink, FilterSink, JsonSink, LinesSink, NdjsonSink, OutputSink, QueryResult,
+    StreamTableSink, TableSink, VerboseSink,
+};
+use nsq::sources::{DataSource, Sources};
+#[cfg(feature = "serve")]
+use nsq::systemd;
+#[cfg(feature = "diagnostics")]
+use nsq::trace::Tracer;
+use nsq::transport::{Source, Transport};
+#[cfg(feature = "zones")]
+use nsq::tsig::TsigKey;
+#[cfg(feature = "zones")]
+use nsq::update::ZoneUpdate;
+#[cfg(feature = "diagnostics")]
+use nsq::watch::Watch;
+#[cfg(feature = "zones")]
+use nsq::xfr::{self, ZoneTransfer};
+#[cfg(feature = "diagnostics")]
+use nsq::{compare, doctor, roots};
+#[cfg(feature = "discovery")]
+use nsq::{consul, k8s, mdns};
+use nsq::{ClassType, Client, ClientError, QueryAnswer, QueryType, Resolver};
+use std::collections::HashSet;
+#[cfg(feature = "interactive")]
+use std::io::IsTerminal;
 use std::io::Write;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+use tokio::sync::Semaphore;
 use tokio::task::JoinHandle;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
+#[command(args_conflicts_with_subcommands = true)]
 struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     /// Hostname to resolve
     hosts: Vec<String>,
 
-    #[arg(short, long, default_value_t = String::from(""))]
-    server: String,
+    /// Prompt for names and commands like nslookup does, the default when no
+    /// name is given
+    #[cfg(feature = "interactive")]
+    #[arg(
+        short,
+        long,
+        conflicts_with_all = ["hosts", "reverse", "file", "dedup", "sort"]
+    )]
+    #[cfg_attr(feature = "zones", arg(conflicts_with_all = ["axfr", "ixfr"]))]
+    #[cfg_attr(feature = "diagnostics", arg(conflicts_with_all = ["watch", "compare", "trace", "export"]))]
+    #[cfg_attr(
+        all(feature = "diagnostics", feature = "serve"),
+        arg(conflicts_with = "vantage")
+    )]
+    interactive: bool,
+
+    /// Query the names again every SECS, printing what changed in their
+    /// answers with the time of the round, until interrupted
+    #[cfg(feature = "diagnostics")]
+    #[arg(
+        long,
+        value_name = "SECS",
+        value_parser = parse_seconds,
+        conflicts_with_all = ["file", "dedup", "sort", "export", "compare", "trace", "compat"]
+    )]
+    watch: Option<Duration>,
+
+    /// Print an alert when a query watched breaks RULE, repeat it for more:
+    /// rtt>200ms, failure or change, streak rules taking the rounds in a row
+    /// they need after a colon like rtt>200ms:3
+    #[cfg(feature = "diagnostics")]
+    #[arg(long, value_name = "RULE", requires = "watch")]
+    alert: Vec<Rule>,
+
+    /// Run CMD with sh for every alert, which it finds in the NSQ_ALERT_HOST,
+    /// NSQ_ALERT_TYPE, NSQ_ALERT_RULE and NSQ_ALERT_MESSAGE variables
+    #[cfg(feature = "diagnostics")]
+    #[arg(long, value_name = "CMD", requires = "alert")]
+    alert_exec: Option<String>,
+
+    /// POST every alert to URL as a JSON object
+    #[cfg(feature = "diagnostics")]
+    #[arg(long, value_name = "URL", requires = "alert")]
+    alert_webhook: Option<String>,
+
+    /// POST the changes of each query watched to URL as a JSON object, every
+    /// round after the first they changed in
+    #[cfg(feature = "diagnostics")]
+    #[arg(long, value_name = "URL", requires = "watch")]
+    notify_url: Option<String>,
+
+    /// Body of the requests to --notify-url and --alert-webhook, slack for a
+    /// message Slack incoming webhooks take
+    #[cfg(feature = "diagnostics")]
+    #[arg(long, value_enum, value_name = "FORMAT", default_value = "json")]
+    webhook_format: WebhookFormat,
+
+    /// File of hostnames to resolve, one per line, or - for stdin, printing
+    /// results as their queries complete instead of once all are done. Lines
+    /// may be dig batch file lines, with types, a class, @server and +options
+    #[arg(
+        short,
+        long,
+        value_name = "PATH",
+        conflicts_with_all = ["reverse", "dedup", "sort"]
+    )]
+    file: Option<String>,
+
+    /// Stop at the first query that fails and exit with its error, instead of
+    /// reporting failed hosts next to the answers of the others
+    #[arg(long)]
+    fail_fast: bool,
+
+    /// Queries in flight at once, bounding the sockets a long list of hosts
+    /// opens
+    #[arg(long, value_name = "N", default_value_t = 64, value_parser = clap::value_parser!(u64).range(1..), env = "NSQ_CONCURRENCY")]
+    concurrency: u64,
+
+    /// Server to query, repeat it for servers to fail over to, instead of the
+    /// nameservers of /etc/resolv.conf
+    #[arg(short, long, env = "NSQ_SERVER", value_delimiter = ',')]
+    server: Vec<String>,
+
+    /// Send queries a server refused again to public resolvers
+    #[arg(long)]
+    fallback_public: bool,
+
+    /// Spread queries over the servers round-robin, like the rotate option of
+    /// /etc/resolv.conf, instead of always starting with the first one
+    #[arg(long)]
+    rotate: bool,
+
+    /// Resolve duplicate queries once, repeating their answers for every copy
+    #[arg(long)]
+    dedup: bool,
+
+    /// Search domain for names with fewer dots than the ndots option of
+    /// /etc/resolv.conf, repeat it for a list, instead of its search list
+    #[arg(
+        long,
+        value_name = "DOMAIN",
+        conflicts_with = "no_search",
+        env = "NSQ_SEARCH",
+        value_delimiter = ','
+    )]
+    search: Vec<String>,
+
+    /// Only query names as given, without trying the search list
+    #[arg(long)]
+    no_search: bool,
+
+    /// Query each name in every one of these domains instead, e.g. api in
+    /// prod.example.com and staging.example.com, its results together
+    #[arg(long, value_name = "DOMAIN", value_delimiter = ',')]
+    suffixes: Vec<String>,
+
+    /// Send and show internationalized names as given, without punycode conversion
+    #[arg(long)]
+    no_idn: bool,
+
+    /// Send query names in the case given, instead of randomizing the case of
+    /// their letters and rejecting responses that don't echo it (0x20)
+    #[arg(long)]
+    no_0x20: bool,
+
+    /// Seed the random choices of the run, transaction IDs, 0x20 casing,
+    /// cookies, rotation, retry jitter and the names and types of bench, so
+    /// it can be reproduced exactly
+    #[arg(long, value_name = "N", env = "NSQ_SEED", global = true)]
+    seed: Option<u64>,
+
+    /// Send queries without the Recursion Desired flag, to ask authoritative
+    /// servers what they know themselves
+    #[arg(long)]
+    norecurse: bool,
+
+    /// Send queries with the Checking Disabled flag, for the answers of a
+    /// validating resolver even when they fail DNSSEC validation
+    #[arg(long)]
+    cd: bool,
+
+    /// Send queries with the Authentic Data flag, for the resolver to tell
+    /// whether it validated the answers
+    #[arg(long)]
+    ad: bool,
+
+    /// Send every query to each --server and report how their answers differ
+    #[cfg(feature = "diagnostics")]
+    #[arg(long, conflicts_with = "file")]
+    compare: bool,
+
+    /// Address of a remote `nsq serve`, optionally named like fra=ADDR, to
+    /// resolve every query from, repeat it for more, reporting how the
+    /// answers of those vantage points differ
+    #[cfg(all(feature = "diagnostics", feature = "serve"))]
+    #[arg(
+        long,
+        value_name = "[NAME=]ADDR",
+        value_parser = parse_vantage,
+        conflicts_with_all = ["file", "compare", "trace", "watch"]
+    )]
+    vantage: Vec<(String, String)>,
+
+    /// Resolve iteratively from the root servers and show each delegation step
+    #[cfg(feature = "diagnostics")]
+    #[arg(long, conflicts_with_all = ["compare", "server", "file"])]
+    trace: bool,
+
+    /// Transfer each host as a zone from the first --server, printing its records
+    /// as they arrive
+    #[cfg(feature = "zones")]
+    #[arg(long, conflicts_with = "file")]
+    #[cfg_attr(feature = "diagnostics", arg(conflicts_with_all = ["watch", "compare", "trace", "export"]))]
+    #[cfg_attr(
+        all(feature = "diagnostics", feature = "serve"),
+        arg(conflicts_with = "vantage")
+    )]
+    #[cfg_attr(feature = "encrypted-transports", arg(conflicts_with = "doh"))]
+    axfr: bool,
+
+    /// Transfer the changes of each host as a zone since SERIAL, added and deleted
+    /// records per serial step, or the whole zone when the server can't
+    #[cfg(feature = "zones")]
+    #[arg(long, value_name = "SERIAL", conflicts_with_all = ["axfr", "file"])]
+    #[cfg_attr(feature = "diagnostics", arg(conflicts_with_all = ["watch", "compare", "trace", "export"]))]
+    #[cfg_attr(
+        all(feature = "diagnostics", feature = "serve"),
+        arg(conflicts_with = "vantage")
+    )]
+    #[cfg_attr(feature = "encrypted-transports", arg(conflicts_with = "doh"))]
+    ixfr: Option<u32>,
+
+    /// Write answers, errors and timings to a database, e.g. sqlite:results.db
+    #[cfg(feature = "diagnostics")]
+    #[arg(long, value_name = "sqlite:FILE", conflicts_with_all = ["compare", "trace"])]
+    export: Option<Export>,
+
+    /// Show what a cache filled by the runs of the --export database would
+    /// have answered at TIMESTAMP, like 2024-05-01T12:00:00 in local time or
+    /// RFC 3339, instead of querying
+    #[cfg(feature = "diagnostics")]
+    #[arg(long, value_name = "TIMESTAMP", value_parser = parse_timestamp, requires = "export", conflicts_with_all = ["file", "watch", "dedup"])]
+    at: Option<DateTime<Utc>>,
+
+    /// Root hints file in named.root format, for a lab root instead of the IANA one
+    #[cfg(feature = "diagnostics")]
+    #[arg(long, value_name = "FILE", requires = "trace")]
+    root_hints: Option<String>,
+
+    /// Class to query, e.g. CH for `version.bind` TXT
+    #[arg(long, default_value_t = ClassType::IN, env = "NSQ_CLASS")]
+    class: ClassType,
+
+    /// Reverse lookup, query the PTR records of an IPv4 or IPv6 address
+    #[arg(short = 'x', long = "reverse", value_name = "IP")]
+    reverse: Vec<IpAddr>,
+
+    /// Record types to query, e.g. MX, TXT or TYPE99, instead of both A and
+    /// AAAA, repeat it or separate them with commas for several
+    #[arg(short = 't', long = "type", value_delimiter = ',')]
+    query_type: Vec<QueryType>,
+
+    /// Types queried instead when a server answers ANY minimally, RFC 8482
+    #[arg(
+        long,
+        value_delimiter = ',',
+        default_value = "A,AAAA,CNAME,MX,NS,SOA,TXT"
+    )]
+    any_types: Vec<QueryType>,
+
+    /// DNS-over-HTTPS endpoint, e.g. https://cloudflare-dns.com/dns-query
+    #[cfg(feature = "encrypted-transports")]
+    #[arg(
+        long,
+        env = "NSQ_DOH",
+        conflicts_with_all = ["fallback_public", "source", "interface", "vrf", "mark"]
+    )]
+    #[cfg_attr(feature = "diagnostics", arg(conflicts_with_all = ["compare", "trace"]))]
+    doh: Option<String>,
+
+    /// HTTP method used for DNS-over-HTTPS queries
+    #[cfg(feature = "encrypted-transports")]
+    #[arg(long, value_enum, default_value_t = DohMethod::Post, env = "NSQ_DOH_METHOD")]
+    doh_method: DohMethod,
+
+    /// Pad DNS-over-HTTPS queries to a multiple of this many bytes, so their
+    /// length doesn't give the name away, 0 not to pad (RFC 7830)
+    #[cfg(feature = "encrypted-transports")]
+    #[arg(long, value_name = "BYTES", default_value_t = 128, env = "NSQ_PAD")]
+    pad: usize,
+
+    /// Plain DNS resolver to look up the --doh host and --server host names
+    /// with, once for the whole run, instead of the system resolver
+    #[arg(long, value_name = "IP", env = "NSQ_BOOTSTRAP")]
+    bootstrap: Option<IpAddr>,
+
+    /// Where CA roots, root hints and resolvers come from, tried in order:
+    /// the system's CA bundles, named.root and /etc/resolv.conf, or those
+    /// embedded in the binary
+    #[arg(
+        long,
+        value_enum,
+        value_name = "SOURCE",
+        value_delimiter = ',',
+        default_value = "system,embedded",
+        env = "NSQ_DATA_SOURCES",
+        global = true
+    )]
+    data_sources: Vec<DataSource>,
+
+    /// Request DNSSEC records and validate answers up to a trust anchor
+    #[cfg(feature = "dnssec")]
+    #[arg(long, env = "NSQ_DNSSEC")]
+    dnssec: bool,
+
+    /// File with DS records to trust instead of the root zone KSKs
+    #[cfg(feature = "dnssec")]
+    #[arg(long, requires = "dnssec", env = "NSQ_TRUST_ANCHOR")]
+    trust_anchor: Option<String>,
+
+    /// Always query upstream instead of serving repeated queries from the cache
+    #[cfg(feature = "cache")]
+    #[arg(long)]
+    no_cache: bool,
+
+    /// Hosts file to answer A and AAAA queries from before any server, by
+    /// default /etc/hosts when querying the servers of /etc/resolv.conf
+    #[arg(long, value_name = "PATH", env = "NSQ_HOSTS_FILE")]
+    hosts_file: Option<String>,
+
+    /// Query the servers for every name, even those of the hosts file
+    #[arg(long, conflicts_with = "hosts_file")]
+    no_hosts: bool,
+
+    /// Send DNS cookies and try again with the server cookie after BADCOOKIE
+    #[arg(long, env = "NSQ_COOKIE")]
+    cookie: bool,
+
+    /// Also measure the round trip of UDP queries with kernel timestamps, Linux only
+    #[arg(long)]
+    timestamping: bool,
+
+    /// Print an annotated hex dump of every query sent and response received
+    /// on stderr
+    #[cfg(feature = "diagnostics")]
+    #[arg(long)]
+    dump: bool,
+
+    /// Write the queries and responses exchanged over UDP to a pcap file, for
+    /// Wireshark
+    #[cfg(feature = "diagnostics")]
+    #[arg(long, value_name = "FILE")]
+    pcap: Option<String>,
+
+    /// Seconds to wait for a response before giving up on an attempt
+    #[arg(long, value_name = "SECS", default_value = "3", value_parser = parse_seconds, env = "NSQ_TIMEOUT")]
+    timeout: Duration,
+
+    /// Timeout of one server or transport, e.g. 192.0.2.1=10 for a slow link or
+    /// udp=0.5, repeat it for each
+    #[arg(long, value_name = "SERVER=SECS", value_parser = parse_server_timeout)]
+    server_timeout: Vec<(String, Duration)>,
+
+    /// Query over multicast DNS whatever the name, as names under .local are
+    #[cfg(feature = "discovery")]
+    #[arg(long, conflicts_with_all = ["source", "interface", "vrf", "mark"])]
+    #[cfg_attr(feature = "zones", arg(conflicts_with_all = ["axfr", "ixfr"]))]
+    #[cfg_attr(feature = "diagnostics", arg(conflicts_with_all = ["compare", "trace"]))]
+    #[cfg_attr(
+        all(feature = "diagnostics", feature = "serve"),
+        arg(conflicts_with = "vantage")
+    )]
+    #[cfg_attr(feature = "encrypted-transports", arg(conflicts_with = "doh"))]
+    mdns: bool,
+
+    /// Seconds mDNS responses are collected for, from every host that answers
+    #[cfg(feature = "discovery")]
+    #[arg(long, value_name = "SECS", default_value = "1", value_parser = parse_seconds)]
+    mdns_window: Duration,
+
+    /// Sign queries and transfers with a key shared with the server, checking
+    /// the signature of responses, e.g. key-name:hmac-sha256:BASE64SECRET
+    #[cfg(feature = "zones")]
+    #[arg(
+        long,
+        value_name = "NAME:ALGORITHM:SECRET",
+        env = "NSQ_TSIG",
+        hide_env_values = true
+    )]
+    tsig: Option<TsigKey>,
+
+    /// Local address queries are sent from, with a port or an ephemeral one,
+    /// e.g. 192.0.2.10 or [2001:db8::10]:5300
+    #[arg(long, value_name = "ADDR[:PORT]", value_parser = parse_source, env = "NSQ_SOURCE")]
+    source: Option<SocketAddr>,
+
+    /// Network interface queries are sent through whatever the routes say,
+    /// e.g. a VPN tunnel (Linux only)
+    #[arg(long, value_name = "NAME", env = "NSQ_INTERFACE")]
+    interface: Option<String>,
+
+    /// VRF device whose routing table queries follow, on routers and
+    /// firewalls (Linux only)
+    #[arg(
+        long,
+        value_name = "NAME",
+        conflicts_with = "interface",
+        env = "NSQ_VRF"
+    )]
+    vrf: Option<String>,
+
+    /// Firewall mark of the queries for policy routing rules, e.g. 0x64, as
+    /// root or with CAP_NET_ADMIN (Linux only)
+    #[arg(long, value_name = "MARK", value_parser = parse_mark, env = "NSQ_MARK")]
+    mark: Option<u32>,
+
+    /// Network namespace to send queries from, by its `ip netns` name or the
+    /// PID of a process in it like a container, reading its resolv.conf and
+    /// hosts file (Linux only, as root)
+    #[arg(long, value_name = "NAME|PID", env = "NSQ_NETNS", global = true)]
+    netns: Option<Netns>,
+
+    /// Network to query on behalf of, sent as an EDNS Client Subnet option, e.g.
+    /// 203.0.113.0/24 to see the answers a CDN gives there
+    #[arg(long, value_name = "SUBNET", env = "NSQ_SUBNET")]
+    subnet: Option<ClientSubnet>,
+
+    /// CNAME and DNAME records a query may go through before failing, chains the
+    /// response leaves unfinished being followed with more queries
+    #[arg(long, value_name = "DEPTH", default_value_t = nsq::client::MAX_CNAME_DEPTH)]
+    cname_depth: usize,
+
+    /// Times a query is sent before giving up, the retry budget permitting
+    #[arg(long, default_value_t = 2, value_parser = clap::value_parser!(u64).range(1..), env = "NSQ_ATTEMPTS")]
+    attempts: u64,
+
+    /// Maximum number of upstream queries for the whole run, DNSSEC lookups included
+    #[arg(long)]
+    max_queries: Option<usize>,
+
+    /// Percentage of queries that may be retried, shared by the whole run
+    #[arg(long, default_value_t = 20)]
+    retry_budget: u64,
+
+    /// How answers are printed
+    #[arg(short, long, value_enum, default_value_t = OutputFormat::Table, env = "NSQ_OUTPUT")]
+    output: OutputFormat,
+
+    /// Print responses in the exact layout of another tool, for scripts that
+    /// scrape its output, sending names in the case given like it does
+    #[arg(long, value_enum, conflicts_with_all = ["output", "short"])]
+    compat: Option<Compat>,
+
+    /// Only print the data of each answer, one per line, like `dig +short`
+    #[arg(long, conflicts_with = "output")]
+    short: bool,
+
+    /// Also show the records of the authority and additional sections, such
+    /// as the SOA of negative answers and the glue of referrals
+    #[arg(long)]
+    all_sections: bool,
+
+    /// Order answers by host, RTT or TTL instead of the query order
+    #[arg(long, value_enum)]
+    sort: Option<SortKey>,
+
+    /// Only print the answers matching EXPR, e.g. "type==A && ttl<60" or
+    /// "data~^10\.", repeat for answers matching every one
+    #[arg(long, value_name = "EXPR")]
+    filter: Vec<Filter>,
+
+    /// Print at most N answers of each query, or records of a zone transfer,
+    /// the others being counted without decoding them
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..), env = "NSQ_LIMIT")]
+    limit: Option<u64>,
+
+    /// Which N answers --limit prints, from 1
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u64).range(1..), requires = "limit")]
+    page: u64,
+
+    /// Show totals of the run: queries, success rate, NXDOMAIN answers and RTTs
+    #[arg(long)]
+    summary: bool,
+
+    /// Display width of the data column in table output, longer values are wrapped
+    #[arg(long, default_value_t = 64, env = "NSQ_WIDTH")]
+    width: usize,
+
+    /// Cut data longer than --width with a marker instead of wrapping it
+    #[arg(long)]
+    truncate: bool,
+
+    /// How IPv6 addresses are written
+    #[arg(long, value_enum, default_value_t = Ipv6Style::Compressed, env = "NSQ_IPV6")]
+    ipv6: Ipv6Style,
+
+    /// Write the hex digits of IPv6 addresses in uppercase
+    #[arg(long)]
+    uppercase: bool,
+
+    /// Only print answers, silencing diagnostics on stderr
+    #[arg(short, long, conflicts_with = "verbose")]
+    quiet: bool,
+
+    /// Print more diagnostics on stderr, repeat for debug logs
+    #[arg(short, long, action = clap::ArgAction::Count)]
+    verbose: u8,
+
+    /// TOML file of defaults for the flags, by their long names, instead of
+    /// ~/.config/nsq/config.toml
+    #[arg(long, value_name = "PATH", env = "NSQ_CONFIG", global = true)]
+    config: Option<String>,
+
+    /// Ignore the config file
+    #[arg(long, conflicts_with = "config", global = true)]
+    no_config: bool,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Send a resolver many queries and report their latencies and responses
+    #[cfg(feature = "diagnostics")]
+    Bench {
+        /// Names to query, in turn, where {1..1000} counts through a range
+        /// and {random} or {random:N} draws a label for every query
+        #[arg(default_value = "example.com")]
+        names: Vec<NameTemplate>,
+
+        /// Resolver to benchmark instead of the first nameserver of /etc/resolv.conf
+        #[arg(short, long)]
+        server: Option<String>,
+
+        /// Record type to query
+        #[arg(short = 't', long = "type", default_value = "A")]
+        query_type: QueryType,
+
+        /// Types to query in proportion to their weights instead, e.g.
+        /// A=70,AAAA=20,MX=10
+        #[arg(long, value_name = "TYPE=WEIGHT,...", conflicts_with = "query_type")]
+        mix: Option<TypeMix>,
+
+        /// Number of queries to send
+        #[arg(short = 'n', long, default_value_t = 1000)]
+        count: usize,
+
+        /// Queries to send before the measured ones, reported apart, to
+        /// measure a resolver answering from its cache
+        #[arg(long, value_name = "N", default_value_t = 0)]
+        warmup: usize,
+
+        /// Put a random label in front of every name so none is answered from
+        /// the cache, to measure uncached resolution
+        #[arg(long)]
+        cache_bust: bool,
+
+        /// Queries per second to send, as fast as responses come back otherwise
+        #[arg(long, value_parser = parse_rate)]
+        qps: Option<f64>,
+
+        /// Queries waiting for a response at once
+        #[arg(long, default_value_t = 64, value_parser = clap::value_parser!(u64).range(1..))]
+        concurrency: u64,
+
+        /// Seconds to wait for each response
+        #[arg(long, value_name = "SECS", default_value = "2", value_parser = parse_seconds)]
+        timeout: Duration,
+    },
+    /// Check resolvers: whether they answer well-known queries and how fast,
+    /// whether they support EDNS, TCP and DNSSEC and whether they rewrite
+    /// NXDOMAIN, failing when one of them is down
+    #[cfg(feature = "diagnostics")]
+    Check {
+        /// Resolvers to check instead of the ones in /etc/resolv.conf
+        resolvers: Vec<String>,
+
+        /// Seconds to wait for each response
+        #[arg(long, value_name = "SECS", default_value = "2", value_parser = parse_seconds)]
+        timeout: Duration,
+    },
+    /// Check the local environment for common DNS problems and suggest fixes
+    #[cfg(feature = "diagnostics")]
+    Doctor {
+        /// Resolvers to check instead of the ones in /etc/resolv.conf
+        #[arg(short, long)]
+        server: Vec<String>,
+    },
+    /// Look a Kubernetes service up through the cluster DNS: its addresses, ports
+    /// and the endpoints of headless services
+    #[cfg(feature = "discovery")]
+    K8s {
+        /// Service as <service>.<namespace>, or <service> in the pod's namespace
+        service: String,
+
+        /// Cluster DNS server instead of the nameservers of /etc/resolv.conf
+        #[arg(short, long)]
+        server: Vec<String>,
+
+        /// Named port to look up, _<port>._<protocol>.<service>.<namespace>.svc.<zone>
+        #[arg(short, long)]
+        port: Option<String>,
+
+        /// Protocol of the named port
+        #[arg(long, default_value = "tcp", requires = "port")]
+        protocol: String,
+
+        /// Cluster domain, taken from the search list of /etc/resolv.conf by default
+        #[arg(long)]
+        zone: Option<String>,
+    },
+    /// Look a Consul service up, e.g. web.service.dc1: the node and port of each
+    /// instance
+    #[cfg(feature = "discovery")]
+    Consul {
+        /// Service as <service>, [<tag>.]<service>.service.<datacenter> or in full
+        service: String,
+
+        /// DNS interface of a Consul agent
+        #[arg(short, long, default_value = consul::AGENT)]
+        server: SocketAddr,
+
+        /// Domain Consul serves, its -domain option
+        #[arg(long, default_value = consul::DEFAULT_DOMAIN)]
+        domain: String,
+    },
+    /// Send a dynamic update (RFC 2136) adding and deleting records of a zone
+    #[cfg(feature = "zones")]
+    Update {
+        /// Zone to update
+        zone: String,
+
+        /// Primary server of the zone
+        #[arg(short, long)]
+        server: String,
+
+        /// Record to add, as "NAME TTL TYPE DATA"
+        #[arg(long, value_name = "RECORD")]
+        add: Vec<String>,
+
+        /// Records to delete, as "NAME", "NAME TYPE" or "NAME TYPE DATA"
+        #[arg(long, value_name = "RECORD")]
+        delete: Vec<String>,
+
+        /// Only update if the name, or its records of TYPE, exist: "NAME [TYPE]"
+        #[arg(long, value_name = "NAME")]
+        exists: Vec<String>,
+
+        /// Only update if the name, or its records of TYPE, don't exist: "NAME [TYPE]"
+        #[arg(long, value_name = "NAME")]
+        absent: Vec<String>,
+
+        /// Sign the update with a key the server accepts updates from
+        #[arg(long, value_name = "NAME:ALGORITHM:SECRET")]
+        tsig: Option<TsigKey>,
+    },
+    /// Show the queries, rcodes and latency percentiles of each domain a
+    /// running `nsq serve` forwarded queries of
+    #[cfg(feature = "serve")]
+    Stats {
+        /// Domain to show alone, e.g. example.com, instead of the busiest ones
+        domain: Option<String>,
+
+        /// Address `nsq serve` listens on
+        #[arg(short, long, default_value = "127.0.0.1:5353")]
+        server: SocketAddr,
+    },
+    /// Inspect or flush the cache of a running `nsq serve`
+    #[cfg(feature = "serve")]
+    Cache {
+        #[command(subcommand)]
+        command: CacheCommand,
+    },
+    /// Forward queries received on a local address to upstream resolvers
+    #[cfg(feature = "serve")]
+    Serve(Box<ServeArgs>),
+}
+
+/// Options of `nsq serve`, boxed in `Command` as they outweigh the others
+#[cfg(feature = "serve")]
+#[derive(Args, Debug)]
+struct ServeArgs {
+    /// Address to listen on over UDP and TCP, unless systemd passed the
+    /// sockets of a socket unit
+    #[arg(short, long, default_value = "127.0.0.1:5353")]
+    listen: SocketAddr,
+
+    /// Upstream server, repeat it for servers to fail over to, instead of
+    /// the nameservers of /etc/resolv.conf
+    #[arg(short, long)]
+    server: Vec<String>,
+
+    /// Seconds to wait for an upstream response
+    #[arg(long, value_name = "SECS", default_value = "3", value_parser = parse_seconds)]
+    timeout: Duration,
+
+    /// Forward every query upstream instead of answering from the cache
+    #[arg(long)]
+    no_cache: bool,
+
+    /// Answer with responses that expired up to SECS ago when every
+    /// upstream server fails, e.g. 86400 to ride out a day-long outage
+    #[arg(long, value_name = "SECS", value_parser = parse_seconds, conflicts_with = "no_cache")]
+    serve_stale: Option<Duration>,
+
+    /// Memory the cache may take, e.g. 16M, evicting the responses
+    /// closest to expiring to stay under it
+    #[arg(long, value_name = "SIZE", value_parser = parse_size, conflicts_with = "no_cache")]
+    cache_max_mem: Option<usize>,
+
+    /// Queries per second each client address may send
+    #[arg(long, value_name = "QPS", value_parser = parse_rate)]
+    rate_limit: Option<f64>,
+
+    /// Queries a client may send at once after a quiet spell, above its
+    /// rate limit, instead of one second worth of them
+    #[arg(long, value_name = "QUERIES", requires = "rate_limit")]
+    burst: Option<u32>,
+
+    /// Queries of each client waiting for upstream at once
+    #[arg(long, value_name = "QUERIES", value_parser = clap::value_parser!(u64).range(1..))]
+    max_in_flight: Option<u64>,
+
+    /// How queries over the limits of their client are answered
+    #[arg(long, value_enum, default_value_t = LimitAction::Drop)]
+    limit_action: LimitAction,
+
+    /// Network to answer, e.g. 192.168.1.0/24, repeat it for a list,
+    /// refusing every other client
+    #[arg(long, value_name = "CIDR")]
+    allow: Vec<Network>,
+
+    /// Network to refuse even when allowed, repeat it for a list
+    #[arg(long, value_name = "CIDR")]
+    deny: Vec<Network>,
+
+    /// Seconds between probes of the upstream servers, queries skipping
+    /// those that failed their last one
+    #[arg(long, value_name = "SECS", default_value = "10", value_parser = parse_seconds)]
+    probe_interval: Duration,
+
+    /// Send queries to every upstream server in order, without probing them
+    #[arg(long)]
+    no_probes: bool,
+
+    /// Seconds a TCP connection may stay without a query before it is
+    /// closed
+    #[arg(long, value_name = "SECS", default_value = "10", value_parser = parse_seconds)]
+    tcp_idle_timeout: Duration,
+
+    /// TCP connections served at once, more waiting until one closes
+    #[arg(long, value_name = "CONNECTIONS", default_value = "150", value_parser = clap::value_parser!(u64).range(1..))]
+    max_tcp_sessions: u64,
+
+    /// User to switch to once the sockets are bound, when started as root
+    /// to listen on port 53
+    #[arg(long, value_name = "NAME")]
+    user: Option<String>,
+
+    /// Forbid running programs and changing files once the sockets are
+    /// bound, with a seccomp filter (Linux on x86_64 and aarch64)
+    #[arg(long)]
+    sandbox: bool,
+}
+
+#[cfg(feature = "serve")]
+#[derive(Subcommand, Debug)]
+enum CacheCommand {
+    /// Show the entries, memory, hits, misses and evictions of the cache
+    Stats {
+        /// Address `nsq serve` listens on
+        #[arg(short, long, default_value = "127.0.0.1:5353")]
+        server: SocketAddr,
+    },
+    /// List the names and types cached, with the seconds left before they
+    /// expire
+    Ls {
+        /// Address `nsq serve` listens on, on this host
+        #[arg(short, long, default_value = "127.0.0.1:5353")]
+        server: SocketAddr,
+    },
+    /// Drop the cached responses of a name, after a change of its records,
+    /// or every cached response
+    Flush {
+        /// Name to drop the responses of, of every type
+        name: Option<String>,
+
+        /// Address `nsq serve` listens on, on this host
+        #[arg(short, long, default_value = "127.0.0.1:5353")]
+        server: SocketAddr,
+    },
+}
+
+/// A vantage point as its name and address, named after its address
+/// without one
+#[cfg(all(feature = "diagnostics", feature = "serve"))]
+fn parse_vantage(value: &str) -> Result<(String, String), String> {
+    let (name, server) = value.split_once('=').unwrap_or((value, value));
+    match name.is_empty() || server.is_empty() {
+        true => Err(std::format!("{} isn't [NAME=]ADDR", value)),
+        false => Ok((name.to_string(), server.to_string())),
+    }
+}
+
+#[cfg(any(feature = "diagnostics", feature = "serve"))]
+fn parse_rate(value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(rate) if rate > 0.0 && rate.is_finite() => Ok(rate),
+        _ => Err(std::format!("{} isn't a positive number of queries", value)),
+    }
+}
+
+/// A number of bytes, with an optional K, M or G suffix of powers of 1024
+#[cfg(feature = "serve")]
+fn parse_size(value: &str) -> Result<usize, String> {
+    let (digits, unit) = match value.char_indices().find(|(_, c)| c.is_ascii_alphabetic()) {
+        Some((at, _)) => value.split_at(at),
+        None => (value, ""),
+    };
+    let unit: usize = match unit.to_ascii_uppercase().trim_end_matches(['B', 'I']) {
+        "" => 1,
+        "K" => 1 << 10,
+        "M" => 1 << 20,
+        "G" => 1 << 30,
+        _ => return Err(std::format!("{} isn't a size, e.g. 512K or 16M", value)),
+    };
+    match digits.trim().parse::<usize>() {
+        Ok(size) if size > 0 => size
+            .checked_mul(unit)
+            .ok_or_else(|| std::format!("{} is too large", value)),
+        _ => Err(std::format!("{} isn't a size, e.g. 512K or 16M", value)),
+    }
+}
+
+fn parse_seconds(value: &str) -> Result<Duration, String> {
+    match value.parse::<f64>() {
+        Ok(secs) if secs > 0.0 && secs.is_finite() => Ok(Duration::from_secs_f64(secs)),
+        _ => Err(std::format!("{} isn't a positive number of seconds", value)),
+    }
+}
+
+/// A moment in RFC 3339, or in local time as --watch prints it
+#[cfg(feature = "diagnostics")]
+fn parse_timestamp(value: &str) -> Result<DateTime<Utc>, String> {
+    if let Ok(time) = DateTime::parse_from_rfc3339(value) {
+        return Ok(time.with_timezone(&Utc));
+    }
+    ["%Y-%m-%dT%H:%M:%S", "%Y-%m-%d %H:%M:%S"]
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(value, format).ok())
+        .and_then(|time| Local.from_local_datetime(&time).earliest())
+        .map(|time| time.with_timezone(&Utc))
+        .ok_or_else(|| std::format!("{} isn't a time like 2024-05-01T12:00:00", value))
+}
+
+/// dig query options and the flags they stand for, the value of `+option=value`
+/// passed on to the flag
+const DIG_OPTIONS: [(&str, &str); 8] = [
+    ("short", "--short"),
+    ("dnssec", "--dnssec"),
+    ("trace", "--trace"),
+    ("cookie", "--cookie"),
+    ("noidnout", "--no-idn"),
+    ("subnet", "--subnet"),
+    ("time", "--timeout"),
+    ("tries", "--attempts"),
+];
+
+/// The config file of the run: that of --config or NSQ_CONFIG, or the one of
+/// the user when it exists, none with --no-config
+fn config_file(args: &[String]) -> Option<String> {
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--no-config" => return None,
+            "--config" => return args.next().cloned(),
+            _ => {
+                if let Some(path) = arg.strip_prefix("--config=") {
+                    return Some(path.to_string());
+                }
+            }
+        }
+    }
+    if let Some(path) = std::env::var_os("NSQ_CONFIG") {
+        return Some(path.to_string_lossy().into_owned());
+    }
+    let path = config::default_path()?;
+    path.exists().then(|| path.to_string_lossy().into_owned())
+}
+
+/// The command line parsed, the flags it doesn't give defaulting to the
+/// values of the config file
+fn parse_cli(args: Vec<String>) -> Result<Cli, clap::Error> {
+    let mut command = Cli::command();
+    if let Some(path) = config_file(&args) {
+        let config = Config::read(&path).map_err(|err| command.error(ErrorKind::Io, err))?;
+        command = config
+            .apply(command)
+            .map_err(|err| Cli::command().error(ErrorKind::InvalidValue, err))?;
+    }
+    let matches = command.try_get_matches_from(args)?;
+    Cli::from_arg_matches(&matches)
+}
+
+/// Rewrites dig style arguments into flags, `nsq @1.1.1.1 example.com MX
+/// +short` being `nsq --server 1.1.1.1 --type MX --short example.com`: `@`
+/// selects the server, a record type after the host the type and `+option`
+/// a flag. As with dig, the first bare word is the host even if it names a
+/// type, so `nsq ns ns` asks for the NS records of `ns`
+fn dig_args(args: Vec<String>) -> Result<Vec<String>, String> {
+    let command = Cli::command();
+    // subcommands take their own arguments
+    if args
+        .get(1)
+        .is_some_and(|arg| command.find_subcommand(arg).is_some())
+    {
+        return Ok(args);
+    }
+    let valued: Vec<String> = command
+        .get_arguments()
+        .filter(|arg| !arg.is_positional() && arg.get_action().takes_values())
+        .flat_map(|arg| {
+            let long = arg.get_long().map(|long| std::format!("--{}", long));
+            let short = arg.get_short().map(|short| std::format!("-{}", short));
+            long.into_iter().chain(short)
+        })
+        .collect();
+    let mut rewritten = Vec::with_capacity(args.len());
+    let mut args = args.into_iter();
+    rewritten.extend(args.next());
+    // the value of the previous flag, taken as is
+    let mut value = false;
+    // a bare word before the host is the host, whatever it spells
+    let mut host = false;
+    for arg in args.by_ref() {
+        if value || arg.starts_with('-') {
+            value = valued.contains(&arg);
+            let end = arg == "--";
+            rewritten.push(arg);
+            if end {
+                break;
+            }
+        } else if let Some(server) = arg.strip_prefix('@') {
+            rewritten.extend(["--server".to_string(), server.to_string()]);
+        } else if let Some(option) = arg.strip_prefix('+') {
+            let (name, option_value) = match option.split_once('=') {
+                Some((name, option_value)) => (name, Some(option_value)),
+                None => (option, None),
+            };
+            let flag = DIG_OPTIONS
+                .iter()
+                .find(|(dig, _)| *dig == name)
+                .map(|(_, flag)| flag.to_string())
+                .ok_or_else(|| std::format!("unsupported dig option {}", arg))?;
+            rewritten.push(flag);
+            rewritten.extend(option_value.map(|value| value.to_string()));
+        } else if host
+            && arg.starts_with(|c: char| c.is_ascii_alphabetic())
+            && arg.parse::<QueryType>().is_ok()
+        {
+            rewritten.extend(["--type".to_string(), arg]);
+        } else {
+            host = true;
+            rewritten.push(arg);
+        }
+    }
+    rewritten.extend(args);
+    Ok(rewritten)
+}
+
+fn parse_source(value: &str) -> Result<SocketAddr, String> {
+    match (value.parse::<SocketAddr>(), value.parse::<IpAddr>()) {
+        (Ok(addr), _) => Ok(addr),
+        (_, Ok(ip)) => Ok(SocketAddr::new(ip, 0)),
+        _ => Err(std::format!(
+            "{} isn't an address with an optional port",
+            value
+        )),
+    }
+}
+
+/// A decimal mark, or a hexadecimal one as `ip rule` prints them
+fn parse_mark(value: &str) -> Result<u32, String> {
+    let mark = match value.strip_prefix("0x").or(value.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => value.parse::<u32>(),
+    };
+    mark.map_err(|_| std::format!("{} isn't a mark, e.g. 100 or 0x64", value))
+}
+
+fn parse_server_timeout(value: &str) -> Result<(String, Duration), String> {
+    let (server, secs) = match value.rsplit_once('=') {
+        Some(pair) => pair,
+        None => return Err(std::format!("{} isn't SERVER=SECS", value)),
+    };
+    if !matches!(server, "udp" | "https") && !server.starts_with("https://") {
+        resolver::split_server(server).map_err(|err| std::format!("{:?}", err))?;
+    }
+    Ok((server.to_string(), parse_seconds(secs)?))
+}
+
+impl Cli {
+    fn log_level(&self) -> &'static str {
+        match (self.quiet, self.verbose) {
+            (true, _) => "error",
+            (false, 0) => "warn",
+            (false, 1) => "info",
+            (false, _) => "debug",
+        }
+    }
+}
+
+/// Settings shared by the clients of a run, one client per query
+#[derive(Clone)]
+struct ClientSettings {
+    /// The primary server first, then the ones to fail over to
+    servers: Vec<String>,
+    #[cfg(feature = "encrypted-transports")]
+    doh: Option<DohEndpoint>,
+    #[cfg(feature = "dnssec")]
+    trust_anchors: Option<Vec<Ds>>,
+    budget: QueryBudget,
+    retry_budget: RetryBudget,
+    class: ClassType,
+    timeout: Duration,
+    server_timeouts: Vec<(String, Duration)>,
+    #[cfg(feature = "zones")]
+    tsig: Option<TsigKey>,
+    /// Window mDNS responses are collected for, when querying the mDNS group
+    #[cfg(feature = "discovery")]
+    mdns: Option<Duration>,
+    cname_depth: usize,
+    subnet: Option<ClientSubnet>,
+    source: Source,
+    attempts: usize,
+    rotation: Option<Rotation>,
+    randomness: Randomness,
+    timestamping: bool,
+    #[cfg(feature = "diagnostics")]
+    capture: Option<Capture>,
+    idn: bool,
+    randomize_case: bool,
+    /// Header flags of queries
+    recursion: bool,
+    checking_disabled: bool,
+    authentic_data: bool,
+    all_sections: bool,
+    /// Answers per page and the page printed, with --limit
+    answer_page: Option<(usize, usize)>,
+    cookie: bool,
+    #[cfg(feature = "cache")]
+    cache: Option<Cache>,
+    hosts: Option<Hosts>,
+    any_types: Vec<QueryType>,
+}
+
+/// A DNS-over-HTTPS endpoint and how it is queried
+#[cfg(feature = "encrypted-transports")]
+#[derive(Clone)]
+struct DohEndpoint {
+    url: String,
+    method: DohMethod,
+    /// Addresses the host was pinned to by the bootstrap resolver
+    addrs: Vec<IpAddr>,
+    sources: Sources,
+    /// Block size queries are padded to, 0 not to pad
+    padding: usize,
+}
+
+/// Resolvers refused queries fall back to with --fallback-public
+const PUBLIC_RESOLVERS: [&str; 2] = ["1.1.1.1:53", "8.8.8.8:53"];
+
+impl ClientSettings {
+    /// The same settings with the public resolvers as servers
+    fn public(self) -> ClientSettings {
+        ClientSettings {
+            servers: PUBLIC_RESOLVERS.iter().map(|s| s.to_string()).collect(),
+            #[cfg(feature = "encrypted-transports")]
+            doh: None,
+            ..self
+        }
+    }
+
+    /// The same settings querying the mDNS group, collecting responses for
+    /// `window`
+    #[cfg(feature = "discovery")]
+    fn mdns(self, window: Duration) -> ClientSettings {
+        ClientSettings {
+            mdns: Some(window),
+            #[cfg(feature = "encrypted-transports")]
+            doh: None,
+            ..self
+        }
+    }
+
+    /// A client of the mDNS group, the DoH endpoint or the servers, in that
+    /// order
+    async fn client(&self) -> Result<Client, ClientError> {
+        #[cfg(feature = "discovery")]
+        if let Some(window) = self.mdns {
+            return Ok(Client::with_transport(Transport::mdns(window).await?));
+        }
+        #[cfg(feature = "encrypted-transports")]
+        if let Some(doh) = &self.doh {
+            let transport =
+                Transport::https_pinned(doh.url.clone(), doh.method, &doh.addrs, &doh.sources)?;
+            return Ok(Client::with_transport(transport).with_padding(doh.padding));
+        }
+        let mut servers = self.servers.iter();
+        let primary = servers.next().cloned().unwrap_or_default();
+        let mut client = Client::with_transport(Transport::udp_from(primary, &self.source).await?);
+        for server in servers {
+            client = client.with_fallback(Transport::udp_from(server.clone(), &self.source).await?);
+        }
+        Ok(client)
+    }
+
+    async fn resolver(self) -> Result<Resolver, ClientError> {
+        let timeout = self.timeout + self.mdns_window();
+        let mut client = self
+            .client()
+            .await?
+            .with_budget(self.budget)
+            .with_retry_budget(self.retry_budget)
+            .with_class(self.class)
+            .with_timeout(timeout)
+            .with_cname_depth(self.cname_depth)
+            .with_attempts(self.attempts)
+            .with_randomness(self.randomness);
+        for (server, timeout) in self.server_timeouts {
+            client = client.with_server_timeout(server, timeout);
+        }
+        #[cfg(feature = "zones")]
+        if let Some(key) = self.tsig {
+            client = client.with_tsig(key);
+        }
+        if let Some(subnet) = self.subnet {
+            client = client.with_subnet(subnet);
+        }
+        if let Some(rotation) = self.rotation {
+            client = client.with_rotation(rotation);
+        }
+        if self.timestamping {
+            client = client.with_timestamping()?;
+        }
+        #[cfg(feature = "diagnostics")]
+        if let Some(capture) = self.capture {
+            client = client.with_capture(capture);
+        }
+        if !self.idn {
+            client = client.without_idn();
+        }
+        if !self.randomize_case {
+            client = client.without_0x20();
+        }
+        if !self.recursion {
+            client = client.without_recursion();
+        }
+        if self.checking_disabled {
+            client = client.with_checking_disabled();
+        }
+        if self.authentic_data {
+            client = client.with_authentic_data();
+        }
+        if self.all_sections {
+            client = client.with_all_sections();
+        }
+        if let Some((limit, page)) = self.answer_page {
+            client = client.with_answer_page(limit, page);
+        }
+        if self.cookie {
+            client = client.with_cookies();
+        }
+        #[cfg(feature = "cache")]
+        if let Some(cache) = self.cache {
+            client = client.with_cache(cache);
+        }
+        if let Some(hosts) = self.hosts {
+            client = client.with_hosts(hosts);
+        }
+        #[cfg(feature = "dnssec")]
+        if let Some(anchors) = self.trust_anchors {
+            client = client.with_dnssec(anchors);
+        }
+        Ok(Resolver::new(client).with_any_types(self.any_types))
+    }
+
+    /// Whether answers are validated with DNSSEC
+    fn dnssec(&self) -> bool {
+        #[cfg(feature = "dnssec")]
+        return self.trust_anchors.is_some();
+        #[cfg(not(feature = "dnssec"))]
+        false
+    }
+
+    /// Window mDNS responses are collected for, none unless querying the
+    /// mDNS group
+    fn mdns_window(&self) -> Duration {
+        #[cfg(feature = "discovery")]
+        return self.mdns.unwrap_or_default();
+        #[cfg(not(feature = "discovery"))]
+        Duration::ZERO
+    }
+}
+
+/// The --doh endpoint, none when built without DNS-over-HTTPS
+fn doh_url(cli: &Cli) -> Option<&str> {
+    #[cfg(feature = "encrypted-transports")]
+    {
+        cli.doh.as_deref()
+    }
+    #[cfg(not(feature = "encrypted-transports"))]
+    {
+        let _ = cli;
+        None
+    }
+}
+
+/// `path`, a file of /etc, as seen from the network namespace of the run
+fn etc_file(cli: &Cli, path: &str) -> String {
+    let namespaced = cli.netns.as_ref().and_then(|netns| {
+        let file = path.strip_prefix("/etc/")?;
+        netns.etc_file(file)
+    });
+    namespaced.unwrap_or_else(|| path.to_string())
+}
+
+fn resolv_conf(cli: &Cli) -> String {
+    etc_file(cli, nsconfig::RESOLV_CONF)
+}
+
+/// The nameservers of /etc/resolv.conf, or the embedded resolver, as the
+/// data sources of the run say
+fn resolv_conf_servers(cli: &Cli) -> Result<Vec<String>, ClientError> {
+    Sources::new(&cli.data_sources).nameservers(&resolv_conf(cli))
+}
+
+/// Socket addresses of the upstream servers of `nsq serve`, those of
+/// `server` or else the nameservers of resolv.conf
+#[cfg(feature = "serve")]
+async fn serve_upstream(cli: &Cli, server: &[String]) -> Result<Vec<String>, ClientError> {
+    let servers = match server.is_empty() {
+        true => resolv_conf_servers(cli)?,
+        false => server.to_vec(),
+    };
+    server_addrs(&servers, None).await
+}
+
+/// Applies the upstream servers and cache limit the command line, config
+/// file and resolv.conf give now to `forwarder`, on SIGHUP. The cache and
+/// the queries in flight are kept, other settings need a restart.
+#[cfg(feature = "serve")]
+async fn reload(forwarder: &Forwarder) -> Result<(), ClientError> {
+    let cli = dig_args(std::env::args().collect())
+        .map_err(ClientError::GenericError)
+        .and_then(|args| {
+            parse_cli(args).map_err(|err| ClientError::GenericError(err.to_string()))
+        })?;
+    let Some(Command::Serve(args)) = &cli.command else {
+        return Ok(());
+    };
+    let upstream = serve_upstream(&cli, &args.server).await?;
+    if let Some(cache) = forwarder.cache() {
+        cache.set_max_memory(args.cache_max_mem);
+    }
+    forwarder.set_upstream(upstream);
+    Ok(())
+}
+
+/// Socket addresses of `servers`, host names looked up at the `bootstrap`
+/// resolver when given
+async fn server_addrs(
+    servers: &[String],
+    bootstrap: Option<IpAddr>,
+) -> Result<Vec<String>, ClientError> {
+    let bootstrap = bootstrap.map(|ip| SocketAddr::new(ip, resolver::DNS_PORT).to_string());
+    let mut addrs = Vec::with_capacity(servers.len());
+    for server in servers {
+        let addr = resolver::server_addr(server, bootstrap.as_deref()).await?;
+        addrs.push(addr.to_string());
+    }
+    Ok(addrs)
+}
+
+/// What a query task brings back, whole responses for the dig output
+enum Lookup {
+    Answers(QueryResponse),
+    Response(Response),
+}
+
+impl Lookup {
+    fn server(&self) -> Option<&str> {
+        match self {
+            Lookup::Answers(response) => {
+                let mut records = response.answers.iter().chain(&response.soa);
+                records.next().map(|answer| answer.server())
+            }
+            Lookup::Response(response) => Some(response.server()),
+        }
+    }
+
+    /// Whether the server refused the query, the dig output keeps the response
+    fn is_refused(lookup: &Result<Lookup, ClientError>) -> bool {
+        match lookup {
+            Ok(Lookup::Response(response)) => response.rcode() == 5,
+            Err(ClientError::RDCodeRefused(_)) => true,
+            _ => false,
+        }
+    }
+
+    /// What came of a query for the sinks, `servers` standing for the servers
+    /// of the run when the query failed
+    fn result(
+        lookup: &Result<Lookup, ClientError>,
+        host: String,
+        query_type: QueryType,
+        servers: &str,
+        rtt: Duration,
+    ) -> QueryResult {
+        let outcome = match lookup {
+            Ok(lookup) if lookup.is_nxdomain() => Outcome::NxDomain,
+            Ok(_) => Outcome::Succeeded,
+            Err(ClientError::RDCodeNameError) => Outcome::NxDomain,
+            Err(_) => Outcome::Failed,
+        };
+        let server = lookup.as_ref().ok().and_then(|l| l.server());
+        QueryResult {
+            host,
+            query_type,
+            server: server.unwrap_or(servers).to_string(),
+            rtt,
+            outcome,
+            error: lookup.as_ref().err().map(|err| err.to_string()),
+            answers: match lookup {
+                Ok(Lookup::Answers(response)) => response.records(),
+                Ok(Lookup::Response(response)) => response.answers(),
+                Err(_) => Vec::new(),
+            },
+            response: match lookup {
+                Ok(Lookup::Response(response)) => Some(response.clone()),
+                _ => None,
+            },
+        }
+    }
+
+    /// Whether the name exists but has no records of the queried type
+    fn is_nodata(&self) -> bool {
+        match self {
+            Lookup::Answers(response) => response.is_nodata(),
+            Lookup::Response(response) => response.rcode() == 0 && response.answer_count() == 0,
+        }
+    }
+
+    fn is_nxdomain(&self) -> bool {
+        match self {
+            Lookup::Answers(response) => response.is_nxdomain(),
+            Lookup::Response(response) => response.rcode() == 3,
+        }
+    }
+}
+
+/// Queries `names` in order until one has answers, the search list candidates
+/// of a host. Names that don't exist or have no records of the type are passed
+/// over, like libc resolvers do, other failures end the search.
+async fn lookup(
+    settings: ClientSettings,
+    names: Vec<String>,
+    query_type: QueryType,
+    dig: bool,
+) -> Result<Lookup, ClientError> {
+    let resolver = settings.resolver().await?;
+    let mut nodata: Option<Lookup> = None;
+    let mut nxdomain: Option<Lookup> = None;
+    for name in names {
+        log::info!("Querying {} {}", name, query_type);
+        let lookup = match dig {
+            true => resolver
+                .client()
+                .exchange(&name, query_type.clone())
+                .await
+                .map(Lookup::Response),
+            false => resolver
+                .resolve(&name, query_type.clone())
+                .await
+                .map(Lookup::Answers),
+        };
+        match lookup {
+            Ok(lookup) if lookup.is_nodata() => {
+                nodata.get_or_insert(lookup);
+            }
+            Ok(lookup) if lookup.is_nxdomain() => nxdomain = Some(lookup),
+            lookup => return lookup,
+        }
+    }
+    match nodata.or(nxdomain) {
+        Some(lookup) => Ok(lookup),
+        None => Err(ClientError::GenericError("No name to query".to_string())),
+    }
+}
+
+/// How the names of the run are looked up, shared by their queries
+#[derive(Clone)]
+struct QueryOptions {
+    search: Vec<String>,
+    ndots: usize,
+    #[cfg(feature = "discovery")]
+    mdns: bool,
+    #[cfg(feature = "discovery")]
+    mdns_window: Duration,
+    fallback_public: bool,
+    dig: bool,
+}
+
+/// Looks `host` up through its search list, or over mDNS for .local names,
+/// sending refused queries to public resolvers when asked to
+async fn resolve(
+    settings: ClientSettings,
+    options: QueryOptions,
+    host: String,
+    query_type: QueryType,
+) -> (Result<Lookup, ClientError>, Duration) {
+    let started = Instant::now();
+    // .local names are answered by the hosts of the link, never searched
+    #[cfg(feature = "discovery")]
+    let (settings, names) = match options.mdns || mdns::is_local(&host) {
+        true => (settings.mdns(options.mdns_window), vec![host.clone()]),
+        false => (
+            settings,
+            resolver::search_names(&host, &options.search, options.ndots),
+        ),
+    };
+    #[cfg(not(feature = "discovery"))]
+    let names = resolver::search_names(&host, &options.search, options.ndots);
+    let dig = options.dig;
+    let answer_page = settings.answer_page;
+    let mut result = lookup(settings.clone(), names.clone(), query_type.clone(), dig).await;
+    if options.fallback_public && Lookup::is_refused(&result) {
+        log::warn!(
+            "{} {} was refused, sending it to {} instead",
+            host,
+            query_type,
+            PUBLIC_RESOLVERS.join(", ")
+        );
+        result = lookup(settings.public(), names, query_type.clone(), dig).await;
+    }
+    if let (Some((limit, page)), Ok(Lookup::Answers(response))) = (answer_page, &result) {
+        show_page(&host, &query_type, response, limit, page);
+    }
+    (result, started.elapsed())
+}
+
+/// Tells which answers of `host` `query_type` the page shows, when it
+/// doesn't show all of them
+fn show_page(
+    host: &str,
+    query_type: &QueryType,
+    response: &QueryResponse,
+    limit: usize,
+    page: usize,
+) {
+    let start = limit * (page - 1);
+    if start == 0 && response.total <= limit {
+        return;
+    }
+    if start >= response.total {
+        log::warn!(
+            "{} {}: no answers on page {}, there are {}",
+            host,
+            query_type,
+            page,
+            response.total
+        );
+        return;
+    }
+    let end = response.total.min(start + limit);
+    let shown = match end - start {
+        1 => std::format!("answer {}", end),
+        _ => std::format!("answers {}-{}", start + 1, end),
+    };
+    let next = match end < response.total {
+        true => std::format!(", --page {} for the next ones", page + 1),
+        false => String::new(),
+    };
+    log::warn!(
+        "{} {}: {} of {}{}",
+        host,
+        query_type,
+        shown,
+        response.total,
+        next
+    );
+}
+
+/// Queries of `host` for each type, email addresses being turned into the
+/// hashed owner name of their keys
+fn host_queries(host: &str, query_types: &[QueryType]) -> Vec<(String, QueryType)> {
+    query_types
+        .iter()
+        .map(|query_type| {
+            let name = match query_type {
+                QueryType::OPENPGPKEY => resolver::openpgpkey_name(host),
+                QueryType::SMIMEA => resolver::smimea_name(host),
+                _ => None,
+            };
+            (name.unwrap_or(host.to_string()), query_type.clone())
+        })
+        .collect()
+}
+
+/// Sends each query to every server on its own, in parallel, and shows the
+/// differences between their responses
+#[cfg(feature = "diagnostics")]
+async fn compare(
+    settings: &ClientSettings,
+    queries: Vec<(String, QueryType)>,
+    cli: &Cli,
+) -> Result<(), ClientError> {
+    if settings.servers.len() < 2 {
+        return Err(ClientError::GenericError(
+            "--compare needs at least two --server values".to_string(),
+        ));
+    }
+    let mut tasks = Vec::with_capacity(queries.len());
+    for (host, query_type) in &queries {
+        let lookups = settings.servers.iter().map(|server| {
+            let settings = ClientSettings {
+                servers: vec![server.clone()],
+                ..settings.clone()
+            };
+            let (host, query_type) = (host.clone(), query_type.clone());
+            tokio::spawn(async move {
+                let resolver = settings.resolver().await?;
+                resolver.client().exchange(&host, query_type).await
+            })
+        });
+        tasks.push(join_all(lookups));
+    }
+    let mut comparisons = Vec::with_capacity(queries.len());
+    for ((host, query_type), joined) in queries.into_iter().zip(join_all(tasks).await) {
+        let mut results: Vec<(String, Result<Response, ClientError>)> = Vec::new();
+        for (server, result) in settings.servers.iter().zip(joined) {
+            let result = match result {
+                Ok(result) => result,
+                Err(err) => Err(ClientError::GenericError(err.to_string())),
+            };
+            results.push((server.clone(), result));
+        }
+        comparisons.push((host, query_type, compare::differences(&results)));
+    }
+    print!(
+        "{}",
+        output::comparisons(
+            &comparisons,
+            settings.servers.len(),
+            cli.output == OutputFormat::Json
+        )
+    );
+    Ok(())
+}
+
+/// Sends every query to each --vantage forwarder, which resolves it afresh
+/// from where it runs, and reports how their answers differ
+#[cfg(all(feature = "diagnostics", feature = "serve"))]
+async fn vantage(queries: Vec<(String, QueryType)>, cli: &Cli) -> Result<(), ClientError> {
+    let mut tasks = Vec::with_capacity(queries.len());
+    for (host, query_type) in &queries {
+        let probes = cli.vantage.iter().map(|(_, server)| {
+            let (server, host, query_type) = (server.clone(), host.clone(), query_type.clone());
+            let timeout = cli.timeout;
+            tokio::spawn(async move { serve::probe(server, &host, query_type, timeout).await })
+        });
+        tasks.push(join_all(probes));
+    }
+    let mut comparisons = Vec::with_capacity(queries.len());
+    for ((host, query_type), joined) in queries.into_iter().zip(join_all(tasks).await) {
+        let mut results: Vec<(String, Result<Response, ClientError>)> = Vec::new();
+        for ((name, _), result) in cli.vantage.iter().zip(joined) {
+            let result = match result {
+                Ok(result) => result,
+                Err(err) => Err(ClientError::GenericError(err.to_string())),
+            };
+            results.push((name.clone(), result));
+        }
+        comparisons.push((host, query_type, compare::differences(&results)));
+    }
+    print!(
+        "{}",
+        output::comparisons(
+            &comparisons,
+            cli.vantage.len(),
+            cli.output == OutputFormat::Json
+        )
+    );
+    Ok(())
+}
+
+/// Resolves each query iteratively from the root servers, one after the
+/// other, and shows every delegation step
+#[cfg(feature = "diagnostics")]
+async fn trace(
+    queries: Vec<(String, QueryType)>,
+    budget: QueryBudget,
+    cli: &Cli,
+) -> Result<(), ClientError> {
+    let roots = match &cli.root_hints {
+        Some(filename) => roots::read_root_hints(filename)?,
+        None => Sources::new(&cli.data_sources).root_hints()?,
+    };
+    let tracer = Tracer::new(roots)
+        .with_timeout(cli.timeout)
+        .with_class(cli.class)
+        .with_budget(budget);
+    let ipv6 = Ipv6Format {
+        style: cli.ipv6,
+        uppercase: cli.uppercase,
+    };
+    for (host, query_type) in queries {
+        let steps = tracer.trace(&host, query_type).await?;
+        print!("{}", output::trace(&steps, &ipv6));
+    }
+    Ok(())
+}
+
+/// Transfers `zone` from `server`, its records printed a message at a time so
+/// large zones stream out. With --limit, the transfer stops once the records
+/// of the page arrived.
+#[cfg(feature = "zones")]
+async fn axfr(zone: &str, server: &str, ipv6: &Ipv6Format, cli: &Cli) -> Result<(), ClientError> {
+    let mut transfer = ZoneTransfer::axfr(
+        server.to_string(),
+        zone,
+        cli.class,
+        cli.timeout,
+        cli.tsig.clone(),
+    )
+    .await?;
+    let page = match cli.limit {
+        Some(limit) => {
+            let start = (limit * (cli.page - 1)) as usize;
+            start..start + limit as usize
+        }
+        None => 0..usize::MAX,
+    };
+    let mut received = 0;
+    while let Some(response) = transfer.next().await? {
+        let count = response.answer_count();
+        let records = page.start.saturating_sub(received)..page.end.saturating_sub(received);
+        print!("{}", output::transfer(&response, records, ipv6));
+        received += count;
+        if received >= page.end && !transfer.is_done() {
+            log::warn!(
+                "Stopped the transfer of {} after record {}, --page {} for the next ones",
+                zone,
+                page.end,
+                cli.page + 1
+            );
+            break;
+        }
+    }
+    if received <= page.start {
+        log::warn!(
+            "No records of {} on page {}, it has {}",
+            zone,
+            cli.page,
+            received
+        );
+    }
+    show_transfer_size(&transfer, cli);
+    Ok(())
+}
+
+/// Transfers the changes of `zone` since `serial` from `server`, or the whole
+/// zone when the server can't send them incrementally
+#[cfg(feature = "zones")]
+async fn ixfr(
+    zone: &str,
+    serial: u32,
+    server: &str,
+    ipv6: &Ipv6Format,
+    cli: &Cli,
+) -> Result<(), ClientError> {
+    let mut transfer = ZoneTransfer::ixfr(
+        server.to_string(),
+        zone,
+        serial,
+        cli.class,
+        cli.timeout,
+        cli.tsig.clone(),
+    )
+    .await?;
+    let mut responses: Vec<Response> = Vec::new();
+    loop {
+        match transfer.next().await {
+            Ok(Some(response)) => responses.push(response),
+            Ok(None) => break,
+            Err(ClientError::RDCodeNotImplemented | ClientError::RDCodeFormatError)
+                if responses.is_empty() =>
+            {
+                log::warn!("{} doesn't do IXFR, transferring all of {}", server, zone);
+                return axfr(zone, server, ipv6, cli).await;
+            }
+            Err(err) => return Err(err),
+        }
+    }
+    match xfr::diffs(&responses) {
+        Some(diffs) if diffs.is_empty() => {
+            if !cli.quiet {
+                eprintln!(";; {} is up to date with serial {}", zone, serial);
+            }
+        }
+        Some(diffs) => print!("{}", output::diffs(&diffs, ipv6)),
+        None => {
+            log::warn!("{} sent all of {} rather than its changes", server, zone);
+            for response in &responses {
+                let records = 0..response.answer_count();
+                print!("{}", output::transfer(response, records, ipv6));
+            }
+        }
+    }
+    show_transfer_size(&transfer, cli);
+    Ok(())
+}
+
+#[cfg(feature = "zones")]
+fn show_transfer_size(transfer: &ZoneTransfer, cli: &Cli) {
+    if !cli.quiet {
+        eprintln!(
+            ";; XFR size: {} records (messages {}, bytes {})",
+            transfer.records(),
+            transfer.messages(),
+            transfer.bytes()
+        );
+    }
+}
+
+/// Sink of the output format asked for, writing to stdout the answers the
+/// --filter expressions match. `streaming` ones print results as queries
+/// complete, which the table and JSON array can't, their table having a
+/// column for DNSSEC when `dnssec`.
+fn output_sink(cli: &Cli, streaming: bool, dnssec: bool) -> Box<dyn OutputSink> {
+    let sink = format_sink(cli, streaming, dnssec);
+    match cli.filter.is_empty() {
+        true => sink,
+        false => Box::new(FilterSink::new(sink, cli.filter.clone())),
+    }
+}
+
+/// Sink of the output format, whatever the filters
+fn format_sink(cli: &Cli, streaming: bool, dnssec: bool) -> Box<dyn OutputSink> {
+    let ipv6 = Ipv6Format {
+        style: cli.ipv6,
+        uppercase: cli.uppercase,
+    };
+    let out = Box::new(std::io::stdout());
+    if cli.short {
+        let sink = LinesSink::short(out, ipv6);
+        return match cli.sort {
+            Some(key) => Box::new(sink.with_sort(key)),
+            None => Box::new(sink),
+        };
+    }
+    if cli.compat == Some(Compat::Dig) {
+        return Box::new(DigSink::new(out, ipv6, true));
+    }
+    match (cli.output, streaming) {
+        (OutputFormat::Table, true) => {
+            let mut stream = AnswerStream::new(cli.width, cli.truncate, dnssec);
+            if cli.all_sections {
+                stream = stream.with_sections();
+            }
+            Box::new(StreamTableSink::new(out, ipv6, stream))
+        }
+        (OutputFormat::Table, false) => {
+            let sink = TableSink::new(out, ipv6, cli.width, cli.truncate);
+            match cli.sort {
+                Some(key) => Box::new(sink.with_sort(key)),
+                None => Box::new(sink),
+            }
+        }
+        (OutputFormat::Debug, _) => {
+            let sink = LinesSink::debug(out);
+            match cli.sort {
+                Some(key) => Box::new(sink.with_sort(key)),
+                None => Box::new(sink),
+            }
+        }
+        (OutputFormat::Zone, _) => {
+            let sink = LinesSink::zone(out);
+            match cli.sort {
+                Some(key) => Box::new(sink.with_sort(key)),
+                None => Box::new(sink),
+            }
+        }
+        (OutputFormat::Json, false) => {
+            let sink = JsonSink::new(out, ipv6);
+            match cli.sort {
+                Some(key) => Box::new(sink.with_sort(key)),
+                None => Box::new(sink),
+            }
+        }
+        (OutputFormat::Json, true) | (OutputFormat::Ndjson, _) => {
+            Box::new(NdjsonSink::new(out, ipv6))
+        }
+        (OutputFormat::Csv, _) => Box::new(CsvSink::new(out, ipv6)),
+        (OutputFormat::Dig, _) => Box::new(DigSink::new(out, ipv6, false)),
+        (OutputFormat::Verbose, _) => Box::new(VerboseSink::new(out, ipv6)),
+    }
+}
+
+/// What a cache filled by the runs of `export` would have answered `queries`
+/// with at `at`
+#[cfg(feature = "diagnostics")]
+fn cached_at(
+    export: &Export,
+    queries: Vec<(String, QueryType)>,
+    at: DateTime<Utc>,
+    cli: &Cli,
+) -> Result<(), ClientError> {
+    let mut results = Vec::new();
+    let mut outcomes = Vec::new();
+    for (host, query_type) in queries {
+        match export.cached_at(&host, &query_type, at)? {
+            Some(result) => {
+                outcomes.push((result.server.clone(), result.rtt, result.outcome));
+                results.push((result, 1));
+            }
+            None if !cli.quiet => eprintln!("{} {}: nothing cached at {}", host, query_type, at),
+            None => {}
+        }
+    }
+    let Export::Sqlite(path) = export;
+    show_results(&results, &Summary::new(&outcomes), path, cli)
+}
+
+/// Sinks results go to whatever the output format: the --export database of
+/// a run against `server`, started now
+fn export_sinks(cli: &Cli, server: &str) -> Vec<Box<dyn OutputSink>> {
+    #[cfg(feature = "diagnostics")]
+    if let Some(export) = &cli.export {
+        return vec![Box::new(SqliteSink::new(
+            export.clone(),
+            server,
+            Utc::now(),
+        ))];
+    }
+    let _ = (cli, server);
+    Vec::new()
+}
+
+/// Answers go to stdout so they can be piped, everything else goes to
+/// stderr. Each result is shown as many times as the queries it stands for.
+fn show_results(
+    results: &[(QueryResult, usize)],
+    summary: &Summary,
+    server: &str,
+    cli: &Cli,
+) -> Result<(), ClientError> {
+    if !cli.quiet && !cli.short && cli.compat.is_none() {
+        eprintln!("Server: {:?}", server);
+        eprintln!("Answers:");
+    }
+    let mut sink = output_sink(cli, false, false);
+    for (result, copies) in results {
+        for _ in 0..*copies {
+            sink.query(result)?;
+        }
+    }
+    // JSON has the summary next to the answers, other formats on stderr
+    match cli.output == OutputFormat::Json && !cli.short && cli.compat.is_none() {
+        true => sink.finish(cli.summary.then_some(summary)),
+        false => {
+            sink.finish(None)?;
+            show_batch_summary(summary, cli);
+            Ok(())
+        }
+    }
+}
+
+/// Totals of the run, only shown when asked for
+fn show_batch_summary(summary: &Summary, cli: &Cli) {
+    if cli.summary && !cli.quiet {
+        eprintln!("{}", summary);
+    }
+}
+
+/// Budget usage of the run, only shown when limits were set or hit
+fn show_summary(budget: &QueryBudget, retry_budget: &RetryBudget, cli: &Cli) {
+    if cli.quiet {
+        return;
+    }
+    if budget.max().is_some() || cli.verbose > 0 {
+        eprintln!("Used {}", budget);
+    }
+    if retry_budget.retried() + retry_budget.denied() > 0 || cli.verbose > 0 {
+        eprintln!("Used {}", retry_budget);
+    }
+}
+
+/// Hits and entries of the cache of the run, with --verbose
+#[cfg(feature = "cache")]
+fn show_cache_usage(cache: Option<&Cache>, cli: &Cli) {
+    if let (Some(cache), false, true) = (cache, cli.quiet, cli.verbose > 0) {
+        eprintln!("Used {}", cache);
+    }
+}
+
+/// Settings of the queries of a line of a batch file, the run's with the
+/// server, class and query options of the line
+async fn line_settings(
+    settings: &ClientSettings,
+    line: &BatchLine,
+    cli: &Cli,
+) -> Result<ClientSettings, ClientError> {
+    let mut settings = settings.clone();
+    match (line.server(), line.port) {
+        (Some(server), _) => settings.servers = server_addrs(&[server], cli.bootstrap).await?,
+        (None, Some(port)) => {
+            for server in settings.servers.iter_mut() {
+                if let Ok(mut addr) = server.parse::<SocketAddr>() {
+                    addr.set_port(port);
+                    *server = addr.to_string();
+                }
+            }
+        }
+        (None, None) => {}
+    }
+    if let Some(class) = line.class {
+        settings.class = class;
+    }
+    if let Some(timeout) = line.timeout {
+        settings.timeout = timeout;
+    }
+    if let Some(attempts) = line.attempts {
+        settings.attempts = attempts;
+    }
+    if let Some(subnet) = &line.subnet {
+        settings.subnet = Some(subnet.clone());
+    }
+    settings.recursion = line.recursion.unwrap_or(settings.recursion);
+    settings.checking_disabled = line.checking_disabled.unwrap_or(settings.checking_disabled);
+    settings.authentic_data = line.authentic_data.unwrap_or(settings.authentic_data);
+    settings.cookie = line.cookie.unwrap_or(settings.cookie);
+    settings.idn = line.idn.unwrap_or(settings.idn);
+    Ok(settings)
+}
+
+/// Resolves the hosts given then the names of `file`, `--concurrency` queries
+/// at a time, printing the results of each as it completes. Blank lines and #
+/// comments are skipped, and a failed query is reported without stopping the
+/// others unless `--fail-fast` is given. So is a line that can't be parsed or
+/// whose server can't be resolved, with its number, counting as a failed
+/// query. The run fails when every query did.
+async fn batch(
+    file: &str,
+    settings: &ClientSettings,
+    options: &QueryOptions,
+    query_types: &[QueryType],
+    server: &str,
+    cli: &Cli,
+) -> Result<(), ClientError> {
+    let input: Box<dyn AsyncRead + Unpin + Send> = match file {
+        "-" => Box::new(tokio::io::stdin()),
+        path => Box::new(
+            tokio::fs::File::open(path)
+                .await
+                .map_err(|err| ClientError::GenericError(std::format!("{}: {}", path, err)))?,
+        ),
+    };
+    let lines = stream::unfold(BufReader::new(input).lines(), |mut lines| async move {
+        match lines.next_line().await {
+            Ok(Some(line)) => Some((Ok(line), lines)),
+            Ok(None) => None,
+            Err(err) => Some((Err(err), lines)),
+        }
+    });
+    // hosts of the command line are names, whatever they look like, and
+    // have no line number
+    let hosts = cli.hosts.iter().map(|host| {
+        let line = BatchLine {
+            queries: vec![BatchQuery {
+                name: host.clone(),
+                query_type: None,
+            }],
+            ..BatchLine::default()
+        };
+        (None, Ok(line))
+    });
+    let lines = lines.enumerate().filter_map(|(i, line)| async move {
+        match line {
+            Ok(line) => {
+                let line = line.trim();
+                (!line.is_empty() && !line.starts_with('#')).then(|| {
+                    let line = line.parse::<BatchLine>().map_err(ClientError::ParseError);
+                    (Some(i + 1), line)
+                })
+            }
+            Err(err) => Some((
+                None,
+                Err(ClientError::GenericError(std::format!("{}: {}", file, err))),
+            )),
+        }
+    });
+    let mut results = stream::iter(hosts)
+        .chain(lines)
+        .then(|(number, line)| async move {
+            let settings = match line {
+                Ok(line) => line_settings(settings, &line, cli)
+                    .await
+                    .map(|settings| (settings, line)),
+                Err(err) => Err(err),
+            };
+            settings.map_err(|err| (number, err))
+        })
+        .flat_map(|line| {
+            stream::iter(match line {
+                Ok((settings, line)) => line
+                    .queries
+                    .iter()
+                    .flat_map(|query| match &query.query_type {
+                        Some(query_type) => {
+                            host_queries(&query.name, std::slice::from_ref(query_type))
+                        }
+                        None => host_queries(&query.name, query_types),
+                    })
+                    .map(|(host, query_type)| Ok((settings.clone(), host, query_type)))
+                    .collect(),
+                Err(err) => vec![Err(err)],
+            })
+        })
+        .map(|query| {
+            let options = options.clone();
+            async move {
+                let (settings, host, query_type) = query?;
+                let task =
+                    tokio::spawn(resolve(settings, options, host.clone(), query_type.clone()));
+                let (lookup, rtt) = task
+                    .await
+                    .map_err(|err| (None, ClientError::GenericError(err.to_string())))?;
+                Ok((host, query_type, lookup, rtt))
+            }
+        })
+        .buffer_unordered(cli.concurrency as usize)
+        .boxed_local();
+
+    if !cli.quiet && !cli.short && cli.compat.is_none() {
+        eprintln!("Server: {:?}", server);
+    }
+    let mut sinks = vec![output_sink(cli, true, settings.dnssec())];
+    sinks.extend(export_sinks(cli, server));
+    let mut outcomes: Vec<(String, Duration, Outcome)> = Vec::new();
+    let mut setups: Vec<Duration> = Vec::new();
+    let (mut failed, mut bad, mut first, mut stopped) = (0, 0, None, None);
+    while let Some(result) = results.next().await {
+        let (host, query_type, lookup, rtt) = match result {
+            Ok(result) => result,
+            Err((number, err)) => {
+                failed += 1;
+                bad += 1;
+                if !cli.quiet {
+                    match number {
+                        Some(number) => eprintln!("{}:{}: {:?}", file, number, err),
+                        None => eprintln!("{:?}", err),
+                    }
+                }
+                if cli.fail_fast {
+                    stopped = Some(err);
+                    break;
+                }
+                first.get_or_insert(err);
+                continue;
+            }
+        };
+        let result = Lookup::result(&lookup, host, query_type, server, rtt);
+        outcomes.push((result.server.clone(), rtt, result.outcome));
+        setups.extend(result.connection_setup());
+        for sink in &mut sinks {
+            sink.query(&result)?;
+        }
+        if let Err(err) = lookup {
+            failed += 1;
+            if cli.fail_fast {
+                stopped = Some(err);
+                break;
+            }
+            if !cli.quiet {
+                eprintln!("{} {}: {}", result.host, result.query_type, err);
+            }
+            first.get_or_insert(err);
+        }
+        // the streamed table has no column for them, the SOA goes to stderr
+        let soa = result
+            .answers
+            .iter()
+            .find(|answer| answer.negative().is_some());
+        if let (Some(soa), false) = (soa, cli.quiet) {
+            eprintln!(
+                "{} {}: {}, SOA {}",
+                result.host,
+                result.query_type,
+                soa.negative().unwrap_or_default(),
+                soa.data()
+            );
+        }
+    }
+    show_summary(&settings.budget, &settings.retry_budget, cli);
+    #[cfg(feature = "cache")]
+    show_cache_usage(settings.cache.as_ref(), cli);
+    for sink in &mut sinks {
+        sink.finish(None)?;
+    }
+    show_batch_summary(
+        &Summary::new(&outcomes).with_connection_setups(&setups),
+        cli,
+    );
+    if let Some(err) = stopped {
+        return Err(err);
+    }
+    match first {
+        Some(err) if failed == outcomes.len() + bad => Err(ClientError::QueriesFailed(
+            failed,
+            outcomes.len() + bad,
+            Box::new(err),
+        )),
+        _ => nxdomain_status(&outcomes),
+    }
+}
+
+/// NXDOMAIN answers are results, but a run of nothing else still exits with
+/// their status for scripts
+fn nxdomain_status(outcomes: &[(String, Duration, Outcome)]) -> Result<(), ClientError> {
+    match !outcomes.is_empty() && outcomes.iter().all(|(.., o)| *o == Outcome::NxDomain) {
+        true => Err(ClientError::RDCodeNameError),
+        false => Ok(()),
+    }
+}
+
+/// A signal to a long-running mode: SIGUSR1 asking for its stats, SIGHUP
+/// asking the forwarder to reload its settings
+#[cfg(any(feature = "diagnostics", feature = "serve"))]
+struct ControlSignal {
+    #[cfg(unix)]
+    signal: Option<tokio::signal::unix::Signal>,
+}
+
+#[cfg(any(feature = "diagnostics", feature = "serve"))]
+impl ControlSignal {
+    fn stats() -> ControlSignal {
+        #[cfg(unix)]
+        {
+            use tokio::signal::unix::SignalKind;
+
+            ControlSignal::unix(SignalKind::user_defined1(), "stats on SIGUSR1")
+        }
+        #[cfg(not(unix))]
+        ControlSignal {}
+    }
+
+    #[cfg(feature = "serve")]
+    fn reload() -> ControlSignal {
+        #[cfg(unix)]
+        {
+            use tokio::signal::unix::SignalKind;
+
+            ControlSignal::unix(SignalKind::hangup(), "reload on SIGHUP")
+        }
+        #[cfg(not(unix))]
+        ControlSignal {}
+    }
+
+    #[cfg(unix)]
+    fn unix(kind: tokio::signal::unix::SignalKind, what: &str) -> ControlSignal {
+        let signal = tokio::signal::unix::signal(kind)
+            .map_err(|err| log::warn!("No {}: {}", what, err))
+            .ok();
+        ControlSignal { signal }
+    }
+
+    /// Waits for the next signal, forever where there is none
+    async fn recv(&mut self) {
+        #[cfg(unix)]
+        if let Some(signal) = &mut self.signal {
+            if signal.recv().await.is_some() {
+                return;
+            }
+        }
+        std::future::pending().await
+    }
+}
+
+/// What a watch saw so far, for SIGUSR1 to dump
+#[cfg(feature = "diagnostics")]
+struct WatchStats {
+    started: DateTime<Local>,
+    rounds: usize,
+    alerts: usize,
+    /// Of each query, how its last round went and the changes seen
+    queries: Vec<(Result<Duration, String>, usize)>,
+}
+
+#[cfg(feature = "diagnostics")]
+impl WatchStats {
+    fn report(&self, queries: &[(String, QueryType)]) -> String {
+        let mut report = std::format!(
+            "Watching since {}: {} rounds, {} alerts",
+            self.started.format("%Y-%m-%dT%H:%M:%S"),
+            self.rounds,
+            self.alerts
+        );
+        for ((host, query_type), (last, changes)) in queries.iter().zip(&self.queries) {
+            let last = match last {
+                Ok(rtt) => std::format!("answered in {:.1} ms", nsq::client::millis(*rtt)),
+                Err(err) => std::format!("failed, {}", err),
+            };
+            report += &std::format!("\n{} {}: {}, {} changes", host, query_type, last, changes);
+        }
+        report
+    }
+}
+
+/// Sends `queries` every `interval`, printing the changes in their answers
+/// from one round to the next, all of them being new in the first one, and
+/// its stats to stderr on SIGUSR1
+#[cfg(feature = "diagnostics")]
+async fn watch(
+    settings: ClientSettings,
+    options: QueryOptions,
+    queries: Vec<(String, QueryType)>,
+    interval: Duration,
+    cli: &Cli,
+) -> Result<(), ClientError> {
+    // answers from the cache would hide the changes, and those off the page
+    let settings = ClientSettings {
+        #[cfg(feature = "cache")]
+        cache: None,
+        answer_page: None,
+        ..settings
+    };
+    let options = QueryOptions {
+        dig: false,
+        ..options
+    };
+    let ipv6 = Ipv6Format {
+        style: cli.ipv6,
+        uppercase: cli.uppercase,
+    };
+    if !cli.quiet {
+        eprintln!(
+            "Watching {} queries every {:?}, interrupt to stop",
+            queries.len(),
+            interval
+        );
+    }
+    let mut watch = Watch::new();
+    let mut alerts = Alerts::new(cli.alert.clone());
+    let sources = Sources::new(&cli.data_sources);
+    let mut notifier = Notifier::new()
+        .with_format(cli.webhook_format)
+        .with_sources(sources.clone());
+    let mut change_notifier = Notifier::new()
+        .with_format(cli.webhook_format)
+        .with_sources(sources);
+    if let Some(url) = &cli.notify_url {
+        change_notifier = change_notifier.with_webhook(url.clone());
+    }
+    let mut first_round = true;
+    let mut stats = WatchStats {
+        started: Local::now(),
+        rounds: 0,
+        alerts: 0,
+        queries: vec![(Ok(Duration::ZERO), 0); queries.len()],
+    };
+    let mut stats_signal = ControlSignal::stats();
+    if let Some(command) = &cli.alert_exec {
+        notifier = notifier.with_command(command.clone());
+    }
+    if let Some(url) = &cli.alert_webhook {
+        notifier = notifier.with_webhook(url.clone());
+    }
+    loop {
+        let round = queries.iter().map(|(host, query_type)| {
+            tokio::spawn(resolve(
+                settings.clone(),
+                options.clone(),
+                host.clone(),
+                query_type.clone(),
+            ))
+        });
+        let results = join_all(round).await;
+        let now = Local::now().format("%Y-%m-%dT%H:%M:%S");
+        for (((host, query_type), result), query_stats) in
+            queries.iter().zip(results).zip(stats.queries.iter_mut())
+        {
+            let (lookup, rtt) = result.map_err(|err| ClientError::GenericError(err.to_string()))?;
+            let answers = lookup.and_then(|lookup| match lookup {
+                lookup if lookup.is_nxdomain() => Err(ClientError::RDCodeNameError),
+                Lookup::Answers(response) => Ok(response.answers),
+                Lookup::Response(response) => Ok(response.answers()),
+            });
+            let changes = watch.update(host, query_type, &answers, &ipv6);
+            query_stats.0 = match &answers {
+                Ok(_) => Ok(rtt),
+                Err(err) => Err(std::format!("{:?}", err)),
+            };
+            query_stats.1 += changes.len();
+            for change in &changes {
+                println!("{} {}", now, change);
+            }
+            // the records of the first round are only what there is to watch
+            if cli.notify_url.is_some() && !changes.is_empty() && !first_round {
+                if let Err(err) = change_notifier
+                    .notify_changes(host, query_type, &changes)
+                    .await
+                {
+                    eprintln!("Failed to notify of the changes: {:?}", err);
+                }
+            }
+            for alert in alerts.check(host, query_type, rtt, answers.as_ref().err(), &changes) {
+                println!("{} {}", now, alert);
+                stats.alerts += 1;
+                // an alert that couldn't be told mustn't stop the watch
+                if let Err(err) = notifier.notify(&alert).await {
+                    eprintln!("Failed to notify of the alert: {:?}", err);
+                }
+            }
+        }
+        first_round = false;
+        stats.rounds += 1;
+        let next_round = tokio::time::sleep(interval);
+        tokio::pin!(next_round);
+        loop {
+            tokio::select! {
+                _ = &mut next_round => break,
+                _ = stats_signal.recv() => eprintln!("{}", stats.report(&queries)),
+            }
+        }
+    }
+}
+
+/// Reads names and commands from stdin until exit, the settings of the
+/// session carrying over from one query to the next like in nslookup
+#[cfg(feature = "interactive")]
+async fn interactive(
+    mut settings: ClientSettings,
+    mut options: QueryOptions,
+    mut query_types: Vec<QueryType>,
+    cli: &Cli,
+) -> Result<(), ClientError> {
+    let prompt = std::io::stdin().is_terminal();
+    let search = options.search.clone();
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
+    loop {
+        if prompt {
+            print!("> ");
+            std::io::stdout().flush().ok();
+        }
+        let line = match lines.next_line().await {
+            Ok(Some(line)) => line,
+            Ok(None) => return Ok(()),
+            Err(err) => return Err(ClientError::GenericError(err.to_string())),
+        };
+        let line = match line.parse::<Line>() {
+            Ok(line) => line,
+            Err(err) => {
+                eprintln!("{}", err);
+                continue;
+            }
+        };
+        match line {
+            Line::Empty => {}
+            Line::Exit => return Ok(()),
+            Line::Help => print!("{}", repl::HELP),
+            Line::Show => {
+                println!("Default server: {}", settings.servers.join(", "));
+                let types: Vec<String> = query_types.iter().map(|t| t.to_string()).collect();
+                println!("  type={}", types.join(","));
+                println!("  class={}", settings.class);
+                println!("  timeout={}", settings.timeout.as_secs_f64());
+                println!("  retry={}", settings.attempts);
+                println!(
+                    "  {}search",
+                    if options.search.is_empty() { "no" } else { "" }
+                );
+                println!("  {}recurse", if settings.recursion { "" } else { "no" });
+                println!("  {}dnssec", if settings.dnssec() { "" } else { "no" });
+            }
+            Line::Server(server) => match server_addrs(&[server], cli.bootstrap).await {
+                Ok(servers) => {
+                    println!("Default server: {}", servers.join(", "));
+                    settings.servers = servers;
+                    #[cfg(feature = "encrypted-transports")]
+                    {
+                        settings.doh = None;
+                    }
+                }
+                Err(err) => eprintln!("{:?}", err),
+            },
+            Line::Set(Setting::Type(query_type)) => query_types = vec![query_type],
+            Line::Set(Setting::Class(class)) => settings.class = class,
+            Line::Set(Setting::Timeout(timeout)) => settings.timeout = timeout,
+            Line::Set(Setting::Attempts(attempts)) => settings.attempts = attempts,
+            Line::Set(Setting::Search(true)) => options.search = search.clone(),
+            Line::Set(Setting::Search(false)) => options.search = Vec::new(),
+            Line::Set(Setting::Recurse(recursion)) => settings.recursion = recursion,
+            #[cfg(feature = "dnssec")]
+            Line::Set(Setting::Dnssec(false)) => settings.trust_anchors = None,
+            #[cfg(feature = "dnssec")]
+            Line::Set(Setting::Dnssec(true)) => {
+                settings.trust_anchors = Some(match &cli.trust_anchor {
+                    Some(filename) => dnssec::read_trust_anchors(filename)?,
+                    None => dnssec::root_trust_anchors(),
+                })
+            }
+            #[cfg(not(feature = "dnssec"))]
+            Line::Set(Setting::Dnssec(_)) => {
+                eprintln!("nsq was built without the dnssec feature")
+            }
+            Line::Lookup { name, server } => {
+                let mut settings = settings.clone();
+                if let Some(server) = server {
+                    match server_addrs(&[server], cli.bootstrap).await {
+                        Ok(servers) => {
+                            settings.servers = servers;
+                            #[cfg(feature = "encrypted-transports")]
+                            {
+                                settings.doh = None;
+                            }
+                        }
+                        Err(err) => {
+                            eprintln!("{:?}", err);
+                            continue;
+                        }
+                    }
+                }
+                let server = settings.servers.join(", ");
+                let (mut results, mut outcomes, mut setups) = (Vec::new(), Vec::new(), Vec::new());
+                for (host, query_type) in host_queries(&name, &query_types) {
+                    let (lookup, rtt) =
+                        resolve(settings.clone(), options.clone(), host, query_type.clone()).await;
+                    let result = Lookup::result(&lookup, name.clone(), query_type, &server, rtt);
+                    outcomes.push((result.server.clone(), rtt, result.outcome));
+                    setups.extend(result.connection_setup());
+                    match lookup {
+                        Ok(_) => results.push((result, 1)),
+                        Err(err) => eprintln!("{} {}: {}", name, result.query_type, err),
+                    }
+                }
+                let summary = Summary::new(&outcomes).with_connection_setups(&setups);
+                if let Err(err) = show_results(&results, &summary, &server, cli) {
+                    eprintln!("{:?}", err);
+                }
+            }
+        }
+    }
 }
 
-fn show_answers(answers: &Vec<QueryAnswer>, server: &String){
-    println!("Server: {:?}", server);
-    println!("Answers:");
-    for answer in answers {
-        println!("{:?}", answer);
+/// Exit status of invalid arguments, and of failures none of the others
+/// stands for
+const USAGE_ERROR: i32 = 1;
+
+/// Exit status of a failed run, for scripts to branch on: 2 when no response
+/// came back, 3 for NXDOMAIN and 4 for the other error response codes
+fn exit_code(err: &ClientError) -> i32 {
+    match err {
+        ClientError::BindError(_)
+        | ClientError::ConnectError(_)
+        | ClientError::SendError(_)
+        | ClientError::RecvError(_)
+        | ClientError::DecodeError(_)
+        | ClientError::DecodeIdError(_)
+        | ClientError::CaseMismatch(..)
+        | ClientError::BadCookie(_)
+        | ClientError::Timeout(_)
+        | ClientError::NotListening(_)
+        | ClientError::AllFailed(_) => 2,
+        ClientError::RDCodeNameError => 3,
+        ClientError::RDCodeFormatError
+        | ClientError::RDCodeServerFailure
+        | ClientError::RDCodeNotImplemented
+        | ClientError::RDCodeRefused(_)
+        | ClientError::RDCode(_) => 4,
+        ClientError::QueriesFailed(_, _, first) => exit_code(first),
+        _ => USAGE_ERROR,
     }
 }
 
-#[tokio::main]
-async fn main() -> Result<(), ClientError> {
-    env_logger::Builder::from_env(Env::default().default_filter_or("warn"))
+fn main() {
+    let parsed = match dig_args(std::env::args().collect()) {
+        Ok(args) => parse_cli(args),
+        Err(err) => Err(Cli::command().error(ErrorKind::UnknownArgument, err)),
+    };
+    let cli = match parsed {
+        Ok(cli) => cli,
+        Err(err) if !err.use_stderr() => err.exit(),
+        Err(err) => {
+            let _ = err.print();
+            std::process::exit(USAGE_ERROR);
+        }
+    };
+    env_logger::Builder::from_env(Env::default().default_filter_or(cli.log_level()))
         .format(|buf, record| {
             writeln!(
                 buf,
@@ -40,66 +2565,722 @@ async fn main() -> Result<(), ClientError> {
             )
         })
         .init();
+    // threads of the runtime are in the namespace the main thread is in
+    // when they start
+    let entered = match &cli.netns {
+        Some(netns) => netns.enter(),
+        None => Ok(()),
+    };
+    let result = entered.and_then(|()| {
+        tokio::runtime::Runtime::new()
+            .map_err(|err| ClientError::GenericError(err.to_string()))?
+            .block_on(run(cli))
+    });
+    let err = match result {
+        Ok(()) => return,
+        Err(err) => err,
+    };
+    match &err {
+        // one line per server tried, rather than a single unreadable one
+        ClientError::AllFailed(attempts) => {
+            eprintln!("Error: every attempt failed:");
+            for attempt in attempts {
+                eprintln!("  {}", attempt);
+            }
+        }
+        ClientError::NotListening(_) => eprintln!("Error: {}", err),
+        err => eprintln!("Error: {:?}", err),
+    }
+    std::process::exit(exit_code(&err));
+}
+
+async fn run(cli: Cli) -> Result<(), ClientError> {
+    #[cfg(feature = "diagnostics")]
+    if let Some(Command::Bench {
+        names,
+        server,
+        query_type,
+        mix,
+        count,
+        warmup,
+        cache_bust,
+        qps,
+        concurrency,
+        timeout,
+    }) = &cli.command
+    {
+        let server = match server {
+            Some(server) => server.clone(),
+            None => resolv_conf_servers(&cli)?.remove(0),
+        };
+        let server = resolver::server_addr(&server, None).await?.to_string();
+        let mix = mix
+            .clone()
+            .unwrap_or_else(|| TypeMix::single(query_type.clone()));
+        let mut bench = Bench::new(server.clone(), names.clone(), mix.clone())
+            .with_count(*count)
+            .with_warmup(*warmup)
+            .with_concurrency(*concurrency as usize)
+            .with_timeout(*timeout);
+        if let Some(qps) = qps {
+            bench = bench.with_qps(*qps);
+        }
+        if let Some(seed) = cli.seed {
+            bench = bench.with_randomness(Randomness::seeded(seed));
+        }
+        if *cache_bust {
+            bench = bench.with_cache_bust();
+        }
+        if !cli.quiet {
+            let mut plan = std::format!("Sending {} {} queries to {}", count, mix, server);
+            if *warmup > 0 {
+                plan.push_str(&std::format!(" after {} to warm its cache up", warmup));
+            }
+            if *cache_bust {
+                plan.push_str(", every name with a random label in front");
+            }
+            eprintln!("{}", plan);
+        }
+        println!("{}", bench.run().await);
+        return Ok(());
+    }
+    #[cfg(feature = "serve")]
+    if let Some(Command::Cache { command }) = &cli.command {
+        match command {
+            CacheCommand::Stats { server } => {
+                println!("{}", serve::cache_stats(server.to_string()).await?)
+            }
+            CacheCommand::Ls { server } => {
+                for name in serve::cache_names(server.to_string()).await? {
+                    println!("{}", name);
+                }
+            }
+            CacheCommand::Flush { name, server } => {
+                let flushed = serve::flush_cache(server.to_string(), name.as_deref()).await?;
+                match name {
+                    Some(name) => println!("Flushed {} responses of {}", flushed, name),
+                    None => println!("Flushed {} responses", flushed),
+                }
+            }
+        }
+        return Ok(());
+    }
+    #[cfg(feature = "serve")]
+    if let Some(Command::Stats { domain, server }) = &cli.command {
+        let summaries = serve::domain_stats(server.to_string(), domain.as_deref()).await?;
+        match (domain, summaries.as_slice()) {
+            (Some(domain), []) => println!("No queries of {} yet", domain),
+            (Some(_), [summary, ..]) => println!("{}", summary),
+            (None, []) => println!("No queries yet"),
+            (None, summaries) => print!("{}", domainstats::domains_table(summaries)),
+        }
+        return Ok(());
+    }
+    #[cfg(feature = "diagnostics")]
+    if let Some(Command::Check { resolvers, timeout }) = &cli.command {
+        let resolvers = match resolvers.is_empty() {
+            true => resolv_conf_servers(&cli)?,
+            false => resolvers.clone(),
+        };
+        let checker = ResolverChecker::new(*timeout);
+        let servers = server_addrs(&resolvers, cli.bootstrap).await?;
+        let checks = join_all(servers.iter().map(|server| checker.check(server))).await;
+        print!("{}", check::checks_table(&checks));
+        let down = checks.iter().filter(|check| !check.is_available()).count();
+        if down > 0 {
+            return Err(ClientError::GenericError(std::format!(
+                "{} of {} resolvers didn't answer every well-known query",
+                down,
+                checks.len()
+            )));
+        }
+        return Ok(());
+    }
+    #[cfg(feature = "diagnostics")]
+    if let Some(Command::Doctor { server }) = &cli.command {
+        for check in doctor::run(server, &resolv_conf(&cli)).await {
+            println!("{}", check);
+        }
+        return Ok(());
+    }
+    #[cfg(feature = "discovery")]
+    if let Some(Command::K8s {
+        service,
+        server,
+        port,
+        protocol,
+        zone,
+    }) = &cli.command
+    {
+        let search = nsconfig::read_search(resolv_conf(&cli)).unwrap_or_default();
+        let (namespace, pod_zone) = k8s::pod_domain(&search);
+        let zone = zone.clone().or(pod_zone);
+        let zone = zone.as_deref().unwrap_or(k8s::DEFAULT_ZONE);
+        let name = k8s::service_name(service, namespace.as_deref().unwrap_or("default"), zone);
+        let srv_name = match port {
+            Some(port) => std::format!("_{}._{}.{}", port, protocol, name),
+            None => name.clone(),
+        };
+        let servers = match server.is_empty() {
+            true => resolv_conf_servers(&cli)?,
+            false => server.clone(),
+        };
+        let mut servers = server_addrs(&servers, None).await?.into_iter();
+        let mut client = Client::new(servers.next().unwrap_or_default()).await?;
+        for server in servers {
+            client = client.with_fallback(Transport::udp(server).await?);
+        }
+        let resolver = Resolver::new(client);
+        let ipv6 = Ipv6Format {
+            style: cli.ipv6,
+            uppercase: cli.uppercase,
+        };
+        let service = k8s::lookup(&resolver, &name, &srv_name).await?;
+        print!("{}", output::k8s_service(&service, &ipv6));
+        return Ok(());
+    }
+    #[cfg(feature = "discovery")]
+    if let Some(Command::Consul {
+        service,
+        server,
+        domain,
+    }) = &cli.command
+    {
+        let name = consul::service_name(service, domain);
+        let resolver = Resolver::new(Client::new(server.to_string()).await?);
+        let instances = resolver.lookup_srv_endpoints(&name).await?;
+        let ipv6 = Ipv6Format {
+            style: cli.ipv6,
+            uppercase: cli.uppercase,
+        };
+        print!("{}", output::consul_service(&name, &instances, &ipv6));
+        return Ok(());
+    }
+    #[cfg(feature = "zones")]
+    if let Some(Command::Update {
+        zone,
+        server,
+        add,
+        delete,
+        exists,
+        absent,
+        tsig,
+    }) = &cli.command
+    {
+        let mut update = ZoneUpdate::new(zone, cli.class)?;
+        if let Some(key) = tsig {
+            update = update.with_tsig(key.clone());
+        }
+        for name in exists {
+            update = update.require_exists(name)?;
+        }
+        for name in absent {
+            update = update.require_absent(name)?;
+        }
+        for record in delete {
+            update = update.delete_records(record)?;
+        }
+        for record in add {
+            update = update.add_record(record)?;
+        }
+        let server = resolver::server_addr(server, None).await?;
+        update.send(server.to_string(), cli.timeout).await?;
+        eprintln!("Update of {} applied", zone);
+        return Ok(());
+    }
+    #[cfg(feature = "serve")]
+    if let Some(Command::Serve(args)) = &cli.command {
+        let ServeArgs {
+            listen,
+            server,
+            timeout,
+            no_cache,
+            serve_stale,
+            cache_max_mem,
+            rate_limit,
+            burst,
+            max_in_flight,
+            limit_action,
+            allow,
+            deny,
+            probe_interval,
+            no_probes,
+            tcp_idle_timeout,
+            max_tcp_sessions,
+            user,
+            sandbox,
+        } = args.as_ref();
+        let mut forwarder = Forwarder::new(serve_upstream(&cli, server).await?)
+            .with_timeout(*timeout)
+            .with_tcp_idle_timeout(*tcp_idle_timeout)
+            .with_max_tcp_sessions(*max_tcp_sessions as usize);
+        if !no_cache {
+            let mut cache = Cache::new();
+            if let Some(window) = serve_stale {
+                cache = cache.with_stale(*window);
+            }
+            if let Some(max) = cache_max_mem {
+                cache = cache.with_max_memory(*max);
+            }
+            forwarder = forwarder.with_cache(cache);
+        }
+        if rate_limit.is_some() || max_in_flight.is_some() {
+            let mut limits = ClientLimits::new().with_action(*limit_action);
+            if let Some(rate) = rate_limit {
+                let burst = burst.map_or(rate.ceil(), f64::from);
+                limits = limits.with_rate(*rate, burst);
+            }
+            if let Some(max) = max_in_flight {
+                limits = limits.with_max_in_flight(*max as usize);
+            }
+            forwarder = forwarder.with_limits(limits);
+        }
+        let mut acl = Acl::new();
+        for network in allow {
+            acl = acl.with_allow(network.clone());
+        }
+        for network in deny {
+            acl = acl.with_deny(network.clone());
+        }
+        forwarder = forwarder.with_acl(acl);
+        if !no_probes {
+            forwarder = forwarder.with_health_probes(*probe_interval);
+        }
+        let reporter = forwarder.clone();
+        tokio::spawn(async move {
+            let mut stats_signal = ControlSignal::stats();
+            loop {
+                stats_signal.recv().await;
+                eprintln!("{}", reporter.report());
+            }
+        });
+        let reloaded = forwarder.clone();
+        tokio::spawn(async move {
+            let mut reload_signal = ControlSignal::reload();
+            loop {
+                reload_signal.recv().await;
+                if let Err(err) = reload(&reloaded).await {
+                    log::warn!("Reload failed, the settings are kept: {:?}", err);
+                }
+            }
+        });
+        let (socket, listener) = match systemd::listen_fds()? {
+            Some(sockets) => sockets,
+            None => serve::bind(*listen)?,
+        };
+        if let Some(user) = user {
+            sandbox::drop_privileges(user)?;
+        }
+        if *sandbox {
+            sandbox::restrict()?;
+        }
+        return forwarder.serve_sockets(socket, listener).await;
+    }
 
-    let cli = Cli::parse();
-    log::debug!("It will resolve {:?}", cli.hosts);
-    let mut tasks: Vec<JoinHandle<Result<Vec<QueryAnswer>, ClientError>>> =
-        Vec::with_capacity(cli.hosts.len());
+    log::info!("It will resolve {:?} {:?}", cli.hosts, cli.reverse);
+    let mut tasks: Vec<JoinHandle<(Result<Lookup, ClientError>, Duration)>> =
+        Vec::with_capacity(cli.hosts.len() + cli.reverse.len());
 
-    let mut server: String = if cli.server.len() > 0 {
-        cli.server
+    let options = nsconfig::read_options(resolv_conf(&cli)).unwrap_or_default();
+    let mut rotate = cli.rotate;
+    let servers: Vec<String> = if let Some(url) = doh_url(&cli) {
+        vec![url.to_string()]
+    } else if !cli.server.is_empty() {
+        cli.server.clone()
     } else {
-        match nsconfig::read_nameservers("/etc/resolv.conf".to_string()) {
-            Err(err) => return Err(ClientError::GenericError(err.to_string())),
-            Ok(vec) => {
-                if vec.len() > 0 {
-                    vec.get(0).unwrap().clone()
-                } else {
-                    "8.8.8.8".to_string()
+        rotate |= options.iter().any(|option| option == "rotate");
+        resolv_conf_servers(&cli)?
+    };
+    let servers: Vec<String> = match doh_url(&cli) {
+        Some(_) => servers,
+        None => server_addrs(&servers, cli.bootstrap).await?,
+    };
+    #[cfg(feature = "discovery")]
+    let all_mdns = cli.mdns
+        || (!cli.hosts.is_empty()
+            && cli.reverse.is_empty()
+            && cli.hosts.iter().all(|host| mdns::is_local(host)));
+    #[cfg(feature = "discovery")]
+    let server = match all_mdns {
+        true => mdns::GROUP_V4.to_string(),
+        false => servers.join(", "),
+    };
+    #[cfg(not(feature = "discovery"))]
+    let server = servers.join(", ");
+    #[cfg(feature = "encrypted-transports")]
+    let doh = match &cli.doh {
+        Some(url) => Some(DohEndpoint {
+            url: url.clone(),
+            method: cli.doh_method,
+            addrs: match cli.bootstrap {
+                Some(ip) => {
+                    let bootstrap = SocketAddr::new(ip, resolver::DNS_PORT).to_string();
+                    doh::bootstrap(url, bootstrap).await?
                 }
-            }
+                None => Vec::new(),
+            },
+            sources: Sources::new(&cli.data_sources),
+            padding: cli.pad,
+        }),
+        None => None,
+    };
+
+    #[cfg(feature = "dnssec")]
+    let trust_anchors = match (&cli.trust_anchor, cli.dnssec) {
+        (Some(filename), _) => Some(dnssec::read_trust_anchors(filename)?),
+        (None, true) => Some(dnssec::root_trust_anchors()),
+        (None, false) => None,
+    };
+
+    let search: Vec<String> = if cli.no_search {
+        Vec::new()
+    } else if !cli.search.is_empty() {
+        cli.search.clone()
+    } else {
+        nsconfig::read_search(resolv_conf(&cli)).unwrap_or_default()
+    };
+    let ndots = nsconfig::ndots(&options);
+
+    let budget = QueryBudget::new(cli.max_queries);
+    #[cfg(feature = "cache")]
+    let cache = if cli.no_cache {
+        None
+    } else {
+        Some(Cache::new())
+    };
+    // the system resolver's hosts file, unless other servers are queried
+    let hosts = match &cli.hosts_file {
+        Some(path) => Some(nsconfig::read_hosts(path.clone()).map_err(|err| {
+            ClientError::GenericError(std::format!("Failed to read {}: {}", path, err))
+        })?),
+        None if cli.no_hosts || !cli.server.is_empty() || doh_url(&cli).is_some() => None,
+        None => nsconfig::read_hosts(etc_file(&cli, nsconfig::HOSTS)).ok(),
+    };
+    let retry_budget = RetryBudget::new(cli.retry_budget);
+    let mut query_types: Vec<QueryType> = Vec::with_capacity(cli.query_type.len());
+    for query_type in &cli.query_type {
+        if !query_types.contains(query_type) {
+            query_types.push(query_type.clone());
+        }
+    }
+    if query_types.is_empty() {
+        query_types = vec![QueryType::AAAA, QueryType::A];
+    }
+    let mut queries: Vec<(String, QueryType)> = Vec::new();
+    for host in &cli.hosts {
+        for name in resolver::suffixed_names(host, &cli.suffixes) {
+            queries.extend(host_queries(&name, &query_types));
+        }
+    }
+    for addr in &cli.reverse {
+        queries.push((resolver::reverse_name(addr), QueryType::PTR));
+    }
+    let mut server_timeouts = Vec::with_capacity(cli.server_timeout.len());
+    for (server, timeout) in &cli.server_timeout {
+        let server = match server.as_str() {
+            "udp" | "https" => server.clone(),
+            url if url.starts_with("https://") => url.to_string(),
+            addr => server_addrs(&[addr.to_string()], cli.bootstrap)
+                .await?
+                .remove(0),
+        };
+        server_timeouts.push((server, *timeout));
+    }
+    let mut source = Source::default();
+    if let Some(addr) = cli.source {
+        source = source.with_addr(addr);
+    }
+    if let Some(interface) = &cli.interface {
+        source = source.with_interface(interface.clone());
+    }
+    if let Some(vrf) = &cli.vrf {
+        source = source.with_vrf(vrf.clone());
+    }
+    if let Some(mark) = cli.mark {
+        source = source.with_mark(mark);
+    }
+    #[cfg(feature = "diagnostics")]
+    let mut capture = None;
+    #[cfg(feature = "diagnostics")]
+    if cli.dump || cli.pcap.is_some() {
+        let mut copies = Capture::new();
+        if cli.dump {
+            copies = copies.with_dump();
         }
+        if let Some(path) = &cli.pcap {
+            copies = copies.with_pcap(path)?;
+        }
+        capture = Some(copies);
+    }
+    let settings = ClientSettings {
+        servers,
+        #[cfg(feature = "encrypted-transports")]
+        doh,
+        #[cfg(feature = "dnssec")]
+        trust_anchors,
+        budget: budget.clone(),
+        retry_budget: retry_budget.clone(),
+        class: cli.class,
+        timeout: cli.timeout,
+        server_timeouts,
+        #[cfg(feature = "zones")]
+        tsig: cli.tsig.clone(),
+        #[cfg(feature = "discovery")]
+        mdns: None,
+        cname_depth: cli.cname_depth,
+        subnet: cli.subnet.clone(),
+        source,
+        attempts: cli.attempts as usize,
+        rotation: if rotate {
+            Some(Rotation::default())
+        } else {
+            None
+        },
+        randomness: cli.seed.map(Randomness::seeded).unwrap_or_default(),
+        timestamping: cli.timestamping,
+        #[cfg(feature = "diagnostics")]
+        capture,
+        idn: !cli.no_idn,
+        // names as given, as dig prints them, for scripts matching them
+        randomize_case: !cli.no_0x20 && cli.compat.is_none(),
+        recursion: !cli.norecurse,
+        checking_disabled: cli.cd,
+        authentic_data: cli.ad,
+        all_sections: cli.all_sections,
+        answer_page: cli.limit.map(|limit| (limit as usize, cli.page as usize)),
+        cookie: cli.cookie,
+        #[cfg(feature = "cache")]
+        cache: cache.clone(),
+        hosts,
+        any_types: cli.any_types.clone(),
     };
-    if !server.ends_with(":53") {
-        server.push_str(":53");
+    #[cfg(feature = "diagnostics")]
+    if cli.trace {
+        return trace(queries, budget, &cli).await;
     }
-
-    for host in cli.hosts {
-        let h = host.clone();
-        let server = server.clone();
-        let srv = server.clone();
-        tasks.push(tokio::spawn(async move {
-            let client = match Client::new(server).await {
-                Ok(client) => client,
-                Err(err) => return Err(err),
-            };
-            match client.query(host, QueryType::AAAA).await {
-                Ok(res) => Ok(res),
-                Err(err) => return Err(err),
+    #[cfg(feature = "zones")]
+    if cli.axfr || cli.ixfr.is_some() {
+        let ipv6 = Ipv6Format {
+            style: cli.ipv6,
+            uppercase: cli.uppercase,
+        };
+        for zone in &cli.hosts {
+            match cli.ixfr {
+                Some(serial) => ixfr(zone, serial, &settings.servers[0], &ipv6, &cli).await?,
+                None => axfr(zone, &settings.servers[0], &ipv6, &cli).await?,
             }
-        }));
+        }
+        return Ok(());
+    }
+    #[cfg(feature = "diagnostics")]
+    if cli.compare {
+        return compare(&settings, queries, &cli).await;
+    }
+    #[cfg(all(feature = "diagnostics", feature = "serve"))]
+    if !cli.vantage.is_empty() {
+        return vantage(queries, &cli).await;
+    }
+    #[cfg(feature = "diagnostics")]
+    if let (Some(at), Some(export)) = (cli.at, &cli.export) {
+        return cached_at(export, queries, at, &cli);
+    }
+    let query_options = QueryOptions {
+        search,
+        ndots,
+        #[cfg(feature = "discovery")]
+        mdns: cli.mdns,
+        #[cfg(feature = "discovery")]
+        mdns_window: cli.mdns_window,
+        fallback_public: cli.fallback_public,
+        dig: matches!(cli.output, OutputFormat::Dig | OutputFormat::Verbose)
+            || cli.compat.is_some(),
+    };
+    if let Some(file) = &cli.file {
+        return batch(file, &settings, &query_options, &query_types, &server, &cli).await;
+    }
+    #[cfg(feature = "diagnostics")]
+    if let Some(interval) = cli.watch {
+        return watch(settings, query_options, queries, interval, &cli).await;
+    }
+    #[cfg(feature = "interactive")]
+    if cli.interactive || queries.is_empty() {
+        return interactive(settings, query_options, query_types, &cli).await;
+    }
+    #[cfg(not(feature = "interactive"))]
+    if queries.is_empty() {
+        return Err(ClientError::GenericError("No name to query".to_string()));
+    }
+    let given = queries.len();
+    let unique = dedup::unique_queries(queries.clone());
+    let unique_count = unique.len();
+    // each query with the number of inputs its results stand for
+    let queries: Vec<((String, QueryType), usize)> = if cli.dedup {
+        unique
+    } else {
+        queries.into_iter().map(|query| (query, 1)).collect()
+    };
+    let copies: Vec<usize> = queries.iter().map(|(_, copies)| *copies).collect();
+    let sent: Vec<(String, QueryType)> = queries.iter().map(|(query, _)| query.clone()).collect();
+    let mut exports = export_sinks(&cli, &server);
+    let in_flight = Arc::new(Semaphore::new(cli.concurrency as usize));
+    for ((host, query_type), _) in queries {
+        let (settings, query_options) = (settings.clone(), query_options.clone());
+        let in_flight = in_flight.clone();
         tasks.push(tokio::spawn(async move {
-            let client = match Client::new(srv).await {
-                Ok(client) => client,
-                Err(err) => return Err(err),
-            };
-            match client.query(h, QueryType::A).await {
-                Ok(res) => Ok(res),
-                Err(err) => return Err(err),
-            }
+            // never closed, the permit is released once the query is done
+            let _permit = in_flight.acquire_owned().await;
+            resolve(settings, query_options, host, query_type).await
         }));
     }
     let joined = join_all(tasks).await;
-    let mut answers: Vec<QueryAnswer> = Vec::new();
-    for result in joined {
-        match result {
-            Ok(r) => match r {
-                Ok(res) => answers.extend(res),
-                Err(err) => return Err(err)
-            }
-            Err(err) => return Err(ClientError::GenericError(err.to_string()))
+    show_summary(&budget, &retry_budget, &cli);
+    #[cfg(feature = "cache")]
+    show_cache_usage(cache.as_ref(), &cli);
+
+    let mut results: Vec<(QueryResult, usize)> = Vec::new();
+    let mut outcomes: Vec<(String, Duration, Outcome)> = Vec::new();
+    let mut setups: Vec<Duration> = Vec::new();
+    let mut failures: Vec<(String, QueryType, ClientError)> = Vec::new();
+    for ((result, copies), (host, query_type)) in joined.into_iter().zip(copies).zip(sent) {
+        let (lookup, rtt) = match result {
+            Ok(result) => result,
+            Err(err) => return Err(ClientError::GenericError(err.to_string())),
+        };
+        let result = Lookup::result(&lookup, host, query_type, &server, rtt);
+        outcomes.push((result.server.clone(), rtt, result.outcome));
+        setups.extend(result.connection_setup());
+        for export in &mut exports {
+            export.query(&result)?;
+        }
+        if let Err(err) = lookup {
+            failures.push((result.host.clone(), result.query_type.clone(), err));
         }
+        results.push((result, copies));
+    }
+    for export in &mut exports {
+        export.finish(None)?;
+    }
+    let summary = Summary::new(&outcomes).with_connection_setups(&setups);
+    let failed = failures.len();
+    let failed_hosts: HashSet<&str> = failures.iter().map(|(host, ..)| host.as_str()).collect();
+    // the error of a single host that didn't resolve is the outcome of the run
+    if failed > 0 && (cli.fail_fast || (failed == results.len() && failed_hosts.len() == 1)) {
+        show_batch_summary(&summary, &cli);
+        return Err(failures.remove(0).2);
+    }
+    if !cli.quiet {
+        for (host, query_type, err) in &failures {
+            eprintln!("{} {}: {}", host, query_type, err);
+        }
+    }
+    if failed == results.len() {
+        show_batch_summary(&summary, &cli);
+        return Err(ClientError::QueriesFailed(
+            failed,
+            results.len(),
+            Box::new(failures.remove(0).2),
+        ));
+    }
+    show_results(&results, &summary, &server, &cli)?;
+    let answers: Vec<QueryAnswer> = results
+        .iter()
+        .flat_map(|(result, copies)| {
+            std::iter::repeat_n(result.answers.iter().cloned(), *copies).flatten()
+        })
+        .collect();
+    let report = DedupReport {
+        queries: given,
+        unique: unique_count,
+        resolved_unique: cli.dedup,
+        shared: dedup::shared_targets(&answers),
+    };
+    if !report.is_empty() && !cli.quiet && !cli.short {
+        eprintln!("{}", report);
+    }
+    nxdomain_status(&outcomes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rewrite(args: &str) -> String {
+        dig_args(args.split(' ').map(String::from).collect())
+            .unwrap()
+            .join(" ")
+    }
+
+    #[test]
+    fn dig_args_types_follow_the_host() {
+        assert_eq!(rewrite("nsq ns ns"), "nsq ns --type ns");
+        assert_eq!(rewrite("nsq https"), "nsq https");
+        assert_eq!(
+            rewrite("nsq @1.1.1.1 example.com MX +short"),
+            "nsq --server 1.1.1.1 example.com --type MX --short"
+        );
+        assert_eq!(
+            rewrite("nsq -t TXT any cert AAAA"),
+            "nsq -t TXT any --type cert --type AAAA"
+        );
+        assert_eq!(rewrite("nsq trace example.com"), "nsq trace example.com");
+    }
+
+    #[tokio::test]
+    async fn batch_reports_bad_lines_and_goes_on() {
+        let file = std::env::temp_dir().join(std::format!("nsq-batch-{}", std::process::id()));
+        std::fs::write(&file, "example.com +bogus\n# comment\nexample.org +bogus\n").unwrap();
+        let args = ["nsq", "--quiet", "--server", "127.0.0.1", "--file"];
+        let args = args.iter().map(|arg| arg.to_string());
+        let cli = parse_cli(args.chain([file.display().to_string()]).collect()).unwrap();
+        let result = run(cli).await;
+        std::fs::remove_file(&file).unwrap();
+        let Err(ClientError::QueriesFailed(2, 2, first)) = result else {
+            panic!("{:?}", result);
+        };
+        assert!(matches!(*first, ClientError::ParseError(_)));
+    }
+
+    #[test]
+    fn exit_codes() {
+        let refused = ClientError::RDCodeRefused(String::new());
+        for (err, code) in [
+            (ClientError::Timeout(3), 2),
+            (ClientError::AllFailed(Vec::new()), 2),
+            (ClientError::RDCodeNameError, 3),
+            (ClientError::RDCodeServerFailure, 4),
+            (ClientError::RDCode(9), 4),
+            (ClientError::ParseError(String::new()), USAGE_ERROR),
+            (ClientError::QueriesFailed(2, 2, Box::new(refused)), 4),
+        ] {
+            assert_eq!(exit_code(&err), code, "{:?}", err);
+        }
+    }
+
+    #[test]
+    fn nxdomain_only_runs_exit_with_its_status() {
+        let outcome = |outcome| ("192.0.2.1:53".to_string(), Duration::ZERO, outcome);
+        let nxdomain = [outcome(Outcome::NxDomain), outcome(Outcome::NxDomain)];
+        let err = nxdomain_status(&nxdomain).unwrap_err();
+        assert_eq!(exit_code(&err), 3);
+        assert!(
+            nxdomain_status(&[outcome(Outcome::NxDomain), outcome(Outcome::Succeeded)]).is_ok()
+        );
+        assert!(nxdomain_status(&[]).is_ok());
+    }
+
+    #[tokio::test]
+    async fn no_server_listening_exits_with_2() {
+        // nothing listens on port 1 of the loopback address
+        let args = [
+            "nsq",
+            "--quiet",
+            "--no-hosts",
+            "--server",
+            "127.0.0.1:1",
+            "example.com",
+        ];
+        let cli = parse_cli(args.iter().map(|arg| arg.to_string()).collect()).unwrap();
+        let err = run(cli).await.unwrap_err();
+        assert_eq!(exit_code(&err), 2, "{:?}", err);
     }
-    show_answers(&answers, &server);
-    Ok(())
 }
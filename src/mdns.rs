@@ -0,0 +1,205 @@
+//! Multicast DNS, RFC 6762: names under `.local` have no nameserver, the
+//! hosts of the link answer for their own names when asked on a multicast
+//! group, so a query is sent there and every response received within a
+//! window is collected.
+use crate::client::{ClassType, ClientError, QueryType};
+use crate::idn;
+use crate::rdata::ResourceRecord;
+use crate::transport::Received;
+use crate::wire::{Message, Record};
+use futures::stream::{self, Stream, StreamExt};
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddrV4, SocketAddrV6};
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use tokio::time::{timeout_at, Instant};
+
+pub const GROUP_V4: SocketAddrV4 = SocketAddrV4::new(Ipv4Addr::new(224, 0, 0, 251), 5353);
+pub const GROUP_V6: SocketAddrV6 =
+    SocketAddrV6::new(Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 0xfb), 5353, 0, 0);
+
+/// Top bit of the class: in a question, asks for a unicast response, RFC 6762
+/// 5.4; in a record, tells caches to flush older records of its set, 10.2
+const TOP_BIT: u16 = 0x8000;
+
+/// Whether `name` is under `.local`, the domain only mDNS resolves
+pub fn is_local(name: &str) -> bool {
+    let name = name.trim_end_matches('.').to_lowercase();
+    name == "local" || name.ends_with(".local")
+}
+
+/// Sends queries to the mDNS groups from an ephemeral port, a one-shot query
+/// of RFC 6762 5.1 answered by unicast, and merges the responses of every
+/// host that answers within the window
+pub struct MdnsTransport {
+    v4: UdpSocket,
+    /// No IPv6 group on hosts without IPv6
+    v6: Option<UdpSocket>,
+    window: Duration,
+}
+
+/// Adds the records of `from` missing from `into`, without the cache flush bit
+fn merge(into: &mut Vec<Record>, from: Vec<Record>) {
+    for mut record in from {
+        if record.query_type != QueryType::OPT {
+            record.class &= !TOP_BIT;
+        }
+        if !into.contains(&record) {
+            into.push(record);
+        }
+    }
+}
+
+impl MdnsTransport {
+    pub async fn new(window: Duration) -> Result<MdnsTransport, ClientError> {
+        let v4 = UdpSocket::bind("0.0.0.0:0")
+            .await
+            .map_err(|err| ClientError::BindError(err.to_string()))?;
+        // responders ignore queries from off the link, RFC 6762 11
+        v4.set_multicast_ttl_v4(255)
+            .map_err(|err| ClientError::BindError(err.to_string()))?;
+        let v6 = match UdpSocket::bind("[::]:0").await {
+            Ok(socket) => Some(socket),
+            Err(err) => {
+                log::info!("Querying mDNS over IPv4 only: {}", err);
+                None
+            }
+        };
+        Ok(MdnsTransport { v4, v6, window })
+    }
+
+    pub fn server(&self) -> String {
+        GROUP_V4.to_string()
+    }
+
+    async fn recv(&self) -> Result<Vec<u8>, ClientError> {
+        let mut v4_buf = vec![0u8; 9000];
+        let mut v6_buf = vec![0u8; 9000];
+        let received = match &self.v6 {
+            Some(v6) => tokio::select! {
+                received = self.v4.recv_from(&mut v4_buf) => received.map(|(len, _)| (len, v4_buf)),
+                received = v6.recv_from(&mut v6_buf) => received.map(|(len, _)| (len, v6_buf)),
+            },
+            None => self
+                .v4
+                .recv_from(&mut v4_buf)
+                .await
+                .map(|(len, _)| (len, v4_buf)),
+        };
+        let (len, mut data) = received.map_err(|err| ClientError::RecvError(err.to_string()))?;
+        data.truncate(len);
+        Ok(data)
+    }
+
+    /// Sends `query` to the groups, asking for unicast responses
+    async fn send(&self, query: &Message) -> Result<(), ClientError> {
+        // RD is meaningless to responders, RFC 6762 18.6
+        let mut sent = query.clone();
+        sent.clear_recursion_desired();
+        for question in sent.questions.iter_mut() {
+            question.class |= TOP_BIT;
+        }
+        let data = sent.encode();
+        self.v4
+            .send_to(&data, GROUP_V4)
+            .await
+            .map_err(|err| ClientError::SendError(err.to_string()))?;
+        if let Some(v6) = &self.v6 {
+            if let Err(err) = v6.send_to(&data, GROUP_V6).await {
+                log::info!("No mDNS query to {}: {}", GROUP_V6, err);
+            }
+        }
+        Ok(())
+    }
+
+    /// Responses to the query of `id` received until the window ends
+    fn responses(&self, id: u16) -> impl Stream<Item = Result<Message, ClientError>> + '_ {
+        let deadline = Instant::now() + self.window;
+        stream::unfold((), move |()| async move {
+            loop {
+                let received = match timeout_at(deadline, self.recv()).await {
+                    Ok(received) => received,
+                    Err(_) => return None,
+                };
+                let data = match received {
+                    Ok(data) => data,
+                    Err(err) => return Some((Err(err), ())),
+                };
+                match Message::decode(&data) {
+                    // one-shot queries get their id back, multicast responses 0
+                    Ok(response)
+                        if response.header.is_response()
+                            && (response.header.id == id || response.header.id == 0) =>
+                    {
+                        return Some((Ok(response), ()))
+                    }
+                    _ => continue,
+                }
+            }
+        })
+    }
+
+    /// Answers of a `query_type` query of `name` as the hosts of the link
+    /// send them, each once, until the window ends: services of a DNS-SD
+    /// browse show up as they are found rather than once it is over
+    pub fn browse(
+        &self,
+        name: &str,
+        query_type: QueryType,
+    ) -> impl Stream<Item = Result<ResourceRecord, ClientError>> + '_ {
+        let query = idn::to_ascii(name)
+            .map(|name| Message::query(rand::random(), &name, &query_type, ClassType::IN.code()));
+        let sent = async move {
+            let query = query?;
+            self.send(&query).await?;
+            Ok::<u16, ClientError>(query.header.id)
+        };
+        stream::once(sent)
+            .map(move |sent| match sent {
+                Ok(id) => self.responses(id).left_stream(),
+                Err(err) => stream::once(async { Err(err) }).right_stream(),
+            })
+            .flatten()
+            .scan(Vec::new(), |seen: &mut Vec<Record>, response| {
+                let records = match response {
+                    Ok(response) => {
+                        let known = seen.len();
+                        merge(seen, response.answers);
+                        let new = seen[known..].iter().cloned();
+                        new.map(ResourceRecord::from_wire).collect()
+                    }
+                    Err(err) => vec![Err(err)],
+                };
+                async move { Some(stream::iter(records)) }
+            })
+            .flatten()
+    }
+
+    pub(crate) async fn exchange(&self, query: &[u8]) -> Result<Received, ClientError> {
+        let query = Message::decode(query)?;
+        self.send(&query).await?;
+        let mut merged: Option<Message> = None;
+        let mut responses = std::pin::pin!(self.responses(query.header.id));
+        while let Some(response) = responses.next().await {
+            let response = response?;
+            let merged = merged.get_or_insert_with(|| Message::reply(&query, 0));
+            merge(&mut merged.answers, response.answers);
+            merge(&mut merged.authorities, response.authorities);
+            merge(&mut merged.additionals, response.additionals);
+        }
+        // silence can't tell a name nobody claims from one without records of
+        // the type, both are shown as no answers
+        let merged = merged.unwrap_or_else(|| {
+            log::info!(
+                "No mDNS responder answered within {} ms",
+                self.window.as_millis()
+            );
+            Message::reply(&query, 0)
+        });
+        Ok(Received {
+            data: merged.encode(),
+            wire_time: None,
+            discarded: 0,
+            setup: None,
+        })
+    }
+}
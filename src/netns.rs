@@ -0,0 +1,82 @@
+//! Network namespaces of Linux, setns(2): queries sent from inside another
+//! one, to see DNS as a container or a VRF does without running nsq in it.
+use crate::client::ClientError;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// Where `ip netns add` mounts named namespaces
+const NETNS_RUN_DIR: &str = "/run/netns";
+
+/// Where `ip netns exec` finds the files it mounts over those of /etc
+const NETNS_ETC_DIR: &str = "/etc/netns";
+
+/// A network namespace, by the name `ip netns` gave it or a process in it
+#[derive(Debug, Clone, PartialEq)]
+pub enum Netns {
+    Named(String),
+    Process(u32),
+}
+
+impl FromStr for Netns {
+    type Err = String;
+
+    /// A process ID, like the one of a container, or the name of a namespace
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(pid) = s.parse::<u32>() {
+            return Ok(Netns::Process(pid));
+        }
+        match s.is_empty() || s.contains('/') || s == "." || s == ".." {
+            true => Err(std::format!("{} isn't a namespace name or a PID", s)),
+            false => Ok(Netns::Named(s.to_string())),
+        }
+    }
+}
+
+impl Netns {
+    /// The file standing for the namespace
+    pub fn path(&self) -> PathBuf {
+        match self {
+            Netns::Named(name) => Path::new(NETNS_RUN_DIR).join(name),
+            Netns::Process(pid) => PathBuf::from(std::format!("/proc/{}/ns/net", pid)),
+        }
+    }
+
+    /// `file` of /etc as seen from the namespace: the one `ip netns exec`
+    /// mounts for a named namespace, or the one under the root of the
+    /// process, when it exists
+    pub fn etc_file(&self, file: &str) -> Option<String> {
+        let path = match self {
+            Netns::Named(name) => Path::new(NETNS_ETC_DIR).join(name).join(file),
+            Netns::Process(pid) => PathBuf::from(std::format!("/proc/{}/root/etc/{}", pid, file)),
+        };
+        path.exists().then(|| path.to_string_lossy().into_owned())
+    }
+
+    /// Moves the calling thread into the namespace, the threads it starts
+    /// afterwards and their sockets being in it too
+    #[cfg(target_os = "linux")]
+    pub fn enter(&self) -> Result<(), ClientError> {
+        use std::os::fd::AsRawFd;
+
+        let path = self.path();
+        let failed = |err: std::io::Error| {
+            ClientError::GenericError(std::format!(
+                "Failed to enter the network namespace {}: {}",
+                path.display(),
+                err
+            ))
+        };
+        let file = std::fs::File::open(&path).map_err(failed)?;
+        if unsafe { libc::setns(file.as_raw_fd(), libc::CLONE_NEWNET) } != 0 {
+            return Err(failed(std::io::Error::last_os_error()));
+        }
+        Ok(())
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn enter(&self) -> Result<(), ClientError> {
+        Err(ClientError::GenericError(
+            "Network namespaces are only available on Linux".to_string(),
+        ))
+    }
+}
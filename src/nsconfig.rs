@@ -1,22 +1,111 @@
 use std::io;
 
+use std::collections::HashMap;
 use std::fs::File;
 use std::io::BufRead;
+use std::net::IpAddr;
+use std::sync::Arc;
+
+pub const RESOLV_CONF: &str = "/etc/resolv.conf";
+
+pub const HOSTS: &str = "/etc/hosts";
 
 pub fn read_nameservers(filename: String) -> io::Result<Vec<String>> {
     let file = File::open(filename)?;
     let mut names: Vec<String> = Vec::new();
-    for line in io::BufReader::new(file).lines() {
-        if let Ok(l) = line {
-            if !l.contains("nameserver") {
-                continue;
-            }
-            let nameserver = l.split("nameserver").last();
-            match nameserver {
-                Some(ns) => names.push(ns.trim().to_string()),
-                None => (),
-            };
+    for l in io::BufReader::new(file).lines().map_while(Result::ok) {
+        if !l.contains("nameserver") {
+            continue;
+        }
+        if let Some(ns) = l.split("nameserver").last() {
+            names.push(ns.trim().to_string());
         }
     }
     Ok(names)
 }
+
+/// Tokens of the `options` lines, like `rotate` or `ndots:2`
+pub fn read_options(filename: String) -> io::Result<Vec<String>> {
+    let file = File::open(filename)?;
+    let mut options: Vec<String> = Vec::new();
+    for l in io::BufReader::new(file).lines().map_while(Result::ok) {
+        let mut tokens = l.split_whitespace();
+        if tokens.next() == Some("options") {
+            options.extend(tokens.map(|t| t.to_string()));
+        }
+    }
+    Ok(options)
+}
+
+/// Search list of the last `search` or `domain` line, which replace each other
+pub fn read_search(filename: String) -> io::Result<Vec<String>> {
+    let file = File::open(filename)?;
+    let mut search: Vec<String> = Vec::new();
+    for l in io::BufReader::new(file).lines().map_while(Result::ok) {
+        let mut tokens = l.split_whitespace();
+        if let Some("search" | "domain") = tokens.next() {
+            search = tokens.map(|t| t.to_string()).collect();
+        }
+    }
+    Ok(search)
+}
+
+/// The `ndots:n` option, 1 by default and at most 15 like libc
+pub fn ndots(options: &[String]) -> usize {
+    options
+        .iter()
+        .rev()
+        .find_map(|option| option.strip_prefix("ndots:")?.parse().ok())
+        .unwrap_or(1)
+        .min(15)
+}
+
+/// Addresses of the names of a hosts file, clones sharing them
+#[derive(Debug, Clone, Default)]
+pub struct Hosts {
+    path: String,
+    addrs: Arc<HashMap<String, Vec<IpAddr>>>,
+}
+
+impl Hosts {
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Addresses of `name` in the order of the file, any case, with or
+    /// without its trailing dot
+    pub fn addrs(&self, name: &str) -> &[IpAddr] {
+        let name = name.trim_end_matches('.').to_lowercase();
+        self.addrs.get(&name).map_or(&[], |addrs| addrs.as_slice())
+    }
+}
+
+/// Lines of an address and its names, canonical name then aliases, like
+/// `127.0.0.1 localhost`. Lines with an address that doesn't parse are
+/// skipped, as libc does.
+pub fn read_hosts(filename: String) -> io::Result<Hosts> {
+    let file = File::open(&filename)?;
+    let mut addrs: HashMap<String, Vec<IpAddr>> = HashMap::new();
+    for l in io::BufReader::new(file).lines().map_while(Result::ok) {
+        let l = l.split('#').next().unwrap_or_default();
+        let mut tokens = l.split_whitespace();
+        // zones of link-local addresses are left out, `fe80::1%eth0`
+        let Some(Ok(addr)) = tokens
+            .next()
+            .map(|addr| addr.split('%').next().unwrap_or_default().parse::<IpAddr>())
+        else {
+            continue;
+        };
+        for name in tokens {
+            let name = name.trim_end_matches('.').to_lowercase();
+            let known = addrs.entry(name).or_default();
+            if !known.contains(&addr) {
+                known.push(addr);
+            }
+        }
+    }
+    Ok(Hosts {
+        path: filename,
+        addrs: Arc::new(addrs),
+    })
+}
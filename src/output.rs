@@ -0,0 +1,1170 @@
+use crate::client::{
+    self, ClassType, Decision, Provenance, QueryAnswer, QueryType, Response, Section,
+};
+#[cfg(feature = "diagnostics")]
+use crate::compare::Difference;
+#[cfg(feature = "discovery")]
+use crate::consul;
+#[cfg(feature = "discovery")]
+use crate::k8s::Service;
+use crate::rdata::{self, RData};
+#[cfg(feature = "discovery")]
+use crate::resolver::SrvEndpoint;
+#[cfg(feature = "diagnostics")]
+use crate::trace::Step;
+use crate::wire::Record;
+#[cfg(feature = "zones")]
+use crate::xfr::Diff;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt;
+#[cfg(feature = "discovery")]
+use std::net::IpAddr;
+use std::net::{Ipv6Addr, SocketAddr};
+#[cfg(feature = "zones")]
+use std::ops::Range;
+use std::time::Duration;
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+const TRUNCATION_MARKER: char = '…';
+
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum OutputFormat {
+    /// Aligned columns, one answer per row
+    Table,
+    /// Rust debug representation of each answer
+    Debug,
+    /// JSON array of answers, for scripts and jq
+    Json,
+    /// A JSON object per answer and line, printed as queries complete
+    Ndjson,
+    /// Comma separated values with a header, for spreadsheets
+    Csv,
+    /// Every section of each response with its header flags, like dig
+    Dig,
+    /// Every field of each response in long form: the header, EDNS and its
+    /// options, the sections and the timing
+    Verbose,
+    /// A record per answer in the presentation format of zone files
+    Zone,
+}
+
+/// Output layouts of other tools, so scripts that scrape them keep working
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum Compat {
+    /// dig's layout, line for line: the command banner, tab aligned records
+    /// and the server, time and size footer
+    Dig,
+}
+
+/// How IPv6 addresses are written
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum Ipv6Style {
+    /// RFC 5952 canonical form, the longest run of zero groups compressed
+    Compressed,
+    /// All eight groups with their leading zeros
+    Expanded,
+    /// Last 32 bits as a dotted IPv4 address, like `64:ff9b::192.0.2.33`
+    Mixed,
+}
+
+/// Presentation of the IPv6 addresses of AAAA answers, e.g. to match them
+/// against firewall configs
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Ipv6Format {
+    pub style: Ipv6Style,
+    pub uppercase: bool,
+}
+
+impl Default for Ipv6Format {
+    fn default() -> Ipv6Format {
+        Ipv6Format {
+            style: Ipv6Style::Compressed,
+            uppercase: false,
+        }
+    }
+}
+
+/// Hex groups with the longest run of two or more zero groups, the first one
+/// on ties, written as `::`, RFC 5952 4.2
+fn compress_groups(groups: &[u16]) -> String {
+    let (mut best_start, mut best_len) = (0, 0);
+    let mut i = 0;
+    while i < groups.len() {
+        let len = groups[i..].iter().take_while(|g| **g == 0).count();
+        if len > best_len {
+            (best_start, best_len) = (i, len);
+        }
+        i += len.max(1);
+    }
+    let hex = |groups: &[u16]| {
+        groups
+            .iter()
+            .map(|g| std::format!("{:x}", g))
+            .collect::<Vec<String>>()
+            .join(":")
+    };
+    if best_len < 2 {
+        return hex(groups);
+    }
+    std::format!(
+        "{}::{}",
+        hex(&groups[..best_start]),
+        hex(&groups[best_start + best_len..])
+    )
+}
+
+impl Ipv6Format {
+    pub fn format(&self, addr: &Ipv6Addr) -> String {
+        let groups = addr.segments();
+        let text = match self.style {
+            Ipv6Style::Compressed => addr.to_string(),
+            Ipv6Style::Expanded => groups
+                .iter()
+                .map(|g| std::format!("{:04x}", g))
+                .collect::<Vec<String>>()
+                .join(":"),
+            Ipv6Style::Mixed => {
+                let [.., a, b, c, d] = addr.octets();
+                let head = compress_groups(&groups[..6]);
+                let sep = if head.ends_with("::") { "" } else { ":" };
+                std::format!("{}{}{}.{}.{}.{}", head, sep, a, b, c, d)
+            }
+        };
+        if self.uppercase {
+            text.to_uppercase()
+        } else {
+            text
+        }
+    }
+
+    /// Zone file presentation of `data`, with AAAA addresses in this format
+    pub fn data(&self, data: &RData) -> String {
+        match data {
+            RData::AAAA(addr) => self.format(addr),
+            data => data.to_string(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct JsonAnswer {
+    host: String,
+    #[serde(rename = "type")]
+    query_type: String,
+    class: String,
+    ttl: u32,
+    rdata: String,
+    section: Section,
+    /// NXDOMAIN or NODATA for the SOA of a negative response
+    #[serde(skip_serializing_if = "Option::is_none")]
+    negative: Option<&'static str>,
+    server: String,
+    rcode: String,
+    /// Flags of the response among aa, tc, ra and ad
+    flags: Vec<&'static str>,
+    query_time_ms: f64,
+    decisions: Vec<Decision>,
+    provenance: Provenance,
+    /// Client subnet the server echoed, and the prefix the answer holds for
+    #[serde(skip_serializing_if = "Option::is_none")]
+    subnet: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    scope_prefix: Option<u8>,
+    /// Expired answer served as upstream failed, RFC 8767
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    stale: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dnssec: Option<String>,
+}
+
+#[derive(Serialize)]
+struct JsonRun<'a> {
+    answers: Vec<JsonAnswer>,
+    summary: &'a Summary,
+}
+
+/// Order of the answers of a run, the order queries were given otherwise
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum SortKey {
+    /// By host name
+    Host,
+    /// Fastest queries first
+    Rtt,
+    /// Shortest TTLs first
+    Ttl,
+}
+
+/// Sorts `answers` by `key`, keeping the query order among equal ones
+pub fn sort_answers(answers: &mut [QueryAnswer], key: SortKey) {
+    match key {
+        SortKey::Host => answers.sort_by(|a, b| a.host().cmp(b.host())),
+        SortKey::Rtt => answers.sort_by_key(|answer| answer.query_time()),
+        SortKey::Ttl => answers.sort_by_key(|answer| answer.ttl()),
+    }
+}
+
+/// How a query of a run ended
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Outcome {
+    Succeeded,
+    NxDomain,
+    Failed,
+}
+
+/// Totals of a bulk run. RTTs only count queries that got a response.
+#[derive(Debug, Default, Serialize)]
+pub struct Summary {
+    queries: usize,
+    succeeded: usize,
+    success_percent: f64,
+    nxdomain: usize,
+    mean_rtt_ms: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    slowest_server: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    slowest_server_rtt_ms: Option<f64>,
+    /// Of the queries that opened a connection, the part of the RTT spent on
+    /// it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    mean_setup_ms: Option<f64>,
+}
+
+impl Summary {
+    /// Summary of queries given as their server, RTT and outcome
+    pub fn new(queries: &[(String, Duration, Outcome)]) -> Summary {
+        let mut servers: HashMap<&str, (Duration, u32)> = HashMap::new();
+        let (mut total, mut responses) = (Duration::ZERO, 0);
+        for (server, rtt, outcome) in queries {
+            if *outcome == Outcome::Failed {
+                continue;
+            }
+            let entry = servers.entry(server).or_default();
+            *entry = (entry.0 + *rtt, entry.1 + 1);
+            total += *rtt;
+            responses += 1;
+        }
+        let slowest = servers
+            .into_iter()
+            .map(|(server, (total, count))| (server, total / count))
+            .max_by_key(|(_, mean)| *mean);
+        let count = |wanted: Outcome| queries.iter().filter(|(_, _, o)| *o == wanted).count();
+        let succeeded = count(Outcome::Succeeded);
+        Summary {
+            queries: queries.len(),
+            succeeded,
+            success_percent: if queries.is_empty() {
+                0.0
+            } else {
+                succeeded as f64 * 100.0 / queries.len() as f64
+            },
+            nxdomain: count(Outcome::NxDomain),
+            mean_rtt_ms: if responses == 0 {
+                0.0
+            } else {
+                client::millis(total / responses)
+            },
+            slowest_server: slowest.map(|(server, _)| server.to_string()),
+            slowest_server_rtt_ms: slowest.map(|(_, mean)| client::millis(mean)),
+            mean_setup_ms: None,
+        }
+    }
+
+    /// With the mean time spent setting up connections, given those of the
+    /// queries that opened one
+    pub fn with_connection_setups(mut self, setups: &[Duration]) -> Summary {
+        if !setups.is_empty() {
+            let total: Duration = setups.iter().sum();
+            self.mean_setup_ms = Some(client::millis(total / setups.len() as u32));
+        }
+        self
+    }
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "Queries: {}, succeeded: {} ({:.1}%), NXDOMAIN: {}",
+            self.queries, self.succeeded, self.success_percent, self.nxdomain
+        )?;
+        write!(f, "Mean RTT: {} ms", self.mean_rtt_ms)?;
+        if let Some(setup) = self.mean_setup_ms {
+            write!(f, ", connection setup: {} ms", setup)?;
+        }
+        match (&self.slowest_server, self.slowest_server_rtt_ms) {
+            (Some(server), Some(rtt)) => write!(f, ", slowest server: {} ({} ms)", server, rtt),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Renders rows as aligned columns, measuring cells by their display width so
+/// wide Unicode hostnames line up. The last column is wrapped at `width`, or
+/// cut with a marker when `truncate` is set.
+pub struct Table {
+    headers: Vec<String>,
+    rows: Vec<Vec<String>>,
+    width: usize,
+    truncate: bool,
+}
+
+impl Table {
+    pub fn new(headers: &[&str], width: usize, truncate: bool) -> Table {
+        Table {
+            headers: headers.iter().map(|h| h.to_string()).collect(),
+            rows: Vec::new(),
+            width: width.max(1),
+            truncate,
+        }
+    }
+
+    pub fn push(&mut self, row: Vec<String>) {
+        self.rows.push(row);
+    }
+
+    fn split_width(value: &str, width: usize) -> (&str, &str) {
+        let mut used = 0;
+        for (i, c) in value.char_indices() {
+            let w = c.width().unwrap_or(0);
+            if used + w > width {
+                return value.split_at(i);
+            }
+            used += w;
+        }
+        (value, "")
+    }
+
+    fn last_column_lines(&self, value: &str) -> Vec<String> {
+        if value.width() <= self.width {
+            return vec![value.to_string()];
+        }
+        if self.truncate {
+            let (head, _) = Table::split_width(value, self.width.saturating_sub(1));
+            return vec![std::format!("{}{}", head, TRUNCATION_MARKER)];
+        }
+        let mut lines = Vec::new();
+        let mut rest = value;
+        while !rest.is_empty() {
+            let (head, tail) = Table::split_width(rest, self.width);
+            if head.is_empty() {
+                // a single char wider than the column, emit it anyway
+                let len = rest.chars().next().map_or(rest.len(), char::len_utf8);
+                lines.push(rest[..len].to_string());
+                rest = &rest[len..];
+                continue;
+            }
+            lines.push(head.to_string());
+            rest = tail;
+        }
+        lines
+    }
+
+    fn write_line(out: &mut String, cells: &[&str], widths: &[usize]) {
+        let mut line = String::new();
+        for (i, cell) in cells.iter().enumerate() {
+            line.push_str(cell);
+            if i + 1 < cells.len() {
+                let pad = widths[i].saturating_sub(cell.width()) + 2;
+                line.push_str(&" ".repeat(pad));
+            }
+        }
+        out.push_str(line.trim_end());
+        out.push('\n');
+    }
+
+    pub fn render(&self) -> String {
+        self.render_chunk(&mut Vec::new(), true)
+    }
+
+    /// Rows padded to at least `widths`, which grow to fit them, for tables
+    /// printed a chunk at a time with the header only before the first one
+    pub fn render_chunk(&self, widths: &mut Vec<usize>, header: bool) -> String {
+        let columns = self.headers.len();
+        widths.resize(columns, 0);
+        for (i, h) in self.headers.iter().enumerate() {
+            widths[i] = widths[i].max(h.width());
+        }
+        for row in &self.rows {
+            for (i, cell) in row.iter().enumerate().take(columns - 1) {
+                widths[i] = widths[i].max(cell.width());
+            }
+        }
+        let mut out = String::new();
+        if header {
+            let headers: Vec<&str> = self.headers.iter().map(|h| h.as_str()).collect();
+            Table::write_line(&mut out, &headers, widths);
+        }
+        for row in &self.rows {
+            let last = row.last().map_or("", |c| c.as_str());
+            for (i, line) in self.last_column_lines(last).iter().enumerate() {
+                let mut cells: Vec<&str> = row[..row.len() - 1]
+                    .iter()
+                    .map(|c| if i == 0 { c.as_str() } else { "" })
+                    .collect();
+                cells.push(line);
+                Table::write_line(&mut out, &cells, widths);
+            }
+        }
+        out
+    }
+}
+
+/// DNSSEC status of an answer as shown, none when its query didn't ask for
+/// DNSSEC
+fn dnssec_status(answer: &QueryAnswer) -> Option<String> {
+    #[cfg(feature = "dnssec")]
+    return answer.dnssec().map(|status| status.to_string());
+    #[cfg(not(feature = "dnssec"))]
+    {
+        let _ = answer;
+        None
+    }
+}
+
+/// Time between sending the query of an answer and receiving the response
+fn query_time(answer: &QueryAnswer) -> String {
+    std::format!("{:.1} ms", client::millis(answer.query_time()))
+}
+
+pub fn answers_table(
+    answers: &[QueryAnswer],
+    width: usize,
+    truncate: bool,
+    ipv6: &Ipv6Format,
+) -> String {
+    let dnssec = answers.iter().any(|answer| dnssec_status(answer).is_some());
+    // only worth a column when several servers answered
+    let servers = answers
+        .iter()
+        .any(|answer| answer.server() != answers[0].server());
+    // nor where answers came from when the network gave them all
+    let sources = answers
+        .iter()
+        .any(|answer| !matches!(answer.provenance(), Provenance::Network { .. }));
+    let scopes = answers.iter().any(|answer| answer.subnet().is_some());
+    // nor the flags when a recursive resolver gave them the usual way
+    let flags = answers.iter().any(|answer| answer.flags() != ["ra"]);
+    // the SOA of negative responses being in the authority one goes without saying
+    let sections = answers
+        .iter()
+        .any(|answer| answer.section() != Section::Answer && answer.negative().is_none());
+    let negatives = answers.iter().any(|answer| answer.negative().is_some());
+    let mut headers = vec!["HOST", "TTL", "TYPE", "CLASS"];
+    if negatives {
+        headers.push("STATUS");
+    }
+    if sections {
+        headers.push("SECTION");
+    }
+    if servers {
+        headers.push("SERVER");
+    }
+    if sources {
+        headers.push("SOURCE");
+    }
+    if scopes {
+        headers.push("SCOPE");
+    }
+    if flags {
+        headers.push("FLAGS");
+    }
+    if dnssec {
+        headers.push("DNSSEC");
+    }
+    headers.push("TIME");
+    headers.push("DATA");
+    let mut table = Table::new(&headers, width, truncate);
+    for answer in answers {
+        let mut row = vec![
+            answer.host().to_string(),
+            answer.ttl().to_string(),
+            answer.query_type().to_string(),
+            std::format!("{:?}", answer.class_type()),
+        ];
+        if negatives {
+            row.push(answer.negative().unwrap_or_default().to_string());
+        }
+        if sections {
+            row.push(answer.section().to_string());
+        }
+        if servers {
+            row.push(answer.server().to_string());
+        }
+        if sources {
+            row.push(answer.provenance().label());
+        }
+        if scopes {
+            row.push(answer.subnet().map_or(String::new(), |subnet| {
+                std::format!("/{}", subnet.scope_prefix())
+            }));
+        }
+        if flags {
+            row.push(answer.flags().join(" "));
+        }
+        if dnssec {
+            row.push(dnssec_status(answer).unwrap_or_default());
+        }
+        row.push(query_time(answer));
+        row.push(ipv6.data(answer.data()));
+        table.push(row);
+    }
+    table.render()
+}
+
+/// Answers of a batch printed as their queries complete: the header once,
+/// then rows padded to the widest cells seen so far, later ones not being
+/// known yet. Columns are fixed up front for the same reason.
+pub struct AnswerStream {
+    widths: Vec<usize>,
+    started: bool,
+    width: usize,
+    truncate: bool,
+    dnssec: bool,
+    sections: bool,
+}
+
+impl AnswerStream {
+    pub fn new(width: usize, truncate: bool, dnssec: bool) -> AnswerStream {
+        AnswerStream {
+            widths: Vec::new(),
+            started: false,
+            width,
+            truncate,
+            dnssec,
+            sections: false,
+        }
+    }
+
+    /// Adds a column of the section each answer was in
+    pub fn with_sections(mut self) -> AnswerStream {
+        self.sections = true;
+        self
+    }
+
+    /// Rows of the answers of one query, after the header on the first call
+    pub fn rows(&mut self, answers: &[QueryAnswer], ipv6: &Ipv6Format) -> String {
+        if answers.is_empty() {
+            return String::new();
+        }
+        let mut headers = vec!["HOST", "TTL", "TYPE", "CLASS"];
+        if self.sections {
+            headers.push("SECTION");
+        }
+        if self.dnssec {
+            headers.push("DNSSEC");
+        }
+        headers.push("TIME");
+        headers.push("DATA");
+        let mut table = Table::new(&headers, self.width, self.truncate);
+        for answer in answers {
+            let mut row = vec![
+                answer.host().to_string(),
+                answer.ttl().to_string(),
+                answer.query_type().to_string(),
+                std::format!("{:?}", answer.class_type()),
+            ];
+            if self.sections {
+                row.push(answer.section().to_string());
+            }
+            if self.dnssec {
+                row.push(dnssec_status(answer).unwrap_or_default());
+            }
+            row.push(query_time(answer));
+            row.push(ipv6.data(answer.data()));
+            table.push(row);
+        }
+        let out = table.render_chunk(&mut self.widths, !self.started);
+        self.started = true;
+        out
+    }
+}
+
+fn json_answer(answer: &QueryAnswer, ipv6: &Ipv6Format) -> JsonAnswer {
+    JsonAnswer {
+        host: answer.host().to_string(),
+        query_type: answer.query_type().to_string(),
+        class: answer.class_type().to_string(),
+        ttl: answer.ttl(),
+        rdata: ipv6.data(answer.data()),
+        section: answer.section(),
+        negative: answer.negative(),
+        server: answer.server().to_string(),
+        rcode: client::rcode_name(answer.rcode()),
+        flags: answer.flags().to_vec(),
+        query_time_ms: client::millis(answer.query_time()),
+        decisions: answer.decisions().to_vec(),
+        provenance: answer.provenance().clone(),
+        subnet: answer.subnet().map(|subnet| subnet.to_string()),
+        scope_prefix: answer.subnet().map(|subnet| subnet.scope_prefix()),
+        stale: answer.is_stale(),
+        dnssec: dnssec_status(answer),
+    }
+}
+
+/// An answer as a JSON object on one line, for JSON Lines streams
+pub fn answer_json_line(answer: &QueryAnswer, ipv6: &Ipv6Format) -> String {
+    serde_json::to_string(&json_answer(answer, ipv6)).unwrap_or_default()
+}
+
+/// Columns of `answer_csv_line`
+pub const CSV_HEADER: &str = "host,type,class,ttl,data,server,rcode,query_time_ms";
+
+/// `field` quoted when it holds a separator, a quote or a line break, its
+/// quotes doubled, RFC 4180 2
+fn csv_field(field: &str) -> String {
+    match field.contains([',', '"', '\n', '\r']) {
+        true => std::format!("\"{}\"", field.replace('"', "\"\"")),
+        false => field.to_string(),
+    }
+}
+
+/// An answer as a line of comma separated values
+pub fn answer_csv_line(answer: &QueryAnswer, ipv6: &Ipv6Format) -> String {
+    let fields = [
+        answer.host().to_string(),
+        answer.query_type().to_string(),
+        answer.class_type().to_string(),
+        answer.ttl().to_string(),
+        ipv6.data(answer.data()),
+        answer.server().to_string(),
+        client::rcode_name(answer.rcode()),
+        client::millis(answer.query_time()).to_string(),
+    ];
+    let fields: Vec<String> = fields.iter().map(|field| csv_field(field)).collect();
+    fields.join(",")
+}
+
+/// Answers as a JSON array, or an object with the answers and the summary of
+/// the run when there is one
+pub fn answers_json(
+    answers: &[QueryAnswer],
+    ipv6: &Ipv6Format,
+    summary: Option<&Summary>,
+) -> String {
+    let answers: Vec<JsonAnswer> = answers
+        .iter()
+        .map(|answer| json_answer(answer, ipv6))
+        .collect();
+    match summary {
+        Some(summary) => serde_json::to_string_pretty(&JsonRun { answers, summary }),
+        None => serde_json::to_string_pretty(&answers),
+    }
+    .unwrap_or_default()
+}
+
+fn class_name(class: u16) -> String {
+    match ClassType::from_code(class) {
+        Ok(class) => class.to_string(),
+        Err(_) => std::format!("CLASS{}", class),
+    }
+}
+
+fn opcode_name(opcode: u8) -> String {
+    match opcode {
+        0 => "QUERY".to_string(),
+        1 => "IQUERY".to_string(),
+        2 => "STATUS".to_string(),
+        4 => "NOTIFY".to_string(),
+        5 => "UPDATE".to_string(),
+        _ => std::format!("OPCODE{}", opcode),
+    }
+}
+
+fn write_record(out: &mut String, record: &Record, ipv6: &Ipv6Format) {
+    let data = rdata::decode(&record.query_type, &record.rdata)
+        .unwrap_or_else(|| RData::Unknown(record.rdata.clone()));
+    out.push_str(&std::format!(
+        "{}\t{}\t{}\t{}\t{}\n",
+        record.name,
+        record.ttl,
+        class_name(record.class),
+        record.query_type,
+        ipv6.data(&data)
+    ));
+}
+
+/// Fields separated by at least one tab, each starting at its column or
+/// past it, the way dig aligns records with 8 wide tab stops
+fn tab_columns(fields: &[(usize, String)]) -> String {
+    let mut line = String::new();
+    let mut column = 0;
+    for (i, (start, field)) in fields.iter().enumerate() {
+        while i > 0 && (line.ends_with(|c| c != '\t') || column < *start) {
+            line.push('\t');
+            column = (column / 8 + 1) * 8;
+        }
+        line.push_str(field);
+        column += field.width();
+    }
+    line
+}
+
+/// Delegation steps of a trace the way `dig +trace` shows them: the records
+/// each server returned, then which server it was
+#[cfg(feature = "diagnostics")]
+pub fn trace(steps: &[Step], ipv6: &Ipv6Format) -> String {
+    let mut out = String::new();
+    for step in steps {
+        for record in step.records() {
+            write_record(&mut out, record, ipv6);
+        }
+        let rcode = step.response.rcode();
+        out.push_str(&std::format!(
+            ";; Received {} bytes from {}({}) in {} ms{}\n\n",
+            step.response.size(),
+            SocketAddr::new(step.addr, 53),
+            step.server,
+            step.response.query_time().as_millis(),
+            match rcode {
+                0 => String::new(),
+                _ => std::format!(", status: {}", client::rcode_name(rcode)),
+            }
+        ));
+    }
+    out
+}
+
+/// Records of a zone transfer message in zone file format, like `dig axfr`,
+/// those at the positions of `records` in the message
+#[cfg(feature = "zones")]
+pub fn transfer(response: &Response, records: Range<usize>, ipv6: &Ipv6Format) -> String {
+    let mut out = String::new();
+    let answers = &response.message.answers;
+    for record in answers.iter().skip(records.start).take(records.len()) {
+        write_record(&mut out, record, ipv6);
+    }
+    out
+}
+
+/// Serial steps of an incremental transfer, each deleted record prefixed
+/// with `-` and each added one with `+`
+#[cfg(feature = "zones")]
+pub fn diffs(diffs: &[Diff], ipv6: &Ipv6Format) -> String {
+    let mut out = String::new();
+    for diff in diffs {
+        out.push_str(&std::format!(";; serial {} -> {}\n", diff.from, diff.to));
+        for (sign, records) in [("-", &diff.deleted), ("+", &diff.added)] {
+            for record in records {
+                out.push_str(sign);
+                write_record(&mut out, record, ipv6);
+            }
+        }
+    }
+    out
+}
+
+#[cfg(feature = "discovery")]
+fn addrs(addrs: &[IpAddr], ipv6: &Ipv6Format) -> String {
+    addrs
+        .iter()
+        .map(|addr| match addr {
+            IpAddr::V6(addr) => ipv6.format(addr),
+            IpAddr::V4(addr) => addr.to_string(),
+        })
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+/// SRV targets with their ports and addresses, the targets named by `name`
+#[cfg(feature = "discovery")]
+fn endpoints_table(
+    header: &str,
+    endpoints: &[SrvEndpoint],
+    name: impl Fn(&str) -> &str,
+    ipv6: &Ipv6Format,
+) -> String {
+    let mut table = Table::new(&[header, "PORT", "ADDRESSES"], 64, false);
+    for endpoint in endpoints {
+        table.push(vec![
+            name(&endpoint.target).to_string(),
+            endpoint.port.to_string(),
+            addrs(&endpoint.addrs, ipv6),
+        ]);
+    }
+    table.render()
+}
+
+/// A Kubernetes service: its kind and addresses, then each endpoint with the
+/// port it serves and its addresses
+#[cfg(feature = "discovery")]
+pub fn k8s_service(service: &Service, ipv6: &Ipv6Format) -> String {
+    let mut out = std::format!(
+        "{} ({})\nAddresses: {}\n",
+        service.name,
+        match service.is_headless() {
+            true => "headless",
+            false => "ClusterIP",
+        },
+        addrs(&service.addrs, ipv6)
+    );
+    if !service.endpoints.is_empty() {
+        out.push_str(&endpoints_table(
+            "ENDPOINT",
+            &service.endpoints,
+            |target| target,
+            ipv6,
+        ));
+    }
+    out
+}
+
+/// Instances of a Consul service, a node and port per row
+#[cfg(feature = "discovery")]
+pub fn consul_service(name: &str, instances: &[SrvEndpoint], ipv6: &Ipv6Format) -> String {
+    std::format!(
+        "{}\n{}",
+        name,
+        endpoints_table("NODE", instances, consul::node_name, ipv6)
+    )
+}
+
+/// Response rendered like dig does: header, question, answer, authority and
+/// additional sections, then query time, server, where the response came
+/// from and message size. The EDNS OPT record is shown as a pseudo section,
+/// RFC 6891 6.1.3.
+pub fn dig(response: &Response, ipv6: &Ipv6Format) -> String {
+    render_dig(response, ipv6, false)
+}
+
+/// Response in dig's exact layout, for scripts written against dig: its
+/// banner, records aligned on its tab stops and its footer, with nothing
+/// dig doesn't print
+pub fn dig_compat(response: &Response, ipv6: &Ipv6Format) -> String {
+    render_dig(response, ipv6, true)
+}
+
+/// Name of EDNS option `code`, RFC 6891 and the registry of IANA
+fn edns_option_name(code: u16) -> String {
+    match code {
+        3 => "NSID".to_string(),
+        8 => "CLIENT-SUBNET".to_string(),
+        10 => "COOKIE".to_string(),
+        11 => "TCP-KEEPALIVE".to_string(),
+        12 => "PADDING".to_string(),
+        15 => "EDE".to_string(),
+        code => std::format!("OPTION{}", code),
+    }
+}
+
+/// Value of an EDNS option as what it holds where known, in hex otherwise
+fn edns_option_value(code: u16, value: &[u8], response: &Response) -> String {
+    match code {
+        8 => response.subnet().map_or(hex::encode(value), |subnet| {
+            std::format!("{} scope /{}", subnet, subnet.scope_prefix())
+        }),
+        10 if value.len() > 8 => std::format!(
+            "client {} server {}",
+            hex::encode(&value[..8]),
+            hex::encode(&value[8..])
+        ),
+        10 => std::format!("client {}", hex::encode(value)),
+        12 => std::format!("{} bytes", value.len()),
+        15 if value.len() >= 2 => {
+            let info = u16::from_be_bytes([value[0], value[1]]);
+            match String::from_utf8_lossy(&value[2..]) {
+                text if text.is_empty() => std::format!("info code {}", info),
+                text => std::format!("info code {}: {}", info, text),
+            }
+        }
+        _ => hex::encode(value),
+    }
+}
+
+/// A named field of the long form, the names aligned
+fn long_field(out: &mut String, name: &str, value: impl fmt::Display) {
+    out.push_str(&std::format!("  {:<16}{}\n", name, value));
+}
+
+/// Every field of a response in long form, one per line: the header with
+/// each flag, the EDNS pseudo-record and its options, every section, and how
+/// long the query took and what it went through
+pub fn verbose(response: &Response, ipv6: &Ipv6Format) -> String {
+    let message = &response.message;
+    let header = &message.header;
+    let mut out = String::from(";; HEADER\n");
+    long_field(
+        &mut out,
+        "id",
+        std::format!("{} ({:#06x})", header.id, header.id),
+    );
+    long_field(
+        &mut out,
+        "opcode",
+        std::format!("{} ({})", opcode_name(header.opcode()), header.opcode()),
+    );
+    let rcode = message.extended_rcode();
+    long_field(
+        &mut out,
+        "rcode",
+        std::format!("{} ({})", client::rcode_name(rcode as u8), rcode),
+    );
+    let flags: Vec<String> = [
+        (0x8000, "qr"),
+        (0x0400, "aa"),
+        (0x0200, "tc"),
+        (0x0100, "rd"),
+        (0x0080, "ra"),
+        (0x0040, "z"),
+        (0x0020, "ad"),
+        (0x0010, "cd"),
+    ]
+    .iter()
+    .map(|(bit, name)| std::format!("{}={}", name, (header.flags & bit != 0) as u8))
+    .collect();
+    long_field(&mut out, "flags", flags.join(" "));
+    long_field(&mut out, "questions", message.questions.len());
+    long_field(&mut out, "answers", message.answers.len());
+    long_field(&mut out, "authority", message.authorities.len());
+    long_field(&mut out, "additional", message.additionals.len());
+    let (opt, additionals): (Vec<&Record>, Vec<&Record>) = message
+        .additionals
+        .iter()
+        .partition(|record| record.query_type == QueryType::OPT);
+    for record in opt {
+        out.push_str("\n;; EDNS\n");
+        long_field(&mut out, "version", (record.ttl >> 16) & 0xff);
+        long_field(&mut out, "udp payload", record.class);
+        long_field(&mut out, "do", (record.ttl & 0x8000 != 0) as u8);
+        long_field(&mut out, "extended rcode", record.ttl >> 24);
+        for (code, value) in message.options() {
+            let described = edns_option_value(code, &value, response);
+            long_field(
+                &mut out,
+                &edns_option_name(code),
+                std::format!("{} ({} bytes)", described, value.len()),
+            );
+        }
+    }
+    out.push_str("\n;; QUESTION\n");
+    for question in &message.questions {
+        out.push_str(&std::format!(
+            "  {}\t{}\t{}\n",
+            question.name,
+            class_name(question.class),
+            question.query_type
+        ));
+    }
+    let sections = [
+        ("ANSWER", message.answers.iter().collect::<Vec<&Record>>()),
+        ("AUTHORITY", message.authorities.iter().collect()),
+        ("ADDITIONAL", additionals),
+    ];
+    for (name, records) in sections {
+        out.push_str(&std::format!("\n;; {}\n", name));
+        for record in records {
+            out.push_str("  ");
+            write_record(&mut out, record, ipv6);
+        }
+    }
+    out.push_str("\n;; TIMING\n");
+    long_field(&mut out, "server", response.server());
+    long_field(&mut out, "source", response.provenance());
+    long_field(&mut out, "size", std::format!("{} bytes", response.size()));
+    long_field(
+        &mut out,
+        "query time",
+        std::format!("{:.3} ms", client::millis(response.query_time())),
+    );
+    if let Some(setup) = response.connection_setup() {
+        let exchange = response.query_time().saturating_sub(setup);
+        long_field(
+            &mut out,
+            "connect",
+            std::format!("{:.3} ms", client::millis(setup)),
+        );
+        long_field(
+            &mut out,
+            "exchange",
+            std::format!("{:.3} ms", client::millis(exchange)),
+        );
+    }
+    for (i, decision) in response.decisions().iter().enumerate() {
+        long_field(&mut out, if i == 0 { "decisions" } else { "" }, decision);
+    }
+    out
+}
+
+/// The server line of dig, address#port(name) and the transport
+fn dig_server(server: &str) -> String {
+    match server.parse::<SocketAddr>() {
+        Ok(addr) => std::format!("{}#{}({}) (UDP)", addr.ip(), addr.port(), addr.ip()),
+        Err(_) => std::format!("{} (HTTPS)", server),
+    }
+}
+
+fn render_dig(response: &Response, ipv6: &Ipv6Format, compat: bool) -> String {
+    let message = &response.message;
+    let header = &message.header;
+    let (opt, additionals): (Vec<&Record>, Vec<&Record>) = message
+        .additionals
+        .iter()
+        .partition(|record| record.query_type == QueryType::OPT);
+    let mut out = String::new();
+    if compat {
+        let question = message.questions.first().map_or(String::new(), |question| {
+            std::format!(
+                " {} {}",
+                question.name.trim_end_matches('.'),
+                question.query_type
+            )
+        });
+        out.push_str(&std::format!(
+            "\n; <<>> nsq {} <<>>{}\n;; global options: +cmd\n;; Got answer:\n",
+            env!("CARGO_PKG_VERSION"),
+            question
+        ));
+    }
+    out.push_str(&std::format!(
+        ";; ->>HEADER<<- opcode: {}, status: {}, id: {}\n",
+        opcode_name(header.opcode()),
+        client::rcode_name(header.rcode()),
+        header.id
+    ));
+    out.push_str(&std::format!(
+        ";; flags: {}; QUERY: {}, ANSWER: {}, AUTHORITY: {}, ADDITIONAL: {}\n",
+        header.flag_names().join(" "),
+        message.questions.len(),
+        message.answers.len(),
+        message.authorities.len(),
+        message.additionals.len()
+    ));
+    for record in &opt {
+        out.push_str("\n;; OPT PSEUDOSECTION:\n");
+        out.push_str(&std::format!(
+            "; EDNS: version: {}, flags:{}; udp: {}\n",
+            (record.ttl >> 16) & 0xff,
+            if record.ttl & 0x8000 == 0x8000 {
+                " do"
+            } else {
+                ""
+            },
+            record.class
+        ));
+        if let Some(cookie) = message.cookie() {
+            out.push_str(&std::format!("; COOKIE: {}\n", hex::encode(cookie)));
+        }
+        if let Some(subnet) = response.subnet() {
+            out.push_str(&std::format!(
+                "; CLIENT-SUBNET: {}/{}\n",
+                subnet,
+                subnet.scope_prefix()
+            ));
+        }
+    }
+    // dig runs the question section on from the pseudo section
+    if !compat || opt.is_empty() {
+        out.push('\n');
+    }
+    out.push_str(";; QUESTION SECTION:\n");
+    for question in &message.questions {
+        let name = std::format!(";{}", question.name);
+        let line = if compat {
+            tab_columns(&[
+                (0, name),
+                (32, class_name(question.class)),
+                (40, question.query_type.to_string()),
+            ])
+        } else {
+            std::format!(
+                "{}\t\t{}\t{}",
+                name,
+                class_name(question.class),
+                question.query_type
+            )
+        };
+        out.push_str(&line);
+        out.push('\n');
+    }
+    let sections = [
+        ("ANSWER", message.answers.iter().collect::<Vec<&Record>>()),
+        ("AUTHORITY", message.authorities.iter().collect()),
+        ("ADDITIONAL", additionals),
+    ];
+    for (name, records) in sections {
+        if records.is_empty() {
+            continue;
+        }
+        out.push_str(&std::format!("\n;; {} SECTION:\n", name));
+        for record in records {
+            if compat {
+                let data = rdata::decode(&record.query_type, &record.rdata)
+                    .unwrap_or_else(|| RData::Unknown(record.rdata.clone()));
+                out.push_str(&tab_columns(&[
+                    (0, record.name.clone()),
+                    (24, record.ttl.to_string()),
+                    (32, class_name(record.class)),
+                    (40, record.query_type.to_string()),
+                    (48, ipv6.data(&data)),
+                ]));
+                out.push('\n');
+            } else {
+                write_record(&mut out, record, ipv6);
+            }
+        }
+    }
+    if compat {
+        out.push_str(&std::format!(
+            "\n;; Query time: {} msec\n;; SERVER: {}\n;; WHEN: {}\n;; MSG SIZE  rcvd: {}\n\n",
+            response.query_time().as_millis(),
+            dig_server(response.server()),
+            chrono::Utc::now().format("%a %b %d %H:%M:%S UTC %Y"),
+            response.size()
+        ));
+    } else {
+        out.push_str(&std::format!(
+            "\n;; Query time: {} msec\n;; SERVER: {}\n;; SOURCE: {}\n;; MSG SIZE  rcvd: {}\n",
+            response.query_time().as_millis(),
+            response.server(),
+            response.provenance(),
+            response.size()
+        ));
+    }
+    out
+}
+
+#[cfg(feature = "diagnostics")]
+#[derive(Serialize)]
+struct JsonComparison<'a> {
+    host: &'a str,
+    #[serde(rename = "type")]
+    query_type: String,
+    differences: &'a [Difference],
+}
+
+/// Differences between servers for each query, as text or JSON
+#[cfg(feature = "diagnostics")]
+pub fn comparisons(
+    comparisons: &[(String, QueryType, Vec<Difference>)],
+    servers: usize,
+    json: bool,
+) -> String {
+    if json {
+        let comparisons: Vec<JsonComparison> = comparisons
+            .iter()
+            .map(|(host, query_type, differences)| JsonComparison {
+                host,
+                query_type: query_type.to_string(),
+                differences,
+            })
+            .collect();
+        return serde_json::to_string_pretty(&comparisons).unwrap_or_default() + "\n";
+    }
+    let mut out = String::new();
+    for (host, query_type, differences) in comparisons {
+        if differences.is_empty() {
+            out.push_str(&std::format!(
+                "{} {}: same answers from {} servers\n",
+                host,
+                query_type,
+                servers
+            ));
+            continue;
+        }
+        out.push_str(&std::format!("{} {}:\n", host, query_type));
+        for difference in differences {
+            out.push_str(&std::format!("  {}\n", difference));
+        }
+    }
+    out
+}
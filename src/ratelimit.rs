@@ -0,0 +1,146 @@
+//! Per client limits of the forwarder, so one host of the network can't flood
+//! the upstream servers: a token bucket of queries per second and a cap on the
+//! queries it has in flight, both kept for each client address.
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+/// Clients tracked before the idle ones are forgotten
+const MAX_CLIENTS: usize = 4096;
+
+/// How queries over the limits of their client are answered
+#[derive(Debug, Clone, Copy, PartialEq, Default, clap::ValueEnum)]
+pub enum LimitAction {
+    /// No response at all, the client times out
+    #[default]
+    Drop,
+    /// A REFUSED response
+    Refused,
+    /// An empty response with the TC flag, for clients to retry over TCP where
+    /// sources can't be spoofed, REFUSED for queries already over TCP
+    Truncated,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+    in_flight: usize,
+}
+
+/// Limits of every client, clones sharing their state
+#[derive(Debug, Clone, Default)]
+pub struct ClientLimits {
+    /// Queries per second and the burst allowed above them
+    rate: Option<(f64, f64)>,
+    max_in_flight: Option<usize>,
+    action: LimitAction,
+    clients: Arc<Mutex<HashMap<IpAddr, Bucket>>>,
+}
+
+impl ClientLimits {
+    pub fn new() -> ClientLimits {
+        ClientLimits::default()
+    }
+
+    /// Allows `rate` queries per second to each client, and bursts of up to
+    /// `burst` queries after it was quiet
+    pub fn with_rate(mut self, rate: f64, burst: f64) -> ClientLimits {
+        self.rate = Some((rate, burst.max(1.0)));
+        self
+    }
+
+    /// Allows each client `max` queries waiting for upstream at once
+    pub fn with_max_in_flight(mut self, max: usize) -> ClientLimits {
+        self.max_in_flight = Some(max);
+        self
+    }
+
+    pub fn with_action(mut self, action: LimitAction) -> ClientLimits {
+        self.action = action;
+        self
+    }
+
+    pub fn action(&self) -> LimitAction {
+        self.action
+    }
+
+    /// Tokens of `bucket` once refilled for the time passed until `now`
+    fn refill(&self, bucket: &mut Bucket, now: Instant) {
+        if let Some((rate, burst)) = self.rate {
+            let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+            bucket.tokens = (bucket.tokens + elapsed * rate).min(burst);
+            bucket.updated = now;
+        }
+    }
+
+    /// Takes a query of `client` into account, none when the client is over
+    /// its limits. The query is in flight until the permit is dropped.
+    pub fn admit(&self, client: IpAddr) -> Option<Permit> {
+        let now = Instant::now();
+        let mut clients = self.clients.lock().unwrap();
+        if clients.len() >= MAX_CLIENTS && !clients.contains_key(&client) {
+            let full = self.rate.map_or(0.0, |(_, burst)| burst);
+            clients.retain(|_, bucket| {
+                self.refill(bucket, now);
+                bucket.in_flight > 0 || bucket.tokens < full
+            });
+        }
+        let bucket = clients.entry(client).or_insert(Bucket {
+            tokens: self.rate.map_or(0.0, |(_, burst)| burst),
+            updated: now,
+            in_flight: 0,
+        });
+        self.refill(bucket, now);
+        if self
+            .max_in_flight
+            .is_some_and(|max| bucket.in_flight >= max)
+        {
+            return None;
+        }
+        if self.rate.is_some() {
+            if bucket.tokens < 1.0 {
+                return None;
+            }
+            bucket.tokens -= 1.0;
+        }
+        bucket.in_flight += 1;
+        Some(Permit {
+            clients: self.clients.clone(),
+            client,
+        })
+    }
+}
+
+/// A query of a client let through, counted in flight while it lives
+#[derive(Debug)]
+pub struct Permit {
+    clients: Arc<Mutex<HashMap<IpAddr, Bucket>>>,
+    client: IpAddr,
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        if let Some(bucket) = self.clients.lock().unwrap().get_mut(&self.client) {
+            bucket.in_flight = bucket.in_flight.saturating_sub(1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn queries_in_flight_are_capped_until_answered() {
+        let limits = ClientLimits::new().with_max_in_flight(2);
+        let client: IpAddr = "192.0.2.10".parse().unwrap();
+        let first = limits.admit(client).unwrap();
+        let _second = limits.admit(client).unwrap();
+        assert!(limits.admit(client).is_none());
+        assert!(limits.admit("192.0.2.20".parse().unwrap()).is_some());
+        drop(first);
+        assert!(limits.admit(client).is_some());
+    }
+}
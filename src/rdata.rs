@@ -0,0 +1,1096 @@
+use crate::client::{ClassType, ClientError, QueryType};
+#[cfg(feature = "dnssec")]
+use crate::dnssec;
+use crate::wire;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use chrono::NaiveDateTime;
+use std::fmt;
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+
+/// Decoded RDATA of an answer
+#[derive(Debug, Clone, PartialEq)]
+pub enum RData {
+    A(Ipv4Addr),
+    AAAA(Ipv6Addr),
+    CNAME(String),
+    NS(String),
+    PTR(String),
+    SOA {
+        mname: String,
+        rname: String,
+        serial: u32,
+        refresh: u32,
+        retry: u32,
+        expire: u32,
+        minimum: u32,
+    },
+    /// Host hardware and operating system, RFC 1035 3.3.2, also the whole
+    /// answer of servers minimizing ANY responses, RFC 8482 4.2
+    HINFO {
+        cpu: Vec<u8>,
+        os: Vec<u8>,
+    },
+    MX {
+        preference: u16,
+        exchange: String,
+    },
+    TXT(Vec<Vec<u8>>),
+    SRV {
+        priority: u16,
+        weight: u16,
+        port: u16,
+        target: String,
+    },
+    NAPTR {
+        order: u16,
+        preference: u16,
+        flags: String,
+        services: String,
+        regexp: String,
+        replacement: String,
+    },
+    CERT {
+        cert_type: u16,
+        key_tag: u16,
+        algorithm: u8,
+        certificate: Vec<u8>,
+    },
+    DNAME(String),
+    APL(Vec<AplItem>),
+    EUI48([u8; 6]),
+    EUI64([u8; 8]),
+    URI {
+        priority: u16,
+        weight: u16,
+        target: String,
+    },
+    SMIMEA {
+        usage: u8,
+        selector: u8,
+        matching_type: u8,
+        data: Vec<u8>,
+    },
+    OPENPGPKEY(Vec<u8>),
+    /// Child zone asking its parent to copy the listed types, RFC 7477
+    CSYNC {
+        serial: u32,
+        flags: u16,
+        types: Vec<QueryType>,
+    },
+    SVCB {
+        priority: u16,
+        target: String,
+        params: Vec<SvcParam>,
+    },
+    HTTPS {
+        priority: u16,
+        target: String,
+        params: Vec<SvcParam>,
+    },
+    /// Presentation format of the DNSSEC records, RFC 4034
+    Dnssec(String),
+    /// RDATA of a type without decoding support
+    Unknown(Vec<u8>),
+}
+
+impl RData {
+    /// CSYNC asks the parent to act right away rather than waiting for the
+    /// child SOA serial, RFC 7477 2.1.1.1
+    pub fn csync_immediate(&self) -> bool {
+        matches!(self, RData::CSYNC { flags, .. } if flags & 0x01 == 0x01)
+    }
+
+    /// CSYNC asks the parent to only act once the child SOA serial reaches
+    /// the one of the record, RFC 7477 2.1.1.2
+    pub fn csync_soa_minimum(&self) -> bool {
+        matches!(self, RData::CSYNC { flags, .. } if flags & 0x02 == 0x02)
+    }
+}
+
+impl fmt::Display for RData {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RData::A(addr) => write!(f, "{}", addr),
+            RData::AAAA(addr) => write!(f, "{}", addr),
+            RData::CNAME(name) | RData::NS(name) | RData::PTR(name) => write!(f, "{}", name),
+            RData::SOA {
+                mname,
+                rname,
+                serial,
+                refresh,
+                retry,
+                expire,
+                minimum,
+            } => write!(
+                f,
+                "{} {} {} {} {} {} {}",
+                mname, rname, serial, refresh, retry, expire, minimum
+            ),
+            RData::MX {
+                preference,
+                exchange,
+            } => write!(f, "{} {}", preference, exchange),
+            RData::HINFO { cpu, os } => write!(
+                f,
+                "{} {}",
+                quote_character_string(cpu),
+                quote_character_string(os)
+            ),
+            RData::TXT(strings) => write!(
+                f,
+                "{}",
+                strings
+                    .iter()
+                    .map(|s| quote_character_string(s))
+                    .collect::<Vec<String>>()
+                    .join(" ")
+            ),
+            RData::SRV {
+                priority,
+                weight,
+                port,
+                target,
+            } => write!(f, "{} {} {} {}", priority, weight, port, target),
+            RData::NAPTR {
+                order,
+                preference,
+                flags,
+                services,
+                regexp,
+                replacement,
+            } => write!(
+                f,
+                "{} {} {} {} {} {}",
+                order,
+                preference,
+                quote_character_string(flags.as_bytes()),
+                quote_character_string(services.as_bytes()),
+                quote_character_string(regexp.as_bytes()),
+                replacement
+            ),
+            RData::CERT {
+                cert_type,
+                key_tag,
+                algorithm,
+                certificate,
+            } => {
+                // mnemonics of RFC 4398 2.1
+                let mnemonic = match cert_type {
+                    1 => "PKIX".to_string(),
+                    2 => "SPKI".to_string(),
+                    3 => "PGP".to_string(),
+                    4 => "IPKIX".to_string(),
+                    5 => "ISPKI".to_string(),
+                    6 => "IPGP".to_string(),
+                    7 => "ACPKIX".to_string(),
+                    8 => "IACPKIX".to_string(),
+                    253 => "URI".to_string(),
+                    254 => "OID".to_string(),
+                    other => other.to_string(),
+                };
+                write!(
+                    f,
+                    "{} {} {} {}",
+                    mnemonic,
+                    key_tag,
+                    algorithm,
+                    STANDARD.encode(certificate)
+                )
+            }
+            RData::DNAME(name) => write!(f, "{}", name),
+            RData::APL(items) => write!(
+                f,
+                "{}",
+                items
+                    .iter()
+                    .map(|item| item.to_string())
+                    .collect::<Vec<String>>()
+                    .join(" ")
+            ),
+            RData::EUI48(address) => write!(f, "{}", format_eui(address)),
+            RData::EUI64(address) => write!(f, "{}", format_eui(address)),
+            RData::URI {
+                priority,
+                weight,
+                target,
+            } => write!(
+                f,
+                "{} {} {}",
+                priority,
+                weight,
+                quote_character_string(target.as_bytes())
+            ),
+            RData::SMIMEA {
+                usage,
+                selector,
+                matching_type,
+                data,
+            } => write!(
+                f,
+                "{} {} {} {}",
+                usage,
+                selector,
+                matching_type,
+                hex::encode_upper(data)
+            ),
+            RData::OPENPGPKEY(key) => write!(f, "{}", STANDARD.encode(key)),
+            RData::CSYNC {
+                serial,
+                flags,
+                types,
+            } => {
+                write!(f, "{} {}", serial, flags)?;
+                for query_type in types {
+                    write!(f, " {}", query_type)?;
+                }
+                Ok(())
+            }
+            RData::SVCB {
+                priority,
+                target,
+                params,
+            }
+            | RData::HTTPS {
+                priority,
+                target,
+                params,
+            } => {
+                write!(f, "{} {}", priority, target)?;
+                for param in params {
+                    write!(f, " {}", param)?;
+                }
+                Ok(())
+            }
+            RData::Dnssec(text) => write!(f, "{}", text),
+            // generic presentation, RFC 3597 5
+            RData::Unknown(data) => write!(f, "\\# {} {}", data.len(), hex::encode_upper(data)),
+        }
+    }
+}
+
+/// A record in the presentation format of zone files, RFC 1035 5.1:
+/// `NAME TTL CLASS TYPE RDATA`, read with the TTL and the class optional
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResourceRecord {
+    /// Fully qualified name of the record
+    pub name: String,
+    pub ttl: u32,
+    pub class: ClassType,
+    pub query_type: QueryType,
+    pub data: RData,
+}
+
+impl fmt::Display for ResourceRecord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}\t{}\t{}\t{}\t{}",
+            self.name, self.ttl, self.class, self.query_type, self.data
+        )
+    }
+}
+
+impl FromStr for ResourceRecord {
+    type Err = String;
+
+    /// A record like `www.example.com. 300 IN A 192.0.2.1`, names taken as
+    /// fully qualified, a TTL of 0 and the IN class unless given
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let fields = fields(s);
+        let Some((name, mut rest)) = fields.split_first() else {
+            return Err("empty record".to_string());
+        };
+        let (mut ttl, mut class) = (None, None);
+        while let Some((field, after)) = rest.split_first() {
+            match (field.parse::<u32>(), field.parse::<ClassType>()) {
+                (Ok(value), _) if ttl.is_none() => ttl = Some(value),
+                (_, Ok(value)) if class.is_none() => class = Some(value),
+                _ => break,
+            }
+            rest = after;
+        }
+        let Some((query_type, data)) = rest.split_first() else {
+            return Err(std::format!("{} has no type", s));
+        };
+        let query_type: QueryType = query_type.parse()?;
+        let data: Vec<&str> = data.iter().map(String::as_str).collect();
+        let rdata = parse(&query_type, &data).map_err(|err| match err {
+            ClientError::ParseError(message) => message,
+            err => std::format!("{:?}", err),
+        })?;
+        Ok(ResourceRecord {
+            name: match name.ends_with('.') {
+                true => name.clone(),
+                false => std::format!("{}.", name),
+            },
+            ttl: ttl.unwrap_or(0),
+            class: class.unwrap_or(ClassType::IN),
+            data: decode(&query_type, &rdata).unwrap_or(RData::Unknown(rdata)),
+            query_type,
+        })
+    }
+}
+
+impl ResourceRecord {
+    /// `record` as received, its data left undecoded when malformed
+    #[cfg(any(feature = "zones", feature = "discovery"))]
+    pub(crate) fn from_wire(record: wire::Record) -> Result<ResourceRecord, ClientError> {
+        Ok(ResourceRecord {
+            name: match record.name.ends_with('.') {
+                true => record.name,
+                false => std::format!("{}.", record.name),
+            },
+            ttl: record.ttl,
+            class: ClassType::from_code(record.class)?,
+            data: decode(&record.query_type, &record.rdata).unwrap_or(RData::Unknown(record.rdata)),
+            query_type: record.query_type,
+        })
+    }
+}
+
+/// Address prefix of an APL record, RFC 3123 4
+#[derive(Debug, Clone, PartialEq)]
+pub struct AplItem {
+    pub negation: bool,
+    pub family: u16,
+    pub prefix: u8,
+    /// Address with its trailing zero octets left out
+    pub afd: Vec<u8>,
+}
+
+impl fmt::Display for AplItem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.negation {
+            write!(f, "!")?;
+        }
+        let mut octets = self.afd.clone();
+        match self.family {
+            1 if octets.len() <= 4 => {
+                octets.resize(4, 0);
+                let octets: [u8; 4] = octets.try_into().unwrap_or([0; 4]);
+                write!(f, "1:{}/{}", Ipv4Addr::from(octets), self.prefix)
+            }
+            2 if octets.len() <= 16 => {
+                octets.resize(16, 0);
+                let octets: [u8; 16] = octets.try_into().unwrap_or([0; 16]);
+                write!(f, "2:{}/{}", Ipv6Addr::from(octets), self.prefix)
+            }
+            family => write!(f, "{}:{}/{}", family, hex::encode(&self.afd), self.prefix),
+        }
+    }
+}
+
+/// Service parameter of SVCB and HTTPS records, RFC 9460 7 and 14.3.2
+#[derive(Debug, Clone, PartialEq)]
+pub enum SvcParam {
+    Mandatory(Vec<u16>),
+    Alpn(Vec<String>),
+    NoDefaultAlpn,
+    Port(u16),
+    Ipv4Hint(Vec<Ipv4Addr>),
+    Ech(Vec<u8>),
+    Ipv6Hint(Vec<Ipv6Addr>),
+    Unknown(u16, Vec<u8>),
+}
+
+impl SvcParam {
+    fn key_name(key: u16) -> String {
+        match key {
+            0 => "mandatory".to_string(),
+            1 => "alpn".to_string(),
+            2 => "no-default-alpn".to_string(),
+            3 => "port".to_string(),
+            4 => "ipv4hint".to_string(),
+            5 => "ech".to_string(),
+            6 => "ipv6hint".to_string(),
+            _ => std::format!("key{}", key),
+        }
+    }
+
+    fn key_code(name: &str) -> Option<u16> {
+        match name {
+            "mandatory" => Some(0),
+            "alpn" => Some(1),
+            "no-default-alpn" => Some(2),
+            "port" => Some(3),
+            "ipv4hint" => Some(4),
+            "ech" => Some(5),
+            "ipv6hint" => Some(6),
+            _ => name.strip_prefix("key")?.parse().ok(),
+        }
+    }
+
+    /// Key and wire value of the `key=value` field of a parameter
+    fn encode(field: &str) -> Option<(u16, Vec<u8>)> {
+        let (name, value) = field.split_once('=').unwrap_or((field, ""));
+        let key = SvcParam::key_code(name)?;
+        let list = || value.split(',').filter(|item| !item.is_empty());
+        let mut writer = wire::Writer::new();
+        match key {
+            0 => {
+                for name in list() {
+                    writer.put_u16(SvcParam::key_code(name)?);
+                }
+            }
+            1 => {
+                for id in list() {
+                    writer.put_bytes(&character_string(id.as_bytes())?);
+                }
+            }
+            2 if value.is_empty() => {}
+            2 => return None,
+            3 => writer.put_u16(value.parse().ok()?),
+            4 => {
+                for addr in list() {
+                    writer.put_bytes(&addr.parse::<Ipv4Addr>().ok()?.octets());
+                }
+            }
+            5 => writer.put_bytes(&STANDARD.decode(value).ok()?),
+            6 => {
+                for addr in list() {
+                    writer.put_bytes(&addr.parse::<Ipv6Addr>().ok()?.octets());
+                }
+            }
+            _ => writer.put_bytes(&unescape(value)?),
+        }
+        Some((key, writer.finish()))
+    }
+
+    fn decode(key: u16, value: &[u8]) -> Option<SvcParam> {
+        match key {
+            0 if value.len().is_multiple_of(2) => Some(SvcParam::Mandatory(
+                value
+                    .chunks(2)
+                    .map(|k| u16::from_be_bytes([k[0], k[1]]))
+                    .collect(),
+            )),
+            1 => Some(SvcParam::Alpn(
+                decode_character_strings(value)?
+                    .iter()
+                    .map(|id| String::from_utf8_lossy(id).to_string())
+                    .collect(),
+            )),
+            2 if value.is_empty() => Some(SvcParam::NoDefaultAlpn),
+            3 if value.len() == 2 => Some(SvcParam::Port(u16::from_be_bytes([value[0], value[1]]))),
+            4 if value.len().is_multiple_of(4) => Some(SvcParam::Ipv4Hint(
+                value
+                    .chunks(4)
+                    .map(|a| Ipv4Addr::new(a[0], a[1], a[2], a[3]))
+                    .collect(),
+            )),
+            5 => Some(SvcParam::Ech(value.to_vec())),
+            6 if value.len().is_multiple_of(16) => Some(SvcParam::Ipv6Hint(
+                value
+                    .chunks(16)
+                    .map(|a| {
+                        let octets: [u8; 16] = a.try_into().unwrap_or([0; 16]);
+                        Ipv6Addr::from(octets)
+                    })
+                    .collect(),
+            )),
+            0..=6 => None,
+            _ => Some(SvcParam::Unknown(key, value.to_vec())),
+        }
+    }
+}
+
+impl fmt::Display for SvcParam {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fn join<T: ToString>(values: &[T]) -> String {
+            values
+                .iter()
+                .map(|v| v.to_string())
+                .collect::<Vec<String>>()
+                .join(",")
+        }
+        match self {
+            SvcParam::Mandatory(keys) => {
+                let names: Vec<String> = keys.iter().map(|k| SvcParam::key_name(*k)).collect();
+                write!(f, "mandatory={}", names.join(","))
+            }
+            SvcParam::Alpn(ids) => {
+                write!(f, "alpn={}", quote_character_string(join(ids).as_bytes()))
+            }
+            SvcParam::NoDefaultAlpn => write!(f, "no-default-alpn"),
+            SvcParam::Port(port) => write!(f, "port={}", port),
+            SvcParam::Ipv4Hint(addrs) => write!(f, "ipv4hint={}", join(addrs)),
+            SvcParam::Ech(config) => write!(f, "ech={}", STANDARD.encode(config)),
+            SvcParam::Ipv6Hint(addrs) => write!(f, "ipv6hint={}", join(addrs)),
+            SvcParam::Unknown(key, value) => write!(
+                f,
+                "{}={}",
+                SvcParam::key_name(*key),
+                quote_character_string(value)
+            ),
+        }
+    }
+}
+
+/// Types listed in a type bit map, windows of up to 256 types each made of
+/// a window number, a bitmap length and the bitmap, RFC 4034 4.1.2
+fn decode_type_bitmap(data: &[u8]) -> Option<Vec<QueryType>> {
+    let mut types: Vec<QueryType> = Vec::new();
+    let mut i = 0;
+    while i < data.len() {
+        let window = *data.get(i)? as u16;
+        let len = *data.get(i + 1)? as usize;
+        let bitmap = data.get(i + 2..i + 2 + len)?;
+        for (octet, bits) in bitmap.iter().enumerate() {
+            for bit in 0..8 {
+                if bits & (0x80 >> bit) != 0 {
+                    let code = (window << 8) | (octet as u16 * 8 + bit);
+                    types.push(QueryType::from_code(code));
+                }
+            }
+        }
+        i += 2 + len;
+    }
+    Some(types)
+}
+
+/// Type bit map of `types`, the inverse of `decode_type_bitmap`
+fn encode_type_bitmap(types: &[u16]) -> Vec<u8> {
+    let mut codes = types.to_vec();
+    codes.sort_unstable();
+    codes.dedup();
+    let mut data = Vec::new();
+    for window in codes.chunk_by(|a, b| a >> 8 == b >> 8) {
+        let mut bitmap = vec![0u8; (window[window.len() - 1] & 0xff) as usize / 8 + 1];
+        for code in window {
+            bitmap[(code & 0xff) as usize / 8] |= 0x80 >> (code % 8);
+        }
+        data.push((window[0] >> 8) as u8);
+        data.push(bitmap.len() as u8);
+        data.extend(bitmap);
+    }
+    data
+}
+
+/// Hex octets separated by dashes, RFC 7043 3.2
+fn format_eui(address: &[u8]) -> String {
+    address
+        .iter()
+        .map(|octet| std::format!("{:02x}", octet))
+        .collect::<Vec<String>>()
+        .join("-")
+}
+
+/// Splits RDATA made of <character-string>s, a length octet followed by
+/// that many octets, RFC 1035 3.3
+fn decode_character_strings(data: &[u8]) -> Option<Vec<&[u8]>> {
+    let mut strings: Vec<&[u8]> = Vec::new();
+    let mut i = 0;
+    while i < data.len() {
+        let len = data[i] as usize;
+        strings.push(data.get(i + 1..i + 1 + len)?);
+        i += 1 + len;
+    }
+    Some(strings)
+}
+
+/// Octets of a field with the \X and \DDD escapes of zone files, RFC 1035
+/// 5.1
+fn unescape(field: &str) -> Option<Vec<u8>> {
+    let mut octets = Vec::with_capacity(field.len());
+    let mut bytes = field.bytes();
+    while let Some(byte) = bytes.next() {
+        if byte != b'\\' {
+            octets.push(byte);
+            continue;
+        }
+        match bytes.next()? {
+            digit @ b'0'..=b'9' => {
+                let digits = [digit, bytes.next()?, bytes.next()?];
+                let value = std::str::from_utf8(&digits).ok()?.parse::<u8>().ok()?;
+                octets.push(value);
+            }
+            other => octets.push(other),
+        }
+    }
+    Some(octets)
+}
+
+/// A <character-string> of `value`, its length octet first
+fn character_string(value: &[u8]) -> Option<Vec<u8>> {
+    let mut string = vec![u8::try_from(value.len()).ok()?];
+    string.extend(value);
+    Some(string)
+}
+
+/// Fields of a record in presentation format, quoted strings kept whole with
+/// their escapes, the parentheses of records spanning lines and comments
+/// left out
+pub(crate) fn fields(text: &str) -> Vec<String> {
+    let mut fields: Vec<String> = Vec::new();
+    let mut field: Option<String> = None;
+    let mut quoted = false;
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                let field = field.get_or_insert_with(String::new);
+                field.push(c);
+                field.extend(chars.next());
+            }
+            '"' => {
+                quoted = !quoted;
+                field.get_or_insert_with(String::new);
+            }
+            ';' if !quoted => break,
+            '(' | ')' if !quoted => fields.extend(field.take()),
+            c if c.is_whitespace() && !quoted => fields.extend(field.take()),
+            c => field.get_or_insert_with(String::new).push(c),
+        }
+    }
+    fields.extend(field);
+    fields
+}
+
+/// Seconds since the epoch of an RRSIG time, YYYYMMDDHHmmSS or a number of
+/// seconds, RFC 4034 3.2
+fn parse_time(field: &str) -> Option<u32> {
+    if field.len() != 14 {
+        return field.parse().ok();
+    }
+    let time = NaiveDateTime::parse_from_str(field, "%Y%m%d%H%M%S").ok()?;
+    u32::try_from(time.and_utc().timestamp()).ok()
+}
+
+/// Zone file presentation of a <character-string>: quoted, with quotes and
+/// backslashes escaped and non printable octets as \DDD
+fn quote_character_string(value: &[u8]) -> String {
+    let mut quoted = String::from("\"");
+    for byte in value {
+        match byte {
+            b'"' | b'\\' => {
+                quoted.push('\\');
+                quoted.push(*byte as char);
+            }
+            0x20..=0x7e => quoted.push(*byte as char),
+            _ => quoted.push_str(&std::format!("\\{:03}", byte)),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Encodes RDATA from the fields of its presentation format, as `RData`
+/// displays it, and any type in the generic `\# <length> <hex>` form of
+/// RFC 3597 5
+pub(crate) fn parse(query_type: &QueryType, fields: &[&str]) -> Result<Vec<u8>, ClientError> {
+    let invalid = || {
+        ClientError::ParseError(std::format!(
+            "invalid {} data: {}",
+            query_type,
+            fields.join(" ")
+        ))
+    };
+    fn int<T: FromStr>(field: &str) -> Option<T> {
+        field.parse().ok()
+    }
+    let string = |field: &str| unescape(field).and_then(|value| character_string(&value));
+    let base64 = |fields: &[&str]| STANDARD.decode(fields.concat()).map_err(|_| invalid());
+    let hex = |fields: &[&str]| hex::decode(fields.concat()).map_err(|_| invalid());
+    let mut writer = wire::Writer::new();
+    match (query_type, fields) {
+        (_, ["\\#", len, data @ ..]) => {
+            let data = hex(data)?;
+            if len.parse::<usize>() != Ok(data.len()) {
+                return Err(invalid());
+            }
+            writer.put_bytes(&data);
+        }
+        (QueryType::A, [addr]) => {
+            let addr: Ipv4Addr = addr.parse().map_err(|_| invalid())?;
+            writer.put_bytes(&addr.octets());
+        }
+        (QueryType::AAAA, [addr]) => {
+            let addr: Ipv6Addr = addr.parse().map_err(|_| invalid())?;
+            writer.put_bytes(&addr.octets());
+        }
+        (QueryType::CNAME | QueryType::NS | QueryType::PTR | QueryType::DNAME, [name]) => {
+            writer.put_name(name)
+        }
+        (QueryType::SOA, [mname, rname, numbers @ ..]) if numbers.len() == 5 => {
+            writer.put_name(mname);
+            writer.put_name(rname);
+            for number in numbers {
+                writer.put_u32(int(number).ok_or_else(invalid)?);
+            }
+        }
+        (QueryType::HINFO, [cpu, os]) => {
+            writer.put_bytes(&string(cpu).ok_or_else(invalid)?);
+            writer.put_bytes(&string(os).ok_or_else(invalid)?);
+        }
+        (QueryType::MX, [preference, exchange]) => {
+            writer.put_u16(int(preference).ok_or_else(invalid)?);
+            writer.put_name(exchange);
+        }
+        (QueryType::TXT, strings) if !strings.is_empty() => {
+            for field in strings {
+                writer.put_bytes(&string(field).ok_or_else(invalid)?);
+            }
+        }
+        (QueryType::SRV, [priority, weight, port, target]) => {
+            for field in [priority, weight, port] {
+                writer.put_u16(int(field).ok_or_else(invalid)?);
+            }
+            writer.put_name(target);
+        }
+        (QueryType::NAPTR, [order, preference, flags, services, regexp, replacement]) => {
+            writer.put_u16(int(order).ok_or_else(invalid)?);
+            writer.put_u16(int(preference).ok_or_else(invalid)?);
+            for field in [flags, services, regexp] {
+                writer.put_bytes(&string(field).ok_or_else(invalid)?);
+            }
+            writer.put_name(replacement);
+        }
+        (QueryType::CERT, [cert_type, key_tag, algorithm, certificate @ ..]) => {
+            let cert_type = match cert_type.to_ascii_uppercase().as_str() {
+                "PKIX" => 1,
+                "SPKI" => 2,
+                "PGP" => 3,
+                "IPKIX" => 4,
+                "ISPKI" => 5,
+                "IPGP" => 6,
+                "ACPKIX" => 7,
+                "IACPKIX" => 8,
+                "URI" => 253,
+                "OID" => 254,
+                other => int(other).ok_or_else(invalid)?,
+            };
+            writer.put_u16(cert_type);
+            writer.put_u16(int(key_tag).ok_or_else(invalid)?);
+            writer.put_bytes(&[int(algorithm).ok_or_else(invalid)?]);
+            writer.put_bytes(&base64(certificate)?);
+        }
+        (QueryType::APL, items) => {
+            for item in items {
+                let (negation, item) = match item.strip_prefix('!') {
+                    Some(item) => (true, item),
+                    None => (false, *item),
+                };
+                let (family, rest) = item.split_once(':').ok_or_else(invalid)?;
+                let (address, prefix) = rest.split_once('/').ok_or_else(invalid)?;
+                let family: u16 = int(family).ok_or_else(invalid)?;
+                let mut afd = match family {
+                    1 => address.parse::<Ipv4Addr>().map(|a| a.octets().to_vec()),
+                    2 => address.parse::<Ipv6Addr>().map(|a| a.octets().to_vec()),
+                    _ => Ok(hex(&[address])?),
+                }
+                .map_err(|_| invalid())?;
+                // trailing zero octets are left out, RFC 3123 4
+                while afd.last() == Some(&0) {
+                    afd.pop();
+                }
+                writer.put_u16(family);
+                writer.put_bytes(&[int(prefix).ok_or_else(invalid)?]);
+                writer.put_bytes(&[(negation as u8) << 7 | afd.len() as u8]);
+                writer.put_bytes(&afd);
+            }
+        }
+        (QueryType::EUI48 | QueryType::EUI64, [address]) => {
+            let octets = hex(&address.split('-').collect::<Vec<&str>>())?;
+            let len = if *query_type == QueryType::EUI48 {
+                6
+            } else {
+                8
+            };
+            if octets.len() != len {
+                return Err(invalid());
+            }
+            writer.put_bytes(&octets);
+        }
+        (QueryType::URI, [priority, weight, target]) => {
+            writer.put_u16(int(priority).ok_or_else(invalid)?);
+            writer.put_u16(int(weight).ok_or_else(invalid)?);
+            writer.put_bytes(&unescape(target).ok_or_else(invalid)?);
+        }
+        (QueryType::SMIMEA, [usage, selector, matching_type, data @ ..]) => {
+            for field in [usage, selector, matching_type] {
+                writer.put_bytes(&[int(field).ok_or_else(invalid)?]);
+            }
+            writer.put_bytes(&hex(data)?);
+        }
+        (QueryType::OPENPGPKEY, key) if !key.is_empty() => writer.put_bytes(&base64(key)?),
+        (QueryType::CSYNC, [serial, flags, types @ ..]) => {
+            writer.put_u32(int(serial).ok_or_else(invalid)?);
+            writer.put_u16(int(flags).ok_or_else(invalid)?);
+            let codes = types
+                .iter()
+                .map(|field| field.parse::<QueryType>().map(|t| t.code()))
+                .collect::<Result<Vec<u16>, String>>()
+                .map_err(|_| invalid())?;
+            writer.put_bytes(&encode_type_bitmap(&codes));
+        }
+        (QueryType::SVCB | QueryType::HTTPS, [priority, target, params @ ..]) => {
+            writer.put_u16(int(priority).ok_or_else(invalid)?);
+            writer.put_name(target);
+            let mut params = params
+                .iter()
+                .map(|field| SvcParam::encode(field))
+                .collect::<Option<Vec<(u16, Vec<u8>)>>>()
+                .ok_or_else(invalid)?;
+            // in increasing order of their keys, RFC 9460 2.2
+            params.sort_by_key(|(key, _)| *key);
+            for (key, value) in params {
+                writer.put_u16(key);
+                writer.put_u16(value.len() as u16);
+                writer.put_bytes(&value);
+            }
+        }
+        (QueryType::DS, [key_tag, algorithm, digest_type, digest @ ..]) => {
+            writer.put_u16(int(key_tag).ok_or_else(invalid)?);
+            for field in [algorithm, digest_type] {
+                writer.put_bytes(&[int(field).ok_or_else(invalid)?]);
+            }
+            writer.put_bytes(&hex(digest)?);
+        }
+        (QueryType::DNSKEY, [flags, protocol, algorithm, key @ ..]) => {
+            writer.put_u16(int(flags).ok_or_else(invalid)?);
+            for field in [protocol, algorithm] {
+                writer.put_bytes(&[int(field).ok_or_else(invalid)?]);
+            }
+            writer.put_bytes(&base64(key)?);
+        }
+        (
+            QueryType::RRSIG,
+            [covered, algorithm, labels, original_ttl, expiration, inception, key_tag, signer, signature @ ..],
+        ) => {
+            let covered: QueryType = covered.parse().map_err(|_| invalid())?;
+            writer.put_u16(covered.code());
+            for field in [algorithm, labels] {
+                writer.put_bytes(&[int(field).ok_or_else(invalid)?]);
+            }
+            writer.put_u32(int(original_ttl).ok_or_else(invalid)?);
+            for field in [expiration, inception] {
+                writer.put_u32(parse_time(field).ok_or_else(invalid)?);
+            }
+            writer.put_u16(int(key_tag).ok_or_else(invalid)?);
+            writer.put_name(signer);
+            writer.put_bytes(&base64(signature)?);
+        }
+        _ => return Err(invalid()),
+    }
+    Ok(writer.finish())
+}
+
+/// Decodes expanded RDATA, `None` when it is malformed for its type
+pub(crate) fn decode(query_type: &QueryType, data: &[u8]) -> Option<RData> {
+    match query_type {
+        QueryType::A => {
+            let octets: [u8; 4] = data.try_into().ok()?;
+            Some(RData::A(Ipv4Addr::from(octets)))
+        }
+        QueryType::AAAA => {
+            let octets: [u8; 16] = data.try_into().ok()?;
+            Some(RData::AAAA(Ipv6Addr::from(octets)))
+        }
+        QueryType::CNAME => Some(RData::CNAME(wire::decode_name(data, 0).ok()?.0)),
+        QueryType::NS => Some(RData::NS(wire::decode_name(data, 0).ok()?.0)),
+        QueryType::PTR => Some(RData::PTR(wire::decode_name(data, 0).ok()?.0)),
+        QueryType::DNAME => Some(RData::DNAME(wire::decode_name(data, 0).ok()?.0)),
+        QueryType::CERT if data.len() > 5 => Some(RData::CERT {
+            cert_type: u16::from_be_bytes([data[0], data[1]]),
+            key_tag: u16::from_be_bytes([data[2], data[3]]),
+            algorithm: data[4],
+            certificate: data[5..].to_vec(),
+        }),
+        QueryType::SOA => {
+            let (mname, i) = wire::decode_name(data, 0).ok()?;
+            let (rname, i) = wire::decode_name(data, i).ok()?;
+            let fields = data.get(i..i + 20)?;
+            let field = |n: usize| {
+                u32::from_be_bytes([
+                    fields[n * 4],
+                    fields[n * 4 + 1],
+                    fields[n * 4 + 2],
+                    fields[n * 4 + 3],
+                ])
+            };
+            Some(RData::SOA {
+                mname,
+                rname,
+                serial: field(0),
+                refresh: field(1),
+                retry: field(2),
+                expire: field(3),
+                minimum: field(4),
+            })
+        }
+        QueryType::MX if data.len() > 2 => Some(RData::MX {
+            preference: u16::from_be_bytes([data[0], data[1]]),
+            exchange: wire::decode_name(data, 2).ok()?.0,
+        }),
+        QueryType::SRV if data.len() > 6 => Some(RData::SRV {
+            priority: u16::from_be_bytes([data[0], data[1]]),
+            weight: u16::from_be_bytes([data[2], data[3]]),
+            port: u16::from_be_bytes([data[4], data[5]]),
+            target: wire::decode_name(data, 6).ok()?.0,
+        }),
+        QueryType::NAPTR if data.len() > 4 => {
+            let mut strings: Vec<String> = Vec::new();
+            let mut i = 4;
+            for _ in 0..3 {
+                let len = *data.get(i)? as usize;
+                let value = data.get(i + 1..i + 1 + len)?;
+                strings.push(String::from_utf8_lossy(value).to_string());
+                i += 1 + len;
+            }
+            Some(RData::NAPTR {
+                order: u16::from_be_bytes([data[0], data[1]]),
+                preference: u16::from_be_bytes([data[2], data[3]]),
+                flags: strings.remove(0),
+                services: strings.remove(0),
+                regexp: strings.remove(0),
+                replacement: wire::decode_name(data, i).ok()?.0,
+            })
+        }
+        // the target is the rest of the RDATA, not a <character-string>, RFC 7553
+        QueryType::URI if data.len() > 4 => Some(RData::URI {
+            priority: u16::from_be_bytes([data[0], data[1]]),
+            weight: u16::from_be_bytes([data[2], data[3]]),
+            target: String::from_utf8_lossy(&data[4..]).to_string(),
+        }),
+        QueryType::SMIMEA if data.len() > 3 => Some(RData::SMIMEA {
+            usage: data[0],
+            selector: data[1],
+            matching_type: data[2],
+            data: data[3..].to_vec(),
+        }),
+        QueryType::OPENPGPKEY => Some(RData::OPENPGPKEY(data.to_vec())),
+        QueryType::APL => {
+            let mut items: Vec<AplItem> = Vec::new();
+            let mut i = 0;
+            while i < data.len() {
+                let header = data.get(i..i + 4)?;
+                let len = (header[3] & 0x7f) as usize;
+                items.push(AplItem {
+                    negation: header[3] & 0x80 == 0x80,
+                    family: u16::from_be_bytes([header[0], header[1]]),
+                    prefix: header[2],
+                    afd: data.get(i + 4..i + 4 + len)?.to_vec(),
+                });
+                i += 4 + len;
+            }
+            Some(RData::APL(items))
+        }
+        QueryType::EUI48 => Some(RData::EUI48(data.try_into().ok()?)),
+        QueryType::EUI64 => Some(RData::EUI64(data.try_into().ok()?)),
+        QueryType::CSYNC if data.len() >= 6 => Some(RData::CSYNC {
+            serial: u32::from_be_bytes([data[0], data[1], data[2], data[3]]),
+            flags: u16::from_be_bytes([data[4], data[5]]),
+            types: decode_type_bitmap(&data[6..])?,
+        }),
+        QueryType::SVCB | QueryType::HTTPS if data.len() > 2 => {
+            let priority = u16::from_be_bytes([data[0], data[1]]);
+            let (target, mut i) = wire::decode_name(data, 2).ok()?;
+            let mut params: Vec<SvcParam> = Vec::new();
+            while i < data.len() {
+                let header = data.get(i..i + 4)?;
+                let key = u16::from_be_bytes([header[0], header[1]]);
+                let len = u16::from_be_bytes([header[2], header[3]]) as usize;
+                params.push(SvcParam::decode(key, data.get(i + 4..i + 4 + len)?)?);
+                i += 4 + len;
+            }
+            Some(match query_type {
+                QueryType::HTTPS => RData::HTTPS {
+                    priority,
+                    target,
+                    params,
+                },
+                _ => RData::SVCB {
+                    priority,
+                    target,
+                    params,
+                },
+            })
+        }
+        QueryType::HINFO => match decode_character_strings(data)?.as_slice() {
+            [cpu, os] => Some(RData::HINFO {
+                cpu: cpu.to_vec(),
+                os: os.to_vec(),
+            }),
+            _ => None,
+        },
+        QueryType::TXT => Some(RData::TXT(
+            decode_character_strings(data)?
+                .iter()
+                .map(|s| s.to_vec())
+                .collect(),
+        )),
+        #[cfg(feature = "dnssec")]
+        QueryType::DS | QueryType::RRSIG | QueryType::DNSKEY => {
+            dnssec::rdata_to_string(query_type, data).map(RData::Dnssec)
+        }
+        QueryType::Unknown(_) => Some(RData::Unknown(data.to_vec())),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_read_back_as_displayed() {
+        let records = [
+            "a.example.\t300\tIN\tA\t192.0.2.1",
+            "a.example.\t300\tIN\tAAAA\t2001:db8::1",
+            "a.example.\t300\tIN\tCNAME\tb.example.",
+            "example.\t86400\tIN\tNS\tns.example.",
+            "1.2.0.192.in-addr.arpa.\t300\tIN\tPTR\ta.example.",
+            "old.example.\t300\tIN\tDNAME\tnew.example.",
+            "example.\t3600\tIN\tSOA\tns.example. hostmaster.example. 2024010101 7200 3600 1209600 300",
+            "example.\t3600\tIN\tHINFO\t\"RFC8482\" \"\"",
+            "example.\t300\tIN\tMX\t10 mail.example.",
+            "example.\t300\tIN\tTXT\t\"v=spf1 -all \\\"\" \"a\\\\b\\001\\000\"",
+            "_ldap._tcp.example.\t300\tIN\tSRV\t10 60 389 a.example.",
+            "example.\t300\tIN\tNAPTR\t100 10 \"S\" \"SIP+D2U\" \"\" _sip._udp.example.",
+            "example.\t300\tIN\tCERT\tPGP 0 0 AQID",
+            "example.\t300\tIN\tAPL\t1:192.168.32.0/21 !1:192.168.38.0/28 2:2001:db8::/32",
+            "host.example.\t300\tIN\tEUI48\t00-00-5e-00-53-2a",
+            "host.example.\t300\tIN\tEUI64\t00-00-5e-ef-10-00-00-2a",
+            "_http._tcp.example.\t300\tIN\tURI\t10 1 \"https://www.example/\"",
+            "example.\t300\tIN\tSMIMEA\t3 1 1 0A0B0C",
+            "example.\t300\tIN\tOPENPGPKEY\tAQIDBA==",
+            "example.\t300\tIN\tCSYNC\t66 3 A NS AAAA",
+            "_dns.example.\t300\tIN\tSVCB\t1 dns.example. alpn=\"h2,h3\" port=8443 key65500=\"x\"",
+            "example.\t300\tIN\tHTTPS\t1 . alpn=\"h3\" no-default-alpn ipv4hint=192.0.2.1,192.0.2.2 ech=AQI= ipv6hint=2001:db8::1",
+            #[cfg(feature = "dnssec")]
+            "example.\t300\tIN\tDS\t370 13 2 BE74359954660069D5C63D200C39F5603827D7DD02B56F120EE9F3A86764247C",
+            #[cfg(feature = "dnssec")]
+            "example.\t300\tIN\tRRSIG\tA 13 1 300 20240201000000 20240101000000 370 example. AQIDBA==",
+            "example.\t300\tCH\tTYPE65280\t\\# 3 010203",
+        ];
+        for text in records {
+            let record: ResourceRecord = text.parse().unwrap();
+            assert_eq!(record.to_string(), text);
+        }
+    }
+
+    #[test]
+    fn records_with_defaults_and_comments() {
+        let record: ResourceRecord = "www.example A 192.0.2.1 ; a comment".parse().unwrap();
+        assert_eq!(record.to_string(), "www.example.\t0\tIN\tA\t192.0.2.1");
+        let record: ResourceRecord = "example. IN 60 SOA ns.example. hostmaster.example. (\
+             1 7200 3600 1209600 300 )"
+            .parse()
+            .unwrap();
+        assert_eq!(record.ttl, 60);
+        #[cfg(feature = "dnssec")]
+        {
+            let record: ResourceRecord =
+                "example. 300 IN DNSKEY 257 3 13 AQID BA==".parse().unwrap();
+            assert!(record
+                .to_string()
+                .ends_with("DNSKEY\t257 3 13 AQIDBA== ; key id = 2068"));
+        }
+        assert!("example. 300 IN MX mail.example."
+            .parse::<ResourceRecord>()
+            .is_err());
+        assert!("example. 300 IN".parse::<ResourceRecord>().is_err());
+    }
+}
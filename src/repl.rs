@@ -0,0 +1,104 @@
+//! Commands of the interactive mode, the ones of nslookup: names to look up,
+//! `server` to switch servers and `set` to change how queries are sent.
+use crate::client::{ClassType, QueryType};
+use std::str::FromStr;
+use std::time::Duration;
+
+pub const HELP: &str = "\
+NAME [SERVER]     look NAME up, through SERVER instead of the default one
+server SERVER     make SERVER the default server
+set type=TYPE     query TYPE records, e.g. MX, instead of both A and AAAA
+set class=CLASS   query CLASS, e.g. CH
+set timeout=SECS  wait SECS for a response
+set retry=N       send a query N times before giving up
+set [no]search    look names up through the search list or as given
+set [no]recurse   ask the server to recurse or only for what it knows
+set [no]dnssec    validate answers with DNSSEC
+set all           show the current settings
+exit              leave
+";
+
+/// A setting of the session changed by `set`
+#[derive(Debug, Clone, PartialEq)]
+pub enum Setting {
+    Type(QueryType),
+    Class(ClassType),
+    Timeout(Duration),
+    Attempts(usize),
+    Search(bool),
+    Recurse(bool),
+    Dnssec(bool),
+}
+
+/// A line typed in interactive mode
+#[derive(Debug, Clone, PartialEq)]
+pub enum Line {
+    Lookup {
+        name: String,
+        server: Option<String>,
+    },
+    Server(String),
+    Set(Setting),
+    Show,
+    Help,
+    Exit,
+    Empty,
+}
+
+fn parse_setting(setting: &str) -> Result<Line, String> {
+    let invalid = |value: &str| std::format!("invalid value {} of set {}", value, setting);
+    let setting = match setting.split_once('=') {
+        Some((key, value)) => match key.to_ascii_lowercase().as_str() {
+            "type" | "querytype" | "q" => Setting::Type(value.parse().map_err(|_| invalid(value))?),
+            "class" | "cl" => Setting::Class(value.parse().map_err(|_| invalid(value))?),
+            "timeout" => match value.parse::<f64>() {
+                Ok(secs) if secs > 0.0 && secs.is_finite() => {
+                    Setting::Timeout(Duration::from_secs_f64(secs))
+                }
+                _ => return Err(invalid(value)),
+            },
+            "retry" | "tries" => match value.parse::<usize>() {
+                Ok(attempts) if attempts > 0 => Setting::Attempts(attempts),
+                _ => return Err(invalid(value)),
+            },
+            _ => return Err(std::format!("unknown setting {}", key)),
+        },
+        None => match setting.to_ascii_lowercase().as_str() {
+            "all" => return Ok(Line::Show),
+            "search" => Setting::Search(true),
+            "nosearch" => Setting::Search(false),
+            "recurse" => Setting::Recurse(true),
+            "norecurse" => Setting::Recurse(false),
+            "dnssec" => Setting::Dnssec(true),
+            "nodnssec" => Setting::Dnssec(false),
+            _ => return Err(std::format!("unknown setting {}", setting)),
+        },
+    };
+    Ok(Line::Set(setting))
+}
+
+impl FromStr for Line {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let words: Vec<&str> = s.split_whitespace().collect();
+        match words.as_slice() {
+            [] => Ok(Line::Empty),
+            ["exit" | "quit"] => Ok(Line::Exit),
+            ["help" | "?"] => Ok(Line::Help),
+            ["server" | "lserver", server] => Ok(Line::Server(server.to_string())),
+            ["server" | "lserver"] => Err("server needs the server to switch to".to_string()),
+            ["set", setting] => parse_setting(setting),
+            ["set", ..] => Err("set takes a single setting, e.g. set type=MX".to_string()),
+            [name] => Ok(Line::Lookup {
+                name: name.to_string(),
+                server: None,
+            }),
+            [name, server] => Ok(Line::Lookup {
+                name: name.to_string(),
+                server: Some(server.to_string()),
+            }),
+            _ => Err(std::format!("unknown command {}", s.trim())),
+        }
+    }
+}
@@ -0,0 +1,835 @@
+use crate::client::{Client, ClientError, QueryAnswer, QueryResponse, QueryType};
+use crate::crypto::{self, Hash};
+use crate::rdata::RData;
+use futures::future::{join, join_all};
+use rand::prelude::*;
+use regex::RegexBuilder;
+use std::net::{IpAddr, SocketAddr};
+
+/// Lookups built on top of `Client` queries, for programs that want addresses
+/// rather than records
+pub struct Resolver {
+    client: Client,
+    /// Types queried one by one when an ANY query is answered minimally
+    any_types: Vec<QueryType>,
+    ip_strategy: IpStrategy,
+}
+
+/// How `lookup_ip` queries the A and AAAA records of a name
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum IpStrategy {
+    Ipv4Only,
+    Ipv6Only,
+    /// A records, then AAAA records when there are none
+    Ipv4ThenIpv6,
+    /// AAAA records, then A records when there are none
+    Ipv6ThenIpv4,
+    /// Both at once, the IPv6 addresses first
+    #[default]
+    Parallel,
+}
+
+/// Types an ANY query falls back to by default, the ones people usually look for
+pub const ANY_TYPES: [QueryType; 7] = [
+    QueryType::A,
+    QueryType::AAAA,
+    QueryType::CNAME,
+    QueryType::MX,
+    QueryType::NS,
+    QueryType::SOA,
+    QueryType::TXT,
+];
+
+/// Whether `answers` are the single HINFO record of a server that doesn't
+/// answer ANY queries in full, RFC 8482 4.2
+pub fn is_minimal_any(answers: &[QueryAnswer]) -> bool {
+    !answers.is_empty()
+        && answers.iter().all(|answer| {
+            matches!(answer.data(), RData::HINFO { cpu, .. } if cpu.as_slice() == b"RFC8482")
+        })
+}
+
+/// An SRV record target, RFC 2782
+#[derive(Debug, Clone, PartialEq)]
+struct SrvTarget {
+    priority: u16,
+    weight: u16,
+    port: u16,
+    target: String,
+}
+
+/// Name under in-addr.arpa or ip6.arpa to query PTR records of `addr`, RFC 1035
+/// 3.5 and RFC 3596 2.5
+pub fn reverse_name(addr: &IpAddr) -> String {
+    match addr {
+        IpAddr::V4(addr) => {
+            let [a, b, c, d] = addr.octets();
+            std::format!("{}.{}.{}.{}.in-addr.arpa.", d, c, b, a)
+        }
+        IpAddr::V6(addr) => {
+            let mut name = String::new();
+            for octet in addr.octets().iter().rev() {
+                name.push_str(&std::format!("{:x}.{:x}.", octet & 0x0f, octet >> 4));
+            }
+            name + "ip6.arpa."
+        }
+    }
+}
+
+/// Names to try for `host` in order, like the search list of libc resolvers,
+/// resolv.conf(5): a name with fewer than `ndots` dots is tried in each search
+/// domain before as given, other names the other way around. Fully qualified
+/// names, with a trailing dot, are only tried as given.
+pub fn search_names(host: &str, search: &[String], ndots: usize) -> Vec<String> {
+    if host.ends_with('.') || search.is_empty() {
+        return vec![host.to_string()];
+    }
+    let mut names: Vec<String> = search
+        .iter()
+        .map(|domain| std::format!("{}.{}", host, domain.trim_end_matches('.')))
+        .collect();
+    if host.matches('.').count() >= ndots {
+        names.insert(0, host.to_string());
+    } else {
+        names.push(host.to_string());
+    }
+    names
+}
+
+/// `host` in each of `suffixes` in order, or as given without any. Fully
+/// qualified names are only queried as given.
+pub fn suffixed_names(host: &str, suffixes: &[String]) -> Vec<String> {
+    if host.ends_with('.') || suffixes.is_empty() {
+        return vec![host.to_string()];
+    }
+    suffixes
+        .iter()
+        .map(|suffix| std::format!("{}.{}", host, suffix.trim_matches('.')))
+        .collect()
+}
+
+/// Port DNS servers listen on, RFC 1035 4.2
+pub const DNS_PORT: u16 = 53;
+
+/// Host and port of a server given as an IPv4 or IPv6 address, with a port as
+/// in `192.0.2.1:5353` or `[2001:db8::1]:5353`, or as a host name with an
+/// optional port, port 53 unless given
+pub fn split_server(server: &str) -> Result<(String, u16), ClientError> {
+    if let Ok(addr) = server.parse::<SocketAddr>() {
+        return Ok((addr.ip().to_string(), addr.port()));
+    }
+    let bare = server.trim_start_matches('[').trim_end_matches(']');
+    if let Ok(ip) = bare.parse::<IpAddr>() {
+        return Ok((ip.to_string(), DNS_PORT));
+    }
+    let invalid = || ClientError::ParseError(std::format!("{} isn't a server address", server));
+    let (host, port) = match server.rsplit_once(':') {
+        Some((host, port)) => (host, port.parse::<u16>().map_err(|_| invalid())?),
+        None => (server, DNS_PORT),
+    };
+    if host.is_empty() || host.contains([':', '[', ']', '/']) {
+        return Err(invalid());
+    }
+    Ok((host.to_string(), port))
+}
+
+/// Socket address of `server` as `split_server` reads it, a host name being
+/// looked up at the `bootstrap` resolver when given, with the system resolver
+/// otherwise
+pub async fn server_addr(server: &str, bootstrap: Option<&str>) -> Result<SocketAddr, ClientError> {
+    let (host, port) = split_server(server)?;
+    if let Ok(ip) = host.parse::<IpAddr>() {
+        return Ok(SocketAddr::new(ip, port));
+    }
+    let ip = match bootstrap {
+        Some(bootstrap) => {
+            // IPv4 first, as the system resolver orders them on hosts
+            // without an IPv6 route
+            Resolver::new(Client::new(bootstrap.to_string()).await?)
+                .with_ip_strategy(IpStrategy::Ipv4ThenIpv6)
+                .lookup_ip(&host)
+                .await?
+                .first()
+                .copied()
+        }
+        None => tokio::net::lookup_host((host.as_str(), port))
+            .await
+            .map_err(|err| ClientError::GenericError(std::format!("{}: {}", host, err)))?
+            .map(|addr| addr.ip())
+            .next(),
+    };
+    match ip {
+        Some(ip) => {
+            log::info!("Server {} is at {}", server, ip);
+            Ok(SocketAddr::new(ip, port))
+        }
+        None => Err(ClientError::GenericError(std::format!(
+            "Server {} has no address",
+            host
+        ))),
+    }
+}
+
+/// Owner name of DANE records keyed by an email address: the SHA-256 of the
+/// local part truncated to 28 octets, under `<label>.<domain>`
+fn email_key_name(email: &str, label: &str) -> Option<String> {
+    let (local, domain) = email.rsplit_once('@')?;
+    if local.is_empty() || domain.is_empty() {
+        return None;
+    }
+    let hash = crypto::backend()
+        .hash(Hash::Sha256, local.as_bytes())
+        .ok()?;
+    Some(std::format!(
+        "{}.{}.{}.",
+        hex::encode(&hash[..28]),
+        label,
+        domain.trim_end_matches('.')
+    ))
+}
+
+/// Name to query the OPENPGPKEY record of `email` at, RFC 7929 3
+pub fn openpgpkey_name(email: &str) -> Option<String> {
+    email_key_name(email, "_openpgpkey")
+}
+
+/// Name to query the SMIMEA record of `email` at, RFC 8162 3
+pub fn smimea_name(email: &str) -> Option<String> {
+    email_key_name(email, "_smimecert")
+}
+
+/// Non-terminal NAPTR rules followed before an ENUM lookup gives up
+const MAX_ENUM_REWRITES: usize = 5;
+
+/// Domain of an E.164 number under e164.arpa, its digits reversed, RFC 6116
+/// 2.4. Separators like spaces and dashes are ignored.
+pub fn enum_domain(number: &str) -> Option<String> {
+    let number = number.trim();
+    if !number.starts_with('+') {
+        return None;
+    }
+    let digits: Vec<char> = number[1..]
+        .chars()
+        .filter(|c| !matches!(c, ' ' | '-' | '.' | '(' | ')'))
+        .collect();
+    if digits.is_empty() || !digits.iter().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let mut name = String::new();
+    for digit in digits.iter().rev() {
+        name.push(*digit);
+        name.push('.');
+    }
+    Some(name + "e164.arpa.")
+}
+
+/// Applies a NAPTR substitution expression, e.g. `!^.*$!sip:info@example.com!`,
+/// to `input`, RFC 3402 3.2. `None` when it is malformed or doesn't match.
+fn apply_naptr_regexp(regexp: &str, input: &str) -> Option<String> {
+    let delim = regexp.chars().next()?;
+    let parts: Vec<&str> = regexp[delim.len_utf8()..].split(delim).collect();
+    let (ere, repl, flags) = match parts[..] {
+        [ere, repl, flags] => (ere, repl, flags),
+        _ => return None,
+    };
+    let re = RegexBuilder::new(ere)
+        .case_insensitive(flags.contains('i'))
+        .build()
+        .ok()?;
+    if !re.is_match(input) {
+        return None;
+    }
+    // backreferences are \1 to \9 in NAPTR, ${1} to ${9} in the regex crate
+    let mut replacement = String::new();
+    let mut chars = repl.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some(n) if n.is_ascii_digit() => replacement.push_str(&std::format!("${{{}}}", n)),
+                Some(other) => replacement.push(other),
+                None => replacement.push('\\'),
+            },
+            '$' => replacement.push_str("$$"),
+            _ => replacement.push(c),
+        }
+    }
+    Some(re.replace(input, replacement.as_str()).to_string())
+}
+
+/// A URI an E.164 number maps to, with the service it provides, e.g. `E2U+sip`
+#[derive(Debug, Clone, PartialEq)]
+pub struct EnumUri {
+    pub order: u16,
+    pub preference: u16,
+    pub services: String,
+    pub uri: String,
+}
+
+/// A target of SRV records with the port it serves and its addresses
+#[derive(Debug, Clone, PartialEq)]
+pub struct SrvEndpoint {
+    pub target: String,
+    pub port: u16,
+    pub addrs: Vec<IpAddr>,
+}
+
+/// A host accepting mail for a domain, with its addresses
+#[derive(Debug, Clone, PartialEq)]
+pub struct MailHost {
+    pub preference: u16,
+    pub exchange: String,
+    pub addrs: Vec<IpAddr>,
+}
+
+impl Resolver {
+    pub fn new(client: Client) -> Resolver {
+        Resolver {
+            client,
+            any_types: ANY_TYPES.to_vec(),
+            ip_strategy: IpStrategy::default(),
+        }
+    }
+
+    /// Queries `types` when an ANY query is answered minimally
+    pub fn with_any_types(mut self, types: Vec<QueryType>) -> Resolver {
+        self.any_types = types;
+        self
+    }
+
+    /// Queries the addresses of `lookup_ip` as `strategy` says
+    pub fn with_ip_strategy(mut self, strategy: IpStrategy) -> Resolver {
+        self.ip_strategy = strategy;
+        self
+    }
+
+    pub fn client(&self) -> &Client {
+        &self.client
+    }
+
+    /// Records of `host` answering a `query_type` query, with the CNAME and
+    /// DNAME records that lead to them, failing with `RDCodeNameError` when
+    /// it doesn't exist
+    pub async fn query(
+        &self,
+        host: &str,
+        query_type: QueryType,
+    ) -> Result<Vec<QueryAnswer>, ClientError> {
+        let response = self.resolve(host, query_type).await?;
+        match response.is_nxdomain() {
+            true => Err(ClientError::RDCodeNameError),
+            false => Ok(response.answers),
+        }
+    }
+
+    /// Response to a `query_type` query of `host`, NXDOMAIN and NODATA ones
+    /// with their SOA. A minimal answer to an ANY query is replaced with the
+    /// records of the fallback types.
+    pub async fn resolve(
+        &self,
+        host: &str,
+        query_type: QueryType,
+    ) -> Result<QueryResponse, ClientError> {
+        let response = self
+            .client
+            .resolve(host.to_string(), query_type.clone())
+            .await?;
+        if query_type != QueryType::ANY
+            || !is_minimal_any(&response.answers)
+            || self.any_types.is_empty()
+        {
+            return Ok(response);
+        }
+        let types: Vec<String> = self.any_types.iter().map(|t| t.to_string()).collect();
+        log::warn!(
+            "{} answered ANY minimally with HINFO \"RFC8482\", the server doesn't return every \
+            record of a name (RFC 8482), querying {} instead",
+            host,
+            types.join(", ")
+        );
+        let results = join_all(
+            self.any_types
+                .iter()
+                .map(|query_type| self.client.query(host.to_string(), query_type.clone())),
+        )
+        .await;
+        let mut fallback: Vec<QueryAnswer> = Vec::new();
+        let mut failure: Option<ClientError> = None;
+        for result in results {
+            match result {
+                Ok(answers) => {
+                    for answer in answers {
+                        // CNAME records leading to the name come with every type
+                        if !fallback.iter().any(|known| {
+                            known.host() == answer.host()
+                                && known.query_type() == answer.query_type()
+                                && known.data() == answer.data()
+                        }) {
+                            fallback.push(answer);
+                        }
+                    }
+                }
+                Err(err) => {
+                    failure.get_or_insert(err);
+                }
+            }
+        }
+        match failure {
+            Some(err) if fallback.is_empty() => Err(err),
+            _ => Ok(QueryResponse {
+                total: fallback.len(),
+                answers: fallback,
+                ..response
+            }),
+        }
+    }
+
+    /// Addresses of `host` answering a `query_type` query, without the CNAME
+    /// records leading to them
+    async fn addrs(&self, host: &str, query_type: QueryType) -> Result<Vec<IpAddr>, ClientError> {
+        let answers = self.client.query(host.to_string(), query_type).await?;
+        Ok(answers
+            .iter()
+            .filter_map(|answer| match answer.data() {
+                RData::A(addr) => Some(IpAddr::V4(*addr)),
+                RData::AAAA(addr) => Some(IpAddr::V6(*addr)),
+                _ => None,
+            })
+            .collect())
+    }
+
+    /// Addresses of `host` as the IP strategy says, failing only when none
+    /// of its queries succeeds
+    pub async fn lookup_ip(&self, host: &str) -> Result<Vec<IpAddr>, ClientError> {
+        let (first, second) = match self.ip_strategy {
+            IpStrategy::Ipv4Only => return self.addrs(host, QueryType::A).await,
+            IpStrategy::Ipv6Only => return self.addrs(host, QueryType::AAAA).await,
+            IpStrategy::Ipv4ThenIpv6 => (QueryType::A, QueryType::AAAA),
+            IpStrategy::Ipv6ThenIpv4 => (QueryType::AAAA, QueryType::A),
+            IpStrategy::Parallel => {
+                let (aaaa, a) = join(
+                    self.addrs(host, QueryType::AAAA),
+                    self.addrs(host, QueryType::A),
+                )
+                .await;
+                return match (aaaa, a) {
+                    (Err(_), Err(err)) => Err(err),
+                    (aaaa, a) => Ok([aaaa.unwrap_or_default(), a.unwrap_or_default()].concat()),
+                };
+            }
+        };
+        let first = match self.addrs(host, first).await {
+            Ok(addrs) if !addrs.is_empty() => return Ok(addrs),
+            // a name that doesn't exist has no records of the other type either
+            Err(ClientError::RDCodeNameError) => return Err(ClientError::RDCodeNameError),
+            first => first,
+        };
+        match (first, self.addrs(host, second).await) {
+            (Ok(addrs), Err(_)) => Ok(addrs),
+            (_, second) => second,
+        }
+    }
+
+    /// Hostnames `addr` points back to, from its PTR records
+    pub async fn lookup_addr(&self, addr: &IpAddr) -> Result<Vec<String>, ClientError> {
+        let answers = self
+            .client
+            .query(reverse_name(addr), QueryType::PTR)
+            .await?;
+        Ok(answers
+            .iter()
+            .filter_map(|answer| match answer.data() {
+                RData::PTR(name) => Some(name.clone()),
+                _ => None,
+            })
+            .collect())
+    }
+
+    /// Socket addresses of `_service._protocol.domain`, ordered by SRV priority
+    /// and weighted selection, RFC 2782. Targets that fail to resolve are
+    /// skipped, and a single "." target means the service isn't available.
+    pub async fn lookup_service(
+        &self,
+        service: &str,
+        protocol: &str,
+        domain: &str,
+    ) -> Result<Vec<SocketAddr>, ClientError> {
+        let name = std::format!("_{}._{}.{}", service, protocol, domain);
+        let answers = self.client.query(name.clone(), QueryType::SRV).await?;
+        let targets: Vec<SrvTarget> = answers
+            .iter()
+            .filter_map(|answer| match answer.data() {
+                RData::SRV {
+                    priority,
+                    weight,
+                    port,
+                    target,
+                } => Some(SrvTarget {
+                    priority: *priority,
+                    weight: *weight,
+                    port: *port,
+                    target: target.clone(),
+                }),
+                _ => None,
+            })
+            .collect();
+        if targets.len() == 1 && targets[0].target == "." {
+            log::info!("Service {} is decidedly not available", name);
+            return Ok(Vec::new());
+        }
+        let mut addrs: Vec<SocketAddr> = Vec::new();
+        for target in Resolver::order_srv_targets(targets, &mut self.client.randomness().rng(&name))
+        {
+            match self.lookup_ip(&target.target).await {
+                Ok(ips) => addrs.extend(ips.into_iter().map(|ip| SocketAddr::new(ip, target.port))),
+                Err(err) => log::warn!("Skipping SRV target {}: {:?}", target.target, err),
+            }
+        }
+        Ok(addrs)
+    }
+
+    /// Each target of the SRV records of `name` with its port and addresses,
+    /// sorted by target and port. Targets that fail to resolve are kept
+    /// without addresses.
+    pub async fn lookup_srv_endpoints(&self, name: &str) -> Result<Vec<SrvEndpoint>, ClientError> {
+        let answers = self.client.query(name.to_string(), QueryType::SRV).await?;
+        let mut endpoints: Vec<SrvEndpoint> = Vec::with_capacity(answers.len());
+        for answer in answers {
+            let (port, target) = match answer.data() {
+                RData::SRV { port, target, .. } => {
+                    (*port, target.trim_end_matches('.').to_string())
+                }
+                _ => continue,
+            };
+            let addrs = match self.lookup_ip(&target).await {
+                Ok(addrs) => addrs,
+                Err(err) => {
+                    log::warn!("Failed to resolve SRV target {}: {:?}", target, err);
+                    Vec::new()
+                }
+            };
+            endpoints.push(SrvEndpoint {
+                target,
+                port,
+                addrs,
+            });
+        }
+        endpoints.sort_by(|a, b| (&a.target, a.port).cmp(&(&b.target, b.port)));
+        Ok(endpoints)
+    }
+
+    /// Lowest priority first, and within a priority a running sum selection
+    /// where each target is picked with a probability proportional to its weight
+    fn order_srv_targets<R: Rng>(mut targets: Vec<SrvTarget>, rng: &mut R) -> Vec<SrvTarget> {
+        targets.sort_by_key(|target| target.priority);
+        let mut ordered: Vec<SrvTarget> = Vec::with_capacity(targets.len());
+        while !targets.is_empty() {
+            let priority = targets[0].priority;
+            let end = targets
+                .iter()
+                .position(|target| target.priority != priority)
+                .unwrap_or(targets.len());
+            let mut group: Vec<SrvTarget> = targets.drain(..end).collect();
+            // zero weight targets go first so they only get a small chance of selection
+            group.sort_by_key(|target| target.weight != 0);
+            while !group.is_empty() {
+                let total: u32 = group.iter().map(|target| target.weight as u32).sum();
+                let pick = rng.gen_range(0..=total);
+                let mut sum = 0;
+                let mut index = group.len() - 1;
+                for (i, target) in group.iter().enumerate() {
+                    sum += target.weight as u32;
+                    if sum >= pick {
+                        index = i;
+                        break;
+                    }
+                }
+                ordered.push(group.remove(index));
+            }
+        }
+        ordered
+    }
+
+    /// Hosts to deliver mail for `domain` to, following RFC 5321 5.1: MX
+    /// records by preference with equal ones shuffled, the domain itself when
+    /// it has no MX record, and an error on a null MX, RFC 7505. Hosts that
+    /// fail to resolve are kept with no addresses.
+    pub async fn lookup_mail_hosts(&self, domain: &str) -> Result<Vec<MailHost>, ClientError> {
+        let answers = self.client.query(domain.to_string(), QueryType::MX).await?;
+        let mut exchanges: Vec<(u16, String)> = answers
+            .iter()
+            .filter_map(|answer| match answer.data() {
+                RData::MX {
+                    preference,
+                    exchange,
+                } => Some((*preference, exchange.clone())),
+                _ => None,
+            })
+            .collect();
+        if exchanges.iter().any(|(_, exchange)| exchange == ".") {
+            return Err(ClientError::NullMx(domain.to_string()));
+        }
+        if exchanges.is_empty() {
+            log::info!("{} has no MX record, using it as an implicit MX", domain);
+            exchanges.push((0, domain.to_string()));
+        }
+        exchanges.shuffle(&mut self.client.randomness().rng(domain));
+        exchanges.sort_by_key(|(preference, _)| *preference);
+        let mut hosts: Vec<MailHost> = Vec::with_capacity(exchanges.len());
+        for (preference, exchange) in exchanges {
+            let addrs = match self.lookup_ip(&exchange).await {
+                Ok(addrs) => addrs,
+                Err(err) => {
+                    log::warn!("Failed to resolve mail host {}: {:?}", exchange, err);
+                    Vec::new()
+                }
+            };
+            hosts.push(MailHost {
+                preference,
+                exchange,
+                addrs,
+            });
+        }
+        Ok(hosts)
+    }
+
+    /// URIs of an E.164 number like `+1 555 123 4567`, from the NAPTR records
+    /// of its e164.arpa domain, RFC 6116. Terminal "u" rules give URIs, only
+    /// the lowest order producing any is kept, RFC 3403 4; non-terminal rules
+    /// are followed when there is no terminal one.
+    pub async fn lookup_enum(&self, number: &str) -> Result<Vec<EnumUri>, ClientError> {
+        let mut domain = match enum_domain(number) {
+            Some(domain) => domain,
+            None => {
+                return Err(ClientError::ParseError(std::format!(
+                    "{} isn't an E.164 number",
+                    number
+                )))
+            }
+        };
+        let aus: String = number
+            .chars()
+            .filter(|c| *c == '+' || c.is_ascii_digit())
+            .collect();
+        for _ in 0..MAX_ENUM_REWRITES {
+            let answers = self.client.query(domain.clone(), QueryType::NAPTR).await?;
+            let mut rules: Vec<&RData> = answers
+                .iter()
+                .map(|answer| answer.data())
+                .filter(|data| matches!(data, RData::NAPTR { .. }))
+                .collect();
+            rules.sort_by_key(|data| match data {
+                RData::NAPTR {
+                    order, preference, ..
+                } => (*order, *preference),
+                _ => (u16::MAX, u16::MAX),
+            });
+            let mut uris: Vec<EnumUri> = Vec::new();
+            let mut next: Option<String> = None;
+            for rule in rules {
+                let (order, preference, flags, services, regexp, replacement) = match rule {
+                    RData::NAPTR {
+                        order,
+                        preference,
+                        flags,
+                        services,
+                        regexp,
+                        replacement,
+                    } => (*order, *preference, flags, services, regexp, replacement),
+                    _ => continue,
+                };
+                if uris.first().is_some_and(|uri| uri.order < order) {
+                    break;
+                }
+                if flags.eq_ignore_ascii_case("u") && services.to_uppercase().starts_with("E2U") {
+                    match apply_naptr_regexp(regexp, &aus) {
+                        Some(uri) => uris.push(EnumUri {
+                            order,
+                            preference,
+                            services: services.clone(),
+                            uri,
+                        }),
+                        None => log::debug!("NAPTR regexp {:?} didn't match {}", regexp, aus),
+                    }
+                } else if flags.is_empty() && next.is_none() {
+                    next = match replacement.as_str() {
+                        "." => apply_naptr_regexp(regexp, &aus),
+                        name => Some(name.to_string()),
+                    };
+                }
+            }
+            match next {
+                Some(name) if uris.is_empty() => {
+                    log::info!(
+                        "Following non-terminal NAPTR rule of {} to {}",
+                        domain,
+                        name
+                    );
+                    domain = name;
+                }
+                _ => return Ok(uris),
+            }
+        }
+        log::warn!("Too many NAPTR rewrites resolving {}", number);
+        Ok(Vec::new())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, Responder};
+    use crate::wire::encode_name;
+    use std::time::Duration;
+
+    async fn resolver(responder: &Responder, strategy: IpStrategy) -> Resolver {
+        let client = Client::new(responder.addr()).await.unwrap();
+        Resolver::new(client.with_timeout(Duration::from_secs(2))).with_ip_strategy(strategy)
+    }
+
+    #[test]
+    fn search_list_and_ndots() {
+        let search = ["corp.example".to_string(), "example.".to_string()];
+        // fewer dots than ndots, in the search domains first
+        assert_eq!(
+            search_names("db", &search, 1),
+            ["db.corp.example", "db.example", "db"]
+        );
+        assert_eq!(
+            search_names("db.eu", &search, 2),
+            ["db.eu.corp.example", "db.eu.example", "db.eu"]
+        );
+        // as given first otherwise
+        assert_eq!(
+            search_names("db.eu", &search, 1),
+            ["db.eu", "db.eu.corp.example", "db.eu.example"]
+        );
+        assert_eq!(search_names("db", &search, 0)[0], "db");
+        // fully qualified or without a search list, as given alone
+        assert_eq!(search_names("db.", &search, 5), ["db."]);
+        assert_eq!(search_names("db", &[], 1), ["db"]);
+    }
+
+    #[tokio::test]
+    async fn parallel_lookups_share_the_client() {
+        // the AAAA and A queries are answered together, in reverse order
+        let responder = Responder::start(2, |query| vec![testing::address(query)]).await;
+        let addrs = resolver(&responder, IpStrategy::Parallel)
+            .await
+            .lookup_ip("example.com")
+            .await
+            .unwrap();
+        assert_eq!(
+            addrs,
+            vec![
+                "2001:db8::1".parse::<IpAddr>().unwrap(),
+                "192.0.2.1".parse().unwrap()
+            ]
+        );
+        assert_eq!(responder.queries().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn ip_strategies() {
+        // v6.example.com has no A records
+        let responder = Responder::answering(|query| match &query.questions[0] {
+            question
+                if question.name.eq_ignore_ascii_case("v6.example.com.")
+                    && question.query_type == QueryType::A =>
+            {
+                vec![testing::reply(query, 0, &[])]
+            }
+            _ => vec![testing::address(query)],
+        })
+        .await;
+        let v4: IpAddr = "192.0.2.1".parse().unwrap();
+        let v6: IpAddr = "2001:db8::1".parse().unwrap();
+        for (strategy, host, addrs, queries) in [
+            (IpStrategy::Ipv4Only, "example.com", vec![v4], 1),
+            (IpStrategy::Ipv6Only, "example.com", vec![v6], 1),
+            (IpStrategy::Ipv4ThenIpv6, "example.com", vec![v4], 1),
+            (IpStrategy::Ipv6ThenIpv4, "example.com", vec![v6], 1),
+            (IpStrategy::Ipv4ThenIpv6, "v6.example.com", vec![v6], 2),
+            (IpStrategy::Ipv4Only, "v6.example.com", vec![], 1),
+        ] {
+            let sent = responder.queries().len();
+            let resolver = resolver(&responder, strategy).await;
+            assert_eq!(resolver.lookup_ip(host).await.unwrap(), addrs);
+            assert_eq!(responder.queries().len() - sent, queries, "{:?}", strategy);
+        }
+    }
+
+    #[tokio::test]
+    async fn service_targets_resolve_once() {
+        let responder = Responder::answering(|query| {
+            if query.questions[0].query_type != QueryType::SRV {
+                return vec![testing::address(query)];
+            }
+            let srv = |priority: u16, target: &str| {
+                let mut rdata = [priority, 10, 389].map(u16::to_be_bytes).concat();
+                rdata.extend(encode_name(target));
+                (QueryType::SRV, 300, rdata)
+            };
+            vec![testing::reply(
+                query,
+                0,
+                &[srv(10, "a.example.com"), srv(20, "b.example.com")],
+            )]
+        })
+        .await;
+        let resolver = resolver(&responder, IpStrategy::Parallel).await;
+        let addrs = resolver
+            .lookup_service("ldap", "tcp", "example.com")
+            .await
+            .unwrap();
+        assert_eq!(addrs.len(), 4);
+        assert!(addrs.iter().all(|addr| addr.port() == 389));
+        // the SRV query, then the AAAA and A queries of each target
+        assert_eq!(responder.queries().len(), 5);
+        let endpoints = resolver
+            .lookup_srv_endpoints("_ldap._tcp.example.com")
+            .await
+            .unwrap();
+        let targets: Vec<&str> = endpoints.iter().map(|e| e.target.as_str()).collect();
+        assert_eq!(targets, ["a.example.com", "b.example.com"]);
+        assert!(endpoints.iter().all(|endpoint| endpoint.addrs.len() == 2));
+    }
+
+    #[tokio::test]
+    async fn minimal_any_falls_back_to_each_type() {
+        let responder = Responder::answering(|query| match query.questions[0].query_type {
+            QueryType::ANY => {
+                let hinfo = [&[7u8][..], b"RFC8482", &[0]].concat();
+                vec![testing::reply(query, 0, &[(QueryType::HINFO, 300, hinfo)])]
+            }
+            _ => vec![testing::address(query)],
+        })
+        .await;
+        let resolver = resolver(&responder, IpStrategy::Parallel)
+            .await
+            .with_any_types(vec![QueryType::A, QueryType::AAAA, QueryType::MX]);
+        let response = resolver
+            .resolve("example.com", QueryType::ANY)
+            .await
+            .unwrap();
+        let types: Vec<QueryType> = response
+            .answers
+            .iter()
+            .map(|answer| answer.query_type().clone())
+            .collect();
+        assert_eq!(types, [QueryType::A, QueryType::AAAA]);
+        assert_eq!(response.total, 2);
+        // the ANY query, then one query of each type
+        assert_eq!(responder.queries().len(), 4);
+    }
+
+    #[tokio::test]
+    async fn nxdomain_stops_the_fallback() {
+        let responder = Responder::answering(|query| vec![testing::reply(query, 3, &[])]).await;
+        let resolver = resolver(&responder, IpStrategy::Ipv6ThenIpv4).await;
+        assert!(matches!(
+            resolver.lookup_ip("nx.example.com").await,
+            Err(ClientError::RDCodeNameError)
+        ));
+        assert_eq!(responder.queries().len(), 1);
+    }
+}
@@ -0,0 +1,128 @@
+use crate::client::ClientError;
+use std::fs;
+use std::net::IpAddr;
+
+/// Root hints files of the common distributions and nameservers
+pub const ROOT_HINTS_FILES: [&str; 4] = [
+    "/usr/share/dns/root.hints",
+    "/etc/bind/db.root",
+    "/var/named/named.ca",
+    "/etc/unbound/root.hints",
+];
+
+/// The IANA root hints, https://www.internic.net/domain/named.root
+#[cfg(feature = "embedded-root-hints")]
+const ROOT_HINTS: &str = "
+.                        3600000      NS    A.ROOT-SERVERS.NET.
+A.ROOT-SERVERS.NET.      3600000      A     198.41.0.4
+A.ROOT-SERVERS.NET.      3600000      AAAA  2001:503:ba3e::2:30
+.                        3600000      NS    B.ROOT-SERVERS.NET.
+B.ROOT-SERVERS.NET.      3600000      A     170.247.170.2
+B.ROOT-SERVERS.NET.      3600000      AAAA  2801:1b8:10::b
+.                        3600000      NS    C.ROOT-SERVERS.NET.
+C.ROOT-SERVERS.NET.      3600000      A     192.33.4.12
+C.ROOT-SERVERS.NET.      3600000      AAAA  2001:500:2::c
+.                        3600000      NS    D.ROOT-SERVERS.NET.
+D.ROOT-SERVERS.NET.      3600000      A     199.7.91.13
+D.ROOT-SERVERS.NET.      3600000      AAAA  2001:500:2d::d
+.                        3600000      NS    E.ROOT-SERVERS.NET.
+E.ROOT-SERVERS.NET.      3600000      A     192.203.230.10
+E.ROOT-SERVERS.NET.      3600000      AAAA  2001:500:a8::e
+.                        3600000      NS    F.ROOT-SERVERS.NET.
+F.ROOT-SERVERS.NET.      3600000      A     192.5.5.241
+F.ROOT-SERVERS.NET.      3600000      AAAA  2001:500:2f::f
+.                        3600000      NS    G.ROOT-SERVERS.NET.
+G.ROOT-SERVERS.NET.      3600000      A     192.112.36.4
+G.ROOT-SERVERS.NET.      3600000      AAAA  2001:500:12::d0d
+.                        3600000      NS    H.ROOT-SERVERS.NET.
+H.ROOT-SERVERS.NET.      3600000      A     198.97.190.53
+H.ROOT-SERVERS.NET.      3600000      AAAA  2001:500:1::53
+.                        3600000      NS    I.ROOT-SERVERS.NET.
+I.ROOT-SERVERS.NET.      3600000      A     192.36.148.17
+I.ROOT-SERVERS.NET.      3600000      AAAA  2001:7fe::53
+.                        3600000      NS    J.ROOT-SERVERS.NET.
+J.ROOT-SERVERS.NET.      3600000      A     192.58.128.30
+J.ROOT-SERVERS.NET.      3600000      AAAA  2001:503:c27::2:30
+.                        3600000      NS    K.ROOT-SERVERS.NET.
+K.ROOT-SERVERS.NET.      3600000      A     193.0.14.129
+K.ROOT-SERVERS.NET.      3600000      AAAA  2001:7fd::1
+.                        3600000      NS    L.ROOT-SERVERS.NET.
+L.ROOT-SERVERS.NET.      3600000      A     199.7.83.42
+L.ROOT-SERVERS.NET.      3600000      AAAA  2001:500:9f::42
+.                        3600000      NS    M.ROOT-SERVERS.NET.
+M.ROOT-SERVERS.NET.      3600000      A     202.12.27.33
+M.ROOT-SERVERS.NET.      3600000      AAAA  2001:dc3::35
+";
+
+/// A root server and its addresses
+#[derive(Debug, Clone, PartialEq)]
+pub struct RootServer {
+    pub name: String,
+    pub addrs: Vec<IpAddr>,
+}
+
+/// Parses root hints in zone file presentation format like named.root: the
+/// NS records of the root and the A and AAAA records of their names. Labs
+/// running their own root can point nsq at it with such a file.
+pub fn parse_root_hints(text: &str) -> Result<Vec<RootServer>, ClientError> {
+    let mut servers: Vec<RootServer> = Vec::new();
+    let mut addrs: Vec<(String, IpAddr)> = Vec::new();
+    for line in text.lines() {
+        let line = line.split(';').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        let invalid = || ClientError::ParseError(std::format!("Invalid root hint: {}", line));
+        let pos = match tokens.iter().position(|t| {
+            ["NS", "A", "AAAA"]
+                .iter()
+                .any(|r| t.eq_ignore_ascii_case(r))
+        }) {
+            Some(pos) if pos > 0 && tokens.len() == pos + 2 => pos,
+            _ => return Err(invalid()),
+        };
+        let owner = tokens[0].trim_end_matches('.').to_lowercase();
+        let data = tokens[pos + 1];
+        if tokens[pos].eq_ignore_ascii_case("NS") {
+            if !owner.is_empty() {
+                return Err(invalid());
+            }
+            servers.push(RootServer {
+                name: data.trim_end_matches('.').to_lowercase(),
+                addrs: Vec::new(),
+            });
+        } else {
+            addrs.push((owner, data.parse().map_err(|_| invalid())?));
+        }
+    }
+    for server in servers.iter_mut() {
+        server.addrs = addrs
+            .iter()
+            .filter(|(name, _)| *name == server.name)
+            .map(|(_, addr)| *addr)
+            .collect();
+    }
+    servers.retain(|server| !server.addrs.is_empty());
+    if servers.is_empty() {
+        return Err(ClientError::ParseError(
+            "Root hints without any root server address".to_string(),
+        ));
+    }
+    Ok(servers)
+}
+
+pub fn read_root_hints(filename: &str) -> Result<Vec<RootServer>, ClientError> {
+    match fs::read_to_string(filename) {
+        Ok(text) => parse_root_hints(&text),
+        Err(err) => Err(ClientError::ParseError(std::format!(
+            "{}: {}", filename, err
+        ))),
+    }
+}
+
+/// The root hints embedded in the binary
+#[cfg(feature = "embedded-root-hints")]
+pub fn root_hints() -> Vec<RootServer> {
+    parse_root_hints(ROOT_HINTS).unwrap_or_default()
+}
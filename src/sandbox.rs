@@ -0,0 +1,286 @@
+//! Confining `nsq serve` once its sockets are bound: started as root to
+//! listen on port 53, it switches to an unprivileged user, and it can also
+//! forbid itself from running programs and changing files with a seccomp
+//! filter, seccomp(2), so a flaw in the forwarder can't reach further.
+use crate::client::ClientError;
+
+/// Switches the process to `user`, with its group and supplementary groups,
+/// for good
+#[cfg(target_os = "linux")]
+pub fn drop_privileges(user: &str) -> Result<(), ClientError> {
+    let failed = |what: &str| {
+        let err = std::io::Error::last_os_error();
+        ClientError::GenericError(std::format!("{} for user {}: {}", what, user, err))
+    };
+    let name = std::ffi::CString::new(user)
+        .map_err(|_| ClientError::GenericError(std::format!("invalid user {:?}", user)))?;
+    let mut passwd: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut found: *mut libc::passwd = std::ptr::null_mut();
+    let mut buf = vec![0 as libc::c_char; 16384];
+    let looked_up = unsafe {
+        libc::getpwnam_r(
+            name.as_ptr(),
+            &mut passwd,
+            buf.as_mut_ptr(),
+            buf.len(),
+            &mut found,
+        )
+    };
+    if looked_up != 0 || found.is_null() {
+        return Err(ClientError::GenericError(std::format!(
+            "no user named {}",
+            user
+        )));
+    }
+    // the groups first, setuid takes away the right to change them
+    unsafe {
+        if libc::initgroups(name.as_ptr(), passwd.pw_gid) != 0 {
+            return Err(failed("initgroups"));
+        }
+        if libc::setgid(passwd.pw_gid) != 0 {
+            return Err(failed("setgid"));
+        }
+        if libc::setuid(passwd.pw_uid) != 0 {
+            return Err(failed("setuid"));
+        }
+        // setuid changes the ids of every thread, glibc and musl relay it
+        if passwd.pw_uid != 0 && libc::setuid(0) == 0 {
+            return Err(ClientError::GenericError(std::format!(
+                "root privileges were kept after switching to user {}",
+                user
+            )));
+        }
+    }
+    log::info!("Switched to user {}", user);
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn drop_privileges(user: &str) -> Result<(), ClientError> {
+    Err(ClientError::GenericError(std::format!(
+        "switching to user {} is only supported on Linux",
+        user
+    )))
+}
+
+/// Architecture the system calls of the filter are numbered for, the
+/// `AUDIT_ARCH_*` of linux/audit.h
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+const AUDIT_ARCH: u32 = 0xc000_003e;
+#[cfg(all(target_os = "linux", target_arch = "aarch64"))]
+const AUDIT_ARCH: u32 = 0xc000_00b7;
+
+/// System calls the forwarder never needs, running programs, debugging
+/// other processes, changing mounts, namespaces or the kernel, and changing
+/// files
+#[cfg(all(
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+const DENIED: &[libc::c_long] = &[
+    libc::SYS_execve,
+    libc::SYS_execveat,
+    libc::SYS_ptrace,
+    libc::SYS_process_vm_writev,
+    libc::SYS_mount,
+    libc::SYS_umount2,
+    libc::SYS_pivot_root,
+    libc::SYS_chroot,
+    libc::SYS_setns,
+    libc::SYS_unshare,
+    libc::SYS_reboot,
+    libc::SYS_kexec_load,
+    libc::SYS_init_module,
+    libc::SYS_finit_module,
+    libc::SYS_delete_module,
+    libc::SYS_bpf,
+    libc::SYS_perf_event_open,
+    libc::SYS_unlinkat,
+    libc::SYS_renameat2,
+    libc::SYS_mkdirat,
+    libc::SYS_symlinkat,
+    libc::SYS_linkat,
+    libc::SYS_fchmodat,
+    libc::SYS_fchownat,
+    libc::SYS_mknodat,
+    libc::SYS_truncate,
+    // its flags are behind a pointer the filter can't follow
+    libc::SYS_openat2,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_renameat,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_creat,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_unlink,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_rename,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_mkdir,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_rmdir,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_symlink,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_link,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_chmod,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_chown,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_lchown,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_mknod,
+];
+
+/// System calls opening files, with the argument holding their flags, only
+/// allowed to open them for reading
+#[cfg(all(
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+const OPENING: &[(libc::c_long, usize)] = &[
+    (libc::SYS_openat, 2),
+    #[cfg(target_arch = "x86_64")]
+    (libc::SYS_open, 1),
+];
+
+/// The seccomp program: the denied calls fail with EPERM, so do files
+/// opened for writing, and calls of another architecture kill the process
+#[cfg(all(
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+fn filter() -> Vec<libc::sock_filter> {
+    use libc::{BPF_ABS, BPF_JEQ, BPF_JMP, BPF_JSET, BPF_K, BPF_LD, BPF_RET, BPF_W};
+
+    let load = |offset: usize| libc::sock_filter {
+        code: (BPF_LD | BPF_W | BPF_ABS) as u16,
+        jt: 0,
+        jf: 0,
+        k: offset as u32,
+    };
+    let jump = |op: u32, k: u32, jt: u8, jf: u8| libc::sock_filter {
+        code: (BPF_JMP | op | BPF_K) as u16,
+        jt,
+        jf,
+        k,
+    };
+    let ret = |k: u32| libc::sock_filter {
+        code: (BPF_RET | BPF_K) as u16,
+        jt: 0,
+        jf: 0,
+        k,
+    };
+    let nr = std::mem::offset_of!(libc::seccomp_data, nr);
+    let arch = std::mem::offset_of!(libc::seccomp_data, arch);
+    // the low half of an argument, on these little endian architectures
+    let arg = |index: usize| std::mem::offset_of!(libc::seccomp_data, args) + 8 * index;
+    let writing = (libc::O_WRONLY | libc::O_RDWR | libc::O_CREAT | libc::O_TRUNC) as u32;
+
+    let mut program = vec![
+        load(arch),
+        jump(BPF_JEQ, AUDIT_ARCH, 1, 0),
+        ret(libc::SECCOMP_RET_KILL_PROCESS),
+        load(nr),
+    ];
+    // x32 calls share the architecture of x86_64, with this bit set
+    #[cfg(target_arch = "x86_64")]
+    program.push(jump(libc::BPF_JGE, 0x4000_0000, 0, 1));
+    #[cfg(target_arch = "x86_64")]
+    program.push(ret(libc::SECCOMP_RET_KILL_PROCESS));
+    // jumps to `deny` are patched once its place is known
+    let mut to_deny = Vec::new();
+    for call in DENIED {
+        to_deny.push(program.len());
+        program.push(jump(BPF_JEQ, *call as u32, 0, 0));
+    }
+    for (call, flags) in OPENING {
+        // its flags replace the call in the accumulator, so the call is
+        // allowed or denied right there
+        program.push(jump(BPF_JEQ, *call as u32, 0, 3));
+        program.push(load(arg(*flags)));
+        to_deny.push(program.len());
+        program.push(jump(BPF_JSET, writing, 0, 0));
+        program.push(ret(libc::SECCOMP_RET_ALLOW));
+    }
+    program.push(ret(libc::SECCOMP_RET_ALLOW));
+    let deny = program.len();
+    program.push(ret(
+        libc::SECCOMP_RET_ERRNO | (libc::EPERM as u32 & libc::SECCOMP_RET_DATA)
+    ));
+    for at in to_deny {
+        program[at].jt = (deny - at - 1) as u8;
+    }
+    program
+}
+
+/// Forbids every thread of the process from running programs, changing
+/// files or opening them for writing, and from regaining privileges
+#[cfg(all(
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+pub fn restrict() -> Result<(), ClientError> {
+    let failed = |what: &str| {
+        let err = std::io::Error::last_os_error();
+        ClientError::GenericError(std::format!("{}: {}", what, err))
+    };
+    let mut program = filter();
+    let prog = libc::sock_fprog {
+        len: program.len() as u16,
+        filter: program.as_mut_ptr(),
+    };
+    unsafe {
+        if libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) != 0 {
+            return Err(failed("no_new_privs"));
+        }
+        // the runtime threads are running already, TSYNC filters them too
+        if libc::syscall(
+            libc::SYS_seccomp,
+            libc::SECCOMP_SET_MODE_FILTER,
+            libc::SECCOMP_FILTER_FLAG_TSYNC,
+            &prog as *const libc::sock_fprog,
+        ) != 0
+        {
+            return Err(failed("seccomp"));
+        }
+    }
+    log::info!("Sandboxed with a seccomp filter");
+    Ok(())
+}
+
+#[cfg(not(all(
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+)))]
+pub fn restrict() -> Result<(), ClientError> {
+    Err(ClientError::GenericError(
+        "the sandbox is only supported on Linux x86_64 and aarch64".to_string(),
+    ))
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unknown_users_are_refused() {
+        assert!(drop_privileges("nsq-no-such-user").is_err());
+        assert!(drop_privileges("nsq\0root").is_err());
+    }
+
+    #[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+    #[test]
+    fn every_jump_of_the_filter_lands_in_it() {
+        let program = filter();
+        assert!(program.len() < 256);
+        for (at, instruction) in program.iter().enumerate() {
+            if instruction.code & 0x07 == libc::BPF_JMP as u16 {
+                assert!(at + 1 + (instruction.jt as usize) < program.len());
+                assert!(at + 1 + (instruction.jf as usize) < program.len());
+            }
+        }
+        let last = program.last().unwrap();
+        assert_eq!(last.code, (libc::BPF_RET | libc::BPF_K) as u16);
+    }
+}
@@ -0,0 +1,1019 @@
+//! A tiny local forwarder: queries received over UDP and TCP are sent to an
+//! upstream resolver by a `Client`, optionally through the cache, and its
+//! responses relayed back.
+use crate::acl::Acl;
+use crate::cache::{Cache, CacheStats, CachedName};
+use crate::client::{ClassType, Client, ClientError, QueryType, Response};
+use crate::domainstats::{DomainStats, DomainSummary};
+use crate::health::UpstreamHealth;
+use crate::ratelimit::{ClientLimits, LimitAction};
+use crate::rdata::{self, RData};
+use crate::systemd;
+use crate::transport::{TcpTransport, Transport};
+use crate::wire::{Message, MessageBuilder};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
+use tokio::sync::Semaphore;
+use tokio::time::timeout;
+
+const FORMERR: u8 = 1;
+const SERVFAIL: u8 = 2;
+const NOTIMP: u8 = 4;
+const REFUSED: u8 = 5;
+
+/// Extended DNS error of stale answers, RFC 8914 4.4
+const STALE_ANSWER: u16 = 3;
+
+/// Name of the CHAOS TXT query the forwarder answers with its cache stats
+pub const STATS_NAME: &str = "stats.cache.nsq";
+
+/// Name of the CHAOS TXT query the forwarder answers with the names and
+/// types its cache keeps, to loopback clients
+pub const LIST_NAME: &str = "list.cache.nsq";
+
+/// Name of the CHAOS TXT query that empties the cache of the forwarder,
+/// `example.com.flush.cache.nsq` dropping the responses of `example.com`
+/// alone, from loopback clients
+pub const FLUSH_NAME: &str = "flush.cache.nsq";
+
+/// Wait for the answer of the forwarder to a cache command
+const CONTROL_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Domain of the CHAOS TXT queries the forwarder answers with the stats of
+/// the domains it forwarded queries of, `example.com.stats.nsq` for those of
+/// `example.com` and `stats.nsq` for the busiest ones
+pub const DOMAIN_STATS_SUFFIX: &str = "stats.nsq";
+
+/// Domains in the answer of `stats.nsq`, to keep it under the UDP payload
+const BUSIEST_DOMAINS: usize = 25;
+
+/// Seconds a TCP connection may stay idle before the forwarder closes it,
+/// RFC 7766 6.2.3
+const TCP_IDLE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// TCP connections served at once, those beyond waiting to be accepted
+const MAX_TCP_SESSIONS: usize = 150;
+
+/// Domain of the CHAOS queries the forwarder resolves afresh from where it
+/// runs, `example.com.probe.nsq` standing for `example.com`, so a remote
+/// forwarder serves as a vantage point
+pub const PROBE_SUFFIX: &str = "probe.nsq";
+
+/// Forwards queries to upstream servers, the first one failed over to the
+/// next ones
+#[derive(Debug, Clone)]
+pub struct Forwarder {
+    timeout: Duration,
+    cache: Option<Cache>,
+    limits: Option<ClientLimits>,
+    acl: Acl,
+    health: UpstreamHealth,
+    probe_interval: Option<Duration>,
+    domains: DomainStats,
+    tcp_idle_timeout: Duration,
+    /// A permit per TCP connection served, shared by the clones
+    tcp_sessions: Arc<Semaphore>,
+}
+
+impl Forwarder {
+    pub fn new(upstream: Vec<String>) -> Forwarder {
+        Forwarder {
+            health: UpstreamHealth::new(upstream),
+            probe_interval: None,
+            timeout: Duration::from_secs(3),
+            cache: None,
+            limits: None,
+            acl: Acl::new(),
+            domains: DomainStats::new(),
+            tcp_idle_timeout: TCP_IDLE_TIMEOUT,
+            tcp_sessions: Arc::new(Semaphore::new(MAX_TCP_SESSIONS)),
+        }
+    }
+
+    /// Gives up on an upstream server once `timeout` passed without a response
+    pub fn with_timeout(mut self, timeout: Duration) -> Forwarder {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Answers repeated queries from `cache` while their records are fresh
+    pub fn with_cache(mut self, cache: Cache) -> Forwarder {
+        self.cache = Some(cache);
+        self
+    }
+
+    /// Holds clients to `limits`, answering their queries over them the way
+    /// its action says without asking upstream
+    pub fn with_limits(mut self, limits: ClientLimits) -> Forwarder {
+        self.limits = Some(limits);
+        self
+    }
+
+    /// Closes TCP connections without a query for `timeout`
+    pub fn with_tcp_idle_timeout(mut self, timeout: Duration) -> Forwarder {
+        self.tcp_idle_timeout = timeout;
+        self
+    }
+
+    /// Serves at most `max` TCP connections at once, accepting more as they
+    /// close
+    pub fn with_max_tcp_sessions(mut self, max: usize) -> Forwarder {
+        self.tcp_sessions = Arc::new(Semaphore::new(max));
+        self
+    }
+
+    /// Refuses the queries of clients `acl` doesn't permit
+    pub fn with_acl(mut self, acl: Acl) -> Forwarder {
+        self.acl = acl;
+        self
+    }
+
+    /// Probes the upstream servers every `interval`, sending queries only to
+    /// those that answered
+    pub fn with_health_probes(mut self, interval: Duration) -> Forwarder {
+        self.probe_interval = Some(interval);
+        self
+    }
+
+    /// Whether each upstream server is up, as of the last probe
+    pub fn health(&self) -> &UpstreamHealth {
+        &self.health
+    }
+
+    /// Sends the next queries to `upstream` instead, for this forwarder and
+    /// its clones serving already, the queries in flight finishing with the
+    /// servers they started with
+    pub fn set_upstream(&self, upstream: Vec<String>) {
+        log::warn!("Forwarding queries to {}", upstream.join(", "));
+        self.health.set_servers(upstream);
+    }
+
+    /// The cache answering repeated queries, if it has one
+    pub fn cache(&self) -> Option<&Cache> {
+        self.cache.as_ref()
+    }
+
+    /// The queries forwarded so far of each domain
+    pub fn domain_stats(&self) -> &DomainStats {
+        &self.domains
+    }
+
+    /// What the forwarder did so far: its queries, its cache and whether
+    /// each upstream server is up
+    pub fn report(&self) -> String {
+        let mut report = self.domains.total().to_string();
+        if let Some(cache) = &self.cache {
+            report += &std::format!("\n{}", cache.stats());
+        }
+        for (server, up) in self.health.states() {
+            let state = if up { "up" } else { "down" };
+            report += &std::format!("\nUpstream {}: {}", server, state);
+        }
+        report
+    }
+
+    /// Serves queries on `listen` over UDP and TCP until an error stops it
+    pub async fn serve(self, listen: SocketAddr) -> Result<(), ClientError> {
+        let (socket, listener) = bind(listen)?;
+        self.serve_sockets(socket, listener).await
+    }
+
+    /// Serves queries on sockets bound already, like those systemd passes,
+    /// until an error stops it
+    pub async fn serve_sockets(
+        self,
+        socket: std::net::UdpSocket,
+        listener: std::net::TcpListener,
+    ) -> Result<(), ClientError> {
+        let bind_error = |err: std::io::Error| ClientError::BindError(err.to_string());
+        socket.set_nonblocking(true).map_err(bind_error)?;
+        listener.set_nonblocking(true).map_err(bind_error)?;
+        let socket = UdpSocket::from_std(socket).map_err(bind_error)?;
+        let listener = TcpListener::from_std(listener).map_err(bind_error)?;
+        let listen = socket.local_addr().map_err(bind_error)?;
+        let upstream: Vec<String> = self
+            .health
+            .states()
+            .into_iter()
+            .map(|(server, _)| server)
+            .collect();
+        log::warn!(
+            "Forwarding queries on {} to {}",
+            listen,
+            upstream.join(", ")
+        );
+        systemd::notify(&std::format!(
+            "READY=1\nSTATUS=Forwarding queries on {}",
+            listen
+        ));
+        if let Some(interval) = self.probe_interval {
+            self.health.clone().spawn(interval, self.timeout);
+        }
+        let udp = self.clone().serve_udp(Arc::new(socket));
+        let tcp = self.serve_tcp(listener);
+        tokio::try_join!(udp, tcp)?;
+        Ok(())
+    }
+
+    async fn serve_udp(self, socket: Arc<UdpSocket>) -> Result<(), ClientError> {
+        let mut buf = [0; 65535];
+        loop {
+            let (len, peer) = socket
+                .recv_from(&mut buf)
+                .await
+                .map_err(|err| ClientError::RecvError(err.to_string()))?;
+            let query = buf[..len].to_vec();
+            let forwarder = self.clone();
+            let socket = socket.clone();
+            tokio::spawn(async move {
+                if let Some(response) = forwarder.answer(&query, peer.ip(), true).await {
+                    if let Err(err) = socket.send_to(&response, peer).await {
+                        log::info!("Failed to respond to {}: {}", peer, err);
+                    }
+                }
+            });
+        }
+    }
+
+    async fn serve_tcp(self, listener: TcpListener) -> Result<(), ClientError> {
+        loop {
+            // connections beyond the limit wait in the listen backlog
+            let permit = self
+                .tcp_sessions
+                .clone()
+                .acquire_owned()
+                .await
+                .map_err(|err| ClientError::ConnectError(err.to_string()))?;
+            let (stream, peer) = listener
+                .accept()
+                .await
+                .map_err(|err| ClientError::ConnectError(err.to_string()))?;
+            let forwarder = self.clone();
+            tokio::spawn(async move {
+                if let Err(err) = forwarder.serve_stream(stream, peer.ip()).await {
+                    log::info!("Closed the connection of {}: {}", peer, err);
+                }
+                drop(permit);
+            });
+        }
+    }
+
+    /// Answers the queries of a TCP connection, each prefixed by its length,
+    /// RFC 1035 4.2.2, until the client closes it or stays idle for the idle
+    /// timeout
+    async fn serve_stream(self, mut stream: TcpStream, peer: IpAddr) -> std::io::Result<()> {
+        let timed_out = |_| std::io::Error::from(std::io::ErrorKind::TimedOut);
+        loop {
+            let len = match timeout(self.tcp_idle_timeout, stream.read_u16()).await {
+                Ok(Ok(len)) => len as usize,
+                Ok(Err(err)) if err.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(()),
+                Ok(Err(err)) => return Err(err),
+                Err(_) => {
+                    log::debug!("Closing the idle connection of {}", peer);
+                    return Ok(());
+                }
+            };
+            let mut query = vec![0; len];
+            timeout(self.tcp_idle_timeout, stream.read_exact(&mut query))
+                .await
+                .map_err(timed_out)??;
+            if let Some(response) = self.answer(&query, peer, false).await {
+                stream.write_u16(response.len() as u16).await?;
+                stream.write_all(&response).await?;
+            }
+        }
+    }
+
+    /// The response to a query, none for data that isn't a DNS query
+    async fn answer(&self, query: &[u8], peer: IpAddr, udp: bool) -> Option<Vec<u8>> {
+        let query = match Message::decode(query) {
+            Ok(query) if !query.header.is_response() => query,
+            Ok(_) => return None,
+            Err(err) => {
+                log::info!("Ignoring a query that failed to decode: {:?}", err);
+                return None;
+            }
+        };
+        if !self.acl.permits(peer) {
+            log::debug!("Refusing {}, the access list denies it", peer);
+            return Some(Message::reply(&query, REFUSED).encode());
+        }
+        // held until the response is ready, the query being in flight till then
+        let _permit = match &self.limits {
+            Some(limits) => match limits.admit(peer) {
+                Some(permit) => Some(permit),
+                None => {
+                    log::debug!("{} is over its limits", peer);
+                    return Forwarder::limited(&query, limits.action(), udp);
+                }
+            },
+            None => None,
+        };
+        let mut response = self.forward(&query, peer).await;
+        if udp && response.encode().len() > query.udp_payload_size() {
+            response.truncate();
+        }
+        Some(response.encode())
+    }
+
+    /// The response to a query over the limits of its client
+    fn limited(query: &Message, action: LimitAction, udp: bool) -> Option<Vec<u8>> {
+        match action {
+            LimitAction::Drop => None,
+            LimitAction::Truncated if udp => {
+                let mut response = Message::reply(query, 0);
+                response.truncate();
+                Some(response.encode())
+            }
+            LimitAction::Refused | LimitAction::Truncated => {
+                Some(Message::reply(query, REFUSED).encode())
+            }
+        }
+    }
+
+    async fn forward(&self, query: &Message, peer: IpAddr) -> Message {
+        if query.header.opcode() != 0 {
+            return Message::reply(query, NOTIMP);
+        }
+        let question = match query.questions.as_slice() {
+            [question] => question,
+            _ => return Message::reply(query, FORMERR),
+        };
+        let class = match ClassType::from_code(question.class) {
+            Ok(class) => class,
+            Err(_) => return Message::reply(query, NOTIMP),
+        };
+        log::info!("{} {} {}", question.name, class, question.query_type);
+        if class == ClassType::CH
+            && question.query_type == QueryType::TXT
+            && question
+                .name
+                .trim_end_matches('.')
+                .eq_ignore_ascii_case(STATS_NAME)
+        {
+            return self.stats(query);
+        }
+        let name = question.name.trim_end_matches('.').to_ascii_lowercase();
+        if let (ClassType::CH, Some(probed)) =
+            (class, name.strip_suffix(&std::format!(".{}", PROBE_SUFFIX)))
+        {
+            return self.probe(query, probed).await;
+        }
+        if class == ClassType::CH && question.query_type == QueryType::TXT {
+            if name == DOMAIN_STATS_SUFFIX {
+                return self.domain_stats_response(query, None);
+            }
+            if let Some(domain) = name.strip_suffix(&std::format!(".{}", DOMAIN_STATS_SUFFIX)) {
+                return self.domain_stats_response(query, Some(domain));
+            }
+            if name == LIST_NAME {
+                return self.list(query, peer);
+            }
+            if name == FLUSH_NAME {
+                return self.flush(query, peer, None);
+            }
+            if let Some(flushed) = name.strip_suffix(&std::format!(".{}", FLUSH_NAME)) {
+                return self.flush(query, peer, Some(flushed));
+            }
+        }
+        let started = Instant::now();
+        let exchanged = async {
+            self.client(class, query)
+                .await?
+                .exchange(&question.name, question.query_type.clone())
+                .await
+        }
+        .await;
+        if class == ClassType::IN {
+            let rcode = exchanged
+                .as_ref()
+                .ok()
+                .map(|response| response.message.header.rcode());
+            self.domains
+                .record(&question.name, rcode, started.elapsed());
+        }
+        match exchanged {
+            Ok(response) => {
+                let stale = response.is_stale();
+                let mut message = response.message;
+                message.header.id = query.header.id;
+                // the OPT record is hop by hop, the client only gets one
+                // when it sent one, RFC 6891 7
+                match (query.has_edns(), message.has_edns()) {
+                    (false, true) => message.remove_edns(),
+                    (true, false) => message.add_edns(query.dnssec_ok()),
+                    _ => {}
+                }
+                if stale && query.has_edns() {
+                    message.add_extended_error(STALE_ANSWER);
+                }
+                message
+            }
+            Err(err) => {
+                log::warn!("{} {}: {:?}", question.name, question.query_type, err);
+                Message::reply(query, SERVFAIL)
+            }
+        }
+    }
+
+    /// The response of the upstream servers to `name`, in the IN class and
+    /// without the cache, under the question of the probe
+    async fn probe(&self, query: &Message, name: &str) -> Message {
+        let question = &query.questions[0];
+        let exchanged = async {
+            self.upstream_client(ClassType::IN)
+                .await?
+                .exchange(name, question.query_type.clone())
+                .await
+        };
+        match exchanged.await {
+            Ok(response) => {
+                let mut message = response.message;
+                message.header.id = query.header.id;
+                message.questions = query.questions.clone();
+                message
+            }
+            Err(err) => {
+                log::warn!("Probe of {} {}: {:?}", name, question.query_type, err);
+                Message::reply(query, SERVFAIL)
+            }
+        }
+    }
+
+    /// The stats of the cache as TXT records, refused without one
+    fn stats(&self, query: &Message) -> Message {
+        let Some(cache) = &self.cache else {
+            return Message::reply(query, REFUSED);
+        };
+        let name = &query.questions[0].name;
+        let mut response = MessageBuilder::reply(query, 0);
+        for txt in stats_txt(&cache.stats()) {
+            if let Ok(rdata) = rdata::parse(&QueryType::TXT, &[&txt]) {
+                response = response.with_answer(name, QueryType::TXT, ClassType::CH, 0, rdata);
+            }
+        }
+        response.message()
+    }
+
+    /// The cache of the forwarder for a command of a loopback client, none
+    /// for another client or without a cache
+    fn control_cache(&self, peer: IpAddr) -> Option<&Cache> {
+        if !peer.is_loopback() {
+            log::debug!("Refusing the cache command of {}, it isn't local", peer);
+            return None;
+        }
+        self.cache.as_ref()
+    }
+
+    /// The names and types the cache keeps, a TXT record of the name, type
+    /// and TTL left each, for loopback clients only as they tell what the
+    /// others looked up
+    fn list(&self, query: &Message, peer: IpAddr) -> Message {
+        let Some(cache) = self.control_cache(peer) else {
+            return Message::reply(query, REFUSED);
+        };
+        let name = &query.questions[0].name;
+        let mut response = MessageBuilder::reply(query, 0);
+        for cached in cache.names() {
+            let fields = [
+                cached.name,
+                cached.query_type.to_string(),
+                cached.ttl.to_string(),
+            ];
+            let fields: Vec<&str> = fields.iter().map(String::as_str).collect();
+            if let Ok(rdata) = rdata::parse(&QueryType::TXT, &fields) {
+                response = response.with_answer(name, QueryType::TXT, ClassType::CH, 0, rdata);
+            }
+        }
+        response.message()
+    }
+
+    /// Drops the responses of `flushed` from the cache, or all of them
+    /// without it, answering with how many as a `flushed=` TXT record
+    fn flush(&self, query: &Message, peer: IpAddr, flushed: Option<&str>) -> Message {
+        let Some(cache) = self.control_cache(peer) else {
+            return Message::reply(query, REFUSED);
+        };
+        let count = cache.flush(flushed);
+        log::warn!(
+            "Flushed {} responses of {} from the cache",
+            count,
+            flushed.unwrap_or("every name")
+        );
+        let name = &query.questions[0].name;
+        let mut response = MessageBuilder::reply(query, 0);
+        if let Ok(rdata) = rdata::parse(&QueryType::TXT, &[&std::format!("flushed={}", count)]) {
+            response = response.with_answer(name, QueryType::TXT, ClassType::CH, 0, rdata);
+        }
+        response.message()
+    }
+
+    /// The stats of `domain`, or of the busiest domains without one, a TXT
+    /// record each
+    fn domain_stats_response(&self, query: &Message, domain: Option<&str>) -> Message {
+        let summaries = match domain {
+            Some(domain) => self.domains.domain(domain).into_iter().collect(),
+            None => self.domains.busiest(BUSIEST_DOMAINS),
+        };
+        let name = &query.questions[0].name;
+        let mut response = MessageBuilder::reply(query, 0);
+        for summary in summaries {
+            let txt = summary.to_txt();
+            let strings: Vec<&str> = txt.iter().map(String::as_str).collect();
+            if let Ok(rdata) = rdata::parse(&QueryType::TXT, &strings) {
+                response = response.with_answer(name, QueryType::TXT, ClassType::CH, 0, rdata);
+            }
+        }
+        response.message()
+    }
+
+    /// A client of the upstream servers up, through the cache, one per query
+    /// so concurrent queries don't read each other's responses. Its queries
+    /// carry the RD, CD and DO flags of `query`, and an OPT record when it
+    /// has one.
+    async fn client(&self, class: ClassType, query: &Message) -> Result<Client, ClientError> {
+        let mut client = self.upstream_client(class).await?;
+        if !query.header.recursion_desired() {
+            client = client.without_recursion();
+        }
+        if query.header.checking_disabled() {
+            client = client.with_checking_disabled();
+        }
+        if query.dnssec_ok() {
+            client = client.with_dnssec_ok();
+        }
+        if query.has_edns() {
+            client = client.with_edns();
+        }
+        Ok(match &self.cache {
+            Some(cache) => client.with_cache(cache.clone()),
+            None => client,
+        })
+    }
+
+    /// A client of the upstream servers up, without the cache
+    async fn upstream_client(&self, class: ClassType) -> Result<Client, ClientError> {
+        let upstream = self.health.up_servers();
+        let mut servers = upstream.iter();
+        let primary = match servers.next() {
+            Some(server) => server.clone(),
+            None => return Err(ClientError::GenericError("No upstream server".to_string())),
+        };
+        let mut client = Client::new(primary).await?;
+        for server in servers {
+            client = client.with_fallback(Transport::udp(server.clone()).await?);
+        }
+        Ok(client
+            .without_idn()
+            .with_class(class)
+            .with_timeout(self.timeout))
+    }
+}
+
+/// The UDP socket and TCP listener of `listen`, bound before serving so the
+/// process can give up the privileges binding took
+pub fn bind(
+    listen: SocketAddr,
+) -> Result<(std::net::UdpSocket, std::net::TcpListener), ClientError> {
+    let bind_error = |err: std::io::Error| ClientError::BindError(err.to_string());
+    let socket = std::net::UdpSocket::bind(listen).map_err(bind_error)?;
+    let listener = std::net::TcpListener::bind(listen).map_err(bind_error)?;
+    Ok((socket, listener))
+}
+
+/// The stats as `key=value` strings, a TXT record each
+fn stats_txt(stats: &CacheStats) -> Vec<String> {
+    let mut txt = vec![
+        std::format!("entries={}", stats.entries),
+        std::format!("memory={}", stats.memory),
+    ];
+    if let Some(max) = stats.max_memory {
+        txt.push(std::format!("max-memory={}", max));
+    }
+    txt.push(std::format!("hits={}", stats.hits));
+    txt.push(std::format!("misses={}", stats.misses));
+    txt.push(std::format!("evictions={}", stats.evictions));
+    txt
+}
+
+/// The stats from their TXT strings, those unknown ignored
+fn parse_stats(txt: &[String]) -> Result<CacheStats, ClientError> {
+    let mut stats = CacheStats {
+        entries: 0,
+        memory: 0,
+        max_memory: None,
+        hits: 0,
+        misses: 0,
+        evictions: 0,
+    };
+    for string in txt {
+        let invalid = || ClientError::GenericError(std::format!("Invalid cache stat {:?}", string));
+        let (key, value) = string.split_once('=').ok_or_else(invalid)?;
+        let value: usize = value.parse().map_err(|_| invalid())?;
+        match key {
+            "entries" => stats.entries = value,
+            "memory" => stats.memory = value,
+            "max-memory" => stats.max_memory = Some(value),
+            "hits" => stats.hits = value,
+            "misses" => stats.misses = value,
+            "evictions" => stats.evictions = value,
+            _ => {}
+        }
+    }
+    Ok(stats)
+}
+
+/// Stats of the cache of the forwarder listening on `server`
+pub async fn cache_stats(server: String) -> Result<CacheStats, ClientError> {
+    let response = Client::new(server)
+        .await?
+        .without_idn()
+        .with_class(ClassType::CH)
+        .with_attempts(1)
+        .exchange(STATS_NAME, QueryType::TXT)
+        .await?;
+    let txt: Vec<String> = response
+        .message
+        .answers
+        .iter()
+        .filter_map(
+            |record| match rdata::decode(&record.query_type, &record.rdata)? {
+                RData::TXT(strings) => Some(strings.concat()),
+                _ => None,
+            },
+        )
+        .map(|txt| String::from_utf8_lossy(&txt).into_owned())
+        .collect();
+    parse_stats(&txt)
+}
+
+/// Strings of each TXT record the forwarder listening on `server` answers
+/// a CHAOS query of `name` with, over TCP as the answer may not fit in a
+/// datagram
+async fn control(server: String, name: &str) -> Result<Vec<Vec<String>>, ClientError> {
+    let query = MessageBuilder::new(rand::random())
+        .with_question(name, QueryType::TXT, ClassType::CH)
+        .build();
+    let exchange = async {
+        let transport = TcpTransport::connect(server).await?;
+        transport.send(&query).await?;
+        transport.recv().await
+    };
+    let response = timeout(CONTROL_TIMEOUT, exchange)
+        .await
+        .unwrap_or(Err(ClientError::Timeout(1)))?;
+    let response = Message::decode(&response)?;
+    match response.header.rcode() {
+        0 => {}
+        REFUSED => {
+            return Err(ClientError::RDCodeRefused(
+                "the forwarder only takes cache commands from its own host, and needs a cache"
+                    .to_string(),
+            ))
+        }
+        rcode => {
+            return Err(ClientError::GenericError(std::format!(
+                "The forwarder answered {} with rcode {}",
+                name,
+                rcode
+            )))
+        }
+    }
+    Ok(response
+        .answers
+        .iter()
+        .filter_map(
+            |record| match rdata::decode(&record.query_type, &record.rdata)? {
+                RData::TXT(strings) => Some(
+                    strings
+                        .iter()
+                        .map(|string| String::from_utf8_lossy(string).into_owned())
+                        .collect(),
+                ),
+                _ => None,
+            },
+        )
+        .collect())
+}
+
+/// The names and types the cache of the forwarder listening on `server`
+/// keeps
+pub async fn cache_names(server: String) -> Result<Vec<CachedName>, ClientError> {
+    let mut names = Vec::new();
+    for fields in control(server, LIST_NAME).await? {
+        let invalid =
+            || ClientError::GenericError(std::format!("Invalid cached name {:?}", fields));
+        let [name, query_type, ttl] = fields.as_slice() else {
+            return Err(invalid());
+        };
+        names.push(CachedName {
+            name: name.clone(),
+            query_type: query_type.parse().map_err(|_| invalid())?,
+            ttl: ttl.parse().map_err(|_| invalid())?,
+        });
+    }
+    Ok(names)
+}
+
+/// Drops the responses of `name` from the cache of the forwarder listening
+/// on `server`, or all of them without it, returning how many it dropped
+pub async fn flush_cache(server: String, name: Option<&str>) -> Result<usize, ClientError> {
+    let query = match name {
+        Some(name) => std::format!("{}.{}", name.trim_end_matches('.'), FLUSH_NAME),
+        None => FLUSH_NAME.to_string(),
+    };
+    let txt = control(server, &query).await?;
+    txt.iter()
+        .flatten()
+        .find_map(|string| string.strip_prefix("flushed="))
+        .and_then(|count| count.parse().ok())
+        .ok_or_else(|| {
+            ClientError::GenericError("The forwarder didn't say what it flushed".to_string())
+        })
+}
+
+/// Stats of the domains the forwarder listening on `server` forwarded queries
+/// of: `domain` alone, or the busiest ones without one
+pub async fn domain_stats(
+    server: String,
+    domain: Option<&str>,
+) -> Result<Vec<DomainSummary>, ClientError> {
+    let name = match domain {
+        Some(domain) => std::format!("{}.{}", domain.trim_end_matches('.'), DOMAIN_STATS_SUFFIX),
+        None => DOMAIN_STATS_SUFFIX.to_string(),
+    };
+    let response = Client::new(server)
+        .await?
+        .without_idn()
+        .with_class(ClassType::CH)
+        .with_attempts(1)
+        .exchange(&name, QueryType::TXT)
+        .await?;
+    response
+        .message
+        .answers
+        .iter()
+        .filter_map(
+            |record| match rdata::decode(&record.query_type, &record.rdata)? {
+                RData::TXT(strings) => Some(strings),
+                _ => None,
+            },
+        )
+        .map(|strings| {
+            let txt: Vec<String> = strings
+                .iter()
+                .map(|string| String::from_utf8_lossy(string).into_owned())
+                .collect();
+            DomainSummary::from_txt(&txt)
+        })
+        .collect()
+}
+
+/// The response of the forwarder listening on `server` to `host`
+/// `query_type`, resolved afresh from where it runs
+pub async fn probe(
+    server: String,
+    host: &str,
+    query_type: QueryType,
+    timeout: Duration,
+) -> Result<Response, ClientError> {
+    Client::new(server)
+        .await?
+        .without_idn()
+        .with_class(ClassType::CH)
+        .with_timeout(timeout)
+        .with_attempts(1)
+        .exchange(
+            &std::format!("{}.{}", host.trim_end_matches('.'), PROBE_SUFFIX),
+            query_type,
+        )
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, Responder};
+
+    const CLIENT: IpAddr = IpAddr::V4(std::net::Ipv4Addr::new(192, 0, 2, 10));
+    const OTHER: IpAddr = IpAddr::V4(std::net::Ipv4Addr::new(192, 0, 2, 20));
+
+    async fn upstream() -> Responder {
+        Responder::answering(|query| vec![testing::address(query)]).await
+    }
+
+    /// Response of `forwarder` to an A query of `client` over UDP, none
+    /// when it drops it
+    async fn ask(forwarder: &Forwarder, client: IpAddr) -> Option<Message> {
+        let query = Message::query(7, "example.com", &QueryType::A, ClassType::IN.code());
+        let response = forwarder.answer(&query.encode(), client, true).await?;
+        Some(Message::decode(&response).unwrap())
+    }
+
+    /// Serves `forwarder` on loopback ports, its UDP and TCP addresses
+    async fn listen(forwarder: Forwarder) -> (SocketAddr, SocketAddr) {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addrs = (socket.local_addr().unwrap(), listener.local_addr().unwrap());
+        tokio::spawn(forwarder.clone().serve_udp(Arc::new(socket)));
+        tokio::spawn(forwarder.serve_tcp(listener));
+        addrs
+    }
+
+    /// An A query of example.com
+    fn query(id: u16) -> MessageBuilder {
+        MessageBuilder::new(id).with_question("example.com", QueryType::A, ClassType::IN)
+    }
+
+    /// Response to `query` over UDP from `server`
+    async fn exchange_udp(server: SocketAddr, query: MessageBuilder) -> Message {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        socket.send_to(&query.build(), server).await.unwrap();
+        let mut buf = [0; 65535];
+        let len = timeout(Duration::from_secs(2), socket.recv(&mut buf))
+            .await
+            .unwrap()
+            .unwrap();
+        Message::decode(&buf[..len]).unwrap()
+    }
+
+    /// Sends `query` on `stream` prefixed by its length
+    async fn send_tcp(stream: &mut TcpStream, query: MessageBuilder) {
+        let query = query.build();
+        stream.write_u16(query.len() as u16).await.unwrap();
+        stream.write_all(&query).await.unwrap();
+    }
+
+    /// The next response on `stream`
+    async fn receive_tcp(stream: &mut TcpStream) -> Message {
+        let len = stream.read_u16().await.unwrap();
+        let mut response = vec![0; len as usize];
+        stream.read_exact(&mut response).await.unwrap();
+        Message::decode(&response).unwrap()
+    }
+
+    #[tokio::test]
+    async fn new_upstream_servers_get_the_next_queries_with_the_cache_kept() {
+        let (first, second) = (upstream().await, upstream().await);
+        let forwarder = Forwarder::new(vec![first.addr()]).with_cache(Cache::new());
+        let (udp, _) = listen(forwarder.clone()).await;
+        assert_eq!(exchange_udp(udp, query(1)).await.answers.len(), 1);
+        forwarder.set_upstream(vec![second.addr()]);
+        assert_eq!(forwarder.health().states(), vec![(second.addr(), true)]);
+        // still answered from the cache
+        assert_eq!(exchange_udp(udp, query(2)).await.answers.len(), 1);
+        let other =
+            MessageBuilder::new(3).with_question("example.org", QueryType::A, ClassType::IN);
+        assert_eq!(exchange_udp(udp, other).await.answers.len(), 1);
+        assert_eq!(first.queries().len(), 1);
+        assert_eq!(second.queries().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn cache_commands_are_taken_from_local_clients_only() {
+        let upstream = upstream().await;
+        let forwarder = Forwarder::new(vec![upstream.addr()]).with_cache(Cache::new());
+        let (udp, tcp) = listen(forwarder.clone()).await;
+        exchange_udp(udp, query(1)).await;
+        let names = cache_names(tcp.to_string()).await.unwrap();
+        let listed: Vec<(&str, &QueryType)> = names
+            .iter()
+            .map(|name| (name.name.as_str(), &name.query_type))
+            .collect();
+        assert_eq!(listed, [("example.com", &QueryType::A)]);
+        for name in [LIST_NAME, FLUSH_NAME] {
+            let command = MessageBuilder::new(2).with_question(name, QueryType::TXT, ClassType::CH);
+            let response = forwarder.answer(&command.build(), CLIENT, true).await;
+            let response = Message::decode(&response.unwrap()).unwrap();
+            assert_eq!(
+                (response.header.rcode(), response.answers.len()),
+                (REFUSED, 0)
+            );
+        }
+        let flushed = |name| flush_cache(tcp.to_string(), name);
+        assert_eq!(flushed(Some("example.org")).await.unwrap(), 0);
+        assert_eq!(flushed(Some("EXAMPLE.com.")).await.unwrap(), 1);
+        assert!(cache_names(tcp.to_string()).await.unwrap().is_empty());
+        assert_eq!(upstream.queries().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn udp_queries_go_upstream_with_their_flags() {
+        // an upstream server adding an OPT record to every response
+        let upstream = Responder::answering(|query| {
+            let mut response = Message::decode(&testing::address(query)).unwrap();
+            response.add_edns(false);
+            vec![response.encode()]
+        })
+        .await;
+        let forwarder = Forwarder::new(vec![upstream.addr()]).with_cache(Cache::new());
+        let (udp, _) = listen(forwarder).await;
+        let flagged = || {
+            query(1)
+                .with_recursion_desired()
+                .with_checking_disabled()
+                .with_edns(1232, true)
+        };
+        let response = exchange_udp(udp, flagged()).await;
+        assert_eq!((response.header.id, response.answers.len()), (1, 1));
+        assert!(response.has_edns());
+        let response = exchange_udp(udp, query(2)).await;
+        assert_eq!((response.header.id, response.answers.len()), (2, 1));
+        assert!(!response.has_edns());
+        let queries = upstream.queries();
+        assert_eq!(queries.len(), 2);
+        let flags = |query: &Message| {
+            (
+                query.header.recursion_desired(),
+                query.header.checking_disabled(),
+                query.dnssec_ok(),
+                query.has_edns(),
+            )
+        };
+        assert_eq!(flags(&queries[0]), (true, true, true, true));
+        assert_eq!(flags(&queries[1]), (false, false, false, false));
+        // each combination of flags has its own cache entry
+        let response = exchange_udp(udp, flagged()).await;
+        assert_eq!(response.answers.len(), 1);
+        assert!(response.has_edns());
+        assert_eq!(upstream.queries().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn tcp_connections_are_closed_once_idle() {
+        let upstream = upstream().await;
+        let forwarder =
+            Forwarder::new(vec![upstream.addr()]).with_tcp_idle_timeout(Duration::from_millis(200));
+        let (_, tcp) = listen(forwarder).await;
+        let mut stream = TcpStream::connect(tcp).await.unwrap();
+        for id in 1..=2 {
+            send_tcp(&mut stream, query(id).with_recursion_desired()).await;
+            let response = receive_tcp(&mut stream).await;
+            assert_eq!((response.header.id, response.answers.len()), (id, 1));
+        }
+        let mut buf = [0; 1];
+        let read = timeout(Duration::from_secs(2), stream.read(&mut buf)).await;
+        assert_eq!(read.unwrap().unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn tcp_connections_beyond_the_limit_wait() {
+        let upstream = upstream().await;
+        let forwarder = Forwarder::new(vec![upstream.addr()]).with_max_tcp_sessions(1);
+        let (_, tcp) = listen(forwarder).await;
+        let mut first = TcpStream::connect(tcp).await.unwrap();
+        send_tcp(&mut first, query(1)).await;
+        assert_eq!(receive_tcp(&mut first).await.header.id, 1);
+        let mut second = TcpStream::connect(tcp).await.unwrap();
+        send_tcp(&mut second, query(2)).await;
+        let waiting = timeout(Duration::from_millis(300), receive_tcp(&mut second)).await;
+        assert!(waiting.is_err());
+        drop(first);
+        let response = timeout(Duration::from_secs(2), receive_tcp(&mut second)).await;
+        assert_eq!(response.unwrap().header.id, 2);
+    }
+
+    #[tokio::test]
+    async fn access_list_refuses_clients_before_upstream() {
+        let upstream = upstream().await;
+        let acl = Acl::new()
+            .with_allow("192.0.2.0/24".parse().unwrap())
+            .with_deny("192.0.2.20".parse().unwrap());
+        let forwarder = Forwarder::new(vec![upstream.addr()]).with_acl(acl);
+        let allowed = ask(&forwarder, CLIENT).await.unwrap();
+        assert_eq!((allowed.header.rcode(), allowed.answers.len()), (0, 1));
+        for client in [OTHER, "198.51.100.1".parse().unwrap()] {
+            let refused = ask(&forwarder, client).await.unwrap();
+            assert_eq!(refused.header.rcode(), REFUSED);
+            assert!(refused.answers.is_empty());
+        }
+        assert_eq!(upstream.queries().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn clients_over_their_rate_are_limited() {
+        let upstream = upstream().await;
+        for (action, limited) in [
+            (LimitAction::Drop, None),
+            (LimitAction::Refused, Some((REFUSED, false))),
+            (LimitAction::Truncated, Some((0, true))),
+        ] {
+            // a token every 100 seconds, none back during the test
+            let limits = ClientLimits::new().with_rate(0.01, 2.0);
+            let forwarder =
+                Forwarder::new(vec![upstream.addr()]).with_limits(limits.with_action(action));
+            let limits_of = |response: Option<Message>| {
+                response.map(|response| (response.header.rcode(), response.header.truncated()))
+            };
+            for _ in 0..2 {
+                assert_eq!(limits_of(ask(&forwarder, CLIENT).await), Some((0, false)));
+            }
+            assert_eq!(limits_of(ask(&forwarder, CLIENT).await), limited);
+            // each client has its own bucket
+            assert_eq!(limits_of(ask(&forwarder, OTHER).await), Some((0, false)));
+        }
+    }
+}
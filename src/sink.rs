@@ -0,0 +1,434 @@
+//! Where the results of queries go. Each output format is an `OutputSink`
+//! taking the results as queries complete, so library users can plug their
+//! own in next to the tables, JSON, CSV and databases of the command line.
+use crate::client::{ClientError, QueryAnswer, QueryType, Response};
+#[cfg(feature = "diagnostics")]
+use crate::export::Export;
+use crate::filter::Filter;
+use crate::output::{self, AnswerStream, Ipv6Format, Outcome, SortKey, Summary};
+#[cfg(feature = "diagnostics")]
+use chrono::{DateTime, Utc};
+use std::io::Write;
+use std::time::Duration;
+
+/// A query of the run and what came of it
+#[derive(Debug, Clone)]
+pub struct QueryResult {
+    pub host: String,
+    pub query_type: QueryType,
+    /// Server that answered, or the servers of the run when none did
+    pub server: String,
+    pub rtt: Duration,
+    pub outcome: Outcome,
+    pub error: Option<String>,
+    pub answers: Vec<QueryAnswer>,
+    /// The whole response, when the output shows them
+    pub response: Option<Response>,
+}
+
+impl QueryResult {
+    /// Time the query spent opening the connection it went over
+    pub fn connection_setup(&self) -> Option<Duration> {
+        match &self.response {
+            Some(response) => response.connection_setup(),
+            None => self.answers.first()?.connection_setup(),
+        }
+    }
+}
+
+/// Takes the results of a run, one query at a time
+pub trait OutputSink {
+    /// Takes the result of a query as soon as it completed
+    fn query(&mut self, result: &QueryResult) -> Result<(), ClientError>;
+
+    /// Ends the run, with its summary when one was asked for
+    fn finish(&mut self, summary: Option<&Summary>) -> Result<(), ClientError> {
+        let _ = summary;
+        Ok(())
+    }
+}
+
+fn write_error(err: std::io::Error) -> ClientError {
+    ClientError::GenericError(std::format!("Failed to write the output: {}", err))
+}
+
+fn sorted(answers: &[QueryAnswer], sort: Option<SortKey>) -> Vec<QueryAnswer> {
+    let mut answers = answers.to_vec();
+    if let Some(key) = sort {
+        output::sort_answers(&mut answers, key);
+    }
+    answers
+}
+
+/// Answers of the run in aligned columns, written once all are in
+pub struct TableSink {
+    out: Box<dyn Write>,
+    ipv6: Ipv6Format,
+    width: usize,
+    truncate: bool,
+    sort: Option<SortKey>,
+    answers: Vec<QueryAnswer>,
+}
+
+impl TableSink {
+    pub fn new(out: Box<dyn Write>, ipv6: Ipv6Format, width: usize, truncate: bool) -> TableSink {
+        TableSink {
+            out,
+            ipv6,
+            width,
+            truncate,
+            sort: None,
+            answers: Vec::new(),
+        }
+    }
+
+    pub fn with_sort(mut self, key: SortKey) -> TableSink {
+        self.sort = Some(key);
+        self
+    }
+}
+
+impl OutputSink for TableSink {
+    fn query(&mut self, result: &QueryResult) -> Result<(), ClientError> {
+        self.answers.extend(result.answers.iter().cloned());
+        Ok(())
+    }
+
+    fn finish(&mut self, _: Option<&Summary>) -> Result<(), ClientError> {
+        let answers = sorted(&self.answers, self.sort);
+        let table = output::answers_table(&answers, self.width, self.truncate, &self.ipv6);
+        self.out.write_all(table.as_bytes()).map_err(write_error)
+    }
+}
+
+/// Rows of answers written as their queries complete, under a single header
+pub struct StreamTableSink {
+    out: Box<dyn Write>,
+    ipv6: Ipv6Format,
+    stream: AnswerStream,
+}
+
+impl StreamTableSink {
+    pub fn new(out: Box<dyn Write>, ipv6: Ipv6Format, stream: AnswerStream) -> StreamTableSink {
+        StreamTableSink { out, ipv6, stream }
+    }
+}
+
+impl OutputSink for StreamTableSink {
+    /// Leaves out the SOA of negative responses, the columns being fixed
+    /// before any is seen
+    fn query(&mut self, result: &QueryResult) -> Result<(), ClientError> {
+        let answers: Vec<QueryAnswer> = result
+            .answers
+            .iter()
+            .filter(|answer| answer.negative().is_none())
+            .cloned()
+            .collect();
+        let rows = self.stream.rows(&answers, &self.ipv6);
+        self.out.write_all(rows.as_bytes()).map_err(write_error)
+    }
+}
+
+/// A JSON array of the answers of the run, in an object with the summary when
+/// there is one
+pub struct JsonSink {
+    out: Box<dyn Write>,
+    ipv6: Ipv6Format,
+    sort: Option<SortKey>,
+    answers: Vec<QueryAnswer>,
+}
+
+impl JsonSink {
+    pub fn new(out: Box<dyn Write>, ipv6: Ipv6Format) -> JsonSink {
+        JsonSink {
+            out,
+            ipv6,
+            sort: None,
+            answers: Vec::new(),
+        }
+    }
+
+    pub fn with_sort(mut self, key: SortKey) -> JsonSink {
+        self.sort = Some(key);
+        self
+    }
+}
+
+impl OutputSink for JsonSink {
+    fn query(&mut self, result: &QueryResult) -> Result<(), ClientError> {
+        self.answers.extend(result.answers.iter().cloned());
+        Ok(())
+    }
+
+    fn finish(&mut self, summary: Option<&Summary>) -> Result<(), ClientError> {
+        let answers = sorted(&self.answers, self.sort);
+        let json = output::answers_json(&answers, &self.ipv6, summary);
+        writeln!(self.out, "{}", json).map_err(write_error)
+    }
+}
+
+/// An answer per line as a JSON object, JSON Lines, written as queries
+/// complete
+pub struct NdjsonSink {
+    out: Box<dyn Write>,
+    ipv6: Ipv6Format,
+}
+
+impl NdjsonSink {
+    pub fn new(out: Box<dyn Write>, ipv6: Ipv6Format) -> NdjsonSink {
+        NdjsonSink { out, ipv6 }
+    }
+}
+
+impl OutputSink for NdjsonSink {
+    fn query(&mut self, result: &QueryResult) -> Result<(), ClientError> {
+        for answer in &result.answers {
+            let line = output::answer_json_line(answer, &self.ipv6);
+            writeln!(self.out, "{}", line).map_err(write_error)?;
+        }
+        Ok(())
+    }
+}
+
+/// Answers as comma separated values under a header, written as queries
+/// complete, RFC 4180
+pub struct CsvSink {
+    out: Box<dyn Write>,
+    ipv6: Ipv6Format,
+    started: bool,
+}
+
+impl CsvSink {
+    pub fn new(out: Box<dyn Write>, ipv6: Ipv6Format) -> CsvSink {
+        CsvSink {
+            out,
+            ipv6,
+            started: false,
+        }
+    }
+}
+
+impl OutputSink for CsvSink {
+    fn query(&mut self, result: &QueryResult) -> Result<(), ClientError> {
+        if !self.started {
+            writeln!(self.out, "{}", output::CSV_HEADER).map_err(write_error)?;
+            self.started = true;
+        }
+        for answer in &result.answers {
+            let line = output::answer_csv_line(answer, &self.ipv6);
+            writeln!(self.out, "{}", line).map_err(write_error)?;
+        }
+        Ok(())
+    }
+}
+
+/// What the line of an answer shows
+enum Line {
+    Data,
+    Debug,
+    Zone,
+}
+
+/// A line per answer, its data like `dig +short`, its debug representation
+/// or its record as in a zone file, written as queries complete unless they
+/// are to be sorted
+pub struct LinesSink {
+    out: Box<dyn Write>,
+    ipv6: Ipv6Format,
+    line: Line,
+    sort: Option<SortKey>,
+    answers: Vec<QueryAnswer>,
+}
+
+impl LinesSink {
+    pub fn short(out: Box<dyn Write>, ipv6: Ipv6Format) -> LinesSink {
+        LinesSink {
+            out,
+            ipv6,
+            line: Line::Data,
+            sort: None,
+            answers: Vec::new(),
+        }
+    }
+
+    pub fn debug(out: Box<dyn Write>) -> LinesSink {
+        LinesSink {
+            line: Line::Debug,
+            ..LinesSink::short(out, Ipv6Format::default())
+        }
+    }
+
+    /// Records in the presentation format of zone files, which other tools
+    /// and `ResourceRecord::from_str` read back
+    pub fn zone(out: Box<dyn Write>) -> LinesSink {
+        LinesSink {
+            line: Line::Zone,
+            ..LinesSink::short(out, Ipv6Format::default())
+        }
+    }
+
+    pub fn with_sort(mut self, key: SortKey) -> LinesSink {
+        self.sort = Some(key);
+        self
+    }
+
+    /// Answers that don't exist have no data to show, like with `dig +short`
+    fn write(&mut self, answers: &[QueryAnswer]) -> Result<(), ClientError> {
+        for answer in answers {
+            let line = match self.line {
+                Line::Debug => std::format!("{:?}", answer),
+                Line::Zone => answer.record().to_string(),
+                Line::Data if answer.negative().is_some() => continue,
+                Line::Data => self.ipv6.data(answer.data()),
+            };
+            writeln!(self.out, "{}", line).map_err(write_error)?;
+        }
+        Ok(())
+    }
+}
+
+impl OutputSink for LinesSink {
+    fn query(&mut self, result: &QueryResult) -> Result<(), ClientError> {
+        match self.sort {
+            Some(_) => {
+                self.answers.extend(result.answers.iter().cloned());
+                Ok(())
+            }
+            None => self.write(&result.answers),
+        }
+    }
+
+    fn finish(&mut self, _: Option<&Summary>) -> Result<(), ClientError> {
+        let answers = sorted(&self.answers, self.sort);
+        self.write(&answers)
+    }
+}
+
+/// Whole responses like dig shows them, or in its exact layout with `compat`
+pub struct DigSink {
+    out: Box<dyn Write>,
+    ipv6: Ipv6Format,
+    compat: bool,
+    started: bool,
+}
+
+impl DigSink {
+    pub fn new(out: Box<dyn Write>, ipv6: Ipv6Format, compat: bool) -> DigSink {
+        DigSink {
+            out,
+            ipv6,
+            compat,
+            started: false,
+        }
+    }
+}
+
+impl OutputSink for DigSink {
+    fn query(&mut self, result: &QueryResult) -> Result<(), ClientError> {
+        let Some(response) = &result.response else {
+            return Ok(());
+        };
+        let rendered = match self.compat {
+            true => output::dig_compat(response, &self.ipv6),
+            // responses are set apart by a blank line, which compat has
+            false if self.started => std::format!("\n{}", output::dig(response, &self.ipv6)),
+            false => output::dig(response, &self.ipv6),
+        };
+        self.started = true;
+        self.out.write_all(rendered.as_bytes()).map_err(write_error)
+    }
+}
+
+/// Whole responses with every field in long form, set apart by a blank line
+pub struct VerboseSink {
+    out: Box<dyn Write>,
+    ipv6: Ipv6Format,
+    started: bool,
+}
+
+impl VerboseSink {
+    pub fn new(out: Box<dyn Write>, ipv6: Ipv6Format) -> VerboseSink {
+        VerboseSink {
+            out,
+            ipv6,
+            started: false,
+        }
+    }
+}
+
+impl OutputSink for VerboseSink {
+    fn query(&mut self, result: &QueryResult) -> Result<(), ClientError> {
+        let Some(response) = &result.response else {
+            return Ok(());
+        };
+        if self.started {
+            writeln!(self.out).map_err(write_error)?;
+        }
+        self.started = true;
+        let rendered = output::verbose(response, &self.ipv6);
+        self.out.write_all(rendered.as_bytes()).map_err(write_error)
+    }
+}
+
+/// Every query with its answers, errors and timings appended to a database
+/// once the run is over
+#[cfg(feature = "diagnostics")]
+pub struct SqliteSink {
+    export: Export,
+    servers: String,
+    started: DateTime<Utc>,
+    results: Vec<QueryResult>,
+}
+
+#[cfg(feature = "diagnostics")]
+impl SqliteSink {
+    /// Sink of a run against `servers` started at `started`
+    pub fn new(export: Export, servers: &str, started: DateTime<Utc>) -> SqliteSink {
+        SqliteSink {
+            export,
+            servers: servers.to_string(),
+            started,
+            results: Vec::new(),
+        }
+    }
+}
+
+#[cfg(feature = "diagnostics")]
+impl OutputSink for SqliteSink {
+    fn query(&mut self, result: &QueryResult) -> Result<(), ClientError> {
+        self.results.push(result.clone());
+        Ok(())
+    }
+
+    fn finish(&mut self, _: Option<&Summary>) -> Result<(), ClientError> {
+        self.export
+            .write(self.started, &self.servers, &self.results)
+    }
+}
+
+/// Passes on the results with only the answers every filter matches, to any
+/// other sink
+pub struct FilterSink {
+    sink: Box<dyn OutputSink>,
+    filters: Vec<Filter>,
+}
+
+impl FilterSink {
+    pub fn new(sink: Box<dyn OutputSink>, filters: Vec<Filter>) -> FilterSink {
+        FilterSink { sink, filters }
+    }
+}
+
+impl OutputSink for FilterSink {
+    fn query(&mut self, result: &QueryResult) -> Result<(), ClientError> {
+        let mut result = result.clone();
+        result
+            .answers
+            .retain(|answer| self.filters.iter().all(|filter| filter.matches(answer)));
+        self.sink.query(&result)
+    }
+
+    fn finish(&mut self, summary: Option<&Summary>) -> Result<(), ClientError> {
+        self.sink.finish(summary)
+    }
+}
@@ -0,0 +1,185 @@
+//! Where the data nsq usually takes from the system comes from: the CA roots
+//! of DNS over HTTPS, the root hints of traces and the resolvers of
+//! /etc/resolv.conf. Each is read from the system or embedded in the binary,
+//! the first source in order that has it winning, so a static binary works
+//! in a scratch container without /etc/ssl or /etc/resolv.conf.
+use crate::client::ClientError;
+use crate::nsconfig;
+use crate::roots::{self, RootServer};
+use std::fmt;
+
+/// CA bundles of the common distributions, after the one of $SSL_CERT_FILE
+pub const CA_BUNDLES: [&str; 4] = [
+    "/etc/ssl/certs/ca-certificates.crt",
+    "/etc/pki/tls/certs/ca-bundle.crt",
+    "/etc/ssl/ca-bundle.pem",
+    "/etc/ssl/cert.pem",
+];
+
+/// Resolver queries go to when neither /etc/resolv.conf nor the command line
+/// gives one
+pub const EMBEDDED_RESOLVER: &str = "8.8.8.8";
+
+/// Where a piece of data comes from
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum DataSource {
+    /// Files of the system: CA bundles, named.root and /etc/resolv.conf
+    System,
+    /// What was built into the binary
+    Embedded,
+}
+
+impl fmt::Display for DataSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DataSource::System => write!(f, "system"),
+            DataSource::Embedded => write!(f, "embedded"),
+        }
+    }
+}
+
+/// Sources tried in order, the system then the binary unless set
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sources(Vec<DataSource>);
+
+impl Default for Sources {
+    fn default() -> Sources {
+        Sources(vec![DataSource::System, DataSource::Embedded])
+    }
+}
+
+impl Sources {
+    /// `sources` in order, duplicates dropped, the default order when empty
+    pub fn new(sources: &[DataSource]) -> Sources {
+        let mut order = Vec::with_capacity(sources.len());
+        for source in sources {
+            if !order.contains(source) {
+                order.push(*source);
+            }
+        }
+        match order.is_empty() {
+            true => Sources::default(),
+            false => Sources(order),
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &DataSource> {
+        self.0.iter()
+    }
+
+    /// The sources as a list, e.g. `system, embedded`
+    pub fn names(&self) -> String {
+        let names: Vec<String> = self.0.iter().map(|source| source.to_string()).collect();
+        names.join(", ")
+    }
+
+    /// The CA certificates of the first CA bundle of the system that has any
+    #[cfg(any(feature = "encrypted-transports", feature = "diagnostics"))]
+    fn system_ca_roots() -> Option<Vec<reqwest::Certificate>> {
+        let from_env = std::env::var("SSL_CERT_FILE").ok();
+        let bundles = from_env.iter().map(String::as_str).chain(CA_BUNDLES);
+        for bundle in bundles {
+            let Ok(pem) = std::fs::read(bundle) else {
+                continue;
+            };
+            match reqwest::Certificate::from_pem_bundle(&pem) {
+                Ok(certs) if !certs.is_empty() => {
+                    log::debug!("Trusting the {} CA roots of {}", certs.len(), bundle);
+                    return Some(certs);
+                }
+                Ok(_) => log::info!("{} has no CA certificate", bundle),
+                Err(err) => log::info!("Ignoring {}: {}", bundle, err),
+            }
+        }
+        None
+    }
+
+    /// `builder` trusting the CA roots of the first source that has any
+    #[cfg(any(feature = "encrypted-transports", feature = "diagnostics"))]
+    pub fn with_ca_roots(
+        &self,
+        mut builder: reqwest::ClientBuilder,
+    ) -> Result<reqwest::ClientBuilder, ClientError> {
+        #[cfg(feature = "embedded-ca-roots")]
+        {
+            builder = builder.tls_built_in_webpki_certs(false);
+        }
+        for source in self.iter() {
+            match source {
+                DataSource::System => {
+                    if let Some(certs) = Sources::system_ca_roots() {
+                        for cert in certs {
+                            builder = builder.add_root_certificate(cert);
+                        }
+                        return Ok(builder);
+                    }
+                }
+                #[cfg(feature = "embedded-ca-roots")]
+                DataSource::Embedded => {
+                    log::debug!("Trusting the embedded CA roots");
+                    return Ok(builder.tls_built_in_webpki_certs(true));
+                }
+                #[cfg(not(feature = "embedded-ca-roots"))]
+                DataSource::Embedded => {
+                    log::debug!("Built without the embedded-ca-roots feature");
+                }
+            }
+        }
+        Err(ClientError::GenericError(std::format!(
+            "No CA roots from the {} sources, set SSL_CERT_FILE to a CA bundle",
+            self.names()
+        )))
+    }
+
+    /// Root servers of the first root hints file of the system that reads,
+    /// or of the embedded root hints
+    pub fn root_hints(&self) -> Result<Vec<RootServer>, ClientError> {
+        for source in self.iter() {
+            match source {
+                DataSource::System => {
+                    for file in roots::ROOT_HINTS_FILES {
+                        if !std::path::Path::new(file).exists() {
+                            continue;
+                        }
+                        match roots::read_root_hints(file) {
+                            Ok(servers) => {
+                                log::debug!("Root hints of {}", file);
+                                return Ok(servers);
+                            }
+                            Err(err) => log::info!("Ignoring {}", err),
+                        }
+                    }
+                }
+                #[cfg(feature = "embedded-root-hints")]
+                DataSource::Embedded => return Ok(roots::root_hints()),
+                #[cfg(not(feature = "embedded-root-hints"))]
+                DataSource::Embedded => {
+                    log::debug!("Built without the embedded-root-hints feature");
+                }
+            }
+        }
+        Err(ClientError::GenericError(std::format!(
+            "No root hints from the {} sources, give a file with --root-hints",
+            self.names()
+        )))
+    }
+
+    /// The nameservers of `resolv_conf` when it has any, or the embedded
+    /// resolver
+    pub fn nameservers(&self, resolv_conf: &str) -> Result<Vec<String>, ClientError> {
+        for source in self.iter() {
+            match source {
+                DataSource::System => match nsconfig::read_nameservers(resolv_conf.to_string()) {
+                    Ok(servers) if !servers.is_empty() => return Ok(servers),
+                    Ok(_) => log::info!("{} has no nameserver", resolv_conf),
+                    Err(err) => log::info!("Failed to read {}: {}", resolv_conf, err),
+                },
+                DataSource::Embedded => return Ok(vec![EMBEDDED_RESOLVER.to_string()]),
+            }
+        }
+        Err(ClientError::GenericError(std::format!(
+            "No nameserver from the {} sources, give one with --server",
+            self.names()
+        )))
+    }
+}
@@ -0,0 +1,122 @@
+//! Running `nsq serve` as a systemd service: the sockets of a socket unit
+//! passed to the process, sd_listen_fds(3), so it answers on port 53 without
+//! root, and its readiness told to the service manager, sd_notify(3).
+use crate::client::ClientError;
+use std::net::{TcpListener, UdpSocket};
+
+/// First file descriptor passed by socket activation, after stdin, stdout
+/// and stderr
+#[cfg(target_os = "linux")]
+const LISTEN_FDS_START: i32 = 3;
+
+/// The UDP socket and the TCP listener passed by systemd, none when the
+/// process wasn't socket activated
+#[cfg(target_os = "linux")]
+pub fn listen_fds() -> Result<Option<(UdpSocket, TcpListener)>, ClientError> {
+    use std::os::fd::FromRawFd;
+
+    let ours = std::env::var("LISTEN_PID")
+        .ok()
+        .and_then(|pid| pid.parse::<u32>().ok())
+        .is_some_and(|pid| pid == std::process::id());
+    let count = match std::env::var("LISTEN_FDS").map(|count| count.parse::<i32>()) {
+        Ok(Ok(count)) if ours && count > 0 => count,
+        _ => return Ok(None),
+    };
+    let invalid = |message: String| ClientError::BindError(message);
+    let (mut udp, mut tcp) = (None, None);
+    for fd in LISTEN_FDS_START..LISTEN_FDS_START + count {
+        let mut kind: libc::c_int = 0;
+        let mut len = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+        let found = unsafe {
+            libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC);
+            libc::getsockopt(
+                fd,
+                libc::SOL_SOCKET,
+                libc::SO_TYPE,
+                &mut kind as *mut libc::c_int as *mut libc::c_void,
+                &mut len,
+            )
+        };
+        if found != 0 {
+            let err = std::io::Error::last_os_error();
+            return Err(invalid(std::format!("passed fd {}: {}", fd, err)));
+        }
+        // the descriptors are the process's own from here on
+        match kind {
+            libc::SOCK_DGRAM if udp.is_none() => udp = Some(unsafe { UdpSocket::from_raw_fd(fd) }),
+            libc::SOCK_STREAM if tcp.is_none() => {
+                tcp = Some(unsafe { TcpListener::from_raw_fd(fd) })
+            }
+            _ => {
+                return Err(invalid(std::format!(
+                    "passed fd {} isn't the one UDP socket or TCP listener expected",
+                    fd
+                )))
+            }
+        }
+    }
+    match (udp, tcp) {
+        (Some(udp), Some(tcp)) => Ok(Some((udp, tcp))),
+        _ => Err(invalid(
+            "socket activation needs both a ListenDatagram and a ListenStream socket".to_string(),
+        )),
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn listen_fds() -> Result<Option<(UdpSocket, TcpListener)>, ClientError> {
+    Ok(None)
+}
+
+/// Tells the service manager about the state of the process, like
+/// `READY=1`, when it started it with a notify socket
+#[cfg(target_os = "linux")]
+pub fn notify(state: &str) {
+    use std::os::linux::net::SocketAddrExt;
+    use std::os::unix::net::{SocketAddr, UnixDatagram};
+
+    let Some(path) = std::env::var_os("NOTIFY_SOCKET") else {
+        return;
+    };
+    let path = path.to_string_lossy();
+    // a leading @ stands for the abstract namespace
+    let addr = match path.strip_prefix('@') {
+        Some(name) => SocketAddr::from_abstract_name(name),
+        None => SocketAddr::from_pathname(path.as_ref()),
+    };
+    let sent = UnixDatagram::unbound()
+        .and_then(|socket| addr.and_then(|addr| socket.send_to_addr(state.as_bytes(), &addr)));
+    if let Err(err) = sent {
+        log::warn!("Failed to notify systemd of {}: {}", state, err);
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn notify(state: &str) {
+    let _ = state;
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+    use std::os::unix::net::UnixDatagram;
+
+    #[test]
+    fn readiness_is_sent_to_the_notify_socket() {
+        let path = std::env::temp_dir().join(std::format!("nsq-notify-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let manager = UnixDatagram::bind(&path).unwrap();
+        std::env::set_var("NOTIFY_SOCKET", &path);
+        notify("READY=1");
+        let mut buf = [0; 64];
+        let len = manager.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"READY=1");
+        std::fs::remove_file(&path).unwrap();
+
+        // sockets passed to another process, like the parent of this one
+        std::env::set_var("LISTEN_PID", "1");
+        std::env::set_var("LISTEN_FDS", "2");
+        assert!(listen_fds().unwrap().is_none());
+    }
+}
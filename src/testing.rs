@@ -0,0 +1,140 @@
+//! A DNS server on a loopback UDP port for the tests of the client, answering
+//! queries as each test says.
+use crate::client::{ClassType, QueryType};
+#[cfg(feature = "cache")]
+use crate::wire;
+use crate::wire::{Message, MessageBuilder};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use tokio::net::UdpSocket;
+
+/// Datagrams sent back for a query, none to leave it unanswered
+type Answer = dyn Fn(&Message) -> Vec<Vec<u8>> + Send + Sync;
+
+/// Answers the queries it receives on 127.0.0.1, keeping them
+pub(crate) struct Responder {
+    addr: SocketAddr,
+    queries: Arc<Mutex<Vec<Message>>>,
+}
+
+impl Responder {
+    /// Answers each query with `answer` once `batch` of them arrived, the
+    /// last one first, so responses come back out of order
+    pub(crate) async fn start(
+        batch: usize,
+        answer: impl Fn(&Message) -> Vec<Vec<u8>> + Send + Sync + 'static,
+    ) -> Responder {
+        Responder::start_on("127.0.0.1:0", batch, answer).await
+    }
+
+    /// Answers each query with `answer` on `addr`, for tests needing servers
+    /// on several loopback addresses and the same port
+    #[cfg(feature = "diagnostics")]
+    pub(crate) async fn answering_on(
+        addr: &str,
+        answer: impl Fn(&Message) -> Vec<Vec<u8>> + Send + Sync + 'static,
+    ) -> Responder {
+        Responder::start_on(addr, 1, answer).await
+    }
+
+    async fn start_on(
+        addr: &str,
+        batch: usize,
+        answer: impl Fn(&Message) -> Vec<Vec<u8>> + Send + Sync + 'static,
+    ) -> Responder {
+        let socket = UdpSocket::bind(addr).await.unwrap();
+        let addr = socket.local_addr().unwrap();
+        let queries = Arc::new(Mutex::new(Vec::new()));
+        let received = queries.clone();
+        let answer: Box<Answer> = Box::new(answer);
+        tokio::spawn(async move {
+            let mut buf = [0; 65535];
+            let mut pending = Vec::new();
+            loop {
+                let Ok((len, peer)) = socket.recv_from(&mut buf).await else {
+                    return;
+                };
+                let Ok(query) = Message::decode(&buf[..len]) else {
+                    continue;
+                };
+                received.lock().unwrap().push(query.clone());
+                pending.push((query, peer));
+                if pending.len() < batch {
+                    continue;
+                }
+                for (query, peer) in pending.drain(..).rev() {
+                    for datagram in answer(&query) {
+                        socket.send_to(&datagram, peer).await.ok();
+                    }
+                }
+            }
+        });
+        Responder { addr, queries }
+    }
+
+    /// Answers each query with `answer` as it arrives
+    pub(crate) async fn answering(
+        answer: impl Fn(&Message) -> Vec<Vec<u8>> + Send + Sync + 'static,
+    ) -> Responder {
+        Responder::start(1, answer).await
+    }
+
+    pub(crate) fn addr(&self) -> String {
+        self.addr.to_string()
+    }
+
+    /// The queries received so far
+    pub(crate) fn queries(&self) -> Vec<Message> {
+        self.queries.lock().unwrap().clone()
+    }
+}
+
+/// Response to `query` with `rcode` and `records` of its name, each a type,
+/// TTL and RDATA in wire format
+pub(crate) fn reply(query: &Message, rcode: u8, records: &[(QueryType, u32, Vec<u8>)]) -> Vec<u8> {
+    let name = query.questions[0].name.clone();
+    let mut response = MessageBuilder::reply(query, rcode);
+    for (query_type, ttl, rdata) in records {
+        response = response.with_answer(
+            &name,
+            query_type.clone(),
+            ClassType::IN,
+            *ttl,
+            rdata.clone(),
+        );
+    }
+    response.build()
+}
+
+/// Response to `query` with an address of the type it asked for, 192.0.2.1
+/// or 2001:db8::1, and none for other types
+pub(crate) fn address(query: &Message) -> Vec<u8> {
+    let rdata = match query.questions[0].query_type {
+        QueryType::A => vec![192, 0, 2, 1],
+        QueryType::AAAA => [0x20, 0x01, 0x0d, 0xb8]
+            .into_iter()
+            .chain([0; 11])
+            .chain([1])
+            .collect(),
+        _ => return reply(query, 0, &[]),
+    };
+    let query_type = query.questions[0].query_type.clone();
+    reply(query, 0, &[(query_type, 300, rdata)])
+}
+
+/// Negative response to `query`, NXDOMAIN or NODATA as `rcode` says, with
+/// an SOA record of `ttl` and `minimum` in its authority section, RFC 2308
+#[cfg(feature = "cache")]
+pub(crate) fn negative(query: &Message, rcode: u8, ttl: u32, minimum: u32) -> Vec<u8> {
+    let mut soa = [
+        wire::encode_name("ns.example."),
+        wire::encode_name("admin.example."),
+    ]
+    .concat();
+    for field in [1, 7200, 3600, 1209600, minimum] {
+        soa.extend(u32::to_be_bytes(field));
+    }
+    MessageBuilder::reply(query, rcode)
+        .with_authority("example.", QueryType::SOA, ClassType::IN, ttl, soa)
+        .build()
+}
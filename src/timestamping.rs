@@ -0,0 +1,132 @@
+//! Kernel packet timestamps of UDP sockets, SO_TIMESTAMPING in the Linux
+//! kernel's Documentation/networking/timestamping.rst, to measure the round
+//! trip of a query on the wire, without the time spent scheduling the process.
+use std::io;
+use std::mem;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6};
+use std::os::fd::AsRawFd;
+use std::ptr;
+use std::time::Duration;
+use tokio::io::Interest;
+use tokio::net::UdpSocket;
+
+/// Asks for software timestamps of the datagrams sent and received on
+/// `socket`, without looping sent payloads back to the error queue
+pub fn enable(socket: &UdpSocket) -> io::Result<()> {
+    let flags: libc::c_uint = libc::SOF_TIMESTAMPING_TX_SOFTWARE
+        | libc::SOF_TIMESTAMPING_RX_SOFTWARE
+        | libc::SOF_TIMESTAMPING_SOFTWARE
+        | libc::SOF_TIMESTAMPING_OPT_TSONLY;
+    let ret = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_TIMESTAMPING,
+            &flags as *const libc::c_uint as *const libc::c_void,
+            mem::size_of::<libc::c_uint>() as libc::socklen_t,
+        )
+    };
+    if ret != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Software timestamp of a SCM_TIMESTAMPING control message, since the epoch
+fn timestamp(msg: &libc::msghdr) -> Option<Duration> {
+    unsafe {
+        let mut cmsg = libc::CMSG_FIRSTHDR(msg);
+        while !cmsg.is_null() {
+            if (*cmsg).cmsg_level == libc::SOL_SOCKET && (*cmsg).cmsg_type == libc::SCM_TIMESTAMPING
+            {
+                let ts: [libc::timespec; 3] =
+                    ptr::read_unaligned(libc::CMSG_DATA(cmsg) as *const [libc::timespec; 3]);
+                return Some(Duration::new(ts[0].tv_sec as u64, ts[0].tv_nsec as u32));
+            }
+            cmsg = libc::CMSG_NXTHDR(msg, cmsg);
+        }
+    }
+    None
+}
+
+/// Address of the peer of a datagram, as recvmsg(2) fills it in
+fn peer(name: &libc::sockaddr_storage) -> Option<SocketAddr> {
+    match name.ss_family as libc::c_int {
+        libc::AF_INET => {
+            let addr = unsafe { &*(name as *const _ as *const libc::sockaddr_in) };
+            Some(SocketAddr::V4(SocketAddrV4::new(
+                Ipv4Addr::from(u32::from_be(addr.sin_addr.s_addr)),
+                u16::from_be(addr.sin_port),
+            )))
+        }
+        libc::AF_INET6 => {
+            let addr = unsafe { &*(name as *const _ as *const libc::sockaddr_in6) };
+            Some(SocketAddr::V6(SocketAddrV6::new(
+                Ipv6Addr::from(addr.sin6_addr.s6_addr),
+                u16::from_be(addr.sin6_port),
+                addr.sin6_flowinfo,
+                addr.sin6_scope_id,
+            )))
+        }
+        _ => None,
+    }
+}
+
+/// A datagram of `socket`, or an entry of its error queue with MSG_ERRQUEUE,
+/// with the address it came from and its timestamp. Fails with WouldBlock
+/// when there is none.
+fn recvmsg(
+    socket: &UdpSocket,
+    buf: &mut [u8],
+    flags: libc::c_int,
+) -> io::Result<(usize, Option<SocketAddr>, Option<Duration>)> {
+    let mut control = [0u8; 128];
+    let mut iov = libc::iovec {
+        iov_base: buf.as_mut_ptr() as *mut libc::c_void,
+        iov_len: buf.len(),
+    };
+    let mut name: libc::sockaddr_storage = unsafe { mem::zeroed() };
+    let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+    msg.msg_name = &mut name as *mut libc::sockaddr_storage as *mut libc::c_void;
+    msg.msg_namelen = mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+    msg.msg_controllen = control.len() as _;
+    let len = unsafe { libc::recvmsg(socket.as_raw_fd(), &mut msg, flags) };
+    if len < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok((len as usize, peer(&name), timestamp(&msg)))
+}
+
+/// Waits for a datagram, returning its length, the address it came from and
+/// when the kernel received it
+pub async fn recv(
+    socket: &UdpSocket,
+    buf: &mut [u8],
+) -> io::Result<(usize, SocketAddr, Option<Duration>)> {
+    let (len, from, received) = socket
+        .async_io(Interest::READABLE, || recvmsg(socket, buf, 0))
+        .await?;
+    let from = from.ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            "datagram without a source address",
+        )
+    })?;
+    Ok((len, from, received))
+}
+
+/// When the kernel sent the last datagram of `socket`, draining the send
+/// timestamps queued so far. Queries sent concurrently on the same socket
+/// make it ambiguous, so only sequential queries get an exact wire time.
+pub fn sent_at(socket: &UdpSocket) -> Option<Duration> {
+    let mut buf = [0u8; 1];
+    let mut last = None;
+    while let Ok((_, _, sent)) = recvmsg(socket, &mut buf, libc::MSG_ERRQUEUE | libc::MSG_DONTWAIT)
+    {
+        last = sent.or(last);
+    }
+    last
+}
@@ -0,0 +1,390 @@
+//! Iterative resolution: queries start at the root servers and follow the
+//! referrals of each zone down to the servers authoritative for the name,
+//! RFC 1034 5.3.3, like `dig +trace`.
+use crate::budget::QueryBudget;
+use crate::client::{ClassType, Client, ClientError, QueryType, Response};
+use crate::idn;
+use crate::rdata::{self, RData};
+use crate::roots::RootServer;
+use crate::wire::Record;
+use futures::future::{BoxFuture, FutureExt};
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+
+/// Referrals followed before giving up, deep enough for any real delegation
+const MAX_REFERRALS: usize = 16;
+
+/// Nameservers without glue resolved on the way, each a trace of its own
+const MAX_DEPTH: usize = 4;
+
+/// A query sent to one of the servers of `zone` and its response
+#[derive(Debug, Clone)]
+pub struct Step {
+    pub zone: String,
+    pub server: String,
+    pub addr: IpAddr,
+    pub response: Response,
+}
+
+impl Step {
+    /// Records the step brought: the answers, or else the referral or SOA
+    /// record of the authority section
+    pub(crate) fn records(&self) -> &[Record] {
+        let message = &self.response.message;
+        match message.answers.is_empty() {
+            true => &message.authorities,
+            false => &message.answers,
+        }
+    }
+}
+
+/// A nameserver of a zone, with the addresses known for it
+#[derive(Debug, Clone)]
+struct Nameserver {
+    name: String,
+    addrs: Vec<IpAddr>,
+}
+
+/// Lowercased without the trailing dot, so names compare as the DNS does
+fn normalize(name: &str) -> String {
+    name.trim_end_matches('.').to_lowercase()
+}
+
+/// Whether `name` is `zone` or a name below it
+fn is_within(name: &str, zone: &str) -> bool {
+    zone.is_empty() || name == zone || name.ends_with(&std::format!(".{}", zone))
+}
+
+/// The zone a response delegates to and its nameservers, when the response
+/// is a referral to a zone closer to `host` than `zone`, RFC 1034 4.3.2
+fn referral(response: &Response, host: &str, zone: &str) -> Option<(String, Vec<Nameserver>)> {
+    let message = &response.message;
+    if !message.answers.is_empty() || message.header.rcode() != 0 {
+        return None;
+    }
+    let mut child: Option<String> = None;
+    let mut nameservers: Vec<Nameserver> = Vec::new();
+    for record in &message.authorities {
+        if record.query_type != QueryType::NS {
+            continue;
+        }
+        let owner = normalize(&record.name);
+        if owner.len() <= zone.len() || !is_within(&owner, zone) || !is_within(host, &owner) {
+            continue;
+        }
+        if child.get_or_insert(owner.clone()) != &owner {
+            continue;
+        }
+        if let Some(RData::NS(name)) = rdata::decode(&record.query_type, &record.rdata) {
+            nameservers.push(Nameserver {
+                name: normalize(&name),
+                addrs: Vec::new(),
+            });
+        }
+    }
+    let child = child?;
+    // glue outside the delegated zone isn't the parent's to give, it could
+    // poison the addresses of any name, RFC 2181 5.4.1
+    for nameserver in nameservers.iter_mut() {
+        if is_within(&nameserver.name, &child) {
+            nameserver.addrs = glue(&message.additionals, &nameserver.name);
+        }
+    }
+    Some((child, nameservers))
+}
+
+/// IPv4 then IPv6 addresses of `name` among `records`
+fn glue(records: &[Record], name: &str) -> Vec<IpAddr> {
+    let mut addrs: Vec<IpAddr> = records
+        .iter()
+        .filter(|record| normalize(&record.name) == name)
+        .filter_map(
+            |record| match rdata::decode(&record.query_type, &record.rdata) {
+                Some(RData::A(addr)) => Some(IpAddr::V4(addr)),
+                Some(RData::AAAA(addr)) => Some(IpAddr::V6(addr)),
+                _ => None,
+            },
+        )
+        .collect();
+    addrs.sort_by_key(|addr| addr.is_ipv6());
+    addrs
+}
+
+/// Resolves names iteratively from a set of root servers
+pub struct Tracer {
+    roots: Vec<RootServer>,
+    timeout: Duration,
+    class: ClassType,
+    budget: QueryBudget,
+    port: u16,
+}
+
+impl Tracer {
+    pub fn new(roots: Vec<RootServer>) -> Tracer {
+        Tracer {
+            roots,
+            timeout: Duration::from_secs(3),
+            class: ClassType::IN,
+            budget: QueryBudget::default(),
+            port: 53,
+        }
+    }
+
+    /// Gives up on a server once `timeout` passed without a response
+    pub fn with_timeout(mut self, timeout: Duration) -> Tracer {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Queries `class` instead of IN
+    pub fn with_class(mut self, class: ClassType) -> Tracer {
+        self.class = class;
+        self
+    }
+
+    /// Sends queries to `port` of every server rather than 53, for lab
+    /// hierarchies served on another port
+    pub fn with_port(mut self, port: u16) -> Tracer {
+        self.port = port;
+        self
+    }
+
+    /// Draws every query, glueless nameserver lookups included, from `budget`
+    pub fn with_budget(mut self, budget: QueryBudget) -> Tracer {
+        self.budget = budget;
+        self
+    }
+
+    /// Each delegation step from the root down to the response of a server
+    /// authoritative for `host`
+    pub async fn trace(&self, host: &str, query_type: QueryType) -> Result<Vec<Step>, ClientError> {
+        self.resolve(idn::to_ascii(host)?, query_type, 0).await
+    }
+
+    fn resolve(
+        &self,
+        host: String,
+        query_type: QueryType,
+        depth: usize,
+    ) -> BoxFuture<'_, Result<Vec<Step>, ClientError>> {
+        async move {
+            let target = normalize(&host);
+            let mut zone = String::new();
+            let mut nameservers: Vec<Nameserver> = self
+                .roots
+                .iter()
+                .map(|root| Nameserver {
+                    name: normalize(&root.name),
+                    addrs: root.addrs.clone(),
+                })
+                .collect();
+            let mut steps: Vec<Step> = Vec::new();
+            for _ in 0..MAX_REFERRALS {
+                let step = self
+                    .ask(&zone, &nameservers, &host, &query_type, depth)
+                    .await?;
+                let delegation = referral(&step.response, &target, &zone);
+                steps.push(step);
+                match delegation {
+                    Some((child, servers)) if !servers.is_empty() => {
+                        let names: Vec<&str> = servers.iter().map(|ns| ns.name.as_str()).collect();
+                        log::info!("{}. is delegated to {}", child, names.join(", "));
+                        zone = child;
+                        nameservers = servers;
+                    }
+                    _ => return Ok(steps),
+                }
+            }
+            Err(ClientError::GenericError(std::format!(
+                "{} not resolved after {} referrals",
+                host,
+                MAX_REFERRALS
+            )))
+        }
+        .boxed()
+    }
+
+    /// Sends the query to the nameservers of `zone` in turn until one of them
+    /// responds, looking up the addresses of those without glue
+    async fn ask(
+        &self,
+        zone: &str,
+        nameservers: &[Nameserver],
+        host: &str,
+        query_type: &QueryType,
+        depth: usize,
+    ) -> Result<Step, ClientError> {
+        let mut failure: Option<ClientError> = None;
+        for nameserver in nameservers {
+            let mut addrs = nameserver.addrs.clone();
+            if addrs.is_empty() && depth < MAX_DEPTH && !is_within(&nameserver.name, zone) {
+                log::info!("Looking up {} without glue", nameserver.name);
+                match self.addrs(&nameserver.name, depth + 1).await {
+                    Ok(found) => addrs = found,
+                    Err(err) => {
+                        failure.get_or_insert(err);
+                        continue;
+                    }
+                }
+            }
+            for addr in addrs {
+                let exchanged = async {
+                    Client::new(SocketAddr::new(addr, self.port).to_string())
+                        .await?
+                        .without_recursion()
+                        .with_timeout(self.timeout)
+                        .with_attempts(1)
+                        .with_class(self.class)
+                        .with_budget(self.budget.clone())
+                        .exchange(host, query_type.clone())
+                        .await
+                };
+                match exchanged.await {
+                    Ok(response) => {
+                        return Ok(Step {
+                            zone: zone.to_string(),
+                            server: nameserver.name.clone(),
+                            addr,
+                            response,
+                        })
+                    }
+                    Err(ClientError::BudgetExhausted(limit)) => {
+                        return Err(ClientError::BudgetExhausted(limit))
+                    }
+                    Err(err) => {
+                        log::info!("{} ({}) failed: {:?}", nameserver.name, addr, err);
+                        failure.get_or_insert(err);
+                    }
+                }
+            }
+        }
+        Err(failure.unwrap_or_else(|| {
+            ClientError::GenericError(std::format!("No address for the nameservers of {}.", zone))
+        }))
+    }
+
+    /// Addresses of a nameserver given without glue, by tracing it too
+    async fn addrs(&self, name: &str, depth: usize) -> Result<Vec<IpAddr>, ClientError> {
+        let steps = self.resolve(name.to_string(), QueryType::A, depth).await?;
+        let answers = match steps.last() {
+            Some(step) => &step.response.message.answers,
+            None => return Ok(Vec::new()),
+        };
+        Ok(answers
+            .iter()
+            .filter_map(
+                |record| match rdata::decode(&record.query_type, &record.rdata) {
+                    Some(RData::A(addr)) => Some(IpAddr::V4(addr)),
+                    _ => None,
+                },
+            )
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Responder;
+    use crate::wire::{self, Message, MessageBuilder};
+    use std::net::Ipv4Addr;
+
+    /// Referral of `query` to `zone`, served by `nameserver` at `glue`
+    fn delegation(query: &Message, zone: &str, nameserver: &str, glue: Ipv4Addr) -> Vec<Vec<u8>> {
+        let referral = MessageBuilder::reply(query, 0)
+            .with_authority(
+                zone,
+                QueryType::NS,
+                ClassType::IN,
+                3600,
+                wire::encode_name(nameserver),
+            )
+            .with_additional(
+                nameserver,
+                QueryType::A,
+                ClassType::IN,
+                3600,
+                glue.octets().to_vec(),
+            );
+        vec![referral.build()]
+    }
+
+    /// Authoritative answer of `query` with `addr`
+    fn answer(query: &Message, addr: Ipv4Addr) -> Vec<Vec<u8>> {
+        let response = MessageBuilder::reply(query, 0)
+            .with_authoritative()
+            .with_answer(
+                &query.questions[0].name,
+                QueryType::A,
+                ClassType::IN,
+                300,
+                addr.octets().to_vec(),
+            );
+        vec![response.build()]
+    }
+
+    #[tokio::test]
+    async fn glue_outside_the_delegated_zone_is_looked_up() {
+        let root = Responder::answering_on("127.0.0.1:0", |query| {
+            match normalize(&query.questions[0].name).as_str() {
+                "ns.other.net" => answer(query, Ipv4Addr::new(127, 0, 0, 3)),
+                _ => delegation(query, "com.", "a.gtld.com.", Ipv4Addr::new(127, 0, 0, 2)),
+            }
+        })
+        .await;
+        let port = root.addr().rsplit(':').next().unwrap().to_string();
+        // the TLD gives glue for a name of another zone, pointing elsewhere
+        let tld = Responder::answering_on(&std::format!("127.0.0.2:{}", port), |query| {
+            delegation(
+                query,
+                "example.com.",
+                "ns.other.net.",
+                Ipv4Addr::new(127, 0, 0, 4),
+            )
+        })
+        .await;
+        let authoritative = Responder::answering_on(&std::format!("127.0.0.3:{}", port), |query| {
+            answer(query, Ipv4Addr::new(192, 0, 2, 1))
+        })
+        .await;
+        let poisoned = Responder::answering_on(&std::format!("127.0.0.4:{}", port), |query| {
+            answer(query, Ipv4Addr::new(192, 0, 2, 66))
+        })
+        .await;
+
+        let roots = vec![RootServer {
+            name: "a.root-servers.net.".to_string(),
+            addrs: vec![IpAddr::V4(Ipv4Addr::LOCALHOST)],
+        }];
+        let steps = Tracer::new(roots)
+            .with_port(port.parse().unwrap())
+            .with_timeout(Duration::from_secs(1))
+            .trace("www.example.com", QueryType::A)
+            .await
+            .unwrap();
+        let servers: Vec<(&str, &str, String)> = steps
+            .iter()
+            .map(|step| {
+                (
+                    step.zone.as_str(),
+                    step.server.as_str(),
+                    step.addr.to_string(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            servers,
+            [
+                ("", "a.root-servers.net", "127.0.0.1".to_string()),
+                // glue inside the delegated zone is taken
+                ("com", "a.gtld.com", "127.0.0.2".to_string()),
+                ("example.com", "ns.other.net", "127.0.0.3".to_string()),
+            ]
+        );
+        let answers = &steps[2].response.message.answers;
+        assert_eq!(answers[0].rdata, [192, 0, 2, 1]);
+        assert_eq!(tld.queries().len(), 1);
+        assert_eq!(authoritative.queries().len(), 1);
+        assert!(poisoned.queries().is_empty());
+    }
+}
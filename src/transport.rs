@@ -0,0 +1,607 @@
+use crate::client::ClientError;
+#[cfg(feature = "encrypted-transports")]
+use crate::doh::{DohClient, DohMethod};
+#[cfg(feature = "discovery")]
+use crate::mdns::MdnsTransport;
+#[cfg(feature = "encrypted-transports")]
+use crate::sources::Sources;
+#[cfg(target_os = "linux")]
+use crate::timestamping;
+use crate::wire;
+use std::collections::HashMap;
+use std::io;
+#[cfg(feature = "encrypted-transports")]
+use std::net::IpAddr;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpStream, UdpSocket};
+use tokio::sync::{oneshot, Mutex};
+
+/// Carries an encoded DNS message to a server and brings its response back
+pub enum Transport {
+    Udp(UdpTransport),
+    #[cfg(feature = "encrypted-transports")]
+    Https(DohClient),
+    #[cfg(feature = "discovery")]
+    Mdns(MdnsTransport),
+}
+
+/// A response as received, with the round trip measured by the kernel when
+/// the transport timestamps packets
+pub struct Received {
+    pub data: Vec<u8>,
+    pub wire_time: Option<Duration>,
+    /// Datagrams dropped before it as they didn't answer the query
+    pub discarded: usize,
+    /// Time spent opening the connection the query went over, when the
+    /// transport had to open one for it
+    pub setup: Option<Duration>,
+}
+
+/// Local end queries leave from, the wildcard address on any interface
+/// unless set
+#[derive(Debug, Clone, Default)]
+pub struct Source {
+    addr: Option<SocketAddr>,
+    interface: Option<String>,
+    /// VRF device whose routing table queries follow
+    vrf: Option<String>,
+    /// Firewall mark of the packets, for policy routing rules
+    mark: Option<u32>,
+}
+
+impl Source {
+    /// Binds to `addr`, an ephemeral port when its port is 0
+    pub fn with_addr(mut self, addr: SocketAddr) -> Source {
+        self.addr = Some(addr);
+        self
+    }
+
+    /// Sends through `interface` whatever the routes say, such as a VPN
+    /// tunnel, with SO_BINDTODEVICE on Linux
+    pub fn with_interface(mut self, interface: String) -> Source {
+        self.interface = Some(interface);
+        self
+    }
+
+    /// Sends through the routing table of the VRF device `vrf`, binding to
+    /// it with SO_BINDTODEVICE on Linux
+    pub fn with_vrf(mut self, vrf: String) -> Source {
+        self.vrf = Some(vrf);
+        self
+    }
+
+    /// Marks the packets with `mark`, SO_MARK on Linux, for the rules of
+    /// policy routing and firewalls to match
+    pub fn with_mark(mut self, mark: u32) -> Source {
+        self.mark = Some(mark);
+        self
+    }
+
+    /// Local address to bind to for queries to `remote_addr`
+    fn local_addr(&self, remote_addr: &SocketAddr) -> Result<SocketAddr, ClientError> {
+        match self.addr {
+            Some(addr) if addr.is_ipv4() != remote_addr.is_ipv4() => {
+                Err(ClientError::BindError(std::format!(
+                    "source {} can't reach {}, another address family",
+                    addr,
+                    remote_addr
+                )))
+            }
+            Some(addr) => Ok(addr),
+            None if remote_addr.is_ipv4() => Ok((Ipv4Addr::UNSPECIFIED, 0).into()),
+            None => Ok((Ipv6Addr::UNSPECIFIED, 0).into()),
+        }
+    }
+
+    /// Binds `socket` to the interface or VRF device and sets its mark
+    fn bind_device(&self, socket: &UdpSocket) -> Result<(), ClientError> {
+        if let Some(vrf) = &self.vrf {
+            if !is_vrf(vrf) {
+                return Err(ClientError::BindError(std::format!(
+                    "{} isn't a VRF device",
+                    vrf
+                )));
+            }
+        }
+        if let Some(device) = self.interface.as_ref().or(self.vrf.as_ref()) {
+            #[cfg(target_os = "linux")]
+            socket
+                .bind_device(Some(device.as_bytes()))
+                .map_err(|err| ClientError::BindError(std::format!("{}: {}", device, err)))?;
+            #[cfg(not(target_os = "linux"))]
+            return Err(ClientError::BindError(std::format!(
+                "Binding to device {} is only available on Linux",
+                device
+            )));
+        }
+        if let Some(mark) = self.mark {
+            #[cfg(target_os = "linux")]
+            set_mark(socket, mark)
+                .map_err(|err| ClientError::BindError(std::format!("mark {}: {}", mark, err)))?;
+            #[cfg(not(target_os = "linux"))]
+            return Err(ClientError::BindError(std::format!(
+                "Marking packets with {} is only available on Linux",
+                mark
+            )));
+        }
+        #[cfg(not(target_os = "linux"))]
+        let _ = socket;
+        Ok(())
+    }
+}
+
+/// Whether the network device `name` is a VRF, as its uevent tells
+fn is_vrf(name: &str) -> bool {
+    std::fs::read_to_string(std::format!("/sys/class/net/{}/uevent", name))
+        .map(|uevent| uevent.lines().any(|line| line == "DEVTYPE=vrf"))
+        .unwrap_or(false)
+}
+
+/// Sets SO_MARK, which takes CAP_NET_ADMIN
+#[cfg(target_os = "linux")]
+fn set_mark(socket: &UdpSocket, mark: u32) -> io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    let ret = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_MARK,
+            &mark as *const u32 as *const libc::c_void,
+            std::mem::size_of::<u32>() as libc::socklen_t,
+        )
+    };
+    if ret != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// A query sent over UDP and waiting for its response
+struct Waiting {
+    query: Vec<u8>,
+    /// Datagrams dropped since it was sent
+    discarded: usize,
+    /// Taken once its response arrived, later copies being dropped
+    response: Option<oneshot::Sender<Received>>,
+}
+
+/// DNS over a connected UDP socket, queries in flight at once sharing it:
+/// whichever of them reads the socket hands each response to its query
+pub struct UdpTransport {
+    remote_addr: SocketAddr,
+    socket: UdpSocket,
+    max_datagram_size: usize,
+    timestamping: bool,
+    waiting: std::sync::Mutex<HashMap<u64, Waiting>>,
+    next_exchange: AtomicU64,
+    /// Held by the query reading the socket
+    reader: Mutex<()>,
+}
+
+/// Takes a query off the waiting ones when its exchange ends, answered,
+/// failed or cancelled by a timeout
+struct Exchange<'a> {
+    transport: &'a UdpTransport,
+    id: u64,
+}
+
+impl Drop for Exchange<'_> {
+    fn drop(&mut self) {
+        self.transport.waiting().remove(&self.id);
+    }
+}
+
+impl UdpTransport {
+    pub async fn new(remote_addr: String) -> Result<UdpTransport, ClientError> {
+        UdpTransport::new_from(remote_addr, &Source::default()).await
+    }
+
+    /// Socket bound to `source` instead of the wildcard address
+    pub async fn new_from(
+        remote_addr: String,
+        source: &Source,
+    ) -> Result<UdpTransport, ClientError> {
+        let remote_addr: SocketAddr = match remote_addr.parse() {
+            Ok(addr) => addr,
+            Err(err) => return Err(ClientError::ParseError(err.to_string())),
+        };
+        let socket = match UdpSocket::bind(source.local_addr(&remote_addr)?).await {
+            Ok(socket) => socket,
+            Err(err) => return Err(ClientError::BindError(err.to_string())),
+        };
+        source.bind_device(&socket)?;
+        let max_datagram_size: usize = 65_507;
+        match socket.connect(&remote_addr).await {
+            Ok(res) => res,
+            Err(err) => return Err(ClientError::ConnectError(err.to_string())),
+        };
+        Ok(UdpTransport {
+            remote_addr,
+            socket,
+            max_datagram_size,
+            timestamping: false,
+            waiting: std::sync::Mutex::new(HashMap::new()),
+            next_exchange: AtomicU64::new(0),
+            reader: Mutex::new(()),
+        })
+    }
+
+    fn waiting(&self) -> std::sync::MutexGuard<'_, HashMap<u64, Waiting>> {
+        self.waiting.lock().unwrap_or_else(|p| p.into_inner())
+    }
+
+    /// Waits for a datagram, with the address it came from and the time
+    /// between the kernel sending the query and receiving it when
+    /// timestamping
+    async fn recv(&self, data: &mut [u8]) -> io::Result<(usize, SocketAddr, Option<Duration>)> {
+        #[cfg(target_os = "linux")]
+        if self.timestamping {
+            let (len, from, received) = timestamping::recv(&self.socket, data).await?;
+            let wire_time = match (timestamping::sent_at(&self.socket), received) {
+                (Some(sent), Some(received)) => received.checked_sub(sent),
+                _ => None,
+            };
+            return Ok((len, from, wire_time));
+        }
+        let (len, from) = self.socket.recv_from(data).await?;
+        Ok((len, from, None))
+    }
+
+    /// Sends `query` and waits for its response until the caller's timeout.
+    /// The responses of other queries in flight on the socket are handed to
+    /// them, and datagrams from elsewhere or answering none of them, stray or
+    /// spoofed, are dropped.
+    async fn exchange(&self, query: &[u8]) -> Result<Received, ClientError> {
+        let (sender, mut response) = oneshot::channel();
+        let exchange = Exchange {
+            transport: self,
+            id: self.next_exchange.fetch_add(1, Ordering::Relaxed),
+        };
+        self.waiting().insert(
+            exchange.id,
+            Waiting {
+                query: query.to_vec(),
+                discarded: 0,
+                response: Some(sender),
+            },
+        );
+        match self.socket.send(query).await {
+            Ok(_) => (),
+            Err(err) if err.kind() == io::ErrorKind::ConnectionRefused => {
+                return Err(ClientError::NotListening(self.remote_addr.to_string()))
+            }
+            Err(err) => return Err(ClientError::SendError(err.to_string())),
+        };
+        loop {
+            let reader = tokio::select! {
+                biased;
+                Ok(received) = &mut response => return Ok(received),
+                reader = self.reader.lock() => reader,
+            };
+            // answered by the query that read the socket before
+            if let Ok(received) = response.try_recv() {
+                return Ok(received);
+            }
+            self.read().await?;
+            drop(reader);
+        }
+    }
+
+    /// Reads a datagram and hands it to the query it answers
+    async fn read(&self) -> Result<(), ClientError> {
+        let mut data = vec![0u8; self.max_datagram_size];
+        let (len, from, wire_time) = match self.recv(&mut data).await {
+            Ok(recv) => recv,
+            // the ICMP error of a connected socket, reported at once
+            // rather than after the timeout
+            Err(err) if err.kind() == io::ErrorKind::ConnectionRefused => {
+                return Err(ClientError::NotListening(self.remote_addr.to_string()))
+            }
+            Err(err) => return Err(ClientError::RecvError(err.to_string())),
+        };
+        data.truncate(len);
+        let mut waiting = self.waiting();
+        let reason = if (from.ip(), from.port()) != (self.remote_addr.ip(), self.remote_addr.port())
+        {
+            "from another address"
+        } else {
+            let answered = waiting
+                .values_mut()
+                .find(|query| query.response.is_some() && answers(&query.query, &data));
+            match answered {
+                Some(query) => {
+                    if let Some(response) = query.response.take() {
+                        let received = Received {
+                            data,
+                            wire_time,
+                            discarded: query.discarded,
+                            setup: None,
+                        };
+                        response.send(received).ok();
+                    }
+                    return Ok(());
+                }
+                None => "not answering a query sent",
+            }
+        };
+        log::warn!(
+            "Discarding a datagram of {} bytes from {}, {}",
+            len,
+            from,
+            reason
+        );
+        for query in waiting.values_mut() {
+            query.discarded += 1;
+        }
+        Ok(())
+    }
+}
+
+/// Whether `response` is a response to `query`: with its ID and its question,
+/// in any case so the client can tell servers that don't echo it apart.
+/// FORMERR responses may leave the question out, RFC 1035 4.1.1.
+fn answers(query: &[u8], response: &[u8]) -> bool {
+    let Ok((_, end)) = wire::decode_name(query, 12) else {
+        return false;
+    };
+    let question = end + 4;
+    if response.len() < 12 || response[..2] != query[..2] || response[2] & 0x80 == 0 {
+        return false;
+    }
+    match response[4..6] {
+        [0, 0] => response[3] & 0x0f == 1,
+        _ => {
+            response[4..6] == query[4..6]
+                && response.len() >= question
+                && response[12..question].eq_ignore_ascii_case(&query[12..question])
+        }
+    }
+}
+
+/// DNS over TCP, each message prefixed by its length, RFC 1035 4.2.2. The
+/// connection stays open, so a query can be answered by several messages as
+/// zone transfers are.
+pub struct TcpTransport {
+    remote_addr: SocketAddr,
+    stream: Mutex<TcpStream>,
+}
+
+impl TcpTransport {
+    pub async fn connect(remote_addr: String) -> Result<TcpTransport, ClientError> {
+        let remote_addr: SocketAddr = match remote_addr.parse() {
+            Ok(addr) => addr,
+            Err(err) => return Err(ClientError::ParseError(err.to_string())),
+        };
+        let stream = match TcpStream::connect(remote_addr).await {
+            Ok(stream) => stream,
+            Err(err) => return Err(ClientError::ConnectError(err.to_string())),
+        };
+        Ok(TcpTransport {
+            remote_addr,
+            stream: Mutex::new(stream),
+        })
+    }
+
+    pub fn server(&self) -> String {
+        self.remote_addr.to_string()
+    }
+
+    pub async fn send(&self, query: &[u8]) -> Result<(), ClientError> {
+        let len = match u16::try_from(query.len()) {
+            Ok(len) => len,
+            Err(_) => return Err(ClientError::EncodeError("Message too long".to_string())),
+        };
+        let mut stream = self.stream.lock().await;
+        let mut framed = len.to_be_bytes().to_vec();
+        framed.extend_from_slice(query);
+        match stream.write_all(&framed).await {
+            Ok(_) => Ok(()),
+            Err(err) => Err(ClientError::SendError(err.to_string())),
+        }
+    }
+
+    /// Waits for the next message on the connection
+    pub async fn recv(&self) -> Result<Vec<u8>, ClientError> {
+        let mut stream = self.stream.lock().await;
+        let len = match stream.read_u16().await {
+            Ok(len) => len as usize,
+            Err(err) => return Err(ClientError::RecvError(err.to_string())),
+        };
+        let mut data = vec![0u8; len];
+        match stream.read_exact(&mut data).await {
+            Ok(_) => Ok(data),
+            Err(err) => Err(ClientError::RecvError(err.to_string())),
+        }
+    }
+}
+
+impl Transport {
+    pub async fn udp(remote_addr: String) -> Result<Transport, ClientError> {
+        Ok(Transport::Udp(UdpTransport::new(remote_addr).await?))
+    }
+
+    /// UDP transport sending from `source`
+    pub async fn udp_from(remote_addr: String, source: &Source) -> Result<Transport, ClientError> {
+        Ok(Transport::Udp(
+            UdpTransport::new_from(remote_addr, source).await?,
+        ))
+    }
+
+    #[cfg(feature = "encrypted-transports")]
+    pub fn https(url: String, method: DohMethod) -> Result<Transport, ClientError> {
+        Transport::https_pinned(url, method, &[], &Sources::default())
+    }
+
+    /// Multicast DNS, the responses received within `window` merged, for
+    /// names under `.local`
+    #[cfg(feature = "discovery")]
+    pub async fn mdns(window: Duration) -> Result<Transport, ClientError> {
+        Ok(Transport::Mdns(MdnsTransport::new(window).await?))
+    }
+
+    /// DoH transport connecting to `addrs` instead of resolving the host of
+    /// `url`, trusting the CA roots of the first of `sources` that has any
+    #[cfg(feature = "encrypted-transports")]
+    pub fn https_pinned(
+        url: String,
+        method: DohMethod,
+        addrs: &[IpAddr],
+        sources: &Sources,
+    ) -> Result<Transport, ClientError> {
+        if !url.starts_with("https://") {
+            return Err(ClientError::ParseError(std::format!(
+                "DoH endpoint {} must be an https:// URL",
+                url
+            )));
+        }
+        Ok(Transport::Https(DohClient::pinned(
+            url, method, addrs, sources,
+        )?))
+    }
+
+    /// Whether queries should carry a zero ID, RFC 8484 4.1 recommends it so
+    /// identical queries stay cacheable by HTTP caches
+    pub fn zero_id(&self) -> bool {
+        match self {
+            #[cfg(feature = "encrypted-transports")]
+            Transport::Https(_) => true,
+            _ => false,
+        }
+    }
+
+    /// Whether off-path attackers can spoof responses, which UDP alone
+    /// doesn't prevent beyond the ID and port
+    pub fn spoofable(&self) -> bool {
+        matches!(self, Transport::Udp(_))
+    }
+
+    /// Whether queries are encrypted, their length being all an observer of
+    /// the network sees of them
+    pub fn encrypted(&self) -> bool {
+        match self {
+            #[cfg(feature = "encrypted-transports")]
+            Transport::Https(_) => true,
+            _ => false,
+        }
+    }
+
+    /// Measures the round trip of UDP queries with kernel timestamps too,
+    /// only available on Linux
+    pub fn enable_timestamping(&mut self) -> Result<(), ClientError> {
+        match self {
+            #[cfg(target_os = "linux")]
+            Transport::Udp(udp) => {
+                if let Err(err) = timestamping::enable(&udp.socket) {
+                    return Err(ClientError::BindError(err.to_string()));
+                }
+                udp.timestamping = true;
+                Ok(())
+            }
+            // none left in builds with UDP as their only transport
+            #[allow(unreachable_patterns)]
+            _ => Err(ClientError::GenericError(
+                "Kernel timestamping is only available for UDP queries on Linux".to_string(),
+            )),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Transport::Udp(_) => "udp",
+            #[cfg(feature = "encrypted-transports")]
+            Transport::Https(_) => "https",
+            #[cfg(feature = "discovery")]
+            Transport::Mdns(_) => "mdns",
+        }
+    }
+
+    /// Local and remote address of the datagrams of UDP queries
+    pub fn addresses(&self) -> Option<(SocketAddr, SocketAddr)> {
+        match self {
+            Transport::Udp(udp) => Some((udp.socket.local_addr().ok()?, udp.remote_addr)),
+            #[allow(unreachable_patterns)]
+            _ => None,
+        }
+    }
+
+    /// Address or URL queries are sent to
+    pub fn server(&self) -> String {
+        match self {
+            Transport::Udp(udp) => udp.remote_addr.to_string(),
+            #[cfg(feature = "encrypted-transports")]
+            Transport::Https(doh) => doh.url().to_string(),
+            #[cfg(feature = "discovery")]
+            Transport::Mdns(mdns) => mdns.server(),
+        }
+    }
+
+    pub async fn exchange(&self, query: &[u8]) -> Result<Received, ClientError> {
+        match self {
+            Transport::Udp(udp) => udp.exchange(query).await,
+            #[cfg(feature = "encrypted-transports")]
+            Transport::Https(doh) => {
+                let (data, setup) = doh.exchange(query).await?;
+                Ok(Received {
+                    data,
+                    wire_time: None,
+                    discarded: 0,
+                    setup,
+                })
+            }
+            #[cfg(feature = "discovery")]
+            Transport::Mdns(mdns) => mdns.exchange(query).await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::{ClassType, QueryType};
+    use crate::testing::{self, Responder};
+    use crate::wire::Message;
+    use tokio::time::timeout;
+
+    #[tokio::test]
+    async fn concurrent_queries_get_their_responses() {
+        // both queries are answered at once, the second one first
+        let responder = Responder::start(2, |query| vec![testing::address(query)]).await;
+        let transport = UdpTransport::new(responder.addr()).await.unwrap();
+        let a = Message::query(1, "example.com.", &QueryType::A, ClassType::IN.code()).encode();
+        let aaaa =
+            Message::query(2, "example.com.", &QueryType::AAAA, ClassType::IN.code()).encode();
+        let wait = Duration::from_secs(2);
+        let (a, aaaa) = tokio::join!(
+            timeout(wait, transport.exchange(&a)),
+            timeout(wait, transport.exchange(&aaaa)),
+        );
+        let (a, aaaa) = (a.unwrap().unwrap(), aaaa.unwrap().unwrap());
+        assert_eq!(Message::decode(&a.data).unwrap().header.id, 1);
+        assert_eq!(Message::decode(&aaaa.data).unwrap().header.id, 2);
+        assert_eq!((a.discarded, aaaa.discarded), (0, 0));
+        assert_eq!(responder.queries().len(), 2);
+        assert!(transport.waiting().is_empty());
+    }
+
+    #[tokio::test]
+    async fn stray_responses_are_dropped() {
+        let responder = Responder::answering(|query| {
+            let mut stray = query.clone();
+            stray.header.id ^= 1;
+            vec![testing::address(&stray), testing::address(query)]
+        })
+        .await;
+        let transport = UdpTransport::new(responder.addr()).await.unwrap();
+        let query = Message::query(7, "example.com.", &QueryType::A, ClassType::IN.code()).encode();
+        let received = timeout(Duration::from_secs(2), transport.exchange(&query))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(Message::decode(&received.data).unwrap().header.id, 7);
+        assert_eq!(received.discarded, 1);
+    }
+}
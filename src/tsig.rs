@@ -0,0 +1,505 @@
+//! Transaction signatures, RFC 8945: an HMAC of each message keyed with a
+//! secret shared with the server, which authoritative servers commonly
+//! require before transferring or updating a zone.
+use crate::client::{self, ClientError, QueryType};
+use crate::crypto::{self, Hash};
+use crate::wire::{Reader, Writer};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use std::fmt;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Seconds of clock skew allowed either way, the value RFC 8945 10 recommends
+const FUDGE: u16 = 300;
+
+/// Class of TSIG records
+const ANY: u16 = 255;
+
+/// Most unsigned messages allowed in a row in a response, RFC 8945 5.3.1
+const MAX_UNSIGNED: usize = 99;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Algorithm {
+    HmacSha1,
+    HmacSha256,
+    HmacSha384,
+    HmacSha512,
+}
+
+impl Algorithm {
+    /// Name of the algorithm in TSIG records, RFC 8945 6
+    fn name(&self) -> &'static str {
+        match self {
+            Algorithm::HmacSha1 => "hmac-sha1.",
+            Algorithm::HmacSha256 => "hmac-sha256.",
+            Algorithm::HmacSha384 => "hmac-sha384.",
+            Algorithm::HmacSha512 => "hmac-sha512.",
+        }
+    }
+
+    fn hash(&self) -> Hash {
+        match self {
+            Algorithm::HmacSha1 => Hash::Sha1,
+            Algorithm::HmacSha256 => Hash::Sha256,
+            Algorithm::HmacSha384 => Hash::Sha384,
+            Algorithm::HmacSha512 => Hash::Sha512,
+        }
+    }
+}
+
+impl FromStr for Algorithm {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim_end_matches('.').to_lowercase().as_str() {
+            "hmac-sha1" => Ok(Algorithm::HmacSha1),
+            "hmac-sha256" => Ok(Algorithm::HmacSha256),
+            "hmac-sha384" => Ok(Algorithm::HmacSha384),
+            "hmac-sha512" => Ok(Algorithm::HmacSha512),
+            _ => Err(std::format!(
+                "unsupported TSIG algorithm {}, expected hmac-sha1, hmac-sha256, \
+                 hmac-sha384 or hmac-sha512",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Algorithm {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name().trim_end_matches('.'))
+    }
+}
+
+/// A key shared with a server, given as `name:algorithm:secret` with the
+/// secret in base64, e.g. `transfer-key:hmac-sha256:c2VjcmV0`
+#[derive(Clone)]
+pub struct TsigKey {
+    name: String,
+    algorithm: Algorithm,
+    secret: Vec<u8>,
+}
+
+impl FromStr for TsigKey {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, algorithm, secret) = match s.splitn(3, ':').collect::<Vec<&str>>()[..] {
+            [name, algorithm, secret] if !name.is_empty() && !secret.is_empty() => {
+                (name, algorithm, secret)
+            }
+            _ => return Err("expected a TSIG key as NAME:ALGORITHM:SECRET".to_string()),
+        };
+        let algorithm: Algorithm = algorithm.parse()?;
+        let secret = STANDARD
+            .decode(secret)
+            .map_err(|err| std::format!("invalid TSIG secret of {}: {}", name, err))?;
+        Ok(TsigKey {
+            name: name.trim_end_matches('.').to_string(),
+            algorithm,
+            secret,
+        })
+    }
+}
+
+/// Leaves the secret out of logs
+impl fmt::Debug for TsigKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("TsigKey")
+            .field("name", &self.name)
+            .field("algorithm", &self.algorithm)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Seconds since the epoch, the time of TSIG records
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default()
+}
+
+/// Time signed, 48 bits, and fudge
+fn put_timers(writer: &mut Writer, time: u64, fudge: u16) {
+    writer.put_u16((time >> 32) as u16);
+    writer.put_u32(time as u32);
+    writer.put_u16(fudge);
+}
+
+/// Name of a TSIG error, RFC 8945 3
+fn error_name(error: u16) -> String {
+    match error {
+        16 => "BADSIG".to_string(),
+        17 => "BADKEY".to_string(),
+        18 => "BADTIME".to_string(),
+        22 => "BADTRUNC".to_string(),
+        error if error < 16 => client::rcode_name(error as u8),
+        error => std::format!("TSIG error {}", error),
+    }
+}
+
+impl TsigKey {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn algorithm(&self) -> Algorithm {
+        self.algorithm
+    }
+
+    /// TSIG variables signed after the message, RFC 8945 4.3.3, the names
+    /// in canonical form whatever their case in the record
+    fn variables(&self, time: u64, fudge: u16, error: u16, other: &[u8]) -> Vec<u8> {
+        let mut writer = Writer::new();
+        writer.put_name(&self.name.to_ascii_lowercase());
+        writer.put_u16(ANY);
+        writer.put_u32(0);
+        writer.put_name(&self.algorithm.name().to_ascii_lowercase());
+        put_timers(&mut writer, time, fudge);
+        writer.put_u16(error);
+        writer.put_u16(other.len() as u16);
+        writer.put_bytes(other);
+        writer.finish()
+    }
+
+    /// Appends a TSIG record to the encoded `message`, returning the signed
+    /// message and its MAC, which the response signature covers
+    pub(crate) fn sign(&self, message: &[u8]) -> Result<(Vec<u8>, Vec<u8>), ClientError> {
+        self.sign_at(message, now())
+    }
+
+    /// Signs `message` as of `time`, in seconds since the epoch
+    fn sign_at(&self, message: &[u8], time: u64) -> Result<(Vec<u8>, Vec<u8>), ClientError> {
+        let header = message
+            .get(..12)
+            .ok_or_else(|| ClientError::EncodeError("Message too short to sign".to_string()))?;
+        let signed = [message, &self.variables(time, FUDGE, 0, &[])].concat();
+        let mac = crypto::backend()
+            .hmac(self.algorithm.hash(), &self.secret, &signed)
+            .map_err(ClientError::Tsig)?;
+
+        let mut rdata = Writer::new();
+        rdata.put_name(self.algorithm.name());
+        put_timers(&mut rdata, time, FUDGE);
+        rdata.put_u16(mac.len() as u16);
+        rdata.put_bytes(&mac);
+        rdata.put_bytes(&header[..2]);
+        rdata.put_u16(0);
+        rdata.put_u16(0);
+        let rdata = rdata.finish();
+
+        let mut writer = Writer::new();
+        writer.put_bytes(message);
+        writer.put_name(&self.name);
+        writer.put_u16(QueryType::TSIG.code());
+        writer.put_u16(ANY);
+        writer.put_u32(0);
+        writer.put_u16(rdata.len() as u16);
+        writer.put_bytes(&rdata);
+        let mut signed = writer.finish();
+        let additionals = u16::from_be_bytes([signed[10], signed[11]]) + 1;
+        signed[10..12].copy_from_slice(&additionals.to_be_bytes());
+        Ok((signed, mac))
+    }
+}
+
+/// The TSIG record ending a message, with where it starts
+#[derive(Debug)]
+struct Signature {
+    offset: usize,
+    name: String,
+    algorithm: String,
+    time: u64,
+    fudge: u16,
+    mac: Vec<u8>,
+    original_id: u16,
+    error: u16,
+    other: Vec<u8>,
+}
+
+impl Signature {
+    /// The signature of `data`, none when its last record isn't a TSIG one
+    fn find(data: &[u8]) -> Result<Option<Signature>, ClientError> {
+        let mut reader = Reader::new(data);
+        reader.get_bytes(4)?;
+        let questions = reader.get_u16()?;
+        let mut records = 0usize;
+        for _ in 0..2 {
+            records += reader.get_u16()? as usize;
+        }
+        let additionals = reader.get_u16()? as usize;
+        if additionals == 0 {
+            return Ok(None);
+        }
+        for _ in 0..questions {
+            reader.get_name()?;
+            reader.get_bytes(4)?;
+        }
+        for _ in 0..records + additionals - 1 {
+            reader.get_name()?;
+            reader.get_bytes(8)?;
+            let len = reader.get_u16()? as usize;
+            reader.get_bytes(len)?;
+        }
+        let offset = reader.position();
+        let name = reader.get_name()?;
+        if reader.get_u16()? != QueryType::TSIG.code() {
+            return Ok(None);
+        }
+        // class, TTL and RDATA length
+        reader.get_bytes(8)?;
+        let algorithm = reader.get_name()?;
+        let time = ((reader.get_u16()? as u64) << 32) | reader.get_u32()? as u64;
+        let fudge = reader.get_u16()?;
+        let len = reader.get_u16()? as usize;
+        let mac = reader.get_bytes(len)?.to_vec();
+        let original_id = reader.get_u16()?;
+        let error = reader.get_u16()?;
+        let len = reader.get_u16()? as usize;
+        let other = reader.get_bytes(len)?.to_vec();
+        Ok(Some(Signature {
+            offset,
+            name,
+            algorithm,
+            time,
+            fudge,
+            mac,
+            original_id,
+            error,
+            other,
+        }))
+    }
+}
+
+/// Checks the signatures of the messages answering a signed request, RFC
+/// 8945 5.3: the first one must be signed, and of a zone transfer the last
+/// one too and at least one in every hundred
+#[derive(Debug)]
+pub(crate) struct TsigVerifier {
+    key: TsigKey,
+    /// MAC of the request, then of the last signed message
+    mac: Vec<u8>,
+    /// Messages received since the last signed one
+    unsigned: Vec<u8>,
+    pending: usize,
+    signed: usize,
+}
+
+impl TsigVerifier {
+    pub(crate) fn new(key: TsigKey, request_mac: Vec<u8>) -> TsigVerifier {
+        TsigVerifier {
+            key,
+            mac: request_mac,
+            unsigned: Vec::new(),
+            pending: 0,
+            signed: 0,
+        }
+    }
+
+    /// Checks the next message of the response
+    pub(crate) fn verify(&mut self, data: &[u8]) -> Result<(), ClientError> {
+        self.verify_at(data, now())
+    }
+
+    /// Checks the next message of the response as of `now`, in seconds since
+    /// the epoch
+    fn verify_at(&mut self, data: &[u8], now: u64) -> Result<(), ClientError> {
+        let signature = match Signature::find(data)? {
+            Some(signature) => signature,
+            None if self.signed == 0 => {
+                let rcode = data.get(3).map(|flags| flags & 0x0f).unwrap_or_default();
+                return Err(ClientError::Tsig(std::format!(
+                    "{} response isn't signed with {}",
+                    client::rcode_name(rcode),
+                    self.key.name
+                )));
+            }
+            None if self.pending >= MAX_UNSIGNED => {
+                return Err(ClientError::Tsig(std::format!(
+                    "more than {} unsigned messages in a row",
+                    MAX_UNSIGNED
+                )))
+            }
+            None => {
+                self.unsigned.extend(data);
+                self.pending += 1;
+                return Ok(());
+            }
+        };
+        if !signature
+            .name
+            .trim_end_matches('.')
+            .eq_ignore_ascii_case(&self.key.name)
+            || !signature
+                .algorithm
+                .eq_ignore_ascii_case(self.key.algorithm.name())
+        {
+            return Err(ClientError::Tsig(std::format!(
+                "response signed with {} {}, expected {} {}",
+                signature.name.trim_end_matches('.'),
+                signature.algorithm.trim_end_matches('.'),
+                self.key.name,
+                self.key.algorithm
+            )));
+        }
+        if signature.error != 0 {
+            return Err(ClientError::Tsig(std::format!(
+                "the server rejected the signature with {}: {}",
+                self.key.name,
+                error_name(signature.error)
+            )));
+        }
+        // the message as it was before signing: its original id, no TSIG record
+        let mut message = data[..signature.offset].to_vec();
+        message[..2].copy_from_slice(&signature.original_id.to_be_bytes());
+        let additionals = u16::from_be_bytes([message[10], message[11]]) - 1;
+        message[10..12].copy_from_slice(&additionals.to_be_bytes());
+
+        let mut signed = Writer::new();
+        signed.put_u16(self.mac.len() as u16);
+        signed.put_bytes(&self.mac);
+        signed.put_bytes(&self.unsigned);
+        signed.put_bytes(&message);
+        if self.signed == 0 {
+            signed.put_bytes(&self.key.variables(
+                signature.time,
+                signature.fudge,
+                signature.error,
+                &signature.other,
+            ));
+        } else {
+            put_timers(&mut signed, signature.time, signature.fudge);
+        }
+        let hash = self.key.algorithm.hash();
+        if !crypto::backend().verify_hmac(hash, &self.key.secret, &signed.finish(), &signature.mac)
+        {
+            return Err(ClientError::Tsig(std::format!(
+                "response signature doesn't match the secret of {}",
+                self.key.name
+            )));
+        }
+        if now.abs_diff(signature.time) > signature.fudge as u64 {
+            return Err(ClientError::Tsig(std::format!(
+                "response signed {}s away from the local time, more than the {}s allowed",
+                now.abs_diff(signature.time),
+                signature.fudge
+            )));
+        }
+        self.mac = signature.mac;
+        self.unsigned.clear();
+        self.pending = 0;
+        self.signed += 1;
+        Ok(())
+    }
+
+    /// Fails when the response ended with unsigned messages
+    pub(crate) fn finish(&self) -> Result<(), ClientError> {
+        match self.pending {
+            0 => Ok(()),
+            pending => Err(ClientError::Tsig(std::format!(
+                "the last {} messages of the response aren't signed",
+                pending
+            ))),
+        }
+    }
+}
+
+#[cfg(all(test, any(feature = "dnssec-ring", feature = "dnssec-openssl")))]
+mod tests {
+    use super::*;
+
+    /// Time the messages of the vector were signed, 2023-11-14
+    const TIME: u64 = 1700000000;
+
+    fn hex(parts: &[&str]) -> Vec<u8> {
+        hex::decode(parts.concat()).unwrap()
+    }
+
+    /// A key named in mixed case, signed with its lowercase name
+    fn key() -> TsigKey {
+        "Transfer-Key.:hmac-sha256:c2VjcmV0LXRyYW5zZmVyLWtleQ=="
+            .parse()
+            .unwrap()
+    }
+
+    /// AXFR query of example. signed at `TIME`, and the MAC of its
+    /// signature. The MACs of these vectors were computed independently of
+    /// this module, with Python's hmac over the digest components as RFC 8945
+    /// 4.3 lays them out.
+    fn signed_query() -> (Vec<u8>, Vec<u8>) {
+        let signed = hex(&[
+            "2a2a00000001000000000001076578616d706c650000fc0001",
+            "0c5472616e736665722d4b65790000fa00ff00000000003d",
+            "0b686d61632d7368613235360000006553f100012c0020",
+            "f883e64c5148441de56f3fc1ec1e1c47043aefc10b3cd3dee6e197086c2b432b",
+            "2a2a00000000",
+        ]);
+        let mac = hex(&["f883e64c5148441de56f3fc1ec1e1c47043aefc10b3cd3dee6e197086c2b432b"]);
+        (signed, mac)
+    }
+
+    /// The transfer answering the query: its SOA, then two A records
+    /// unsigned and the closing SOA signed, RFC 8945 5.3.1
+    fn transfer() -> Vec<Vec<u8>> {
+        let soa = "076578616d706c65000006000100000e10002f026e73076578616d706c650005\
+                   61646d696e076578616d706c65000000000100001c2000000e10001275000000012c";
+        let tsig = "0c7472616e736665722d6b65790000fa00ff00000000003d0b686d61632d73686132353600";
+        vec![
+            hex(&[
+                "2a2a84000001000100000001076578616d706c650000fc0001",
+                soa,
+                tsig,
+                "00006553f101012c0020",
+                "2923f9cac1f2b4b8c1da8cc82252b307cc245ec9c0b522d7898643b918aff018",
+                "2a2a00000000",
+            ]),
+            hex(&["2a2a840000000001000000000161076578616d706c65000001000100000e100004c0000201"]),
+            hex(&["2a2a840000000001000000000162076578616d706c65000001000100000e100004c0000202"]),
+            hex(&[
+                "2a2a84000000000100000001",
+                soa,
+                tsig,
+                "00006553f102012c0020",
+                "5656d98deb168efc7284b5de0dff209d756081a7c9be8f16249781f653ba5c4f",
+                "2a2a00000000",
+            ]),
+        ]
+    }
+
+    #[test]
+    fn queries_are_signed_with_the_canonical_key_name() {
+        let query = hex(&["2a2a00000001000000000000076578616d706c650000fc0001"]);
+        assert_eq!(key().sign_at(&query, TIME).unwrap(), signed_query());
+    }
+
+    #[test]
+    fn transfers_are_verified_across_unsigned_messages() {
+        let (_, mac) = signed_query();
+        let mut verifier = TsigVerifier::new(key(), mac.clone());
+        for message in transfer() {
+            verifier.verify_at(&message, TIME + 2).unwrap();
+        }
+        verifier.finish().unwrap();
+
+        // the closing signature covers the unsigned messages before it
+        let mut tampered = transfer();
+        *tampered[1].last_mut().unwrap() ^= 1;
+        let mut verifier = TsigVerifier::new(key(), mac.clone());
+        let verified: Vec<bool> = tampered
+            .iter()
+            .map(|message| verifier.verify_at(message, TIME + 2).is_ok())
+            .collect();
+        assert_eq!(verified, [true, true, true, false]);
+
+        // nor may the transfer end unsigned, or be signed too long ago
+        let mut verifier = TsigVerifier::new(key(), mac.clone());
+        for message in &transfer()[..3] {
+            verifier.verify_at(message, TIME + 2).unwrap();
+        }
+        assert!(verifier.finish().is_err());
+        let mut verifier = TsigVerifier::new(key(), mac);
+        assert!(verifier.verify_at(&transfer()[0], TIME + 600).is_err());
+    }
+}
@@ -0,0 +1,284 @@
+//! Dynamic updates of a zone on its primary server, RFC 2136: records to add
+//! and delete, sent with the prerequisites the zone has to meet for the
+//! server to apply them.
+use crate::client::{self, ClassType, ClientError, QueryType};
+use crate::idn;
+use crate::rdata::{self, fields};
+use crate::transport::TcpTransport;
+use crate::tsig::{TsigKey, TsigVerifier};
+use crate::wire::{Message, Record};
+use rand::random;
+use std::time::Duration;
+use tokio::time::timeout;
+
+/// Classes of prerequisite and update records with a special meaning, RFC
+/// 2136 2.4 and 2.5
+const NONE: u16 = 254;
+const ANY: u16 = 255;
+
+/// Changes to a zone, built up then sent in a single UPDATE message
+#[derive(Debug, Clone)]
+pub struct ZoneUpdate {
+    zone: String,
+    class: ClassType,
+    prerequisites: Vec<Record>,
+    updates: Vec<Record>,
+    tsig: Option<TsigKey>,
+}
+
+impl ZoneUpdate {
+    pub fn new(zone: &str, class: ClassType) -> Result<ZoneUpdate, ClientError> {
+        Ok(ZoneUpdate {
+            zone: idn::to_ascii(zone)?,
+            class,
+            prerequisites: Vec::new(),
+            updates: Vec::new(),
+            tsig: None,
+        })
+    }
+
+    /// A record of `name` with no TTL and no data, the form most
+    /// prerequisites and deletions take
+    fn empty(&self, name: &str, query_type: QueryType, class: u16) -> Result<Record, ClientError> {
+        Ok(Record {
+            name: idn::to_ascii(name)?,
+            query_type,
+            class,
+            ttl: 0,
+            rdata: Vec::new(),
+        })
+    }
+
+    /// `NAME [TYPE]` and whether the name, or its records of TYPE, must exist
+    fn prerequisite(&self, text: &str, exists: bool) -> Result<Record, ClientError> {
+        let class = if exists { ANY } else { NONE };
+        match fields(text).as_slice() {
+            [name] => self.empty(name, QueryType::ANY, class),
+            [name, query_type] => self.empty(
+                name,
+                query_type.parse().map_err(ClientError::ParseError)?,
+                class,
+            ),
+            _ => Err(ClientError::ParseError(std::format!(
+                "expected NAME [TYPE], got {}",
+                text
+            ))),
+        }
+    }
+
+    /// Only applies the update when `NAME` is in use, or has records of `TYPE`
+    /// when given as `NAME TYPE`
+    pub fn require_exists(mut self, text: &str) -> Result<ZoneUpdate, ClientError> {
+        let record = self.prerequisite(text, true)?;
+        self.prerequisites.push(record);
+        Ok(self)
+    }
+
+    /// Only applies the update when `NAME` isn't in use, or has no records of
+    /// `TYPE` when given as `NAME TYPE`
+    pub fn require_absent(mut self, text: &str) -> Result<ZoneUpdate, ClientError> {
+        let record = self.prerequisite(text, false)?;
+        self.prerequisites.push(record);
+        Ok(self)
+    }
+
+    /// Adds the record `NAME TTL TYPE DATA`, e.g. `www.example.com 300 A 192.0.2.1`
+    pub fn add_record(mut self, text: &str) -> Result<ZoneUpdate, ClientError> {
+        let fields = fields(text);
+        let (name, ttl, query_type, data) = match fields.as_slice() {
+            [name, ttl, query_type, data @ ..] if !data.is_empty() => (name, ttl, query_type, data),
+            _ => {
+                return Err(ClientError::ParseError(std::format!(
+                    "expected NAME TTL TYPE DATA, got {}",
+                    text
+                )))
+            }
+        };
+        let query_type: QueryType = query_type.parse().map_err(ClientError::ParseError)?;
+        let data: Vec<&str> = data.iter().map(|field| field.as_str()).collect();
+        self.updates.push(Record {
+            name: idn::to_ascii(name)?,
+            ttl: ttl
+                .parse()
+                .map_err(|_| ClientError::ParseError(std::format!("invalid TTL {}", ttl)))?,
+            rdata: rdata::parse(&query_type, &data)?,
+            query_type,
+            class: self.class.code(),
+        });
+        Ok(self)
+    }
+
+    /// Deletes every record of `NAME`, its records of `TYPE` with `NAME TYPE`,
+    /// or a single record with `NAME TYPE DATA`
+    pub fn delete_records(mut self, text: &str) -> Result<ZoneUpdate, ClientError> {
+        let record = match fields(text).as_slice() {
+            [name] => self.empty(name, QueryType::ANY, ANY)?,
+            [name, query_type] => self.empty(
+                name,
+                query_type.parse().map_err(ClientError::ParseError)?,
+                ANY,
+            )?,
+            [name, query_type, data @ ..] => {
+                let query_type: QueryType = query_type.parse().map_err(ClientError::ParseError)?;
+                let data: Vec<&str> = data.iter().map(|field| field.as_str()).collect();
+                let mut record = self.empty(name, query_type.clone(), NONE)?;
+                record.rdata = rdata::parse(&query_type, &data)?;
+                record
+            }
+            [] => {
+                return Err(ClientError::ParseError(
+                    "expected NAME [TYPE [DATA]]".to_string(),
+                ))
+            }
+        };
+        self.updates.push(record);
+        Ok(self)
+    }
+
+    /// Signs the update with `key`, which primaries commonly require, RFC 8945
+    pub fn with_tsig(mut self, key: TsigKey) -> ZoneUpdate {
+        self.tsig = Some(key);
+        self
+    }
+
+    pub(crate) fn message(&self, id: u16) -> Message {
+        let mut message = Message::update(id, &self.zone, self.class.code());
+        message.answers = self.prerequisites.clone();
+        message.authorities = self.updates.clone();
+        message
+    }
+
+    /// Sends the update to `server` over TCP, failing unless it was applied
+    pub async fn send(&self, server: String, wait: Duration) -> Result<(), ClientError> {
+        let transport = match timeout(wait, TcpTransport::connect(server)).await {
+            Ok(transport) => transport?,
+            Err(_) => return Err(ClientError::Timeout(1)),
+        };
+        let id: u16 = random();
+        let message = self.message(id);
+        log::debug!("Update {:x?}", message);
+        let mut verifier = None;
+        let data = match &self.tsig {
+            Some(key) => {
+                let (signed, mac) = key.sign(&message.encode())?;
+                verifier = Some(TsigVerifier::new(key.clone(), mac));
+                signed
+            }
+            None => message.encode(),
+        };
+        transport.send(&data).await?;
+        let data = match timeout(wait, transport.recv()).await {
+            Ok(data) => data?,
+            Err(_) => return Err(ClientError::Timeout(1)),
+        };
+        let response = Message::decode(&data)?;
+        log::debug!("Response {:x?}", &response);
+        if response.header.id != id {
+            return Err(ClientError::DecodeIdError(std::format!(
+                "Expected id {}, received {}",
+                id,
+                response.header.id
+            )));
+        }
+        if let Some(verifier) = &mut verifier {
+            verifier.verify(&data)?;
+        }
+        match response.header.rcode() {
+            0 => Ok(()),
+            rcode => Err(ClientError::GenericError(std::format!(
+                "{} didn't apply the update of {}: {}",
+                transport.server(),
+                self.zone,
+                client::rcode_name(rcode)
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prerequisites_and_updates_round_trip_with_their_classes() {
+        let update = ZoneUpdate::new("example.com", ClassType::IN)
+            .unwrap()
+            .require_exists("a.example.com")
+            .unwrap()
+            .require_exists("a.example.com A")
+            .unwrap()
+            .require_absent("b.example.com")
+            .unwrap()
+            .require_absent("b.example.com TXT")
+            .unwrap()
+            .add_record("www.example.com 300 A 192.0.2.1")
+            .unwrap()
+            .delete_records("old.example.com")
+            .unwrap()
+            .delete_records("old.example.com A")
+            .unwrap()
+            .delete_records("old.example.com A 192.0.2.9")
+            .unwrap();
+        let encoded = update.message(0x1234).encode();
+        // opcode UPDATE, a zone, four prerequisites and four updates
+        assert_eq!(
+            &encoded[..12],
+            &[0x12, 0x34, 0x28, 0, 0, 1, 0, 4, 0, 4, 0, 0]
+        );
+        let message = Message::decode(&encoded).unwrap();
+        let zone = &message.questions[0];
+        assert_eq!(
+            (zone.name.as_str(), &zone.query_type, zone.class),
+            ("example.com.", &QueryType::SOA, 1)
+        );
+        let fields = |records: &[Record]| -> Vec<(String, QueryType, u16, u32, Vec<u8>)> {
+            records
+                .iter()
+                .map(|record| {
+                    let name = record.name.clone();
+                    let rdata = record.rdata.clone();
+                    (
+                        name,
+                        record.query_type.clone(),
+                        record.class,
+                        record.ttl,
+                        rdata,
+                    )
+                })
+                .collect()
+        };
+        let name = |name: &str| name.to_string();
+        // RFC 2136 2.4.4, 2.4.1, 2.4.5 and 2.4.3
+        assert_eq!(
+            fields(&message.answers),
+            [
+                (name("a.example.com."), QueryType::ANY, ANY, 0, vec![]),
+                (name("a.example.com."), QueryType::A, ANY, 0, vec![]),
+                (name("b.example.com."), QueryType::ANY, NONE, 0, vec![]),
+                (name("b.example.com."), QueryType::TXT, NONE, 0, vec![]),
+            ]
+        );
+        // RFC 2136 2.5.1, 2.5.3, 2.5.2 and 2.5.4
+        assert_eq!(
+            fields(&message.authorities),
+            [
+                (
+                    name("www.example.com."),
+                    QueryType::A,
+                    1,
+                    300,
+                    vec![192, 0, 2, 1]
+                ),
+                (name("old.example.com."), QueryType::ANY, ANY, 0, vec![]),
+                (name("old.example.com."), QueryType::A, ANY, 0, vec![]),
+                (
+                    name("old.example.com."),
+                    QueryType::A,
+                    NONE,
+                    0,
+                    vec![192, 0, 2, 9]
+                ),
+            ]
+        );
+    }
+}
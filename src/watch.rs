@@ -0,0 +1,201 @@
+//! Changes between rounds of the same queries: records appearing and going
+//! away, TTLs going up instead of counting down, and names starting or
+//! ceasing to exist.
+use crate::client::{Client, ClientError, QueryAnswer, QueryType};
+use crate::output::Ipv6Format;
+use futures::stream::{self, Stream, StreamExt};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::time::Duration;
+
+/// Last round of a query: how it ended and its records with their TTLs
+#[derive(Debug, Default)]
+struct Snapshot {
+    status: String,
+    records: BTreeMap<(String, String), u32>,
+}
+
+/// A difference with the previous round of a query
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "change", rename_all = "snake_case")]
+pub enum Change {
+    Added {
+        host: String,
+        #[serde(rename = "type")]
+        query_type: String,
+        data: String,
+        ttl: u32,
+    },
+    Removed {
+        host: String,
+        #[serde(rename = "type")]
+        query_type: String,
+        data: String,
+    },
+    /// The TTL went up, the record was fetched again from the authoritative
+    /// servers or changed there
+    TtlReset {
+        host: String,
+        #[serde(rename = "type")]
+        query_type: String,
+        data: String,
+        from: u32,
+        to: u32,
+    },
+    /// NOERROR, NXDOMAIN or the failure of the query changed, or is the
+    /// first one seen when it isn't NOERROR
+    Status {
+        host: String,
+        #[serde(rename = "type")]
+        query_type: String,
+        from: String,
+        to: String,
+    },
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Change::Added {
+                host,
+                query_type,
+                data,
+                ttl,
+            } => write!(f, "+ {} {} {} {}", host, ttl, query_type, data),
+            Change::Removed {
+                host,
+                query_type,
+                data,
+            } => write!(f, "- {} {} {}", host, query_type, data),
+            Change::TtlReset {
+                host,
+                query_type,
+                data,
+                from,
+                to,
+            } => write!(
+                f,
+                "~ {} {} {}: TTL reset from {} to {}",
+                host, query_type, data, from, to
+            ),
+            Change::Status {
+                host,
+                query_type,
+                from,
+                to,
+            } if from.is_empty() => write!(f, "! {} {}: {}", host, query_type, to),
+            Change::Status {
+                host,
+                query_type,
+                from,
+                to,
+            } => write!(f, "! {} {}: {} -> {}", host, query_type, from, to),
+        }
+    }
+}
+
+/// Queries watched, with what each returned last
+#[derive(Debug, Default)]
+pub struct Watch {
+    snapshots: HashMap<(String, QueryType), Snapshot>,
+}
+
+impl Watch {
+    pub fn new() -> Watch {
+        Watch::default()
+    }
+
+    /// Records the result of a round of `host` `query_type` and returns how
+    /// it differs from the previous one, every record being new the first
+    /// time. Records of a failed query are kept for when it answers again.
+    pub fn update(
+        &mut self,
+        host: &str,
+        query_type: &QueryType,
+        result: &Result<Vec<QueryAnswer>, ClientError>,
+        ipv6: &Ipv6Format,
+    ) -> Vec<Change> {
+        let key = (host.to_string(), query_type.clone());
+        let first = !self.snapshots.contains_key(&key);
+        let previous = self.snapshots.entry(key).or_default();
+        let (status, records) = match result {
+            Ok(answers) => (
+                "NOERROR".to_string(),
+                answers
+                    .iter()
+                    .map(|answer| {
+                        let record = (answer.query_type().to_string(), ipv6.data(answer.data()));
+                        (record, answer.ttl())
+                    })
+                    .collect(),
+            ),
+            Err(ClientError::RDCodeNameError) => ("NXDOMAIN".to_string(), BTreeMap::new()),
+            Err(err) => (std::format!("failed ({})", err), previous.records.clone()),
+        };
+        let mut changes = Vec::new();
+        if status != previous.status && !(first && result.is_ok()) {
+            changes.push(Change::Status {
+                host: host.to_string(),
+                query_type: query_type.to_string(),
+                from: previous.status.clone(),
+                to: status.clone(),
+            });
+        }
+        for ((record_type, data), ttl) in &records {
+            match previous.records.get(&(record_type.clone(), data.clone())) {
+                None => changes.push(Change::Added {
+                    host: host.to_string(),
+                    query_type: record_type.clone(),
+                    data: data.clone(),
+                    ttl: *ttl,
+                }),
+                Some(from) if ttl > from => changes.push(Change::TtlReset {
+                    host: host.to_string(),
+                    query_type: record_type.clone(),
+                    data: data.clone(),
+                    from: *from,
+                    to: *ttl,
+                }),
+                Some(_) => {}
+            }
+        }
+        for (record_type, data) in previous.records.keys() {
+            if !records.contains_key(&(record_type.clone(), data.clone())) {
+                changes.push(Change::Removed {
+                    host: host.to_string(),
+                    query_type: record_type.clone(),
+                    data: data.clone(),
+                });
+            }
+        }
+        *previous = Snapshot { status, records };
+        changes
+    }
+}
+
+/// The changes of `host` `query_type` as `client` queries it every
+/// `interval`, the records of the first round being added, for programs
+/// watching a record without keeping its rounds
+pub fn changes(
+    client: Client,
+    host: String,
+    query_type: QueryType,
+    interval: Duration,
+) -> impl Stream<Item = Change> {
+    let rounds = stream::unfold(
+        (client, Watch::new(), true),
+        move |(client, mut watch, first)| {
+            let (host, query_type) = (host.clone(), query_type.clone());
+            async move {
+                if !first {
+                    tokio::time::sleep(interval).await;
+                }
+                let result = client.query(host.clone(), query_type.clone()).await;
+                let changes = watch.update(&host, &query_type, &result, &Ipv6Format::default());
+                Some((stream::iter(changes), (client, watch, false)))
+            }
+        },
+    );
+    rounds.flatten()
+}
@@ -0,0 +1,1136 @@
+use crate::client::{ClassType, ClientError, QueryType};
+
+/// UDP payload size advertised with EDNS0
+const EDNS_PAYLOAD_SIZE: u16 = 4096;
+
+/// EDNS option code of DNS cookies, RFC 7873 4
+const COOKIE: u16 = 10;
+
+/// EDNS option code of padding, RFC 7830 3
+const PADDING: u16 = 12;
+
+/// EDNS option code of extended DNS errors, RFC 8914 2
+#[cfg(feature = "serve")]
+const EXTENDED_ERROR: u16 = 15;
+
+/// Extended response code of a missing or invalid server cookie, RFC 7873 8
+pub(crate) const BADCOOKIE: u16 = 23;
+
+/// Serializes a message in network byte order
+#[derive(Debug, Default)]
+pub(crate) struct Writer {
+    buf: Vec<u8>,
+}
+
+impl Writer {
+    pub(crate) fn new() -> Writer {
+        Writer::default()
+    }
+
+    pub(crate) fn put_u16(&mut self, value: u16) {
+        self.buf.extend(value.to_be_bytes());
+    }
+
+    pub(crate) fn put_u32(&mut self, value: u32) {
+        self.buf.extend(value.to_be_bytes());
+    }
+
+    pub(crate) fn put_bytes(&mut self, value: &[u8]) {
+        self.buf.extend(value);
+    }
+
+    /// Writes `name` uncompressed
+    pub(crate) fn put_name(&mut self, name: &str) {
+        self.buf.extend(encode_name(name));
+    }
+
+    pub(crate) fn finish(self) -> Vec<u8> {
+        self.buf
+    }
+}
+
+/// Reads a message in network byte order, failing on truncated data
+#[derive(Debug)]
+pub(crate) struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub(crate) fn new(data: &'a [u8]) -> Reader<'a> {
+        Reader { data, pos: 0 }
+    }
+
+    pub(crate) fn position(&self) -> usize {
+        self.pos
+    }
+
+    pub(crate) fn remaining(&self) -> usize {
+        self.data.len().saturating_sub(self.pos)
+    }
+
+    pub(crate) fn get_bytes(&mut self, len: usize) -> Result<&'a [u8], ClientError> {
+        match self.data.get(self.pos..self.pos + len) {
+            Some(bytes) => {
+                self.pos += len;
+                Ok(bytes)
+            }
+            None => Err(ClientError::DecodeError(std::format!(
+                "Truncated message, expected {} bytes at {}",
+                len,
+                self.pos
+            ))),
+        }
+    }
+
+    pub(crate) fn get_u16(&mut self) -> Result<u16, ClientError> {
+        let bytes = self.get_bytes(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    pub(crate) fn get_u32(&mut self) -> Result<u32, ClientError> {
+        let bytes = self.get_bytes(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    /// Reads a possibly compressed name, pointers are resolved against the
+    /// whole message
+    pub(crate) fn get_name(&mut self) -> Result<String, ClientError> {
+        let (name, end) = decode_name(self.data, self.pos)?;
+        self.pos = end;
+        Ok(name)
+    }
+}
+
+/// Reads the name at `start`, following compression pointers (RFC 1035
+/// 4.1.4) anywhere in `data`. Returns the name and the offset right after
+/// it in the original position. Each pointer must point before the previous
+/// one's target, which rules out loops, and names are limited to 255 octets,
+/// RFC 1035 2.3.4.
+pub(crate) fn decode_name(data: &[u8], start: usize) -> Result<(String, usize), ClientError> {
+    let truncated = || ClientError::DecodeError("Truncated name on decoded response".to_string());
+    let mut labels: Vec<String> = Vec::new();
+    let mut i = start;
+    let mut end: Option<usize> = None;
+    let mut limit = usize::MAX;
+    let mut size = 1;
+    loop {
+        let len = *data.get(i).ok_or_else(truncated)? as usize;
+        match len & 0xc0 {
+            0xc0 => {
+                let low = *data.get(i + 1).ok_or_else(truncated)? as usize;
+                let target = ((len & 0x3f) << 8) | low;
+                if target >= i.min(limit) {
+                    return Err(ClientError::DecodeError(std::format!(
+                        "Compression pointer at {} doesn't point backwards",
+                        i
+                    )));
+                }
+                end.get_or_insert(i + 2);
+                limit = target;
+                i = target;
+                continue;
+            }
+            0x00 => (),
+            _ => {
+                return Err(ClientError::DecodeError(std::format!(
+                    "Unsupported label type {:x?} on decoded name",
+                    len
+                )))
+            }
+        }
+        i += 1;
+        if len == 0 {
+            break;
+        }
+        size += len + 1;
+        if size > 255 {
+            return Err(ClientError::DecodeError(
+                "Name longer than 255 octets on decoded response".to_string(),
+            ));
+        }
+        let label = data.get(i..i + len).ok_or_else(truncated)?;
+        labels.push(String::from_utf8_lossy(label).to_string());
+        i += len;
+    }
+    Ok((labels.join(".") + ".", end.unwrap_or(i)))
+}
+
+pub(crate) fn encode_name(name: &str) -> Vec<u8> {
+    let mut encoded: Vec<u8> = Vec::new();
+    // skipping empty words lets fully qualified names and the root "." through
+    for word in name.split('.').filter(|word| !word.is_empty()) {
+        encoded.push(word.len() as u8);
+        encoded.extend(word.as_bytes());
+    }
+    encoded.push(0x0);
+    encoded
+}
+
+/// Message header, the section counts are derived from the sections
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Header {
+    pub(crate) id: u16,
+    pub(crate) flags: u16,
+}
+
+impl Header {
+    /// Recursion Desired, the only flag set on queries
+    const RD: u16 = 0x0100;
+
+    /// Opcode of dynamic updates, RFC 2136 1.3
+    #[cfg(feature = "zones")]
+    const UPDATE: u16 = 5;
+
+    /// Query/Response flag, set on responses
+    const QR: u16 = 0x8000;
+
+    /// Authoritative Answer flag
+    const AA: u16 = 0x0400;
+
+    /// TrunCation flag, set on responses cut to fit the UDP payload
+    const TC: u16 = 0x0200;
+
+    /// Recursion Available flag
+    const RA: u16 = 0x0080;
+
+    /// Authentic Data flag, RFC 4035 3.2.3
+    const AD: u16 = 0x0020;
+
+    /// Checking Disabled flag, RFC 4035 3.2.2
+    const CD: u16 = 0x0010;
+
+    pub(crate) fn is_response(&self) -> bool {
+        self.flags & Header::QR == Header::QR
+    }
+
+    /// Authentic Data flag, set by validating resolvers, RFC 4035 3.2.3
+    #[cfg(any(feature = "dnssec", feature = "diagnostics"))]
+    pub(crate) fn authenticated_data(&self) -> bool {
+        self.flags & Header::AD == Header::AD
+    }
+
+    /// Authoritative Answer flag, set by servers authoritative for the name
+    pub(crate) fn authoritative(&self) -> bool {
+        self.flags & Header::AA == Header::AA
+    }
+
+    /// TrunCation flag, set on responses too large for the UDP payload
+    pub(crate) fn truncated(&self) -> bool {
+        self.flags & Header::TC == Header::TC
+    }
+
+    /// Recursion Desired flag, cleared by clients asking authoritative
+    /// servers
+    #[cfg(feature = "serve")]
+    pub(crate) fn recursion_desired(&self) -> bool {
+        self.flags & Header::RD == Header::RD
+    }
+
+    /// Checking Disabled flag, set by clients validating answers themselves
+    #[cfg(feature = "serve")]
+    pub(crate) fn checking_disabled(&self) -> bool {
+        self.flags & Header::CD == Header::CD
+    }
+
+    /// Recursion Available flag, set by servers offering recursion
+    pub(crate) fn recursion_available(&self) -> bool {
+        self.flags & Header::RA == Header::RA
+    }
+
+    pub(crate) fn rcode(&self) -> u8 {
+        (self.flags & 0x000f) as u8
+    }
+
+    pub(crate) fn opcode(&self) -> u8 {
+        ((self.flags >> 11) & 0x000f) as u8
+    }
+
+    /// Names of the flags set, in the order dig lists them
+    pub(crate) fn flag_names(&self) -> Vec<&'static str> {
+        [
+            (0x8000, "qr"),
+            (0x0400, "aa"),
+            (0x0200, "tc"),
+            (0x0100, "rd"),
+            (0x0080, "ra"),
+            (0x0020, "ad"),
+            (0x0010, "cd"),
+        ]
+        .iter()
+        .filter(|(bit, _)| self.flags & bit == *bit)
+        .map(|(_, name)| *name)
+        .collect()
+    }
+
+    /// Names of the flags a server sets on its responses: aa, tc, ra and ad
+    pub(crate) fn response_flag_names(&self) -> Vec<&'static str> {
+        self.flag_names()
+            .into_iter()
+            .filter(|name| ["aa", "tc", "ra", "ad"].contains(name))
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Question {
+    pub(crate) name: String,
+    pub(crate) query_type: QueryType,
+    pub(crate) class: u16,
+}
+
+/// A resource record, with any compressed name of its RDATA expanded so it
+/// can be read without the rest of the message
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Record {
+    pub(crate) name: String,
+    pub(crate) query_type: QueryType,
+    pub(crate) class: u16,
+    pub(crate) ttl: u32,
+    pub(crate) rdata: Vec<u8>,
+}
+
+impl Record {
+    fn encode(&self, writer: &mut Writer) {
+        writer.put_name(&self.name);
+        writer.put_u16(self.query_type.code());
+        writer.put_u16(self.class);
+        writer.put_u32(self.ttl);
+        writer.put_u16(self.rdata.len() as u16);
+        writer.put_bytes(&self.rdata);
+    }
+
+    fn decode(reader: &mut Reader) -> Result<Record, ClientError> {
+        let name = reader.get_name()?;
+        let query_type = QueryType::from_code(reader.get_u16()?);
+        let class = reader.get_u16()?;
+        let ttl = reader.get_u32()?;
+        let len = reader.get_u16()? as usize;
+        let start = reader.position();
+        reader.get_bytes(len)?;
+        Ok(Record {
+            rdata: expand_rdata(reader.data, start, start + len, &query_type)?,
+            name,
+            query_type,
+            class,
+            ttl,
+        })
+    }
+}
+
+/// RDATA at `start..end` of `data`, with the compressed names of the types
+/// that may have them expanded
+fn expand_rdata(
+    data: &[u8],
+    start: usize,
+    end: usize,
+    query_type: &QueryType,
+) -> Result<Vec<u8>, ClientError> {
+    match query_type {
+        QueryType::MX if end - start > 2 => {
+            let mut rdata = data[start..start + 2].to_vec();
+            let (exchange, _) = decode_name(&data[..end], start + 2)?;
+            rdata.extend(encode_name(&exchange));
+            Ok(rdata)
+        }
+        // RFC 2782 forbids compressing the target, some servers do anyway
+        QueryType::SRV if end - start > 6 => {
+            let mut rdata = data[start..start + 6].to_vec();
+            let (target, _) = decode_name(&data[..end], start + 6)?;
+            rdata.extend(encode_name(&target));
+            Ok(rdata)
+        }
+        QueryType::NAPTR => {
+            // order, preference, then the flags, services and regexp strings
+            let mut i = start + 4;
+            for _ in 0..3 {
+                i += 1 + *data
+                    .get(i)
+                    .ok_or_else(|| ClientError::DecodeError("Truncated NAPTR record".to_string()))?
+                    as usize;
+            }
+            if i > end {
+                return Err(ClientError::DecodeError(
+                    "Truncated NAPTR record".to_string(),
+                ));
+            }
+            let (replacement, _) = decode_name(&data[..end], i)?;
+            let mut rdata = data[start..i].to_vec();
+            rdata.extend(encode_name(&replacement));
+            Ok(rdata)
+        }
+        QueryType::CNAME | QueryType::NS | QueryType::PTR | QueryType::DNAME => {
+            let (name, _) = decode_name(&data[..end], start)?;
+            Ok(encode_name(&name))
+        }
+        QueryType::SOA => {
+            let (mname, i) = decode_name(&data[..end], start)?;
+            let (rname, i) = decode_name(&data[..end], i)?;
+            let mut rdata = encode_name(&mname);
+            rdata.extend(encode_name(&rname));
+            rdata.extend(&data[i..end]);
+            Ok(rdata)
+        }
+        _ => Ok(data[start..end].to_vec()),
+    }
+}
+
+/// A DNS message, RFC 1035 4.1
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Message {
+    pub(crate) header: Header,
+    pub(crate) questions: Vec<Question>,
+    pub(crate) answers: Vec<Record>,
+    pub(crate) authorities: Vec<Record>,
+    pub(crate) additionals: Vec<Record>,
+}
+
+impl Message {
+    /// Recursive query of `name` in `class`
+    pub(crate) fn query(id: u16, name: &str, query_type: &QueryType, class: u16) -> Message {
+        Message {
+            header: Header {
+                id,
+                flags: Header::RD,
+            },
+            questions: vec![Question {
+                name: name.to_string(),
+                query_type: query_type.clone(),
+                class,
+            }],
+            answers: Vec::new(),
+            authorities: Vec::new(),
+            additionals: Vec::new(),
+        }
+    }
+
+    /// Dynamic update of `zone`, its zone section in place of the question,
+    /// RFC 2136 2.3. Prerequisites go in the answer section and updates in
+    /// the authority one.
+    #[cfg(feature = "zones")]
+    pub(crate) fn update(id: u16, zone: &str, class: u16) -> Message {
+        Message {
+            header: Header {
+                id,
+                flags: Header::UPDATE << 11,
+            },
+            questions: vec![Question {
+                name: zone.to_string(),
+                query_type: QueryType::SOA,
+                class,
+            }],
+            answers: Vec::new(),
+            authorities: Vec::new(),
+            additionals: Vec::new(),
+        }
+    }
+
+    /// Response to `query` with its question only and `rcode`, for queries
+    /// answered without asking upstream
+    #[cfg(any(feature = "serve", feature = "discovery", test))]
+    pub(crate) fn reply(query: &Message, rcode: u8) -> Message {
+        Message {
+            header: Header {
+                id: query.header.id,
+                flags: Header::QR
+                    | (query.header.flags & (0x7800 | Header::RD))
+                    | Header::RA
+                    | rcode as u16,
+            },
+            questions: query.questions.clone(),
+            answers: Vec::new(),
+            authorities: Vec::new(),
+            additionals: Vec::new(),
+        }
+    }
+
+    /// Drops the records and sets the TC flag, for a response too large for
+    /// the UDP payload of the client, RFC 2181 9
+    #[cfg(any(feature = "serve", test))]
+    pub(crate) fn truncate(&mut self) {
+        self.header.flags |= Header::TC;
+        self.answers.clear();
+        self.authorities.clear();
+        self.additionals.clear();
+    }
+
+    /// UDP payload size the sender accepts, as advertised by its OPT record,
+    /// 512 bytes without one, RFC 6891 6.2.5
+    #[cfg(feature = "serve")]
+    pub(crate) fn udp_payload_size(&self) -> usize {
+        self.additionals
+            .iter()
+            .find(|record| record.query_type == QueryType::OPT)
+            .map_or(512, |opt| opt.class.max(512) as usize)
+    }
+
+    /// Whether the sender supports EDNS, with an OPT record, RFC 6891 7
+    #[cfg(feature = "serve")]
+    pub(crate) fn has_edns(&self) -> bool {
+        self.additionals
+            .iter()
+            .any(|record| record.query_type == QueryType::OPT)
+    }
+
+    /// DO bit of the OPT record, set by senders wanting DNSSEC records,
+    /// RFC 3225
+    #[cfg(feature = "serve")]
+    pub(crate) fn dnssec_ok(&self) -> bool {
+        self.additionals
+            .iter()
+            .any(|record| record.query_type == QueryType::OPT && record.ttl & 0x8000 != 0)
+    }
+
+    /// Drops the OPT record, for a response to a sender without EDNS support,
+    /// RFC 6891 7
+    #[cfg(feature = "serve")]
+    pub(crate) fn remove_edns(&mut self) {
+        self.additionals
+            .retain(|record| record.query_type != QueryType::OPT);
+    }
+
+    /// Appends an EDNS0 OPT pseudo-RR (RFC 6891) advertising a 4096 bytes UDP
+    /// payload, with the DO bit (RFC 3225) set when DNSSEC records are wanted
+    pub(crate) fn add_edns(&mut self, dnssec_ok: bool) {
+        self.additionals.push(Record {
+            name: ".".to_string(),
+            query_type: QueryType::OPT,
+            class: EDNS_PAYLOAD_SIZE,
+            ttl: if dnssec_ok { 0x8000 } else { 0 },
+            rdata: Vec::new(),
+        });
+    }
+
+    /// Clears the Recursion Desired flag, to ask authoritative servers what
+    /// they know themselves
+    pub(crate) fn clear_recursion_desired(&mut self) {
+        self.header.flags &= !Header::RD;
+    }
+
+    /// Sets the Checking Disabled flag, for the answers of a validating
+    /// resolver even when they fail validation
+    pub(crate) fn set_checking_disabled(&mut self) {
+        self.header.flags |= Header::CD;
+    }
+
+    /// Sets the Authentic Data flag, asking for it in the response even
+    /// without the DO bit, RFC 6840 5.7
+    pub(crate) fn set_authentic_data(&mut self) {
+        self.header.flags |= Header::AD;
+    }
+
+    /// Sets option `code` of the OPT record to `value`, keeping the other
+    /// options
+    pub(crate) fn set_option(&mut self, code: u16, value: &[u8]) {
+        let opt = match self
+            .additionals
+            .iter_mut()
+            .find(|record| record.query_type == QueryType::OPT)
+        {
+            Some(opt) => opt,
+            None => return,
+        };
+        let mut writer = Writer::new();
+        let mut reader = Reader::new(&opt.rdata);
+        while reader.remaining() >= 4 {
+            let (other, len) = match (reader.get_u16(), reader.get_u16()) {
+                (Ok(other), Ok(len)) => (other, len),
+                _ => break,
+            };
+            match reader.get_bytes(len as usize) {
+                Ok(_) if other == code => {}
+                Ok(bytes) => {
+                    writer.put_u16(other);
+                    writer.put_u16(len);
+                    writer.put_bytes(bytes);
+                }
+                Err(_) => break,
+            }
+        }
+        writer.put_u16(code);
+        writer.put_u16(value.len() as u16);
+        writer.put_bytes(value);
+        opt.rdata = writer.finish();
+    }
+
+    /// Sets the COOKIE option of the OPT record, the client cookie followed by
+    /// the server cookie once the server sent one, RFC 7873 4
+    pub(crate) fn set_cookie(&mut self, client: &[u8], server: Option<&[u8]>) {
+        let mut cookie = client.to_vec();
+        cookie.extend(server.unwrap_or_default());
+        self.set_option(COOKIE, &cookie);
+    }
+
+    /// Sets the PADDING option of the OPT record so the encoded message is a
+    /// multiple of `block` bytes long, RFC 7830 and RFC 8467 4.1
+    pub(crate) fn pad(&mut self, block: usize) {
+        if block == 0 {
+            return;
+        }
+        self.set_option(PADDING, &[]);
+        let len = self.encode().len();
+        self.set_option(PADDING, &vec![0; (block - len % block) % block]);
+    }
+
+    /// Adds an extended DNS error to the OPT record, appending one when
+    /// missing, RFC 8914
+    #[cfg(feature = "serve")]
+    pub(crate) fn add_extended_error(&mut self, info_code: u16) {
+        if !self
+            .additionals
+            .iter()
+            .any(|record| record.query_type == QueryType::OPT)
+        {
+            self.add_edns(false);
+        }
+        if let Some(opt) = self
+            .additionals
+            .iter_mut()
+            .find(|record| record.query_type == QueryType::OPT)
+        {
+            let mut writer = Writer::new();
+            writer.put_bytes(&opt.rdata);
+            writer.put_u16(EXTENDED_ERROR);
+            writer.put_u16(2);
+            writer.put_u16(info_code);
+            opt.rdata = writer.finish();
+        }
+    }
+
+    /// Codes and values of the options of the OPT record, up to the first
+    /// that doesn't fit in it
+    pub(crate) fn options(&self) -> Vec<(u16, Vec<u8>)> {
+        let mut options = Vec::new();
+        let Some(opt) = self
+            .additionals
+            .iter()
+            .find(|record| record.query_type == QueryType::OPT)
+        else {
+            return options;
+        };
+        let mut reader = Reader::new(&opt.rdata);
+        while reader.remaining() >= 4 {
+            let (Ok(code), Ok(len)) = (reader.get_u16(), reader.get_u16()) else {
+                break;
+            };
+            let Ok(value) = reader.get_bytes(len as usize) else {
+                break;
+            };
+            options.push((code, value.to_vec()));
+        }
+        options
+    }
+
+    /// Value of option `code` of the OPT record, if any
+    pub(crate) fn option(&self, code: u16) -> Option<Vec<u8>> {
+        self.options()
+            .into_iter()
+            .find(|(other, _)| *other == code)
+            .map(|(_, value)| value)
+    }
+
+    /// Value of the COOKIE option of the OPT record, if any
+    pub(crate) fn cookie(&self) -> Option<Vec<u8>> {
+        self.option(COOKIE)
+    }
+
+    /// Response code with the upper 8 bits the OPT record carries, RFC 6891 6.1.3
+    pub(crate) fn extended_rcode(&self) -> u16 {
+        let upper = self
+            .additionals
+            .iter()
+            .find(|record| record.query_type == QueryType::OPT)
+            .map(|opt| (opt.ttl >> 24) as u16)
+            .unwrap_or(0);
+        (upper << 4) | self.header.rcode() as u16
+    }
+
+    pub(crate) fn encode(&self) -> Vec<u8> {
+        let mut writer = Writer::new();
+        writer.put_u16(self.header.id);
+        writer.put_u16(self.header.flags);
+        writer.put_u16(self.questions.len() as u16);
+        writer.put_u16(self.answers.len() as u16);
+        writer.put_u16(self.authorities.len() as u16);
+        writer.put_u16(self.additionals.len() as u16);
+        for question in &self.questions {
+            writer.put_name(&question.name);
+            writer.put_u16(question.query_type.code());
+            writer.put_u16(question.class);
+        }
+        for record in self
+            .answers
+            .iter()
+            .chain(&self.authorities)
+            .chain(&self.additionals)
+        {
+            record.encode(&mut writer);
+        }
+        writer.finish()
+    }
+
+    pub(crate) fn decode(data: &[u8]) -> Result<Message, ClientError> {
+        let mut reader = Reader::new(data);
+        let header = Header {
+            id: reader.get_u16()?,
+            flags: reader.get_u16()?,
+        };
+        let counts = [
+            reader.get_u16()?,
+            reader.get_u16()?,
+            reader.get_u16()?,
+            reader.get_u16()?,
+        ];
+        let mut questions: Vec<Question> = Vec::new();
+        for _ in 0..counts[0] {
+            questions.push(Question {
+                name: reader.get_name()?,
+                query_type: QueryType::from_code(reader.get_u16()?),
+                class: reader.get_u16()?,
+            });
+        }
+        let mut sections: Vec<Vec<Record>> = Vec::new();
+        for count in &counts[1..] {
+            let mut records: Vec<Record> = Vec::new();
+            for _ in 0..*count {
+                records.push(Record::decode(&mut reader)?);
+            }
+            sections.push(records);
+        }
+        if reader.remaining() > 0 {
+            log::debug!("Ignoring {} trailing bytes", reader.remaining());
+        }
+        let additionals = sections.pop().unwrap_or_default();
+        let authorities = sections.pop().unwrap_or_default();
+        let answers = sections.pop().unwrap_or_default();
+        Ok(Message {
+            header,
+            questions,
+            answers,
+            authorities,
+            additionals,
+        })
+    }
+}
+
+/// Builds any DNS message, flag by flag and record by record, for tools
+/// made with the library and the responses of `nsq serve`. The RDATA of the
+/// records is in wire format, with names uncompressed.
+#[derive(Debug, Clone)]
+pub struct MessageBuilder {
+    message: Message,
+}
+
+impl MessageBuilder {
+    /// A message with `id`, no flag set and empty sections
+    pub fn new(id: u16) -> MessageBuilder {
+        MessageBuilder {
+            message: Message {
+                header: Header { id, flags: 0 },
+                questions: Vec::new(),
+                answers: Vec::new(),
+                authorities: Vec::new(),
+                additionals: Vec::new(),
+            },
+        }
+    }
+
+    /// A response to `query` with its ID, opcode, RD flag and question, RA
+    /// set and `rcode`
+    #[cfg(any(feature = "serve", test))]
+    pub(crate) fn reply(query: &Message, rcode: u8) -> MessageBuilder {
+        MessageBuilder {
+            message: Message::reply(query, rcode),
+        }
+    }
+
+    fn with_flag(mut self, flag: u16) -> MessageBuilder {
+        self.message.header.flags |= flag;
+        self
+    }
+
+    /// Marks the message as a response (QR)
+    pub fn with_response(self) -> MessageBuilder {
+        self.with_flag(Header::QR)
+    }
+
+    /// Sets the opcode, 0 for queries and 5 for dynamic updates
+    pub fn with_opcode(mut self, opcode: u8) -> MessageBuilder {
+        self.message.header.flags &= !0x7800;
+        self.message.header.flags |= ((opcode & 0x0f) as u16) << 11;
+        self
+    }
+
+    /// Sets the 4 bits of the response code the header has
+    pub fn with_rcode(mut self, rcode: u8) -> MessageBuilder {
+        self.message.header.flags &= !0x000f;
+        self.message.header.flags |= (rcode & 0x0f) as u16;
+        self
+    }
+
+    pub fn with_authoritative(self) -> MessageBuilder {
+        self.with_flag(Header::AA)
+    }
+
+    pub fn with_truncated(self) -> MessageBuilder {
+        self.with_flag(Header::TC)
+    }
+
+    pub fn with_recursion_desired(self) -> MessageBuilder {
+        self.with_flag(Header::RD)
+    }
+
+    pub fn with_recursion_available(self) -> MessageBuilder {
+        self.with_flag(Header::RA)
+    }
+
+    pub fn with_authentic_data(self) -> MessageBuilder {
+        self.with_flag(Header::AD)
+    }
+
+    pub fn with_checking_disabled(self) -> MessageBuilder {
+        self.with_flag(Header::CD)
+    }
+
+    /// Adds a question, any number of them being allowed
+    pub fn with_question(
+        mut self,
+        name: &str,
+        query_type: QueryType,
+        class: ClassType,
+    ) -> MessageBuilder {
+        self.message.questions.push(Question {
+            name: name.to_string(),
+            query_type,
+            class: class.code(),
+        });
+        self
+    }
+
+    fn record(
+        name: &str,
+        query_type: QueryType,
+        class: ClassType,
+        ttl: u32,
+        rdata: Vec<u8>,
+    ) -> Record {
+        Record {
+            name: name.to_string(),
+            query_type,
+            class: class.code(),
+            ttl,
+            rdata,
+        }
+    }
+
+    pub fn with_answer(
+        mut self,
+        name: &str,
+        query_type: QueryType,
+        class: ClassType,
+        ttl: u32,
+        rdata: Vec<u8>,
+    ) -> MessageBuilder {
+        let record = MessageBuilder::record(name, query_type, class, ttl, rdata);
+        self.message.answers.push(record);
+        self
+    }
+
+    pub fn with_authority(
+        mut self,
+        name: &str,
+        query_type: QueryType,
+        class: ClassType,
+        ttl: u32,
+        rdata: Vec<u8>,
+    ) -> MessageBuilder {
+        let record = MessageBuilder::record(name, query_type, class, ttl, rdata);
+        self.message.authorities.push(record);
+        self
+    }
+
+    pub fn with_additional(
+        mut self,
+        name: &str,
+        query_type: QueryType,
+        class: ClassType,
+        ttl: u32,
+        rdata: Vec<u8>,
+    ) -> MessageBuilder {
+        let record = MessageBuilder::record(name, query_type, class, ttl, rdata);
+        self.message.additionals.push(record);
+        self
+    }
+
+    /// Adds an OPT record advertising `payload_size` bytes of UDP payload,
+    /// with the DO bit when `dnssec_ok`, RFC 6891
+    pub fn with_edns(mut self, payload_size: u16, dnssec_ok: bool) -> MessageBuilder {
+        self.message.add_edns(dnssec_ok);
+        if let Some(opt) = self.message.additionals.last_mut() {
+            opt.class = payload_size;
+        }
+        self
+    }
+
+    /// Sets EDNS option `code` to `value`, once the OPT record was added
+    pub fn with_edns_option(mut self, code: u16, value: &[u8]) -> MessageBuilder {
+        self.message.set_option(code, value);
+        self
+    }
+
+    #[cfg(feature = "serve")]
+    pub(crate) fn message(self) -> Message {
+        self.message
+    }
+
+    /// The message in wire format
+    pub fn build(self) -> Vec<u8> {
+        self.message.encode()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(name: &str, query_type: QueryType, rdata: &[u8]) -> Record {
+        Record {
+            name: name.to_string(),
+            query_type,
+            class: 1,
+            ttl: 300,
+            rdata: rdata.to_vec(),
+        }
+    }
+
+    #[test]
+    fn query_round_trip() {
+        let mut query = Message::query(0x1234, "www.crates.io", &QueryType::AAAA, 1);
+        query.add_edns(true);
+        let encoded = query.encode();
+        assert_eq!(&encoded[..12], &[0x12, 0x34, 1, 0, 0, 1, 0, 0, 0, 0, 0, 1]);
+        let decoded = Message::decode(&encoded).unwrap();
+        assert_eq!(decoded.header, query.header);
+        assert_eq!(decoded.questions[0].name, "www.crates.io.");
+        assert_eq!(decoded.questions[0].query_type, QueryType::AAAA);
+        assert_eq!(decoded.additionals, query.additionals);
+    }
+
+    #[test]
+    fn response_round_trip() {
+        let mut response = Message::query(7, "crates.io.", &QueryType::MX, 1);
+        response.header.flags = 0x8180;
+        response.answers = vec![
+            record(
+                "crates.io.",
+                QueryType::MX,
+                b"\x00\x0a\x04mail\x06crates\x02io\x00",
+            ),
+            record("crates.io.", QueryType::A, &[192, 0, 2, 1]),
+        ];
+        response.authorities = vec![record("io.", QueryType::NS, b"\x02ns\x02io\x00")];
+        let decoded = Message::decode(&response.encode()).unwrap();
+        assert_eq!(decoded, response);
+        assert!(decoded.header.is_response());
+        assert_eq!(decoded.header.rcode(), 0);
+    }
+
+    #[test]
+    fn compressed_names_are_expanded() {
+        let mut data = Message::query(1, "example.com", &QueryType::CNAME, 1).encode();
+        data[2] = 0x81;
+        data[7] = 1;
+        // owner points to the question name, the target to "example.com" in it
+        data.extend([0xc0, 0x0c, 0, 5, 0, 1, 0, 0, 0, 60, 0, 6]);
+        data.extend([3, b'w', b'w', b'w', 0xc0, 0x0c]);
+        let decoded = Message::decode(&data).unwrap();
+        assert_eq!(decoded.answers[0].name, "example.com.");
+        assert_eq!(decoded.answers[0].rdata, encode_name("www.example.com"));
+    }
+
+    #[test]
+    fn forward_pointers_are_rejected() {
+        let data = [0xc0, 0x02, 0x00];
+        assert!(decode_name(&data, 0).is_err());
+        let data = [0x01, b'a', 0xc0, 0x00];
+        assert!(decode_name(&data, 2).is_err());
+    }
+
+    #[test]
+    fn truncated_messages_fail() {
+        let encoded = Message::query(1, "example.com", &QueryType::A, 1).encode();
+        for len in 0..encoded.len() {
+            assert!(Message::decode(&encoded[..len]).is_err());
+        }
+    }
+
+    #[test]
+    fn root_name() {
+        assert_eq!(encode_name("."), vec![0]);
+        assert_eq!(decode_name(&[0], 0).unwrap(), (".".to_string(), 1));
+    }
+
+    #[test]
+    fn padded_to_block() {
+        for name in ["a.io", "www.example.com", "a-rather-long-label.example.org"] {
+            let mut query = Message::query(1, name, &QueryType::A, 1);
+            query.add_edns(false);
+            query.set_cookie(&[1; 8], None);
+            query.pad(128);
+            assert_eq!(query.encode().len(), 128);
+            assert!(query.option(PADDING).unwrap().iter().all(|b| *b == 0));
+        }
+    }
+
+    #[test]
+    fn built_message() {
+        let encoded = MessageBuilder::new(0xbeef)
+            .with_response()
+            .with_opcode(0)
+            .with_rcode(3)
+            .with_authoritative()
+            .with_question("nope.crates.io.", QueryType::A, ClassType::IN)
+            .with_question("nope.crates.io.", QueryType::AAAA, ClassType::IN)
+            .with_authority(
+                "crates.io.",
+                QueryType::NS,
+                ClassType::IN,
+                60,
+                encode_name("ns.crates.io"),
+            )
+            .with_additional(
+                "ns.crates.io.",
+                QueryType::A,
+                ClassType::IN,
+                60,
+                vec![192, 0, 2, 53],
+            )
+            .with_edns(1232, true)
+            .with_edns_option(COOKIE, &[7; 8])
+            .build();
+        let message = Message::decode(&encoded).unwrap();
+        assert_eq!(message.header.id, 0xbeef);
+        assert_eq!(message.header.flag_names(), vec!["qr", "aa"]);
+        assert_eq!(message.header.rcode(), 3);
+        assert_eq!(message.questions.len(), 2);
+        assert!(message.answers.is_empty());
+        assert_eq!(message.authorities[0].rdata, encode_name("ns.crates.io"));
+        assert_eq!(message.additionals[1].class, 1232);
+        assert_eq!(message.additionals[1].ttl, 0x8000);
+        assert_eq!(message.cookie(), Some(vec![7; 8]));
+    }
+
+    /// Round trips of generated messages, each decoding back to the one
+    /// encoded, as record types are added to the codec
+    mod round_trip {
+        use super::*;
+        use proptest::prelude::*;
+
+        /// A fully qualified name, as decoded names are, of 0 to 4 labels
+        fn name() -> impl Strategy<Value = String> {
+            prop::collection::vec("[a-z0-9]([a-z0-9-]{0,20}[a-z0-9])?", 0..5)
+                .prop_map(|labels| labels.join(".") + ".")
+        }
+
+        fn u16s(count: usize) -> impl Strategy<Value = Vec<u8>> {
+            prop::collection::vec(any::<u16>(), count)
+                .prop_map(|values| values.iter().flat_map(|v| v.to_be_bytes()).collect())
+        }
+
+        /// A type and RDATA of its wire format, types with names in their
+        /// RDATA included, as decoding expands those
+        fn rdata() -> impl Strategy<Value = (QueryType, Vec<u8>)> {
+            prop_oneof![
+                any::<[u8; 4]>().prop_map(|addr| (QueryType::A, addr.to_vec())),
+                any::<[u8; 16]>().prop_map(|addr| (QueryType::AAAA, addr.to_vec())),
+                // NS, CNAME, PTR and DNAME
+                (prop::sample::select(vec![2u16, 5, 12, 39]), name())
+                    .prop_map(|(code, target)| (QueryType::from_code(code), encode_name(&target))),
+                (any::<u16>(), name()).prop_map(|(preference, exchange)| {
+                    let mut rdata = preference.to_be_bytes().to_vec();
+                    rdata.extend(encode_name(&exchange));
+                    (QueryType::MX, rdata)
+                }),
+                (u16s(3), name()).prop_map(|(mut rdata, target)| {
+                    rdata.extend(encode_name(&target));
+                    (QueryType::SRV, rdata)
+                }),
+                (name(), name(), u16s(10)).prop_map(|(mname, rname, numbers)| {
+                    let mut rdata = encode_name(&mname);
+                    rdata.extend(encode_name(&rname));
+                    rdata.extend(numbers);
+                    (QueryType::SOA, rdata)
+                }),
+                prop::collection::vec(prop::collection::vec(any::<u8>(), 0..40), 1..4).prop_map(
+                    |strings| {
+                        let mut rdata = Vec::new();
+                        for string in strings {
+                            rdata.push(string.len() as u8);
+                            rdata.extend(string);
+                        }
+                        (QueryType::TXT, rdata)
+                    }
+                ),
+                // private use types, carried as they are
+                (65280u16..65535, prop::collection::vec(any::<u8>(), 0..64))
+                    .prop_map(|(code, rdata)| (QueryType::from_code(code), rdata)),
+            ]
+        }
+
+        fn records() -> impl Strategy<Value = Vec<Record>> {
+            prop::collection::vec((name(), rdata(), any::<u16>(), any::<u32>()), 0..4).prop_map(
+                |records| {
+                    records
+                        .into_iter()
+                        .map(|(name, (query_type, rdata), class, ttl)| Record {
+                            name,
+                            query_type,
+                            class,
+                            ttl,
+                            rdata,
+                        })
+                        .collect()
+                },
+            )
+        }
+
+        fn message() -> impl Strategy<Value = Message> {
+            let question =
+                (name(), any::<u16>(), any::<u16>()).prop_map(|(name, code, class)| Question {
+                    name,
+                    query_type: QueryType::from_code(code),
+                    class,
+                });
+            (
+                any::<u16>(),
+                any::<u16>(),
+                prop::collection::vec(question, 0..3),
+                records(),
+                records(),
+                records(),
+            )
+                .prop_map(
+                    |(id, flags, questions, answers, authorities, additionals)| Message {
+                        header: Header { id, flags },
+                        questions,
+                        answers,
+                        authorities,
+                        additionals,
+                    },
+                )
+        }
+
+        proptest! {
+            #[test]
+            fn encoded_messages_decode_back(message in message()) {
+                let decoded = Message::decode(&message.encode()).unwrap();
+                prop_assert_eq!(decoded, message);
+            }
+
+            #[test]
+            fn any_bytes_decode_or_fail(data in prop::collection::vec(any::<u8>(), 0..512)) {
+                let _ = Message::decode(&data);
+            }
+        }
+    }
+}
@@ -0,0 +1,457 @@
+//! Zone transfers from authoritative servers over TCP, full ones with AXFR
+//! (RFC 5936) and incremental ones with IXFR (RFC 1995)
+use crate::client::{self, ClassType, ClientError, QueryType, Response};
+use crate::idn;
+use crate::rdata::{self, RData, ResourceRecord};
+use crate::transport::TcpTransport;
+use crate::tsig::{TsigKey, TsigVerifier};
+use crate::wire::{Message, Record, Writer};
+use futures::stream::{self, Stream, TryStreamExt};
+use rand::random;
+use std::time::{Duration, Instant};
+use tokio::time::timeout;
+
+/// A transfer in progress, its records streamed a message at a time
+pub struct ZoneTransfer {
+    transport: TcpTransport,
+    zone: String,
+    id: u16,
+    timeout: Duration,
+    /// Serial of the SOA record the transfer starts with
+    serial: Option<u32>,
+    /// Records with that SOA that end the transfer, unknown for an IXFR until
+    /// its second record tells whether it's incremental
+    closing: Option<usize>,
+    /// Serial an IXFR asked the changes since
+    since: Option<u32>,
+    soas: usize,
+    /// Checks the signatures of the messages when the query was signed
+    tsig: Option<TsigVerifier>,
+    messages: usize,
+    records: usize,
+    bytes: usize,
+}
+
+/// Serial of a SOA record
+fn soa_serial(record: &Record) -> Option<u32> {
+    if record.query_type != QueryType::SOA {
+        return None;
+    }
+    match rdata::decode(&record.query_type, &record.rdata)? {
+        RData::SOA { serial, .. } => Some(serial),
+        _ => None,
+    }
+}
+
+impl ZoneTransfer {
+    /// Asks `server` for every record of `zone`, signing the query with `tsig`
+    /// when given
+    pub async fn axfr(
+        server: String,
+        zone: &str,
+        class: ClassType,
+        wait: Duration,
+        tsig: Option<TsigKey>,
+    ) -> Result<ZoneTransfer, ClientError> {
+        let mut transfer = ZoneTransfer::connect(server, zone, wait).await?;
+        let query = Message::query(transfer.id, &transfer.zone, &QueryType::AXFR, class.code());
+        transfer.closing = Some(2);
+        transfer.send(query, tsig).await?;
+        Ok(transfer)
+    }
+
+    /// Asks `server` for the changes of `zone` since `serial`, signing the
+    /// query with `tsig` when given
+    pub async fn ixfr(
+        server: String,
+        zone: &str,
+        serial: u32,
+        class: ClassType,
+        wait: Duration,
+        tsig: Option<TsigKey>,
+    ) -> Result<ZoneTransfer, ClientError> {
+        let mut transfer = ZoneTransfer::connect(server, zone, wait).await?;
+        transfer.since = Some(serial);
+        let mut query = Message::query(transfer.id, &transfer.zone, &QueryType::IXFR, class.code());
+        // the SOA the client has, only its serial matters, RFC 1995 3
+        let mut soa = Writer::new();
+        soa.put_name(".");
+        soa.put_name(".");
+        for value in [serial, 0, 0, 0, 0] {
+            soa.put_u32(value);
+        }
+        query.authorities.push(Record {
+            name: transfer.zone.clone(),
+            query_type: QueryType::SOA,
+            class: class.code(),
+            ttl: 0,
+            rdata: soa.finish(),
+        });
+        transfer.send(query, tsig).await?;
+        Ok(transfer)
+    }
+
+    async fn connect(
+        server: String,
+        zone: &str,
+        wait: Duration,
+    ) -> Result<ZoneTransfer, ClientError> {
+        let transport = match timeout(wait, TcpTransport::connect(server)).await {
+            Ok(transport) => transport?,
+            Err(_) => return Err(ClientError::Timeout(1)),
+        };
+        Ok(ZoneTransfer {
+            transport,
+            zone: idn::to_ascii(zone)?,
+            id: random(),
+            timeout: wait,
+            serial: None,
+            closing: None,
+            since: None,
+            soas: 0,
+            tsig: None,
+            messages: 0,
+            records: 0,
+            bytes: 0,
+        })
+    }
+
+    async fn send(&mut self, mut query: Message, tsig: Option<TsigKey>) -> Result<(), ClientError> {
+        query.clear_recursion_desired();
+        log::debug!("Query {:x?}", query);
+        let data = match tsig {
+            Some(key) => {
+                let (signed, mac) = key.sign(&query.encode())?;
+                self.tsig = Some(TsigVerifier::new(key, mac));
+                signed
+            }
+            None => query.encode(),
+        };
+        self.transport.send(&data).await
+    }
+
+    /// The next message of the transfer, none once the closing SOA record
+    /// arrived
+    pub async fn next(&mut self) -> Result<Option<Response>, ClientError> {
+        if self.is_done() {
+            return Ok(None);
+        }
+        let started = Instant::now();
+        let data = match timeout(self.timeout, self.transport.recv()).await {
+            Ok(data) => data?,
+            Err(_) => return Err(ClientError::Timeout(1)),
+        };
+        let message = Message::decode(&data)?;
+        log::debug!("Response {:x?}", &message);
+        if message.header.id != self.id {
+            return Err(ClientError::DecodeIdError(std::format!(
+                "Expected id {}, received {}",
+                self.id,
+                message.header.id
+            )));
+        }
+        if let Some(verifier) = &mut self.tsig {
+            verifier.verify(&data)?;
+        }
+        match message.header.rcode() {
+            0 => (),
+            1 => return Err(ClientError::RDCodeFormatError),
+            4 => return Err(ClientError::RDCodeNotImplemented),
+            rcode => {
+                return Err(ClientError::GenericError(std::format!(
+                    "{} answered the transfer of {} with {}",
+                    self.transport.server(),
+                    self.zone,
+                    client::rcode_name(rcode)
+                )))
+            }
+        }
+        let serial = match (self.serial, message.answers.first().and_then(soa_serial)) {
+            (Some(serial), _) => serial,
+            (None, Some(serial)) => serial,
+            (None, None) => {
+                return Err(ClientError::DecodeError(std::format!(
+                    "The transfer of {} doesn't start with its SOA record",
+                    self.zone
+                )))
+            }
+        };
+        if self.closing.is_none() {
+            // a lone SOA when up to date, a SOA of an older serial second when
+            // incremental, else the whole zone, RFC 1995 4. Servers may send a
+            // record per message, so the second one can come in the next.
+            let second = message.answers.get(1 - self.records.min(1));
+            let newer = |since: u32| serial.wrapping_sub(since) as i32 > 0;
+            self.closing = match second.map(soa_serial) {
+                None if self.since.is_some_and(newer) => None,
+                None => Some(1),
+                Some(Some(older)) if older != serial => Some(3),
+                Some(_) => Some(2),
+            };
+        }
+        self.serial = Some(serial);
+        self.soas += message
+            .answers
+            .iter()
+            .filter(|record| soa_serial(record) == Some(serial))
+            .count();
+        self.messages += 1;
+        self.records += message.answers.len();
+        self.bytes += data.len();
+        if let (true, Some(verifier)) = (self.is_done(), &self.tsig) {
+            verifier.finish()?;
+        }
+        Ok(Some(Response::new(
+            message,
+            self.transport.server(),
+            data.len(),
+            started.elapsed(),
+        )))
+    }
+
+    /// The records of the transfer as they arrive, both SOA records included,
+    /// holding a single message at a time however large the zone
+    pub fn into_records(self) -> impl Stream<Item = Result<ResourceRecord, ClientError>> {
+        stream::try_unfold(self, |mut transfer| async move {
+            let Some(response) = transfer.next().await? else {
+                return Ok(None);
+            };
+            let records = response.message.answers.into_iter();
+            let records = records.map(ResourceRecord::from_wire);
+            Ok(Some((stream::iter(records), transfer)))
+        })
+        .try_flatten()
+    }
+
+    /// Whether the closing SOA record was received
+    pub fn is_done(&self) -> bool {
+        matches!(self.closing, Some(closing) if self.soas >= closing)
+    }
+
+    /// Messages received so far
+    pub fn messages(&self) -> usize {
+        self.messages
+    }
+
+    /// Records received so far, both SOA records included
+    pub fn records(&self) -> usize {
+        self.records
+    }
+
+    /// Bytes received so far, without the length prefixes
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+}
+
+/// Records a zone lost and gained going from one serial to the next
+#[derive(Debug)]
+pub struct Diff {
+    pub from: u32,
+    pub to: u32,
+    pub(crate) deleted: Vec<Record>,
+    pub(crate) added: Vec<Record>,
+}
+
+/// The serial steps of an IXFR response, RFC 1995 4: no step when the zone is
+/// up to date, none at all when the server sent the whole zone instead
+pub fn diffs(responses: &[Response]) -> Option<Vec<Diff>> {
+    let records: Vec<&Record> = responses
+        .iter()
+        .flat_map(|response| &response.message.answers)
+        .collect();
+    let current = soa_serial(records.first()?)?;
+    let changes = match records.len() {
+        0 | 1 => return Some(Vec::new()),
+        len => &records[1..len - 1],
+    };
+    match changes.first().map(|record| soa_serial(record)) {
+        Some(Some(older)) if older != current => (),
+        _ => return None,
+    }
+    let mut diffs: Vec<Diff> = Vec::new();
+    // each step is the old SOA, the deleted records, the new SOA, the added ones
+    let mut adding = true;
+    for record in changes {
+        match (soa_serial(record), adding) {
+            (Some(from), true) => {
+                diffs.push(Diff {
+                    from,
+                    to: from,
+                    deleted: Vec::new(),
+                    added: Vec::new(),
+                });
+                adding = false;
+            }
+            (Some(to), false) => {
+                diffs.last_mut()?.to = to;
+                adding = true;
+            }
+            (None, true) => diffs.last_mut()?.added.push((*record).clone()),
+            (None, false) => diffs.last_mut()?.deleted.push((*record).clone()),
+        }
+    }
+    Some(diffs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wire::{self, MessageBuilder};
+    use futures::StreamExt;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    /// SOA RDATA of example. with `serial`
+    fn soa(serial: u32) -> (QueryType, Vec<u8>) {
+        let mut rdata = [
+            wire::encode_name("ns.example."),
+            wire::encode_name("admin.example."),
+        ]
+        .concat();
+        for value in [serial, 7200, 3600, 1209600, 300] {
+            rdata.extend(value.to_be_bytes());
+        }
+        (QueryType::SOA, rdata)
+    }
+
+    fn address(last: u8) -> (QueryType, Vec<u8>) {
+        (QueryType::A, vec![192, 0, 2, last])
+    }
+
+    /// Answers a transfer on a loopback TCP port with `messages`, each holding
+    /// records of example.
+    async fn server(messages: Vec<Vec<(QueryType, Vec<u8>)>>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut query = vec![0; stream.read_u16().await.unwrap() as usize];
+            stream.read_exact(&mut query).await.unwrap();
+            let query = Message::decode(&query).unwrap();
+            for records in messages {
+                let mut response = MessageBuilder::reply(&query, 0);
+                for (query_type, rdata) in records {
+                    response =
+                        response.with_answer("example.", query_type, ClassType::IN, 300, rdata);
+                }
+                let response = response.build();
+                stream.write_u16(response.len() as u16).await.unwrap();
+                stream.write_all(&response).await.unwrap();
+            }
+        });
+        addr
+    }
+
+    /// Every message of `transfer`
+    async fn receive(mut transfer: ZoneTransfer) -> Vec<Response> {
+        let mut responses = Vec::new();
+        while let Some(response) = transfer.next().await.unwrap() {
+            responses.push(response);
+        }
+        assert!(transfer.is_done());
+        responses
+    }
+
+    async fn ixfr(server: String, serial: u32) -> ZoneTransfer {
+        let wait = Duration::from_secs(2);
+        ZoneTransfer::ixfr(server, "example.", serial, ClassType::IN, wait, None)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn axfr_with_a_record_per_message() {
+        let records = vec![soa(5), address(1), address(2), soa(5)];
+        let addr = server(records.into_iter().map(|record| vec![record]).collect()).await;
+        let wait = Duration::from_secs(2);
+        let transfer = ZoneTransfer::axfr(addr, "example.", ClassType::IN, wait, None)
+            .await
+            .unwrap();
+        let records: Vec<_> = transfer.into_records().collect().await;
+        assert_eq!(records.len(), 4);
+        assert!(records.iter().all(|record| record.is_ok()));
+    }
+
+    #[tokio::test]
+    async fn ixfr_with_a_record_per_message() {
+        // the changes from 3 to 5, deleting 192.0.2.1 and adding 192.0.2.2
+        let records = vec![soa(5), soa(3), address(1), soa(5), address(2), soa(5)];
+        let addr = server(records.into_iter().map(|record| vec![record]).collect()).await;
+        let responses = receive(ixfr(addr, 3).await).await;
+        assert_eq!(responses.len(), 6);
+        let steps = diffs(&responses).unwrap();
+        assert_eq!((steps.len(), steps[0].from, steps[0].to), (1, 3, 5));
+        assert_eq!(steps[0].deleted[0].rdata, [192, 0, 2, 1]);
+        assert_eq!(steps[0].added[0].rdata, [192, 0, 2, 2]);
+
+        // a whole zone instead, one record at a time
+        let records = vec![soa(5), address(1), soa(5)];
+        let addr = server(records.into_iter().map(|record| vec![record]).collect()).await;
+        let responses = receive(ixfr(addr, 3).await).await;
+        assert_eq!(responses.len(), 3);
+        assert!(diffs(&responses).is_none());
+    }
+
+    #[test]
+    fn multi_step_ixfr_round_trips_into_diffs() {
+        // 1 to 2 replacing 192.0.2.1 with .2, then 2 to 4 replacing it with .3
+        let query = Message::query(9, "example.", &QueryType::IXFR, 1);
+        let mut response = MessageBuilder::reply(&query, 0);
+        for (query_type, rdata) in [
+            soa(4),
+            soa(1),
+            address(1),
+            soa(2),
+            address(2),
+            soa(2),
+            address(2),
+            soa(4),
+            address(3),
+            soa(4),
+        ] {
+            response = response.with_answer("example.", query_type, ClassType::IN, 300, rdata);
+        }
+        let encoded = response.build();
+        assert_eq!(&encoded[4..12], &[0, 1, 0, 10, 0, 0, 0, 0]);
+        let message = Message::decode(&encoded).unwrap();
+        let response = Response::new(
+            message,
+            "192.0.2.53:53".to_string(),
+            encoded.len(),
+            Duration::ZERO,
+        );
+        let steps = diffs(&[response]).unwrap();
+        let summary: Vec<(u32, u32, Vec<u8>, Vec<u8>)> = steps
+            .iter()
+            .map(|step| {
+                assert_eq!((step.deleted.len(), step.added.len()), (1, 1));
+                (
+                    step.from,
+                    step.to,
+                    step.deleted[0].rdata.clone(),
+                    step.added[0].rdata.clone(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                (1, 2, vec![192, 0, 2, 1], vec![192, 0, 2, 2]),
+                (2, 4, vec![192, 0, 2, 2], vec![192, 0, 2, 3]),
+            ]
+        );
+        assert!(steps
+            .iter()
+            .flat_map(|step| step.deleted.iter().chain(&step.added))
+            .all(|record| record.name == "example." && record.class == 1));
+    }
+
+    #[tokio::test]
+    async fn ixfr_of_an_up_to_date_zone_is_a_lone_soa() {
+        let addr = server(vec![vec![soa(5)]]).await;
+        let responses = receive(ixfr(addr, 5).await).await;
+        assert_eq!(responses.len(), 1);
+        assert!(diffs(&responses).unwrap().is_empty());
+    }
+}